@@ -323,6 +323,7 @@ fn main() {
         .file("tectonic/dpx-otl_conf.c")
         .file("tectonic/dpx-otl_opt.c")
         .file("tectonic/dpx-pdfcolor.c")
+        .file("tectonic/dpx-pdfconform.c")
         .file("tectonic/dpx-pdfdev.c")
         .file("tectonic/dpx-pdfdoc.c")
         .file("tectonic/dpx-pdfdraw.c")
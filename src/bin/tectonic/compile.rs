@@ -8,7 +8,6 @@
 use structopt::StructOpt;
 
 use std::{
-    env,
     path::{Path, PathBuf},
     str::FromStr,
     time,
@@ -16,7 +15,7 @@ use std::{
 
 use tectonic::{
     config::PersistentConfig,
-    driver::{OutputFormat, PassSetting, ProcessingSessionBuilder},
+    driver::{source_date_epoch, OutputFormat, PassSetting, ProcessingSessionBuilder, RerunPolicy},
     errmsg,
     errors::{ErrorKind, Result},
     status::StatusBackend,
@@ -63,6 +62,22 @@ pub struct CompileOptions {
     #[structopt(name = "count", long = "reruns", short = "r")]
     reruns: Option<usize>,
 
+    /// Rerun the TeX engine at most this many times when deciding automatically
+    #[structopt(long, name = "max_count")]
+    max_reruns: Option<usize>,
+
+    /// Don't rerun the TeX engine because files matching <rerun_pattern> changed
+    #[structopt(long, name = "rerun_pattern", number_of_values = 1)]
+    rerun_ignore: Vec<String>,
+
+    /// Disregard lines containing <text> when deciding if a file changed
+    #[structopt(long, name = "text", number_of_values = 1)]
+    rerun_ignore_lines: Vec<String>,
+
+    /// Report every change that may cause the TeX engine to be rerun
+    #[structopt(long)]
+    rerun_verbose: bool,
+
     /// Keep the intermediate files generated during processing
     #[structopt(short, long)]
     keep_intermediates: bool,
@@ -75,6 +90,18 @@ pub struct CompileOptions {
     #[structopt(long)]
     synctex: bool,
 
+    /// Precompile the preamble of <input> and reuse it while it's unchanged
+    #[structopt(long)]
+    preamble_cache: bool,
+
+    /// Produce byte-for-byte identical output across runs, dated from $SOURCE_DATE_EPOCH
+    #[structopt(long)]
+    reproducible: bool,
+
+    /// Run the external `biber` program if the document needs it
+    #[structopt(long)]
+    biber: bool,
+
     /// Tell the engine that no file at <hide_path> exists, if it tries to read it
     #[structopt(long, name = "hide_path")]
     hide: Option<Vec<PathBuf>>,
@@ -87,6 +114,10 @@ pub struct CompileOptions {
     #[structopt(name = "outdir", short, long, parse(from_os_str))]
     outdir: Option<PathBuf>,
 
+    /// How to format status messages: for humans, or as JSON lines for other programs
+    #[structopt(long, name = "style", default_value = "human", possible_values(&["human", "json"]))]
+    pub message_format: String,
+
     /// Unstable options. Pass -Zhelp to show a list
     // TODO we can't pass -Zhelp without also passing <input>
     #[structopt(name = "option", short = "Z", number_of_values = 1)]
@@ -95,6 +126,13 @@ pub struct CompileOptions {
 
 impl CompileOptions {
     pub fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        // The engine's chatter would be interleaved with the JSON records.
+        if self.print_stdout && self.message_format == "json" {
+            return Err(errmsg!(
+                "--print cannot be combined with --message-format=json, since both use standard output"
+            ));
+        }
+
         let unstable = UnstableOptions::from_unstable_args(self.unstable.into_iter());
 
         let mut sess_builder = ProcessingSessionBuilder::default();
@@ -105,7 +143,9 @@ impl CompileOptions {
             .keep_logs(self.keep_logs)
             .keep_intermediates(self.keep_intermediates)
             .format_cache_path(config.format_cache_path()?)
-            .synctex(self.synctex);
+            .synctex(self.synctex)
+            .preamble_cache(self.preamble_cache)
+            .biber(self.biber);
 
         sess_builder.output_format(OutputFormat::from_str(&self.outfmt).unwrap());
 
@@ -116,6 +156,23 @@ impl CompileOptions {
             sess_builder.reruns(s);
         }
 
+        let mut rerun_policy = RerunPolicy::new();
+        rerun_policy.verbose(self.rerun_verbose);
+
+        if let Some(n) = self.max_reruns {
+            rerun_policy.max_passes(n);
+        }
+
+        for pattern in self.rerun_ignore {
+            rerun_policy.ignore_file(pattern);
+        }
+
+        for text in self.rerun_ignore_lines {
+            rerun_policy.converged_pattern(text);
+        }
+
+        sess_builder.rerun_policy(rerun_policy);
+
         if let Some(p) = self.makefile_rules {
             sess_builder.makefile_output_path(p);
         }
@@ -186,17 +243,15 @@ impl CompileOptions {
             sess_builder.bundle(config.default_bundle(only_cached, status)?);
         }
 
-        let build_date_str = env::var("SOURCE_DATE_EPOCH").ok();
-        let build_date = match build_date_str {
-            Some(s) => {
-                let epoch = u64::from_str_radix(&s, 10).expect("invalid build date (not a number)");
-                time::SystemTime::UNIX_EPOCH
-                    .checked_add(time::Duration::from_secs(epoch))
-                    .expect("time overflow")
-            }
+        let reproducible = self.reproducible || config.reproducible();
+        let build_date = match source_date_epoch()? {
+            Some(date) => date,
+            None if reproducible => time::SystemTime::UNIX_EPOCH,
             None => time::SystemTime::now(),
         };
-        sess_builder.build_date(build_date);
+        sess_builder
+            .build_date(build_date)
+            .reproducible(reproducible);
 
         let mut sess = sess_builder.create(status)?;
         let result = sess.run(status);
@@ -7,6 +7,7 @@ use structopt::StructOpt;
 
 use tectonic::{
     config::PersistentConfig,
+    status::json::JsonStatusBackend,
     status::plain::PlainStatusBackend,
     status::termcolor::TermcolorStatusBackend,
     status::{ChatterLevel, StatusBackend},
@@ -48,6 +49,11 @@ struct CliOptions {
 }
 
 fn main() {
+    // Run the engines in worker processes, so that a crash in the C/C++ code
+    // can't take us down with it. If this process *is* a worker, this call
+    // doesn't return.
+    tectonic::engines::worker::activate();
+
     // Migration to the "cargo-style" command-line interface. If the first
     // argument is `-X`, or argv[0] contains `nextonic`, we activate the
     // alternative operation mode. Once this experimental mode is working OK,
@@ -120,7 +126,9 @@ fn main() {
         _ => unreachable!(),
     };
 
-    let mut status = if use_cli_color {
+    let mut status = if args.compile.message_format == "json" {
+        Box::new(JsonStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
+    } else if use_cli_color {
         Box::new(TermcolorStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
     } else {
         Box::new(PlainStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
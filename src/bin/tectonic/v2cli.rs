@@ -4,16 +4,31 @@
 //! The "v2cli" command-line interface -- a "multitool" interface resembling
 //! Cargo, as compared to the classic "rustc-like" CLI.
 
-use std::{ffi::OsString, path::PathBuf, process, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    thread,
+    time::{Duration, SystemTime},
+};
 use structopt::{clap::AppSettings, StructOpt};
 use tectonic::{
     self,
     config::PersistentConfig,
     ctry,
+    document::{BuildOptions, Document},
+    driver::IoEvents,
+    errmsg,
     errors::Result,
+    io::{cached_itarbundle::CachedITarBundle, InputOrigin},
     status::{
-        plain::PlainStatusBackend, termcolor::TermcolorStatusBackend, ChatterLevel, StatusBackend,
+        json::JsonStatusBackend, plain::PlainStatusBackend, termcolor::TermcolorStatusBackend,
+        ChatterLevel, StatusBackend,
     },
+    synctex::SyncTexData,
     tt_note,
     workspace::{self, Workspace},
 };
@@ -82,7 +97,9 @@ pub fn v2_main(effective_args: &[OsString]) {
         _ => unreachable!(),
     };
 
-    let mut status = if use_cli_color {
+    let mut status = if args.command.message_format() == "json" {
+        Box::new(JsonStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
+    } else if use_cli_color {
         Box::new(TermcolorStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
     } else {
         Box::new(PlainStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
@@ -110,6 +127,10 @@ enum Commands {
     /// Build a document
     Build(BuildCommand),
 
+    #[structopt(name = "bundle")]
+    /// Commands relating to bundles of TeX support files
+    Bundle(BundleCommand),
+
     #[structopt(name = "compile")]
     /// Run a standalone (La)TeX compilation
     Compile(crate::compile::CompileOptions),
@@ -117,14 +138,38 @@ enum Commands {
     #[structopt(name = "new")]
     /// Create a new document
     New(NewCommand),
+
+    #[structopt(name = "synctex")]
+    /// Map between source lines and output positions using SyncTeX data
+    Synctex(SynctexCommand),
+
+    #[structopt(name = "watch")]
+    /// Rebuild a document whenever its source files change
+    Watch(WatchCommand),
 }
 
 impl Commands {
     fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
         match self {
             Commands::Build(o) => o.execute(config, status),
+            Commands::Bundle(o) => o.execute(config, status),
             Commands::Compile(o) => o.execute(config, status),
             Commands::New(o) => o.execute(config, status),
+            Commands::Synctex(o) => o.execute(config, status),
+            Commands::Watch(o) => o.execute(config, status),
+        }
+    }
+
+    /// The requested style of status messages, for subcommands that accept
+    /// the `--message-format` option.
+    fn message_format(&self) -> &str {
+        match self {
+            Commands::Build(o) => &o.message_format,
+            Commands::Bundle(_) => "human",
+            Commands::Compile(o) => &o.message_format,
+            Commands::New(_) => "human",
+            Commands::Synctex(_) => "human",
+            Commands::Watch(o) => &o.build.message_format,
         }
     }
 }
@@ -147,27 +192,402 @@ pub struct BuildCommand {
     /// Print the engine's chatter during processing
     #[structopt(long = "print", short)]
     print_stdout: bool,
+
+    /// How to format status messages: for humans, or as JSON lines for other programs
+    #[structopt(long, name = "style", default_value = "human", possible_values(&["human", "json"]))]
+    message_format: String,
+
+    /// Generate SyncTeX data for use with `tectonic -X synctex`
+    #[structopt(long)]
+    synctex: bool,
+
+    /// Precompile the document preamble and reuse it while it's unchanged
+    #[structopt(long)]
+    preamble_cache: bool,
+
+    /// Produce byte-for-byte identical output across runs, dated from $SOURCE_DATE_EPOCH
+    #[structopt(long)]
+    reproducible: bool,
+
+    /// Run the external `biber` program if the document needs it
+    #[structopt(long)]
+    biber: bool,
+
+    /// Build only the named document of the workspace [default: all of them]
+    #[structopt(long = "doc", name = "doc")]
+    doc: Option<String>,
 }
 
 impl BuildCommand {
+    /// Get the workspace documents that should be built.
+    fn documents<'a>(&self, ws: &'a Workspace) -> Result<Vec<&'a Document>> {
+        Ok(match self.doc {
+            Some(ref name) => vec![ws.find_document(name)?],
+            None => ws.documents().iter().collect(),
+        })
+    }
+
+    fn build_options(
+        &self,
+        doc: &Document,
+        output_name: &str,
+        config: &PersistentConfig,
+    ) -> Result<BuildOptions> {
+        // The engine's chatter would be interleaved with the JSON records.
+        if self.print_stdout && self.message_format == "json" {
+            return Err(errmsg!(
+                "--print cannot be combined with --message-format=json, since both use standard output"
+            ));
+        }
+
+        let mut opts = doc.build_options_for(output_name);
+        opts.format_cache_path(config.format_cache_path()?)
+            .only_cached(self.only_cached)
+            .print_stdout(self.print_stdout)
+            .preamble_cache(self.preamble_cache)
+            .reproducible(self.reproducible || config.reproducible())
+            .biber(self.biber);
+
+        // These flags can only turn on what the output profile leaves off.
+
+        if self.keep_intermediates {
+            opts.keep_intermediates(true);
+        }
+
+        if self.keep_logs {
+            opts.keep_logs(true);
+        }
+
+        if self.synctex {
+            opts.synctex(true);
+        }
+
+        Ok(opts)
+    }
+
     fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
         let ws = Workspace::open_from_environment()?;
-        let doc = ws.first_document();
 
-        for output_name in doc.output_names() {
-            let mut opts = doc.build_options_for(output_name);
-            opts.format_cache_path(config.format_cache_path()?)
-                .only_cached(self.only_cached)
-                .keep_intermediates(self.keep_intermediates)
-                .keep_logs(self.keep_logs)
-                .print_stdout(self.print_stdout);
-            doc.build(output_name, &opts, status)?;
+        for doc in self.documents(&ws)? {
+            for output_name in doc.output_names() {
+                let opts = self.build_options(doc, output_name, &config)?;
+                doc.build(output_name, &opts, status)?;
+            }
         }
 
         Ok(0)
     }
 }
 
+/// `bundle`: Commands relating to bundles of TeX support files
+#[derive(Debug, PartialEq, StructOpt)]
+pub struct BundleCommand {
+    #[structopt(subcommand)]
+    command: BundleCommands,
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+enum BundleCommands {
+    #[structopt(name = "export")]
+    /// Copy files out of a bundle for use without network access
+    Export(BundleExportCommand),
+}
+
+impl BundleCommand {
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        match self.command {
+            BundleCommands::Export(o) => o.execute(config, status),
+        }
+    }
+}
+
+/// `bundle export`: Copy files out of a bundle for use without network access
+#[derive(Debug, PartialEq, StructOpt)]
+pub struct BundleExportCommand {
+    /// Use only resource files cached locally
+    #[structopt(short = "C", long)]
+    only_cached: bool,
+
+    /// Export only the files needed to build the current document
+    #[structopt(long)]
+    only_used: bool,
+
+    /// Create a directory of files rather than a Zip archive
+    #[structopt(short, long)]
+    directory: bool,
+
+    /// The workspace document whose bundle to export [default: the first]
+    #[structopt(long = "doc", name = "doc")]
+    doc: Option<String>,
+
+    /// The Zip archive or directory to create
+    #[structopt(parse(from_os_str))]
+    dest: PathBuf,
+}
+
+impl BundleExportCommand {
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        // Export the current document's bundle if there is one, or the
+        // default bundle otherwise. Figuring out which files the document
+        // uses requires a document, of course.
+
+        let ws = if self.only_used {
+            Some(Workspace::open_from_environment()?)
+        } else {
+            Workspace::open_from_environment().ok()
+        };
+        let doc = match (ws.as_ref(), self.doc.as_ref()) {
+            (Some(ws), Some(name)) => Some(ws.find_document(name)?),
+            (Some(ws), None) => Some(ws.first_document()),
+            (None, Some(_)) => return Err(errmsg!("`--doc` can only be used inside a workspace")),
+            (None, None) => None,
+        };
+
+        let bundle_locs = match doc {
+            Some(doc) => doc.bundle_locs().iter().map(|l| l.as_ref()).collect(),
+            None => config.default_bundle_locs(),
+        };
+
+        // Local bundles are already available offline, so only a network
+        // bundle needs exporting.
+
+        let network_locs: Vec<&str> = bundle_locs
+            .into_iter()
+            .filter(|loc| match reqwest::Url::parse(loc) {
+                Ok(url) => url.scheme() != "file",
+                Err(_) => false,
+            })
+            .collect();
+
+        let bundle_loc = match network_locs[..] {
+            [loc] => loc,
+            [] => return Err(errmsg!("there is no network bundle to export")),
+            _ => {
+                return Err(errmsg!(
+                    "there are {} network bundles; only one can be exported",
+                    network_locs.len()
+                ));
+            }
+        };
+
+        let mut bundle = CachedITarBundle::new(bundle_loc, self.only_cached, None, status)?;
+
+        let names = match doc {
+            Some(doc) if self.only_used => {
+                // Build with an empty format cache and without consulting
+                // the build manifest, so that every bundle file the build
+                // needs -- including those that go into the format -- is
+                // actually read and recorded.
+
+                let format_cache =
+                    ctry!(tempfile::tempdir(); "couldn't create a temporary format cache");
+                let mut names = HashSet::new();
+
+                for output_name in doc.output_names() {
+                    let mut opts = doc.build_options_for(output_name);
+                    opts.format_cache_path(format_cache.path())
+                        .only_cached(self.only_cached)
+                        .build_cache(false);
+
+                    let mut events = IoEvents::new();
+                    doc.build_with_events(output_name, &opts, status, &mut events)?;
+
+                    names.extend(
+                        events
+                            .0
+                            .iter()
+                            .filter(|(_, summ)| summ.input_origin == InputOrigin::Other)
+                            .filter_map(|(name, _)| name.to_str().map(|s| s.to_owned())),
+                    );
+                }
+
+                names.into_iter().collect()
+            }
+            _ => bundle.all_files(),
+        };
+
+        let names = names.iter().map(|n| n.as_ref());
+
+        let n_files = if self.directory {
+            bundle.export_dir(names, &self.dest, status)?
+        } else {
+            let file = ctry!(File::create(&self.dest); "couldn't create `{}`", self.dest.display());
+            bundle.export_zip(names, file, status)?
+        };
+
+        tt_note!(
+            status,
+            "exported {} files to `{}`",
+            n_files,
+            self.dest.display()
+        );
+        Ok(0)
+    }
+}
+
+/// `watch`: Rebuild a document whenever its source files change
+#[derive(Debug, PartialEq, StructOpt)]
+pub struct WatchCommand {
+    #[structopt(flatten)]
+    build: BuildCommand,
+
+    /// How long to wait for changes to settle before rebuilding, in milliseconds
+    #[structopt(long, name = "ms", default_value = "300")]
+    debounce: u64,
+}
+
+/// How often to check the watched files for modifications.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The modification times of a set of watched files.
+type MtimeSnapshot = HashMap<PathBuf, Option<SystemTime>>;
+
+/// Get the modification times of a set of files. Files that can't be
+/// examined (e.g., because they don't exist) are recorded as `None`, so that
+/// their creation counts as a change.
+fn snapshot_mtimes<'a, I: IntoIterator<Item = &'a PathBuf>>(paths: I) -> MtimeSnapshot {
+    paths
+        .into_iter()
+        .map(|p| (p.clone(), fs::metadata(p).and_then(|md| md.modified()).ok()))
+        .collect()
+}
+
+/// Find a file whose modification time in `after` differs from the one in
+/// `before`, if any.
+fn find_changed<'a>(before: &'a MtimeSnapshot, after: &MtimeSnapshot) -> Option<&'a Path> {
+    before
+        .iter()
+        .find(|(p, mtime)| after.get(*p) != Some(*mtime))
+        .map(|(p, _)| p.as_ref())
+}
+
+/// Add every file below `dir` to `paths`. Errors are ignored, since this is
+/// only used to find something to watch when a build didn't get far enough
+/// to tell us what it reads.
+fn collect_files(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => collect_files(&path, paths),
+            Ok(_) => paths.push(path),
+            Err(_) => {}
+        }
+    }
+}
+
+impl WatchCommand {
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let ws = Workspace::open_from_environment()?;
+        let toml_path = ws.root_dir().join("Tectonic.toml");
+        let mut src_dirs: Vec<PathBuf> = self
+            .build
+            .documents(&ws)?
+            .iter()
+            .map(|d| d.tex_dir())
+            .collect();
+        let debounce = Duration::from_millis(self.debounce);
+
+        loop {
+            // Build everything, gathering up the files that the builds
+            // read from the source tree. Failures aren't fatal here, not
+            // even a broken `Tectonic.toml`: we want to try again once the
+            // user has fixed things.
+
+            let mut inputs = Vec::new();
+
+            match Workspace::open_from_environment() {
+                Ok(ws) => match self.build_all(&ws, &config, status, &mut inputs) {
+                    Ok(dirs) => src_dirs = dirs,
+                    Err(e) => status.report_error(&e),
+                },
+                Err(e) => status.report_error(&e),
+            }
+
+            // If the builds failed before reading anything, we don't know
+            // what they depend on, so watch the whole source tree.
+
+            if inputs.is_empty() {
+                for dir in &src_dirs {
+                    collect_files(dir, &mut inputs);
+                }
+            }
+
+            // Always watch the workspace configuration and the source
+            // directories themselves, so that we notice edits to the former
+            // and new files appearing in the latter.
+
+            inputs.push(toml_path.clone());
+            inputs.extend(src_dirs.iter().cloned());
+
+            let mut snapshot = snapshot_mtimes(&inputs);
+            tt_note!(
+                status,
+                "watching {} source files for changes",
+                snapshot.len()
+            );
+
+            let changed = loop {
+                thread::sleep(WATCH_POLL_INTERVAL);
+                let current = snapshot_mtimes(snapshot.keys());
+
+                if let Some(p) = find_changed(&snapshot, &current) {
+                    break p.to_owned();
+                }
+            };
+
+            // Debounce: wait until the files have stopped changing for a
+            // little while, so that a burst of edits (or an editor that
+            // saves by writing several files) only triggers one rebuild.
+
+            loop {
+                snapshot = snapshot_mtimes(snapshot.keys());
+                thread::sleep(debounce);
+                let current = snapshot_mtimes(snapshot.keys());
+
+                if find_changed(&snapshot, &current).is_none() {
+                    break;
+                }
+            }
+
+            tt_note!(status, "`{}` changed; rebuilding", changed.display());
+        }
+    }
+
+    /// Build all of the selected documents of the workspace, adding the
+    /// files that the builds read from the source tree to `inputs`. Returns
+    /// the source directories of the documents.
+    fn build_all(
+        &self,
+        ws: &Workspace,
+        config: &PersistentConfig,
+        status: &mut dyn StatusBackend,
+        inputs: &mut Vec<PathBuf>,
+    ) -> Result<Vec<PathBuf>> {
+        let docs = self.build.documents(ws)?;
+
+        for doc in &docs {
+            for output_name in doc.output_names() {
+                let opts = self.build.build_options(doc, output_name, config)?;
+                let mut events = IoEvents::new();
+
+                if let Err(e) = doc.build_with_events(output_name, &opts, status, &mut events) {
+                    status.report_error(&e);
+                }
+
+                inputs.extend(doc.filesystem_inputs(&events));
+            }
+        }
+
+        Ok(docs.iter().map(|d| d.tex_dir()).collect())
+    }
+}
+
 /// `new`: Create a new document
 #[derive(Debug, PartialEq, StructOpt)]
 pub struct NewCommand {
@@ -192,3 +612,271 @@ impl NewCommand {
         Ok(0)
     }
 }
+
+/// `synctex`: Map between source lines and output positions using SyncTeX data
+#[derive(Debug, PartialEq, StructOpt)]
+pub struct SynctexCommand {
+    #[structopt(subcommand)]
+    command: SynctexCommands,
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+enum SynctexCommands {
+    #[structopt(name = "view")]
+    /// Find where a source line appears in the output (forward search)
+    View(SynctexViewCommand),
+
+    #[structopt(name = "edit")]
+    /// Find the source line behind a point of the output (inverse search)
+    Edit(SynctexEditCommand),
+}
+
+impl SynctexCommand {
+    fn execute(self, _config: PersistentConfig, _status: &mut dyn StatusBackend) -> Result<i32> {
+        match self.command {
+            SynctexCommands::View(o) => o.execute(),
+            SynctexCommands::Edit(o) => o.execute(),
+        }
+    }
+}
+
+/// Where to find SyncTeX data.
+#[derive(Debug, PartialEq, StructOpt)]
+struct SynctexSource {
+    /// The SyncTeX file to use [default: that of the current document's output]
+    #[structopt(long, parse(from_os_str))]
+    file: Option<PathBuf>,
+
+    /// The workspace document whose SyncTeX data to use [default: the first]
+    #[structopt(long = "doc", name = "doc")]
+    doc: Option<String>,
+
+    /// The output of the current document whose SyncTeX data to use [default: the first]
+    #[structopt(long, name = "name")]
+    output: Option<String>,
+}
+
+impl SynctexSource {
+    /// Get the workspace document whose SyncTeX data to use.
+    fn document<'a>(&self, ws: &'a Workspace) -> Result<&'a Document> {
+        match self.doc {
+            Some(ref name) => ws.find_document(name),
+            None => Ok(ws.first_document()),
+        }
+    }
+
+    /// Load the SyncTeX data, along with the document they belong to if
+    /// they were found through the workspace.
+    fn load(&self) -> Result<(SyncTexData, Option<Workspace>)> {
+        if let Some(ref path) = self.file {
+            return Ok((SyncTexData::open(path)?, None));
+        }
+
+        let ws = Workspace::open_from_environment()?;
+        let doc = self.document(&ws)?;
+
+        let output = match self.output {
+            Some(ref name) => {
+                if !doc.output_names().any(|n| n == name) {
+                    return Err(errmsg!("the document has no output named `{}`", name));
+                }
+                name.clone()
+            }
+            None => match doc.output_names().next() {
+                Some(name) => name.to_owned(),
+                None => return Err(errmsg!("the document has no outputs")),
+            },
+        };
+
+        let path = doc.synctex_path(&output);
+
+        if !path.exists() {
+            return Err(errmsg!(
+                "there is no SyncTeX data at `{}`; build with `tectonic -X build --synctex` first",
+                path.display()
+            ));
+        }
+
+        let data = SyncTexData::open(&path)?;
+        Ok((data, Some(ws)))
+    }
+}
+
+/// `synctex view`: Find where a source line appears in the output
+#[derive(Debug, PartialEq, StructOpt)]
+pub struct SynctexViewCommand {
+    #[structopt(flatten)]
+    source: SynctexSource,
+
+    /// The source file
+    input: String,
+
+    /// The line number within the source file, counting from 1
+    line: u32,
+}
+
+impl SynctexViewCommand {
+    fn execute(self) -> Result<i32> {
+        let (data, _ws) = self.source.load()?;
+        let regions = data.forward(&self.input, self.line);
+
+        if regions.is_empty() {
+            return Err(errmsg!(
+                "no output corresponds to line {} of `{}`",
+                self.line,
+                self.input
+            ));
+        }
+
+        // This mimics the output of the `synctex` program, so that editors
+        // can reuse the code they already have for it.
+
+        println!("SyncTeX result begin");
+        for r in regions {
+            println!("Page:{}", r.page);
+            println!("x:{:.2}", r.x);
+            println!("y:{:.2}", r.y);
+            println!("h:{:.2}", r.x);
+            println!("v:{:.2}", r.y + r.depth);
+            println!("W:{:.2}", r.width);
+            println!("H:{:.2}", r.height + r.depth);
+        }
+        println!("SyncTeX result end");
+        Ok(0)
+    }
+}
+
+/// `synctex edit`: Find the source line behind a point of the output
+#[derive(Debug, PartialEq, StructOpt)]
+pub struct SynctexEditCommand {
+    #[structopt(flatten)]
+    source: SynctexSource,
+
+    /// The page number, counting from 1
+    page: u32,
+
+    /// The horizontal position on the page, in big points from the left edge
+    x: f64,
+
+    /// The vertical position on the page, in big points from the top edge
+    y: f64,
+}
+
+impl SynctexEditCommand {
+    fn execute(self) -> Result<i32> {
+        let (data, ws) = self.source.load()?;
+
+        let loc = match data.inverse(self.page, self.x, self.y) {
+            Some(loc) => loc,
+            None => return Err(errmsg!("there is no SyncTeX data for page {}", self.page)),
+        };
+
+        // Names in a document's SyncTeX data are relative to its source
+        // directory.
+
+        let input = match ws {
+            Some(ref ws) => self
+                .source
+                .document(ws)?
+                .source_path(&loc.file)
+                .display()
+                .to_string(),
+            None => loc.file.clone(),
+        };
+
+        println!("SyncTeX result begin");
+        println!("Input:{}", input);
+        println!("Line:{}", loc.line);
+        println!("Column:{}", loc.column.map_or(-1, |c| c as i64));
+        println!("SyncTeX result end");
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filetime::FileTime;
+    use std::time::UNIX_EPOCH;
+
+    fn snapshot(entries: &[(&str, Option<u64>)]) -> MtimeSnapshot {
+        entries
+            .iter()
+            .map(|(p, t)| {
+                (
+                    PathBuf::from(p),
+                    t.map(|t| UNIX_EPOCH + Duration::from_secs(t)),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn watch_find_changed() {
+        let before = snapshot(&[("a.tex", Some(1)), ("b.tex", None)]);
+        assert_eq!(find_changed(&before, &before.clone()), None);
+
+        // Modified.
+        let after = snapshot(&[("a.tex", Some(2)), ("b.tex", None)]);
+        assert_eq!(find_changed(&before, &after), Some(Path::new("a.tex")));
+
+        // Created.
+        let after = snapshot(&[("a.tex", Some(1)), ("b.tex", Some(3))]);
+        assert_eq!(find_changed(&before, &after), Some(Path::new("b.tex")));
+
+        // Deleted.
+        let after = snapshot(&[("a.tex", None), ("b.tex", None)]);
+        assert_eq!(find_changed(&before, &after), Some(Path::new("a.tex")));
+    }
+
+    /// The rebuild waits for a poll that sees a change, then for a debounce
+    /// interval across which nothing changes.
+    #[test]
+    fn watch_snapshots_and_debounce() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let a = tempdir.path().join("a.tex");
+        let b = tempdir.path().join("b.tex");
+        fs::write(&a, "A").unwrap();
+        filetime::set_file_mtime(&a, FileTime::from_unix_time(1000, 0)).unwrap();
+
+        let paths = vec![a.clone(), b.clone()];
+        let first = snapshot_mtimes(&paths);
+        assert!(first[&a].is_some());
+        assert_eq!(first[&b], None);
+
+        // A quiet interval: no rebuild is triggered, and debouncing settles.
+        let quiet = snapshot_mtimes(first.keys());
+        assert_eq!(find_changed(&first, &quiet), None);
+
+        filetime::set_file_mtime(&a, FileTime::from_unix_time(2000, 0)).unwrap();
+        let edited = snapshot_mtimes(first.keys());
+        assert_eq!(find_changed(&first, &edited), Some(a.as_ref()));
+
+        // Another edit during the debounce interval keeps us waiting.
+        fs::write(&b, "B").unwrap();
+        let busy = snapshot_mtimes(edited.keys());
+        assert_eq!(find_changed(&edited, &busy), Some(b.as_ref()));
+        assert_eq!(find_changed(&busy, &snapshot_mtimes(busy.keys())), None);
+    }
+
+    #[test]
+    fn watch_fallback_files() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let src = tempdir.path().join("src");
+        fs::create_dir_all(src.join("chapters")).unwrap();
+        fs::write(src.join("index.tex"), "").unwrap();
+        fs::write(src.join("chapters").join("one.tex"), "").unwrap();
+
+        let mut files = Vec::new();
+        collect_files(&src, &mut files);
+        files.sort();
+        assert_eq!(
+            files,
+            vec![src.join("chapters").join("one.tex"), src.join("index.tex")]
+        );
+
+        let mut files = Vec::new();
+        collect_files(&tempdir.path().join("missing"), &mut files);
+        assert!(files.is_empty());
+    }
+}
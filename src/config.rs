@@ -20,6 +20,7 @@ use crate::app_dirs;
 use crate::errors::{ErrorKind, Result};
 use crate::io::cached_itarbundle::CachedITarBundle;
 use crate::io::dirbundle::DirBundle;
+use crate::io::layeredbundle::LayeredBundle;
 use crate::io::zipbundle::ZipBundle;
 use crate::io::Bundle;
 use crate::status::StatusBackend;
@@ -45,6 +46,9 @@ pub fn is_config_test_mode_activated() -> bool {
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct PersistentConfig {
     default_bundles: Vec<BundleInfo>,
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    reproducible: bool,
 }
 
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
@@ -135,10 +139,25 @@ impl PersistentConfig {
         Ok(bundle)
     }
 
-    pub fn default_bundle_loc(&self) -> &str {
-        &self.default_bundles[0].url
+    /// Whether builds should be reproducible by default. See
+    /// [`crate::driver::ProcessingSessionBuilder::reproducible`].
+    pub fn reproducible(&self) -> bool {
+        self.reproducible
+    }
+
+    /// Get the locations of all of the default bundles, in order of priority.
+    pub fn default_bundle_locs(&self) -> Vec<&str> {
+        self.default_bundles
+            .iter()
+            .map(|b| b.url.as_ref())
+            .collect()
     }
 
+    /// Get the default bundle.
+    ///
+    /// If more than one default bundle is configured, they are layered into a
+    /// single bundle, with files in earlier bundles shadowing those in later
+    /// ones.
     pub fn default_bundle(
         &self,
         only_cached: bool,
@@ -151,25 +170,36 @@ impl PersistentConfig {
             return Ok(Box::new(crate::test_util::TestBundle::default()));
         }
 
-        if self.default_bundles.len() != 1 {
+        if self.default_bundles.is_empty() {
             return Err(ErrorKind::Msg(
-                "exactly one default_bundle item must be specified (for now)".to_owned(),
+                "at least one default_bundle item must be specified".to_owned(),
             )
             .into());
         }
 
-        let url = Url::parse(&self.default_bundles[0].url)
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "failed to parse url"))?;
-        if url.scheme() == "file" {
-            // load the local zip file.
-            let file_path = url.to_file_path().map_err(|_| {
-                io::Error::new(io::ErrorKind::InvalidInput, "failed to parse local path")
-            })?;
-            return self.make_local_file_provider(file_path, status);
+        let mut bundles = Vec::new();
+
+        for info in &self.default_bundles {
+            let url = Url::parse(&info.url)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "failed to parse url"))?;
+
+            if url.scheme() == "file" {
+                // load the local zip file.
+                let file_path = url.to_file_path().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "failed to parse local path")
+                })?;
+                bundles.push(self.make_local_file_provider(file_path, status)?);
+            } else {
+                bundles.push(self.make_cached_url_provider(
+                    &info.url,
+                    only_cached,
+                    None,
+                    status,
+                )?);
+            }
         }
-        let bundle =
-            self.make_cached_url_provider(&self.default_bundles[0].url, only_cached, None, status)?;
-        Ok(Box::new(bundle) as _)
+
+        LayeredBundle::compose(bundles)
     }
 
     pub fn format_cache_path(&self) -> Result<PathBuf> {
@@ -187,6 +217,7 @@ impl Default for PersistentConfig {
             default_bundles: vec![BundleInfo {
                 url: String::from("https://archive.org/services/purl/net/pkgwpub/tectonic-default"),
             }],
+            reproducible: false,
         }
     }
 }
@@ -75,6 +75,12 @@ impl DigestData {
     }
 }
 
+impl AsRef<[u8]> for DigestData {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 impl ToString for DigestData {
     fn to_string(&self) -> String {
         bytes_to_hex(&self.0)
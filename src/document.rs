@@ -9,21 +9,28 @@ use std::{
     env, fs,
     io::{self, Read, Write},
     path::{Component, Path, PathBuf},
+    time::SystemTime,
 };
 
 use crate::{
     config, ctry,
-    driver::{OutputFormat, PassSetting, ProcessingSessionBuilder},
+    driver::{source_date_epoch, IoEvents, OutputFormat, PassSetting, ProcessingSessionBuilder},
+    engines::{
+        PdfConformance, PdfDocInfo, PdfEncryption, PdfOptions, PdfOutputIntent, PdfPermissions,
+        ShellEscapePolicy,
+    },
     errmsg,
     errors::{ErrorKind, Result},
     io::{
         cached_itarbundle::{resolve_url, CachedITarBundle},
         dirbundle::DirBundle,
+        layeredbundle::LayeredBundle,
         zipbundle::ZipBundle,
-        Bundle,
+        Bundle, InputOrigin,
     },
     status::StatusBackend,
     test_util, tt_error, tt_note,
+    unstable_opts::UnstableOptions,
     workspace::WorkspaceCreator,
 };
 
@@ -42,9 +49,33 @@ pub struct Document {
     /// need to be the same as the document title.
     name: String,
 
-    /// The name of core TeX file bundle upon which this document is based.
-    /// Either a URL or a local path.
-    bundle_loc: String,
+    /// The names of the core TeX file bundles upon which this document is
+    /// based, each either a URL or a local path. Files in earlier bundles
+    /// shadow those in later ones.
+    bundle_locs: Vec<String>,
+
+    /// Which `\write18` commands the document may run while being built.
+    shell_escape: ShellEscapePolicy,
+
+    /// The names of the files, relative to the source directory, that make up
+    /// the document: the preamble, the body, and the postamble. Documents
+    /// sharing a source directory are distinguished by these.
+    preamble_file: String,
+    index_file: String,
+    postamble_file: String,
+
+    /// Metadata about the document, such as its title and authors, which is
+    /// recorded in PDF outputs.
+    info: PdfDocInfo,
+
+    /// A fixed creation date for the document. If unset, builds use the date
+    /// given by `$SOURCE_DATE_EPOCH`, or else the start of the Unix epoch, so
+    /// that they are reproducible.
+    date: Option<SystemTime>,
+
+    /// Whether builds should be byte-for-byte reproducible. See
+    /// [`ProcessingSessionBuilder::reproducible`].
+    reproducible: bool,
 
     /// The different outputs that are created from the document source. These
     /// may have different formats (e.g., PDF and HTML) or the same format but
@@ -61,46 +92,165 @@ fn default_outputs() -> HashMap<String, OutputProfile> {
             name: "default".to_owned(),
             target_type: BuildTargetType::Pdf,
             tex_format: "latex".to_owned(),
+            pdf: PdfOptions::default(),
+            preamble_cache: false,
+            paper_size: None,
+            synctex: false,
+            keep_intermediates: false,
+            keep_logs: false,
+            pass: PassSetting::Default,
+            reruns: None,
+            tex_defs: Vec::new(),
+            preamble_file: None,
+            index_file: None,
+            postamble_file: None,
         },
     );
     outputs
 }
 
 impl Document {
-    /// Initialize a Document based on a TOML specification
-    pub(crate) fn new_from_toml<R: Read>(
+    /// Load the Documents defined in a TOML specification. A specification
+    /// usually defines a single document, but may define several that share
+    /// a source directory. In the latter case, each document's build
+    /// artifacts go in a subdirectory of `build_dir` named after it.
+    pub(crate) fn load_from_toml<R: Read>(
         src_dir: PathBuf,
         build_dir: PathBuf,
         toml_data: &mut R,
-    ) -> Result<Self> {
+    ) -> Result<Vec<Self>> {
         let mut toml_text = String::new();
         toml_data.read_to_string(&mut toml_text)?;
-        let doc: syntax::Document = toml::from_str(&toml_text)?;
+        let spec: syntax::Document = toml::from_str(&toml_text)?;
+
+        let sections = match spec.doc {
+            syntax::DocSections::One(section) => vec![*section],
+            syntax::DocSections::Many(sections) => sections,
+        };
+
+        if sections.is_empty() {
+            return Err(errmsg!(
+                "TOML specification must define at least one document"
+            ));
+        }
+
+        let multi = sections.len() > 1;
+        let mut docs: Vec<Document> = Vec::new();
+
+        for section in sections {
+            if docs.iter().any(|d| d.name == section.name) {
+                return Err(errmsg!(
+                    "duplicated document name `{}` in TOML specification",
+                    section.name
+                ));
+            }
+
+            let mut components = Path::new(&section.name).components();
+
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(c)), None) if c == section.name.as_str() => {}
+                _ => {
+                    return Err(errmsg!(
+                        "invalid document name `{}` in TOML specification: \
+                         it must be a plain file name",
+                        section.name
+                    ));
+                }
+            }
+
+            let doc_build_dir = if multi {
+                build_dir.join(&section.name)
+            } else {
+                build_dir.clone()
+            };
+
+            docs.push(Document::from_toml_section(
+                src_dir.clone(),
+                doc_build_dir,
+                section,
+                &spec.outputs,
+            )?);
+        }
+
+        Ok(docs)
+    }
+
+    /// Initialize a Document based on one `[doc]` section of a TOML
+    /// specification. The outputs defined at the top level of the
+    /// specification apply to every document.
+    fn from_toml_section(
+        src_dir: PathBuf,
+        build_dir: PathBuf,
+        section: syntax::DocSection,
+        shared_outputs: &[syntax::OutputProfile],
+    ) -> Result<Self> {
+        let bundle_locs = section.bundle.to_runtime();
+
+        if bundle_locs.is_empty() {
+            return Err(errmsg!(
+                "TOML specification must define at least one bundle for document `{}`",
+                section.name
+            ));
+        }
 
         let mut outputs = HashMap::new();
 
-        for toml_output in &doc.outputs {
-            let output = toml_output.to_runtime();
+        for toml_output in shared_outputs.iter().chain(&section.outputs) {
+            let output = toml_output.to_runtime()?;
 
             if outputs.insert(output.name.clone(), output).is_some() {
                 return Err(errmsg!(
-                    "duplicated output name `{}` in TOML specification",
-                    &toml_output.name
+                    "duplicated output name `{}` in TOML specification of document `{}`",
+                    &toml_output.name,
+                    section.name
                 ));
             }
         }
 
         if outputs.is_empty() {
             return Err(errmsg!(
-                "TOML specification must define at least one output"
+                "TOML specification must define at least one output for document `{}`",
+                section.name
             ));
         }
 
+        let date = match section.date {
+            Some(ref d) => Some(ctry!(
+                syntax::datetime_to_runtime(d);
+                "invalid date for document `{}`", section.name
+            )),
+            None => None,
+        };
+
+        let info = PdfDocInfo {
+            title: section.title,
+            authors: section.authors,
+            subject: section.subject,
+            keywords: section.keywords,
+            language: section.language,
+        };
+
         Ok(Document {
             src_dir,
             build_dir,
-            name: doc.doc.name,
-            bundle_loc: doc.doc.bundle,
+            info,
+            date,
+            reproducible: section.reproducible.unwrap_or(false),
+            shell_escape: section
+                .shell_escape
+                .map(|s| s.to_runtime())
+                .unwrap_or_default(),
+            preamble_file: section
+                .preamble
+                .unwrap_or_else(|| DEFAULT_PREAMBLE_FILE.to_owned()),
+            index_file: section
+                .index
+                .unwrap_or_else(|| DEFAULT_INDEX_FILE.to_owned()),
+            postamble_file: section
+                .postamble
+                .unwrap_or_else(|| DEFAULT_POSTAMBLE_FILE.to_owned()),
+            name: section.name,
+            bundle_locs,
             outputs,
         })
     }
@@ -151,12 +301,21 @@ impl Document {
             name
         };
 
-        // Determine the bundle URL that we'll put in as the default.
+        // Determine the bundle URLs that we'll put in as the default.
 
-        let bundle_loc = if config::is_config_test_mode_activated() {
-            "test-bundle".to_owned()
+        let bundle_locs = if config::is_config_test_mode_activated() {
+            vec!["test-bundle".to_owned()]
         } else {
-            resolve_url(config.default_bundle_loc(), status)?
+            let mut locs = Vec::new();
+
+            for loc in config.default_bundle_locs() {
+                match Url::parse(loc) {
+                    Ok(ref url) if url.scheme() != "file" => locs.push(resolve_url(loc, status)?),
+                    _ => locs.push(loc.to_owned()),
+                }
+            }
+
+            locs
         };
 
         // All done.
@@ -164,7 +323,14 @@ impl Document {
             src_dir,
             build_dir,
             name,
-            bundle_loc,
+            bundle_locs,
+            shell_escape: ShellEscapePolicy::Deny,
+            preamble_file: DEFAULT_PREAMBLE_FILE.to_owned(),
+            index_file: DEFAULT_INDEX_FILE.to_owned(),
+            postamble_file: DEFAULT_POSTAMBLE_FILE.to_owned(),
+            info: PdfDocInfo::default(),
+            date: None,
+            reproducible: false,
             outputs: default_outputs(),
         })
     }
@@ -179,11 +345,31 @@ impl Document {
             .map(|r| syntax::OutputProfile::from_runtime(r))
             .collect();
 
+        fn changed(value: &str, dflt: &str) -> Option<String> {
+            if value == dflt {
+                None
+            } else {
+                Some(value.to_owned())
+            }
+        }
+
         let doc = syntax::Document {
-            doc: syntax::DocSection {
+            doc: syntax::DocSections::One(Box::new(syntax::DocSection {
                 name: self.name.clone(),
-                bundle: self.bundle_loc.clone(),
-            },
+                bundle: syntax::BundleLocs::from_runtime(&self.bundle_locs),
+                shell_escape: syntax::ShellEscape::from_runtime(&self.shell_escape),
+                preamble: changed(&self.preamble_file, DEFAULT_PREAMBLE_FILE),
+                index: changed(&self.index_file, DEFAULT_INDEX_FILE),
+                postamble: changed(&self.postamble_file, DEFAULT_POSTAMBLE_FILE),
+                title: self.info.title.clone(),
+                authors: self.info.authors.clone(),
+                subject: self.info.subject.clone(),
+                keywords: self.info.keywords.clone(),
+                language: self.info.language.clone(),
+                date: self.date.map(syntax::datetime_from_runtime),
+                reproducible: if self.reproducible { Some(true) } else { None },
+                outputs: Vec::new(),
+            })),
             outputs,
         };
 
@@ -210,6 +396,37 @@ pub struct OutputProfile {
     name: String,
     target_type: BuildTargetType,
     tex_format: String,
+    pdf: PdfOptions,
+
+    /// Whether to dump the document preamble into a format file of its own,
+    /// so that later builds can skip it when it hasn't changed.
+    preamble_cache: bool,
+
+    /// The paper size to use for the output, in the form understood by
+    /// `xdvipdfmx`'s `-p` option (e.g., `a4` or `21cm,29.7cm`). If unset,
+    /// US Letter is used.
+    paper_size: Option<String>,
+
+    /// The defaults for the corresponding `BuildOptions`.
+    synctex: bool,
+    keep_intermediates: bool,
+    keep_logs: bool,
+
+    /// Which kind of pass to run, and how many times to rerun the TeX engine.
+    /// If `reruns` is unset, it is figured out automatically.
+    pass: PassSetting,
+    reruns: Option<usize>,
+
+    /// Macros to `\def` before the document preamble, as pairs of control
+    /// sequence name and replacement text. This lets outputs share the same
+    /// source but, say, build a draft or a handout.
+    tex_defs: Vec<(String, String)>,
+
+    /// Overrides of the document's preamble, body and postamble files for
+    /// this output.
+    preamble_file: Option<String>,
+    index_file: Option<String>,
+    postamble_file: Option<String>,
 }
 
 /// The output target type of a document build.
@@ -217,16 +434,47 @@ pub struct OutputProfile {
 pub enum BuildTargetType {
     /// Output to the Portable Document Format (PDF).
     Pdf,
+
+    /// Output to HTML.
+    Html,
+
+    /// Output to extended DVI (XDV), the native output of the TeX engine.
+    Xdv,
+
+    /// Only run the TeX engine far enough to create the `.aux` file.
+    Aux,
 }
 
 /// Temporary options for a document build.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct BuildOptions {
     format_cache_path: Option<PathBuf>,
     only_cached: bool,
     keep_intermediates: bool,
     keep_logs: bool,
     print_stdout: bool,
+    build_cache: bool,
+    synctex: bool,
+    preamble_cache: bool,
+    reproducible: bool,
+    biber: bool,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions {
+            format_cache_path: None,
+            only_cached: false,
+            keep_intermediates: false,
+            keep_logs: false,
+            print_stdout: false,
+            build_cache: true,
+            synctex: false,
+            preamble_cache: false,
+            reproducible: false,
+            biber: false,
+        }
+    }
 }
 
 impl BuildOptions {
@@ -254,15 +502,53 @@ impl BuildOptions {
         self.print_stdout = value;
         self
     }
+
+    /// If set to `false`, the build is always run in full, even if the
+    /// build manifest from a previous run shows that nothing has changed.
+    pub fn build_cache(&mut self, value: bool) -> &mut Self {
+        self.build_cache = value;
+        self
+    }
+
+    /// If set to `true`, SyncTeX data are written alongside the output. See
+    /// [`Document::synctex_path`].
+    pub fn synctex(&mut self, value: bool) -> &mut Self {
+        self.synctex = value;
+        self
+    }
+
+    /// If set to `true`, the preamble is precompiled even if the output
+    /// profile doesn't ask for it.
+    pub fn preamble_cache(&mut self, value: bool) -> &mut Self {
+        self.preamble_cache = value;
+        self
+    }
+
+    /// If set to `true`, the build is made reproducible even if the
+    /// document doesn't ask for it.
+    pub fn reproducible(&mut self, value: bool) -> &mut Self {
+        self.reproducible = value;
+        self
+    }
+
+    /// If set to `true`, the external `biber` program is run if the document
+    /// needs it. See [`ProcessingSessionBuilder::biber`].
+    pub fn biber(&mut self, value: bool) -> &mut Self {
+        self.biber = value;
+        self
+    }
 }
 
-const DEFAULT_PRIMARY_INPUT: &[u8] = br#"
-\input _preamble.tex
-\input index.tex
-\input _postamble.tex
-"#;
+const DEFAULT_PREAMBLE_FILE: &str = "_preamble.tex";
+const DEFAULT_INDEX_FILE: &str = "index.tex";
+const DEFAULT_POSTAMBLE_FILE: &str = "_postamble.tex";
 
 impl Document {
+    /// Get the name of this document.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     /// Iterate over the names of the output profiles defined for this document.
     /// These may have different formats (e.g., PDF and HTML) or the same format
     /// but different settings (e.g., PDF with A4 paper and PDF with US Letter
@@ -275,11 +561,23 @@ impl Document {
     ///
     /// Panics if the output name is not one of the ones associated with this
     /// document.
-    pub fn build_options_for(&self, _output_profile: &str) -> BuildOptions {
-        BuildOptions::default()
+    pub fn build_options_for(&self, output_profile: &str) -> BuildOptions {
+        let profile = self.outputs.get(output_profile).unwrap();
+        let mut opts = BuildOptions::default();
+        opts.synctex(profile.synctex)
+            .keep_intermediates(profile.keep_intermediates)
+            .keep_logs(profile.keep_logs);
+        opts
+    }
+
+    /// Get the locations of the bundles used by this document, in order of
+    /// priority. Each is either a URL or a local path.
+    pub fn bundle_locs(&self) -> &[String] {
+        &self.bundle_locs
     }
 
-    /// Get the bundle used by this document.
+    /// Get the bundle used by this document. If the document specifies more
+    /// than one bundle, they are layered into a single bundle.
     pub fn bundle(
         &self,
         only_cached: bool,
@@ -294,20 +592,65 @@ impl Document {
         }
 
         if config::is_config_test_mode_activated() {
-            Ok(Box::new(test_util::TestBundle::default()))
-        } else if let Ok(url) = Url::parse(&self.bundle_loc) {
-            if url.scheme() != "file" {
-                let bundle = CachedITarBundle::new(&self.bundle_loc, only_cached, None, status)?;
-                Ok(Box::new(bundle))
+            return Ok(Box::new(test_util::TestBundle::default()));
+        }
+
+        let mut bundles = Vec::new();
+
+        for loc in &self.bundle_locs {
+            let bundle: Box<dyn Bundle> = if let Ok(url) = Url::parse(loc) {
+                if url.scheme() != "file" {
+                    Box::new(CachedITarBundle::new(loc, only_cached, None, status)?)
+                } else {
+                    let file_path = url.to_file_path().map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "failed to parse local path")
+                    })?;
+                    bundle_from_path(file_path)?
+                }
             } else {
-                let file_path = url.to_file_path().map_err(|_| {
-                    io::Error::new(io::ErrorKind::InvalidInput, "failed to parse local path")
-                })?;
-                bundle_from_path(file_path)
-            }
-        } else {
-            bundle_from_path(Path::new(&self.bundle_loc).to_owned())
+                bundle_from_path(Path::new(loc).to_owned())?
+            };
+
+            bundles.push(bundle);
         }
+
+        LayeredBundle::compose(bundles)
+    }
+
+    /// The directory containing the TeX source files of this document.
+    pub fn tex_dir(&self) -> PathBuf {
+        let mut tex_dir = self.src_dir.clone();
+        tex_dir.push("src");
+        tex_dir
+    }
+
+    /// Get the path of a file in the document’s source tree, given its name
+    /// as the TeX engine knows it.
+    pub fn source_path(&self, tex_name: &str) -> PathBuf {
+        self.tex_dir().join(tex_name)
+    }
+
+    /// Get the path where a build of an output writes its SyncTeX data, if
+    /// SyncTeX is enabled.
+    pub fn synctex_path(&self, output_profile: &str) -> PathBuf {
+        let mut path = self.build_dir.clone();
+        path.push(output_profile);
+        path.push(format!("{}.synctex.gz", output_profile));
+        path
+    }
+
+    /// Get the paths of the files that a build read from the document’s
+    /// source tree, as recorded in its I/O events. Resource files that came
+    /// from the bundle are not included.
+    pub fn filesystem_inputs(&self, events: &IoEvents) -> Vec<PathBuf> {
+        let tex_dir = self.tex_dir();
+
+        events
+            .0
+            .iter()
+            .filter(|(_, summ)| summ.input_origin == InputOrigin::Filesystem)
+            .map(|(name, _)| tex_dir.join(name))
+            .collect()
     }
 
     /// Build one of the document’s outputs.
@@ -316,23 +659,99 @@ impl Document {
         output_profile: &str,
         options: &BuildOptions,
         status: &mut dyn StatusBackend,
+    ) -> Result<i32> {
+        let mut events = IoEvents::new();
+        self.build_with_events(output_profile, options, status, &mut events)
+    }
+
+    /// Build one of the document’s outputs, recording the I/O performed
+    /// during processing into `events`.
+    ///
+    /// The events are recorded even if the build fails, provided that
+    /// processing got started at all, so that callers can learn which files
+    /// a broken build depended upon.
+    pub fn build_with_events(
+        &self,
+        output_profile: &str,
+        options: &BuildOptions,
+        status: &mut dyn StatusBackend,
+        events: &mut IoEvents,
     ) -> Result<i32> {
         let profile = self.outputs.get(output_profile).unwrap();
 
         let output_format = match profile.target_type {
             BuildTargetType::Pdf => OutputFormat::Pdf,
+            BuildTargetType::Html => OutputFormat::Html,
+            BuildTargetType::Xdv => OutputFormat::Xdv,
+            BuildTargetType::Aux => OutputFormat::Aux,
         };
 
+        let preamble_cache = profile.preamble_cache || options.preamble_cache;
+
+        let preamble_file = profile
+            .preamble_file
+            .as_ref()
+            .unwrap_or(&self.preamble_file);
+        let index_file = profile.index_file.as_ref().unwrap_or(&self.index_file);
+        let postamble_file = profile
+            .postamble_file
+            .as_ref()
+            .unwrap_or(&self.postamble_file);
+
+        // The definitions all go on the first line, so that they don't throw
+        // off the line numbers of the preamble below.
+        let mut primary_input = Vec::new();
+
+        for (name, value) in &profile.tex_defs {
+            write!(primary_input, "\\def\\{}{{{}}}", name, value)?;
+        }
+
+        write!(
+            primary_input,
+            "\n\\input {}\n\\input {}\n\\input {}\n",
+            preamble_file, index_file, postamble_file
+        )?;
+
         let mut sess_builder = ProcessingSessionBuilder::default();
         sess_builder
             .output_format(output_format)
             .format_name(&profile.tex_format)
-            .pass(PassSetting::Default)
-            .primary_input_buffer(DEFAULT_PRIMARY_INPUT)
+            .pass(profile.pass)
+            .primary_input_buffer(&primary_input)
             .tex_input_name(output_profile)
             .keep_logs(options.keep_logs)
             .keep_intermediates(options.keep_intermediates)
-            .print_stdout(options.print_stdout);
+            .print_stdout(options.print_stdout)
+            .build_cache(options.build_cache)
+            .synctex(options.synctex)
+            .preamble_cache(preamble_cache)
+            .preamble_file(preamble_file)
+            .reproducible(self.reproducible || options.reproducible)
+            .biber(options.biber)
+            .pdf_options(PdfOptions {
+                info: self.info.clone(),
+                ..profile.pdf.clone()
+            })
+            .unstables(UnstableOptions {
+                paper_size: profile.paper_size.clone(),
+                shell_escape: self.shell_escape.clone(),
+                ..Default::default()
+            });
+
+        if let Some(n) = profile.reruns {
+            sess_builder.reruns(n);
+        }
+
+        // A date given in the document takes precedence over one given in
+        // the environment.
+        let date = match self.date {
+            Some(date) => Some(date),
+            None => source_date_epoch()?,
+        };
+
+        if let Some(date) = date {
+            sess_builder.build_date(date);
+        }
 
         if options.only_cached {
             tt_note!(status, "using only cached resource files");
@@ -345,9 +764,7 @@ impl Document {
             sess_builder.format_cache_path(p);
         }
 
-        let mut tex_dir = self.src_dir.clone();
-        tex_dir.push("src");
-        sess_builder.filesystem_root(&tex_dir);
+        sess_builder.filesystem_root(self.tex_dir());
 
         let mut output_dir = self.build_dir.clone();
         output_dir.push(output_profile);
@@ -374,6 +791,7 @@ impl Document {
             }
         }
 
+        *events = sess.events;
         result.map(|_| 0)
     }
 }
@@ -381,21 +799,142 @@ impl Document {
 /// The concrete syntax for saving document state, wired up via serde.
 mod syntax {
     use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+    use std::{
+        collections::BTreeMap,
+        time::{Duration, SystemTime},
+    };
+    use toml::value::Datetime;
+
+    use super::{
+        PassSetting, PdfConformance, PdfEncryption, PdfOptions, PdfOutputIntent, PdfPermissions,
+    };
+    use crate::{ctry, errmsg, errors::Result as TectonicResult};
 
     #[derive(Debug, Deserialize, Serialize)]
     #[serde(deny_unknown_fields)]
     pub struct Document {
-        pub doc: DocSection,
+        pub doc: DocSections,
 
-        #[serde(rename = "output")]
+        #[serde(default, rename = "output")]
         pub outputs: Vec<OutputProfile>,
     }
 
+    /// Either a single `[doc]` table, or an array of `[[doc]]` tables
+    /// defining several documents.
+    #[derive(Debug, Serialize)]
+    #[serde(untagged)]
+    pub enum DocSections {
+        One(Box<DocSection>),
+        Many(Vec<DocSection>),
+    }
+
+    // Deserialized by hand, rather than with `untagged`, so that mistakes in
+    // the document definitions get useful error messages.
+    impl<'de> Deserialize<'de> for DocSections {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let value = toml::Value::deserialize(deserializer)?;
+
+            if value.is_array() {
+                value
+                    .try_into()
+                    .map(DocSections::Many)
+                    .map_err(D::Error::custom)
+            } else {
+                value
+                    .try_into()
+                    .map(|section| DocSections::One(Box::new(section)))
+                    .map_err(D::Error::custom)
+            }
+        }
+    }
+
     #[derive(Debug, Deserialize, Serialize)]
     #[serde(deny_unknown_fields)]
     pub struct DocSection {
         pub name: String,
-        pub bundle: String,
+        pub bundle: BundleLocs,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub shell_escape: Option<ShellEscape>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub preamble: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub index: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub postamble: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub title: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub authors: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub subject: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub keywords: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub language: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub date: Option<Datetime>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub reproducible: Option<bool>,
+        #[serde(default, rename = "output", skip_serializing_if = "Vec::is_empty")]
+        pub outputs: Vec<OutputProfile>,
+    }
+
+    /// Either a single bundle location, or a list of them in order of
+    /// priority.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(untagged)]
+    pub enum BundleLocs {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    impl BundleLocs {
+        pub fn from_runtime(rt: &[String]) -> Self {
+            if rt.len() == 1 {
+                BundleLocs::One(rt[0].clone())
+            } else {
+                BundleLocs::Many(rt.to_vec())
+            }
+        }
+
+        pub fn to_runtime(&self) -> Vec<String> {
+            match self {
+                BundleLocs::One(loc) => vec![loc.clone()],
+                BundleLocs::Many(locs) => locs.clone(),
+            }
+        }
+    }
+
+    /// Either `true` or `false` to allow or deny all `\write18` commands, or
+    /// a list of the programs that may be run.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(untagged)]
+    pub enum ShellEscape {
+        Enabled(bool),
+        Allowlist(Vec<String>),
+    }
+
+    impl ShellEscape {
+        pub fn from_runtime(rt: &super::ShellEscapePolicy) -> Option<Self> {
+            match rt {
+                super::ShellEscapePolicy::Deny => None,
+                super::ShellEscapePolicy::Allowlist(cmds) => {
+                    Some(ShellEscape::Allowlist(cmds.clone()))
+                }
+                super::ShellEscapePolicy::AllowAll => Some(ShellEscape::Enabled(true)),
+            }
+        }
+
+        pub fn to_runtime(&self) -> super::ShellEscapePolicy {
+            match self {
+                ShellEscape::Enabled(false) => super::ShellEscapePolicy::Deny,
+                ShellEscape::Enabled(true) => super::ShellEscapePolicy::AllowAll,
+                ShellEscape::Allowlist(cmds) => super::ShellEscapePolicy::Allowlist(cmds.clone()),
+            }
+        }
     }
 
     #[derive(Debug, Deserialize, Serialize)]
@@ -405,6 +944,31 @@ mod syntax {
         #[serde(rename = "type")]
         pub target_type: BuildTargetType,
         pub tex_format: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub preamble_cache: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub paper_size: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub synctex: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub keep_intermediates: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub keep_logs: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub pass: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub reruns: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub preamble: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub index: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub postamble: Option<String>,
+        // Tables must come after plain values when serializing.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub defs: BTreeMap<String, String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub pdf: Option<PdfSection>,
     }
 
     impl OutputProfile {
@@ -415,15 +979,80 @@ mod syntax {
                 Some(rt.tex_format.clone())
             };
 
+            fn flag(value: bool) -> Option<bool> {
+                if value {
+                    Some(true)
+                } else {
+                    None
+                }
+            }
+
+            let pass = match rt.pass {
+                PassSetting::Default => None,
+                PassSetting::Tex => Some("tex".to_owned()),
+                PassSetting::BibtexFirst => Some("bibtex_first".to_owned()),
+            };
+
             OutputProfile {
                 name: rt.name.clone(),
                 target_type: BuildTargetType::from_runtime(&rt.target_type),
                 tex_format,
+                preamble_cache: flag(rt.preamble_cache),
+                paper_size: rt.paper_size.clone(),
+                synctex: flag(rt.synctex),
+                keep_intermediates: flag(rt.keep_intermediates),
+                keep_logs: flag(rt.keep_logs),
+                pass,
+                reruns: rt.reruns,
+                preamble: rt.preamble_file.clone(),
+                index: rt.index_file.clone(),
+                postamble: rt.postamble_file.clone(),
+                defs: rt.tex_defs.iter().cloned().collect(),
+                pdf: PdfSection::from_runtime(&rt.pdf),
             }
         }
 
-        pub fn to_runtime(&self) -> super::OutputProfile {
-            super::OutputProfile {
+        pub fn to_runtime(&self) -> TectonicResult<super::OutputProfile> {
+            if self.pdf.is_some() && self.target_type != BuildTargetType::Pdf {
+                return Err(errmsg!(
+                    "output `{}` has PDF settings, but it isn't a PDF output",
+                    self.name
+                ));
+            }
+
+            let pdf = match self.pdf {
+                Some(ref p) => ctry!(
+                    p.to_runtime();
+                    "invalid PDF settings for output `{}`", self.name
+                ),
+                None => PdfOptions::default(),
+            };
+
+            let pass = match self.pass {
+                Some(ref p) => match p.parse() {
+                    Ok(pass) => pass,
+                    Err(_) => {
+                        return Err(errmsg!(
+                            "unknown pass `{}` for output `{}`; expected `default`, `tex` or `bibtex_first`",
+                            p,
+                            self.name
+                        ))
+                    }
+                },
+                None => PassSetting::Default,
+            };
+
+            for name in self.defs.keys() {
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+                    return Err(errmsg!(
+                        "cannot define `\\{}` for output `{}`; macro names may only contain letters",
+                        name,
+                        self.name
+                    ));
+                }
+            }
+
+            Ok(super::OutputProfile {
                 name: self.name.clone(),
                 target_type: self.target_type.to_runtime(),
                 tex_format: self
@@ -432,25 +1061,423 @@ mod syntax {
                     .map(|s| s.as_ref())
                     .unwrap_or("latex")
                     .to_owned(),
+                pdf,
+                preamble_cache: self.preamble_cache.unwrap_or(false),
+                paper_size: self.paper_size.clone(),
+                synctex: self.synctex.unwrap_or(false),
+                keep_intermediates: self.keep_intermediates.unwrap_or(false),
+                keep_logs: self.keep_logs.unwrap_or(false),
+                pass,
+                reruns: self.reruns,
+                tex_defs: self
+                    .defs
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+                preamble_file: self.preamble.clone(),
+                index_file: self.index.clone(),
+                postamble_file: self.postamble.clone(),
+            })
+        }
+    }
+
+    /// Settings for PDF output. Anything left out takes its default value.
+    #[derive(Debug, Default, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct PdfSection {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub version: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub compression_level: Option<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub annot_grow: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub bookmark_open: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub magnification: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub font_dpi: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub thumbnails: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub decimal_digits: Option<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub x_offset: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub y_offset: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub encryption: Option<EncryptionSection>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub conformance: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub icc_profile: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub output_condition: Option<String>,
+    }
+
+    impl PdfSection {
+        pub fn from_runtime(rt: &PdfOptions) -> Option<Self> {
+            let dflt = PdfOptions::default();
+
+            if *rt == dflt {
+                return None;
+            }
+
+            fn changed<T: Clone + PartialEq>(v: &T, dflt: &T) -> Option<T> {
+                if v == dflt {
+                    None
+                } else {
+                    Some(v.clone())
+                }
+            }
+
+            Some(PdfSection {
+                version: changed(&rt.version, &dflt.version)
+                    .map(|(major, minor)| format!("{}.{}", major, minor)),
+                compression_level: changed(&rt.compression_level, &dflt.compression_level),
+                annot_grow: changed(&rt.annot_grow, &dflt.annot_grow),
+                bookmark_open: changed(&rt.bookmark_open, &dflt.bookmark_open),
+                magnification: changed(&rt.magnification, &dflt.magnification),
+                font_dpi: changed(&rt.font_dpi, &dflt.font_dpi),
+                thumbnails: changed(&rt.thumbnails, &dflt.thumbnails),
+                decimal_digits: changed(&rt.decimal_digits, &dflt.decimal_digits),
+                x_offset: changed(&rt.x_offset, &dflt.x_offset),
+                y_offset: changed(&rt.y_offset, &dflt.y_offset),
+                encryption: rt.encryption.as_ref().map(EncryptionSection::from_runtime),
+                conformance: rt.conformance.map(|c| c.to_string().to_lowercase()),
+                icc_profile: rt.output_intent.as_ref().map(|i| i.profile.clone()),
+                output_condition: rt.output_intent.as_ref().map(|i| i.identifier.clone()),
+            })
+        }
+
+        pub fn to_runtime(&self) -> TectonicResult<PdfOptions> {
+            let mut rt = PdfOptions::default();
+
+            if let Some(ref v) = self.version {
+                rt.version = match parse_pdf_version(v) {
+                    Some(version) => version,
+                    None => return Err(errmsg!("PDF version `{}` should look like `1.5`", v)),
+                };
+            }
+
+            if let Some(v) = self.compression_level {
+                rt.compression_level = v;
+            }
+
+            if let Some(v) = self.annot_grow {
+                rt.annot_grow = v;
+            }
+
+            if let Some(v) = self.bookmark_open {
+                rt.bookmark_open = v;
+            }
+
+            if let Some(v) = self.magnification {
+                rt.magnification = v;
+            }
+
+            if let Some(v) = self.font_dpi {
+                rt.font_dpi = v;
+            }
+
+            if let Some(v) = self.thumbnails {
+                rt.thumbnails = v;
+            }
+
+            if let Some(v) = self.decimal_digits {
+                rt.decimal_digits = v;
+            }
+
+            if let Some(v) = self.x_offset {
+                rt.x_offset = v;
+            }
+
+            if let Some(v) = self.y_offset {
+                rt.y_offset = v;
+            }
+
+            if let Some(ref e) = self.encryption {
+                rt.encryption = Some(e.to_runtime()?);
+            }
+
+            if let Some(ref c) = self.conformance {
+                let conformance: PdfConformance = c.parse()?;
+
+                // Pick the newest version that the standard allows, unless
+                // one was given explicitly.
+                if self.version.is_none() {
+                    rt.version = rt.version.min(conformance.max_version());
+                }
+
+                rt.conformance = Some(conformance);
+            }
+
+            rt.output_intent = match (&self.icc_profile, &self.output_condition) {
+                (Some(profile), condition) => Some(PdfOutputIntent {
+                    profile: profile.clone(),
+                    identifier: condition.clone().unwrap_or_else(|| profile.clone()),
+                }),
+                (None, Some(condition)) => match rt.conformance {
+                    Some(conformance) => Some(PdfOutputIntent {
+                        identifier: condition.clone(),
+                        ..PdfOutputIntent::default_for(conformance)
+                    }),
+                    None => {
+                        return Err(errmsg!(
+                            "`output_condition` can only be given along with `icc_profile`"
+                        ))
+                    }
+                },
+                (None, None) => None,
+            };
+
+            rt.validate()?;
+            Ok(rt)
+        }
+    }
+
+    /// Settings for encrypting PDF output.
+    #[derive(Debug, Default, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct EncryptionSection {
+        pub owner_password: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub user_password: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub key_bits: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub permissions: Option<Vec<String>>,
+    }
+
+    const PERMISSION_NAMES: &[&str] = &[
+        "print",
+        "modify",
+        "copy",
+        "annotate",
+        "fill_forms",
+        "extract_for_accessibility",
+        "assemble",
+        "print_high_quality",
+    ];
+
+    fn permission_flag<'a>(perms: &'a mut PdfPermissions, name: &str) -> Option<&'a mut bool> {
+        Some(match name {
+            "print" => &mut perms.print,
+            "modify" => &mut perms.modify,
+            "copy" => &mut perms.copy,
+            "annotate" => &mut perms.annotate,
+            "fill_forms" => &mut perms.fill_forms,
+            "extract_for_accessibility" => &mut perms.extract_for_accessibility,
+            "assemble" => &mut perms.assemble,
+            "print_high_quality" => &mut perms.print_high_quality,
+            _ => return None,
+        })
+    }
+
+    impl EncryptionSection {
+        pub fn from_runtime(rt: &PdfEncryption) -> Self {
+            let dflt = PdfEncryption::default();
+
+            let permissions = if rt.permissions == dflt.permissions {
+                None
+            } else {
+                let mut perms = rt.permissions;
+                Some(
+                    PERMISSION_NAMES
+                        .iter()
+                        .filter(|n| *permission_flag(&mut perms, n).unwrap())
+                        .map(|n| (*n).to_owned())
+                        .collect(),
+                )
+            };
+
+            EncryptionSection {
+                owner_password: rt.owner_password.clone(),
+                user_password: Some(rt.user_password.clone()).filter(|p| !p.is_empty()),
+                key_bits: Some(rt.key_bits).filter(|b| *b != dflt.key_bits),
+                permissions,
             }
         }
+
+        pub fn to_runtime(&self) -> TectonicResult<PdfEncryption> {
+            let mut rt = PdfEncryption {
+                owner_password: self.owner_password.clone(),
+                ..Default::default()
+            };
+
+            if let Some(ref p) = self.user_password {
+                rt.user_password = p.clone();
+            }
+
+            if let Some(b) = self.key_bits {
+                rt.key_bits = b;
+            }
+
+            if let Some(ref names) = self.permissions {
+                rt.permissions = PdfPermissions::from_bits(0);
+
+                for name in names {
+                    match permission_flag(&mut rt.permissions, name) {
+                        Some(flag) => *flag = true,
+                        None => {
+                            return Err(errmsg!(
+                                "unknown PDF permission `{}`; expected one of: {}",
+                                name,
+                                PERMISSION_NAMES.join(", ")
+                            ))
+                        }
+                    }
+                }
+            }
+
+            Ok(rt)
+        }
+    }
+
+    /// Convert a TOML date or date-time to a point in time. Times without an
+    /// offset are taken to be in UTC, and a bare date means its midnight.
+    pub fn datetime_to_runtime(dt: &Datetime) -> TectonicResult<SystemTime> {
+        let text = dt.to_string();
+
+        let bad = || {
+            errmsg!(
+                "`{}` should look like `2020-05-01` or `2020-05-01T12:00:00Z`",
+                text
+            )
+        };
+
+        let number = |s: &str| -> TectonicResult<i64> {
+            if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(bad());
+            }
+            s.parse().map_err(|_| bad())
+        };
+
+        let (date, time) = match text.find(['T', 't', ' ']) {
+            Some(i) => (&text[..i], &text[i + 1..]),
+            None => (&text[..], ""),
+        };
+
+        let mut pieces = date.split('-');
+        let (year, month, day) = match (pieces.next(), pieces.next(), pieces.next(), pieces.next())
+        {
+            (Some(y), Some(m), Some(d), None) => (number(y)?, number(m)?, number(d)?),
+            _ => return Err(bad()),
+        };
+
+        let mut seconds = days_from_civil(year, month, day) * 86400;
+
+        if !time.is_empty() {
+            // Split off the offset, if any, and ignore fractional seconds.
+            let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+                Some(i) => (&time[..i], &time[i..]),
+                None => (time, ""),
+            };
+            let clock = clock.split('.').next().unwrap_or("");
+
+            let mut pieces = clock.split(':');
+            let (h, m, s) = match (pieces.next(), pieces.next(), pieces.next(), pieces.next()) {
+                (Some(h), Some(m), Some(s), None) => (number(h)?, number(m)?, number(s)?),
+                _ => return Err(bad()),
+            };
+            seconds += h * 3600 + m * 60 + s;
+
+            if offset.len() > 1 {
+                let sign = if offset.starts_with('-') { -1 } else { 1 };
+                let mut pieces = offset[1..].split(':');
+                let (oh, om) = match (pieces.next(), pieces.next(), pieces.next()) {
+                    (Some(h), Some(m), None) => (number(h)?, number(m)?),
+                    _ => return Err(bad()),
+                };
+                seconds -= sign * (oh * 3600 + om * 60);
+            }
+        }
+
+        if seconds < 0 {
+            return Err(errmsg!("`{}` is before 1970", text));
+        }
+
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds as u64))
+    }
+
+    pub fn datetime_from_runtime(rt: SystemTime) -> Datetime {
+        let seconds = rt
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()) as i64;
+        let (year, month, day) = civil_from_days(seconds / 86400);
+        let time = seconds % 86400;
+
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            time / 3600,
+            time / 60 % 60,
+            time % 60
+        )
+        .parse()
+        .expect("formatted date should be valid")
+    }
+
+    /// The number of days from 1970-01-01 to a date in the proleptic
+    /// Gregorian calendar. See <http://howardhinnant.github.io/date_algorithms.html>.
+    fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = (if y >= 0 { y } else { y - 399 }) / 400;
+        let yoe = y - era * 400;
+        let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    /// The inverse of `days_from_civil`.
+    fn civil_from_days(days: i64) -> (i64, i64, i64) {
+        let z = days + 719_468;
+        let era = (if z >= 0 { z } else { z - 146_096 }) / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year, month, day)
+    }
+
+    fn parse_pdf_version(text: &str) -> Option<(u8, u8)> {
+        let mut pieces = text.splitn(2, '.');
+        let major = pieces.next()?.parse().ok()?;
+        let minor = pieces.next()?.parse().ok()?;
+        Some((major, minor))
     }
 
     #[derive(Clone, Copy, Debug, Eq, PartialEq)]
     pub enum BuildTargetType {
         Pdf,
+        Html,
+        Xdv,
+        Aux,
     }
 
+    const TARGET_TYPE_NAMES: &[&str] = &["pdf", "html", "xdv", "aux"];
+
     impl BuildTargetType {
         pub fn from_runtime(rt: &super::BuildTargetType) -> Self {
             match rt {
                 super::BuildTargetType::Pdf => BuildTargetType::Pdf,
+                super::BuildTargetType::Html => BuildTargetType::Html,
+                super::BuildTargetType::Xdv => BuildTargetType::Xdv,
+                super::BuildTargetType::Aux => BuildTargetType::Aux,
             }
         }
 
         pub fn to_runtime(&self) -> super::BuildTargetType {
             match self {
                 BuildTargetType::Pdf => super::BuildTargetType::Pdf,
+                BuildTargetType::Html => super::BuildTargetType::Html,
+                BuildTargetType::Xdv => super::BuildTargetType::Xdv,
+                BuildTargetType::Aux => super::BuildTargetType::Aux,
             }
         }
     }
@@ -462,6 +1489,9 @@ mod syntax {
         {
             serializer.serialize_str(match *self {
                 BuildTargetType::Pdf => "pdf",
+                BuildTargetType::Html => "html",
+                BuildTargetType::Xdv => "xdv",
+                BuildTargetType::Aux => "aux",
             })
         }
     }
@@ -473,8 +1503,162 @@ mod syntax {
             let s = String::deserialize(deserializer)?;
             Ok(match s.as_str() {
                 "pdf" => BuildTargetType::Pdf,
-                other => return Err(<D as Deserializer>::Error::unknown_variant(other, &["pdf"])),
+                "html" => BuildTargetType::Html,
+                "xdv" => BuildTargetType::Xdv,
+                "aux" => BuildTargetType::Aux,
+                other => {
+                    return Err(<D as Deserializer>::Error::unknown_variant(
+                        other,
+                        TARGET_TYPE_NAMES,
+                    ))
+                }
             })
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engines::IoEventBackend;
+    use std::{
+        ffi::OsStr,
+        time::{Duration, SystemTime},
+    };
+    use toml::value::Datetime;
+
+    const TOML: &str = "[doc]\n\
+                        name = \"doc\"\n\
+                        bundle = \"bundle.zip\"\n\
+                        \n\
+                        [[output]]\n\
+                        name = \"default\"\n\
+                        type = \"pdf\"\n";
+
+    fn load(toml: &str) -> Result<Vec<Document>> {
+        Document::load_from_toml(
+            PathBuf::from("ws"),
+            PathBuf::from("ws").join("build"),
+            &mut toml.as_bytes(),
+        )
+    }
+
+    /// Convert a TOML date to seconds since the epoch.
+    fn epoch_seconds(text: &str) -> Result<u64> {
+        let dt: Datetime = text.parse().unwrap();
+        let rt = syntax::datetime_to_runtime(&dt)?;
+        Ok(rt.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs())
+    }
+
+    #[test]
+    fn filesystem_inputs() {
+        let doc = load(TOML).unwrap().pop().unwrap();
+        let mut events = IoEvents::new();
+
+        events.input_opened(OsStr::new("index.tex"), InputOrigin::Filesystem);
+        events.input_opened(OsStr::new("chapters/one.tex"), InputOrigin::Filesystem);
+        events.input_opened(OsStr::new("article.cls"), InputOrigin::Other);
+        events.input_not_available(OsStr::new("missing.tex"));
+
+        // Files that the engine wrote before reading them aren't sources.
+        events.output_opened(OsStr::new("index.aux"));
+        events.input_opened(OsStr::new("index.aux"), InputOrigin::Filesystem);
+
+        let mut inputs = doc.filesystem_inputs(&events);
+        inputs.sort();
+
+        let src = PathBuf::from("ws").join("src");
+        assert_eq!(
+            inputs,
+            vec![src.join("chapters/one.tex"), src.join("index.tex")]
+        );
+    }
+
+    #[test]
+    fn datetime_parsing() {
+        assert_eq!(epoch_seconds("1970-01-01").unwrap(), 0);
+        assert_eq!(epoch_seconds("2020-05-01").unwrap(), 1_588_291_200);
+        assert_eq!(
+            epoch_seconds("2020-05-01T12:30:15Z").unwrap(),
+            1_588_336_215
+        );
+        assert_eq!(
+            epoch_seconds("2020-05-01t12:30:15z").unwrap(),
+            1_588_336_215
+        );
+
+        // Offsets are subtracted to get back to UTC.
+        assert_eq!(
+            epoch_seconds("2020-05-01T12:30:15+02:00").unwrap(),
+            1_588_336_215 - 7200
+        );
+        assert_eq!(
+            epoch_seconds("2020-05-01T12:30:15-05:30").unwrap(),
+            1_588_336_215 + 19800
+        );
+
+        // Local date-times are taken to be in UTC.
+        assert_eq!(epoch_seconds("2020-05-01 12:30:15").unwrap(), 1_588_336_215);
+        assert_eq!(epoch_seconds("2020-05-01T12:30:15").unwrap(), 1_588_336_215);
+
+        // Fractional seconds are dropped.
+        assert_eq!(
+            epoch_seconds("2020-05-01T12:30:15.999Z").unwrap(),
+            1_588_336_215
+        );
+        assert_eq!(
+            epoch_seconds("2020-05-01T12:30:15.5+02:00").unwrap(),
+            1_588_336_215 - 7200
+        );
+    }
+
+    #[test]
+    fn datetime_leap_days() {
+        assert_eq!(epoch_seconds("2020-02-29").unwrap(), 1_582_934_400);
+        assert_eq!(
+            epoch_seconds("2020-03-01").unwrap() - epoch_seconds("2020-02-28").unwrap(),
+            2 * 86400
+        );
+        assert_eq!(
+            epoch_seconds("2100-03-01").unwrap() - epoch_seconds("2100-02-28").unwrap(),
+            86400
+        );
+        assert_eq!(
+            epoch_seconds("2000-03-01").unwrap() - epoch_seconds("2000-02-28").unwrap(),
+            2 * 86400
+        );
+    }
+
+    #[test]
+    fn datetime_before_1970() {
+        for text in &[
+            "1969-12-31",
+            "1969-12-31T23:59:59Z",
+            "1970-01-01T00:30:00+01:00",
+        ] {
+            let e = epoch_seconds(text).unwrap_err();
+            assert!(e.to_string().contains("before 1970"), "{}: {}", text, e);
+        }
+
+        assert_eq!(epoch_seconds("1970-01-01T01:00:00+01:00").unwrap(), 0);
+    }
+
+    #[test]
+    fn datetime_round_trip() {
+        let rt = SystemTime::UNIX_EPOCH + Duration::from_secs(1_582_983_045);
+        let dt = syntax::datetime_from_runtime(rt);
+        assert_eq!(dt.to_string(), "2020-02-29T13:30:45Z");
+        assert_eq!(syntax::datetime_to_runtime(&dt).unwrap(), rt);
+
+        // Sub-second precision is lost on the way out.
+        let dt = syntax::datetime_from_runtime(rt + Duration::from_millis(250));
+        assert_eq!(syntax::datetime_to_runtime(&dt).unwrap(), rt);
+
+        // Walk across a few centuries to exercise the calendar arithmetic.
+        for days in (0..100_000u64).step_by(97) {
+            let rt = SystemTime::UNIX_EPOCH + Duration::from_secs(days * 86400 + 3661);
+            let dt = syntax::datetime_from_runtime(rt);
+            assert_eq!(syntax::datetime_to_runtime(&dt).unwrap(), rt, "{}", dt);
+        }
+    }
+}
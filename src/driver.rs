@@ -14,17 +14,17 @@
 use byte_unit::Byte;
 use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
-use std::fs::File;
-use std::io::Write;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::result::Result as StdResult;
 use std::str::FromStr;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use crate::{
     ctry,
-    digest::DigestData,
+    digest::{self, Digest, DigestData},
     engines::IoEventBackend,
     errmsg,
     errors::{ErrorKind, Result, ResultExt},
@@ -33,9 +33,11 @@ use crate::{
         OpenResult,
     },
     status::StatusBackend,
+    synctex::SyncTexData,
     tt_error, tt_note, tt_warning,
     unstable_opts::UnstableOptions,
-    BibtexEngine, Spx2HtmlEngine, TexEngine, TexResult, XdvipdfmxEngine,
+    BiberEngine, BibtexEngine, MakeindexEngine, PdfOptions, Spx2HtmlEngine, TexEngine, TexResult,
+    XdvipdfmxEngine,
 };
 
 /// Different patterns with which files may have been accessed by the
@@ -102,11 +104,13 @@ impl FileSummary {
 
 /// The IoEvents type implements the IoEventBackend. It is used to figure out when to rerun the TeX
 /// engine, to figure out which files should be written to disk, and to emit Makefile rules.
+#[derive(Default)]
 pub struct IoEvents(pub HashMap<OsString, FileSummary>);
 
 impl IoEvents {
-    fn new() -> IoEvents {
-        IoEvents(HashMap::new())
+    /// Create a new, empty record of I/O events.
+    pub fn new() -> IoEvents {
+        Default::default()
     }
 }
 
@@ -274,6 +278,235 @@ impl FromStr for PassSetting {
     }
 }
 
+/// How the default pass decides whether to rerun the TeX engine.
+///
+/// The engine is rerun when a file that it read was rewritten with different
+/// contents. This policy bounds the number of passes, and allows some changes
+/// to be disregarded so that documents that never quite converge can still
+/// be built efficiently.
+#[derive(Clone, Debug)]
+pub struct RerunPolicy {
+    max_passes: usize,
+    ignored_files: Vec<String>,
+    converged_patterns: Vec<String>,
+    verbose: bool,
+}
+
+impl Default for RerunPolicy {
+    fn default() -> Self {
+        RerunPolicy {
+            max_passes: DEFAULT_MAX_TEX_PASSES,
+            ignored_files: Vec::new(),
+            converged_patterns: Vec::new(),
+            verbose: false,
+        }
+    }
+}
+
+impl RerunPolicy {
+    /// Create the default policy, which reruns the TeX engine up to six
+    /// times whenever any relevant file changes.
+    pub fn new() -> RerunPolicy {
+        Default::default()
+    }
+
+    /// Set the maximum number of times that the TeX engine will be rerun.
+    /// This has no effect if an exact number of reruns is requested with
+    /// [`ProcessingSessionBuilder::reruns`].
+    pub fn max_passes(&mut self, n: usize) -> &mut Self {
+        self.max_passes = n;
+        self
+    }
+
+    /// Never rerun the TeX engine because a file whose name matches this
+    /// pattern changed. The pattern may contain the wildcards `*` and `?`.
+    pub fn ignore_file<S: Into<String>>(&mut self, pattern: S) -> &mut Self {
+        self.ignored_files.push(pattern.into());
+        self
+    }
+
+    /// When deciding whether a file changed, disregard lines that contain
+    /// this text. This is useful for things like timestamps that are written
+    /// to the `.aux` file on every pass.
+    pub fn converged_pattern<S: Into<String>>(&mut self, text: S) -> &mut Self {
+        self.converged_patterns.push(text.into());
+        self
+    }
+
+    /// If set, report every changed file that might trigger a rerun, along
+    /// with its digests before and after the pass and a diff if it is text.
+    pub fn verbose(&mut self, verbose: bool) -> &mut Self {
+        self.verbose = verbose;
+        self
+    }
+
+    fn ignores_file(&self, name: &str) -> bool {
+        self.ignored_files.iter().any(|p| glob_matches(p, name))
+    }
+
+    /// Whether the file contents need to be kept around between passes.
+    fn needs_contents(&self) -> bool {
+        self.verbose || !self.converged_patterns.is_empty()
+    }
+
+    /// Check whether two versions of a file differ only in lines that match
+    /// the converged patterns.
+    fn converged(&self, old: &[u8], new: &[u8]) -> bool {
+        let significant = |data: &'_ [u8]| -> Vec<Vec<u8>> {
+            data.split(|b| *b == b'\n')
+                .filter(|line| {
+                    !self.converged_patterns.iter().any(|p| {
+                        let p = p.as_bytes();
+                        !p.is_empty() && line.windows(p.len()).any(|w| w == p)
+                    })
+                })
+                .map(|line| line.to_owned())
+                .collect()
+        };
+
+        !self.converged_patterns.is_empty() && significant(old) == significant(new)
+    }
+}
+
+/// Match a file name against a pattern with `*` and `?` wildcards.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((bp, bn)) = backtrack {
+            // Let the last star swallow one more character.
+            backtrack = Some((bp, bn + 1));
+            p = bp + 1;
+            n = bn + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Produce a unified diff between two texts, with three lines of context.
+/// Returns None if the texts are too long to compare in reasonable time and
+/// memory.
+fn unified_diff(old: &str, new: &str) -> Option<String> {
+    const CONTEXT: usize = 3;
+    const MAX_CELLS: usize = 1 << 22;
+
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let (n, m) = (a.len(), b.len());
+
+    if (n + 1).saturating_mul(m + 1) > MAX_CELLS {
+        return None;
+    }
+
+    // lcs[i * (m + 1) + j] is the length of the longest common subsequence
+    // of a[i..] and b[j..].
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i * (m + 1) + j] = if a[i] == b[j] {
+                lcs[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                lcs[(i + 1) * (m + 1) + j].max(lcs[i * (m + 1) + j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < n || j < m {
+        if i < n && j < m && a[i] == b[j] {
+            ops.push((' ', a[i]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[(i + 1) * (m + 1) + j] >= lcs[i * (m + 1) + j + 1]) {
+            ops.push(('-', a[i]));
+            i += 1;
+        } else {
+            ops.push(('+', b[j]));
+            j += 1;
+        }
+    }
+
+    // The line numbers in each file at the start of each operation.
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut oi, mut ni) = (0, 0);
+
+    for (tag, _) in &ops {
+        positions.push((oi, ni));
+
+        match tag {
+            ' ' => {
+                oi += 1;
+                ni += 1;
+            }
+            '-' => oi += 1,
+            _ => ni += 1,
+        }
+    }
+
+    positions.push((oi, ni));
+
+    let range = |start: usize, len: usize| {
+        if len == 0 {
+            format!("{},0", start)
+        } else {
+            format!("{},{}", start + 1, len)
+        }
+    };
+
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, (tag, _))| *tag != ' ')
+        .map(|(k, _)| k)
+        .collect();
+
+    let mut out = String::new();
+    let mut c = 0;
+
+    while c < changes.len() {
+        let start = changes[c].saturating_sub(CONTEXT);
+        let mut end = changes[c] + 1;
+        c += 1;
+
+        while c < changes.len() && changes[c] <= end + 2 * CONTEXT {
+            end = changes[c] + 1;
+            c += 1;
+        }
+
+        let end = (end + CONTEXT).min(ops.len());
+        let (o0, n0) = positions[start];
+        let (o1, n1) = positions[end];
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(o0, o1 - o0),
+            range(n0, n1 - n0)
+        ));
+
+        for (tag, line) in &ops[start..end] {
+            out.push(*tag);
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+
+    Some(out)
+}
+
 /// Different places from which the "primary input" might originate.
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum PrimaryInputMode {
@@ -315,6 +548,26 @@ impl Default for OutputDestination {
     }
 }
 
+/// Get the build date requested through the `SOURCE_DATE_EPOCH` environment
+/// variable, if it is set.
+///
+/// See <https://reproducible-builds.org/specs/source-date-epoch/>. The value
+/// must be a nonnegative integer number of seconds since the Unix epoch.
+pub fn source_date_epoch() -> Result<Option<SystemTime>> {
+    let s = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(s) => s,
+        Err(std::env::VarError::NotPresent) => return Ok(None),
+        Err(_) => return Err(errmsg!("SOURCE_DATE_EPOCH is not valid Unicode")),
+    };
+
+    let secs = ctry!(s.trim().parse::<u64>(); "invalid SOURCE_DATE_EPOCH \"{}\" (not a number)", s);
+
+    match SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs)) {
+        Some(t) => Ok(Some(t)),
+        None => Err(errmsg!("SOURCE_DATE_EPOCH \"{}\" is out of range", s)),
+    }
+}
+
 /// A builder-style interface for creating a [`ProcessingSession`].
 #[derive(Default)]
 pub struct ProcessingSessionBuilder {
@@ -329,13 +582,20 @@ pub struct ProcessingSessionBuilder {
     hidden_input_paths: HashSet<PathBuf>,
     pass: PassSetting,
     reruns: Option<usize>,
+    rerun_policy: RerunPolicy,
     print_stdout: bool,
     bundle: Option<Box<dyn Bundle>>,
     keep_intermediates: bool,
     keep_logs: bool,
     synctex: bool,
     build_date: Option<SystemTime>,
+    pdf_options: PdfOptions,
     unstables: UnstableOptions,
+    build_cache: bool,
+    preamble_cache: bool,
+    preamble_file: Option<String>,
+    reproducible: bool,
+    biber: bool,
 }
 
 impl ProcessingSessionBuilder {
@@ -446,6 +706,13 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// Set the policy used to decide whether the TeX engine needs to be
+    /// re-run, when `reruns` is unset.
+    pub fn rerun_policy(&mut self, policy: RerunPolicy) -> &mut Self {
+        self.rerun_policy = policy;
+        self
+    }
+
     /// If set to `true`, stdout from the TeX engine will be forwarded to actual stdout. (By
     /// default, it will be suppressed.)
     pub fn print_stdout(&mut self, p: bool) -> &mut Self {
@@ -492,17 +759,93 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// Sets the options used when creating PDF output. See
+    /// [`PdfOptions`] for details.
+    pub fn pdf_options(&mut self, opts: PdfOptions) -> &mut Self {
+        self.pdf_options = opts;
+        self
+    }
+
     /// Loads unstable options into the processing session
     pub fn unstables(&mut self, opts: UnstableOptions) -> &mut Self {
         self.unstables = opts;
         self
     }
 
+    /// If set to `true`, a manifest recording the digests of the session's
+    /// inputs and outputs will be saved alongside the output files. When a
+    /// later session finds that nothing recorded in the manifest has changed,
+    /// it will skip processing entirely.
+    ///
+    /// The manifest is not used if output files are not being written to
+    /// disk, if a Makefile is being generated, or if the output is a format
+    /// file.
+    pub fn build_cache(&mut self, enabled: bool) -> &mut Self {
+        self.build_cache = enabled;
+        self
+    }
+
+    /// If set to `true`, the preamble of the primary input (everything
+    /// before `\begin{document}`) will be dumped into a format of its own,
+    /// which is kept in the format cache. Later sessions start the TeX engine
+    /// from that format, skipping the preamble, for as long as neither the
+    /// preamble nor any of the files that it loads have changed.
+    ///
+    /// This is ignored if the output is a format file.
+    pub fn preamble_cache(&mut self, enabled: bool) -> &mut Self {
+        self.preamble_cache = enabled;
+        self
+    }
+
+    /// Sets the name of a file, `\input` by the primary input, that holds the
+    /// preamble to precompile when [`ProcessingSessionBuilder::preamble_cache`]
+    /// is enabled, instead of taking it from the primary input itself.
+    ///
+    /// The format is then dumped once the file reaches `\begin{document}`,
+    /// so anything that the primary input does before `\input`ting it is
+    /// precompiled too. Since the file is read through the usual I/O layers,
+    /// it's tracked like any other input, and the engine reports errors and
+    /// SyncTeX positions in terms of it.
+    pub fn preamble_file<T: AsRef<str>>(&mut self, name: T) -> &mut Self {
+        self.preamble_file = Some(name.as_ref().to_owned());
+        self
+    }
+
+    /// If set to `true`, the external `biber` program will be run for
+    /// `biblatex` documents that use the biber backend. Like shell escape,
+    /// this is off by default, since it means running a program from outside
+    /// of Tectonic.
+    pub fn biber(&mut self, enabled: bool) -> &mut Self {
+        self.biber = enabled;
+        self
+    }
+
+    /// If set to `true`, the session will try to produce output that is
+    /// byte-for-byte identical across runs: font subset tags are derived
+    /// from the font contents rather than chosen at random, and every input
+    /// file reports the build date as its modification time. Combine this
+    /// with [`Self::build_date`] (see [`source_date_epoch`]) to pin the
+    /// date as well; otherwise the Unix epoch is used.
+    pub fn reproducible(&mut self, enabled: bool) -> &mut Self {
+        self.reproducible = enabled;
+        self
+    }
+
     /// Creates a `ProcessingSession`.
     pub fn create(self, status: &mut dyn StatusBackend) -> Result<ProcessingSession> {
         let mut io = IoSetupBuilder::default();
         io.bundle(self.bundle.expect("a bundle must be specified"))
             .use_genuine_stdout(self.print_stdout);
+        let build_date = self.build_date.unwrap_or(SystemTime::UNIX_EPOCH);
+
+        if self.reproducible {
+            let mtime = match build_date.duration_since(SystemTime::UNIX_EPOCH) {
+                Ok(d) => d.as_secs() as i64,
+                Err(e) => -(e.duration().as_secs() as i64),
+            };
+            io.frozen_mtime(mtime);
+        }
+
         for p in &self.hidden_input_paths {
             io.hide_path(p);
         }
@@ -570,6 +913,10 @@ impl ProcessingSessionBuilder {
         });
         let mut pdf_path = aux_path.clone();
         pdf_path.set_extension("pdf");
+        let mut synctex_path = aux_path.clone();
+        synctex_path.set_extension("synctex.gz");
+        let mut bcf_path = aux_path.clone();
+        bcf_path.set_extension("bcf");
 
         Ok(ProcessingSession {
             io: io.create(status)?,
@@ -581,21 +928,34 @@ impl ProcessingSessionBuilder {
             tex_aux_path: aux_path.into_os_string(),
             tex_xdv_path: xdv_path.into_os_string(),
             tex_pdf_path: pdf_path.into_os_string(),
+            tex_synctex_path: synctex_path.into_os_string(),
+            tex_bcf_path: bcf_path.into_os_string(),
             output_format: self.output_format,
             makefile_output_path: self.makefile_output_path,
             output_path,
             tex_rerun_specification: self.reruns,
+            rerun_policy: self.rerun_policy,
+            rerun_snapshot: HashMap::new(),
             keep_intermediates: self.keep_intermediates,
             keep_logs: self.keep_logs,
             synctex_enabled: self.synctex,
-            build_date: self.build_date.unwrap_or(SystemTime::UNIX_EPOCH),
+            build_date,
+            pdf_options: self.pdf_options,
             unstables: self.unstables,
+            build_cache: self.build_cache,
+            preamble_cache: self.preamble_cache,
+            preamble_file: self.preamble_file,
+            reproducible: self.reproducible,
+            biber: self.biber,
+            preamble_format_name: None,
+            index_logs: HashSet::new(),
         })
     }
 }
 
 #[derive(Debug, Clone)]
 enum RerunReason {
+    Biber,
     Bibtex,
     FileChange(String),
 }
@@ -633,6 +993,10 @@ pub struct ProcessingSession {
     tex_aux_path: OsString,
     tex_xdv_path: OsString,
     tex_pdf_path: OsString,
+    tex_synctex_path: OsString,
+
+    /// The control file that `biblatex` writes when it wants biber to be run.
+    tex_bcf_path: OsString,
 
     /// If we're writing out Makefile rules, this is where they go. The TeX
     /// engine doesn't know about this path at all.
@@ -648,6 +1012,12 @@ pub struct ProcessingSession {
     pass: PassSetting,
     output_format: OutputFormat,
     tex_rerun_specification: Option<usize>,
+    rerun_policy: RerunPolicy,
+
+    /// If the rerun policy needs them, the contents of the memory layer
+    /// before the most recent TeX pass, to compare against afterwards.
+    rerun_snapshot: HashMap<OsString, Vec<u8>>,
+
     keep_intermediates: bool,
     keep_logs: bool,
     synctex_enabled: bool,
@@ -655,7 +1025,236 @@ pub struct ProcessingSession {
     /// See `TexEngine::with_date` and `XdvipdfmxEngine::with_date`.
     build_date: SystemTime,
 
+    /// See `XdvipdfmxEngine::with_pdf_options`.
+    pdf_options: PdfOptions,
+
     unstables: UnstableOptions,
+
+    /// Whether to use a build manifest to skip processing when nothing has
+    /// changed. See [`ProcessingSessionBuilder::build_cache`].
+    build_cache: bool,
+
+    /// Whether to precompile the preamble of the primary input. See
+    /// [`ProcessingSessionBuilder::preamble_cache`].
+    preamble_cache: bool,
+
+    /// The file holding the preamble, if it isn't in the primary input. See
+    /// [`ProcessingSessionBuilder::preamble_file`].
+    preamble_file: Option<String>,
+
+    /// Whether to aim for reproducible output. See
+    /// [`ProcessingSessionBuilder::reproducible`].
+    reproducible: bool,
+
+    /// Whether the external biber program may be run. See
+    /// [`ProcessingSessionBuilder::biber`].
+    biber: bool,
+
+    /// If the TeX engine is starting from a format containing the preamble,
+    /// this is its name.
+    preamble_format_name: Option<String>,
+
+    /// The transcripts written by the index processor, which are treated
+    /// like log files.
+    index_logs: HashSet<OsString>,
+}
+
+/// A record of the inputs and outputs of a successful processing session.
+///
+/// If a later session with the same settings finds that all of the inputs
+/// are unchanged and the outputs are still in place, it can skip processing
+/// altogether. The manifest is saved as a simple line-oriented text file:
+///
+/// ```text
+/// tectonic-build-manifest 1
+/// key <digest of settings, bundle, format, and primary input>
+/// input <digest, or "-" if the file must not exist> <name>
+/// output <digest> <name>
+/// ```
+#[derive(Debug)]
+struct BuildManifest {
+    key: DigestData,
+    inputs: Vec<(String, Option<DigestData>)>,
+    outputs: Vec<(String, DigestData)>,
+}
+
+const BUILD_MANIFEST_HEADER: &str = "tectonic-build-manifest 1";
+
+impl BuildManifest {
+    /// Load a manifest, returning None if it does not exist or can't be
+    /// understood.
+    fn load(path: &Path) -> Option<BuildManifest> {
+        let f = File::open(path).ok()?;
+        let mut lines = BufReader::new(f).lines();
+
+        if lines.next()?.ok()? != BUILD_MANIFEST_HEADER {
+            return None;
+        }
+
+        let mut key = None;
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+
+        for line in lines {
+            let line = line.ok()?;
+            let mut pieces = line.splitn(3, ' ');
+            let kind = pieces.next()?;
+            let digest = pieces.next()?;
+
+            match kind {
+                "key" => key = Some(digest.parse().ok()?),
+                "input" => {
+                    let digest = if digest == "-" {
+                        None
+                    } else {
+                        Some(digest.parse().ok()?)
+                    };
+                    inputs.push((pieces.next()?.to_owned(), digest));
+                }
+                "output" => outputs.push((pieces.next()?.to_owned(), digest.parse().ok()?)),
+                _ => return None,
+            }
+        }
+
+        Some(BuildManifest {
+            key: key?,
+            inputs,
+            outputs,
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let mut text = format!("{}\nkey {}\n", BUILD_MANIFEST_HEADER, self.key.to_string());
+
+        for (name, digest) in &self.inputs {
+            let digest = match digest {
+                Some(d) => d.to_string(),
+                None => "-".to_owned(),
+            };
+            text.push_str(&format!("input {} {}\n", digest, name));
+        }
+
+        for (name, digest) in &self.outputs {
+            text.push_str(&format!("output {} {}\n", digest.to_string(), name));
+        }
+
+        let mut f = File::create(path)?;
+        f.write_all(text.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Compute the digest of everything that can be read from a stream.
+fn digest_stream<R: Read>(mut stream: R) -> Result<DigestData> {
+    let mut dc = digest::create();
+    let mut buf = [0u8; 8192];
+
+    loop {
+        let n = stream.read(&mut buf)?;
+
+        if n == 0 {
+            break;
+        }
+
+        dc.update(&buf[..n]);
+    }
+
+    Ok(DigestData::from(dc))
+}
+
+/// The TeX code appended to a document preamble to dump it as a format.
+///
+/// LaTeX formats may redefine `\dump`, keeping the primitive around as
+/// `\@@dump`, so we prefer the latter if it exists.
+const PREAMBLE_DUMP_TRAILER: &[u8] = b"\n\\expandafter\\ifx\\csname @@dump\\endcsname\\relax\
+    \\expandafter\\dump\\else\\csname @@dump\\expandafter\\endcsname\\fi\n";
+
+/// Find where the preamble of a LaTeX document ends: the offset of the first
+/// `\begin{document}` that isn't in a comment.
+fn find_begin_document(data: &[u8]) -> Option<usize> {
+    const BEGIN_DOCUMENT: &[u8] = b"\\begin{document}";
+    let mut in_comment = false;
+    let mut i = 0;
+
+    while i < data.len() {
+        match data[i] {
+            b'\n' | b'\r' => in_comment = false,
+            _ if in_comment => {}
+            b'%' => in_comment = true,
+            b'\\' => {
+                if data[i..].starts_with(BEGIN_DOCUMENT) {
+                    return Some(i);
+                }
+
+                // Skip over the escaped character, so that `\%` doesn't
+                // start a comment.
+                i += 1;
+            }
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    None
+}
+
+/// Find the uses of a command in an `.aux` file that are followed by `n`
+/// braced arguments, returning the arguments of each.
+fn aux_arguments<'a>(aux: &'a str, command: &str, n: usize) -> Vec<Vec<&'a str>> {
+    let mut found = Vec::new();
+
+    'outer: for (start, _) in aux.match_indices(command) {
+        let mut rest = &aux[start + command.len()..];
+        let mut args = Vec::with_capacity(n);
+
+        while args.len() < n {
+            if !rest.starts_with('{') {
+                continue 'outer;
+            }
+
+            let mut depth = 0;
+            let mut end = None;
+
+            for (i, c) in rest.char_indices() {
+                match c {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+
+                        if depth == 0 {
+                            end = Some(i);
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            match end {
+                Some(i) => {
+                    args.push(&rest[1..i]);
+                    rest = &rest[i + 1..];
+                }
+                None => continue 'outer,
+            }
+        }
+
+        found.push(args);
+    }
+
+    found
+}
+
+/// Convert a file name into a form that can be stored in a build manifest.
+fn manifest_name(name: &OsStr) -> Result<String> {
+    match name.to_str() {
+        Some(s) if !s.contains('\n') => Ok(s.to_owned()),
+        _ => Err(errmsg!(
+            "cannot record the file name \"{}\" in a build manifest",
+            name.to_string_lossy()
+        )),
+    }
 }
 
 const DEFAULT_MAX_TEX_PASSES: usize = 6;
@@ -668,33 +1267,144 @@ impl ProcessingSession {
     /// was a file that the engine read and then rewrote, and the rewritten
     /// version is different than the version that it read in.
     fn is_rerun_needed(&self, status: &mut dyn StatusBackend) -> Option<RerunReason> {
-        // TODO: we should probably wire up diagnostics since I expect this
-        // stuff could get finicky and we're going to want to be able to
-        // figure out why rerun detection is breaking.
+        let policy = &self.rerun_policy;
+        let mut reason = None;
 
-        for (name, info) in &self.events.0 {
-            if info.access_pattern == AccessPattern::ReadThenWritten {
-                let file_changed = match (&info.read_digest, &info.write_digest) {
-                    (&Some(ref d1), &Some(ref d2)) => d1 != d2,
-                    (&None, &Some(_)) => true,
-                    (_, _) => {
-                        // Other cases shouldn't happen.
-                        tt_warning!(
+        // Go in a fixed order so that the diagnostics are reproducible.
+        let mut names: Vec<&OsString> = self
+            .events
+            .0
+            .iter()
+            .filter(|(_, info)| info.access_pattern == AccessPattern::ReadThenWritten)
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        for name in names {
+            let info = &self.events.0[name];
+            let sname = name.to_string_lossy();
+
+            let file_changed = match (&info.read_digest, &info.write_digest) {
+                (&Some(ref d1), &Some(ref d2)) => d1 != d2,
+                (&None, &Some(_)) => true,
+                (_, _) => {
+                    // Other cases shouldn't happen.
+                    tt_warning!(
+                        status,
+                        "internal consistency problem when checking if {} changed",
+                        sname
+                    );
+                    true
+                }
+            };
+
+            if !file_changed {
+                continue;
+            }
+
+            if policy.ignores_file(&sname) {
+                if policy.verbose {
+                    tt_note!(
+                        status,
+                        "\"{}\" changed, but the rerun policy ignores it",
+                        sname
+                    );
+                }
+                continue;
+            }
+
+            let files = self.io.mem.files.borrow();
+            let new = files.get(name).map(|f| &f.data[..]);
+            let old = match self.rerun_snapshot.get(name) {
+                Some(data) => Some(&data[..]),
+                None if info.read_digest == Some(DigestData::of_nothing()) => Some(&b""[..]),
+                None => None,
+            };
+
+            if let (Some(old), Some(new)) = (old, new) {
+                if policy.converged(old, new) {
+                    if policy.verbose {
+                        tt_note!(
                             status,
-                            "internal consistency problem when checking if {} changed",
-                            name.to_string_lossy()
+                            "\"{}\" changed, but only in lines that the rerun policy ignores",
+                            sname
                         );
-                        true
                     }
+                    continue;
+                }
+            }
+
+            if policy.verbose {
+                let digest_text = |d: &Option<DigestData>| match d {
+                    Some(d) => d.to_string(),
+                    None => "(unknown)".to_owned(),
                 };
 
-                if file_changed {
-                    return Some(RerunReason::FileChange(name.to_string_lossy().into_owned()));
+                tt_note!(
+                    status,
+                    "\"{}\" changed: {} -> {}",
+                    sname,
+                    digest_text(&info.read_digest),
+                    digest_text(&info.write_digest)
+                );
+
+                match (old.map(str::from_utf8), new.map(str::from_utf8)) {
+                    (Some(Ok(old)), Some(Ok(new))) => match unified_diff(old, new) {
+                        Some(diff) => {
+                            tt_note!(
+                                status,
+                                "changes to \"{}\":\n--- {0} (before)\n+++ {0} (after)\n{}",
+                                sname,
+                                diff.trim_end()
+                            );
+                        }
+                        None => {
+                            tt_note!(status, "\"{}\" is too large to show its changes", sname);
+                        }
+                    },
+                    (None, _) | (_, None) => {
+                        tt_note!(
+                            status,
+                            "the previous contents of \"{}\" are unavailable",
+                            sname
+                        );
+                    }
+                    _ => {}
                 }
             }
+
+            if reason.is_none() {
+                reason = Some(RerunReason::FileChange(sname.into_owned()));
+            }
+
+            // In verbose mode we keep going to report all of the changes.
+            if !policy.verbose {
+                break;
+            }
+        }
+
+        reason
+    }
+
+    /// Remember the contents of the memory layer, if the rerun policy will
+    /// need to compare them against what the next TeX pass writes.
+    fn snapshot_for_rerun(&mut self) {
+        if !self.rerun_policy.needs_contents() {
+            return;
         }
 
-        None
+        let files = self.io.mem.files.borrow();
+
+        self.rerun_snapshot = files
+            .iter()
+            .filter(|(name, _)| {
+                **name != self.tex_xdv_path
+                    && **name != self.tex_pdf_path
+                    && **name != self.tex_synctex_path
+                    && name.as_os_str() != self.io.mem.stdout_key()
+            })
+            .map(|(name, file)| (name.clone(), file.data.clone()))
+            .collect();
     }
 
     #[allow(dead_code)]
@@ -721,57 +1431,269 @@ impl ProcessingSession {
         }
     }
 
-    /// Runs the session, generating the desired outputs.
-    ///
-    /// What this does depends on which [`PassSetting`] you asked for. The most common choice is
-    /// `PassSetting::Default`, in which case this method does the following:
-    ///
-    /// - if a `.fmt` file does not yet exist, generate one and cache it
-    /// - run the TeX engine once
-    /// - run BibTeX, if it seems to be required
-    /// - repeat the last two steps as often as needed
-    /// - write the output files to disk, including a Makefile if it was requested.
-    pub fn run(&mut self, status: &mut dyn StatusBackend) -> Result<()> {
-        // Do we need to generate the format file?
+    /// Get the path where the build manifest should be saved, if the build
+    /// cache is in use for this session.
+    fn build_manifest_path(&self) -> Option<PathBuf> {
+        if !self.build_cache
+            || self.makefile_output_path.is_some()
+            || self.output_format == OutputFormat::Format
+        {
+            return None;
+        }
 
-        let generate_format = if self.output_format == OutputFormat::Format {
-            false
-        } else {
-            let fmt_result = {
-                let mut stack = self.io.as_stack();
-                stack.input_open_format(OsStr::new(&self.format_name), status)
-            };
+        let mut name = PathBuf::from(&self.primary_input_tex_path);
+        name.set_extension("tectonic-manifest");
+        self.output_path.as_ref().map(|root| root.join(name))
+    }
 
-            match fmt_result {
-                OpenResult::Ok(_) => false,
-                OpenResult::NotAvailable => true,
-                OpenResult::Err(e) => {
-                    return Err(e)
-                        .chain_err(|| format!("could not open format file {}", self.format_name));
-                }
-            }
-        };
+    /// Compute a digest summarizing everything about the session that isn't
+    /// captured by the files that the engines read: its settings, the
+    /// bundle, the format file, and the primary input.
+    fn build_manifest_key(&mut self, status: &mut dyn StatusBackend) -> Result<DigestData> {
+        let mut dc = digest::create();
+
+        let settings = format!(
+            "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+            self.primary_input_tex_path,
+            self.format_name,
+            self.output_format,
+            self.pass,
+            self.tex_rerun_specification,
+            self.rerun_policy,
+            self.biber,
+            self.keep_intermediates,
+            self.keep_logs,
+            self.synctex_enabled,
+            self.build_date,
+            self.pdf_options,
+            self.unstables,
+            self.preamble_cache,
+            self.reproducible,
+        );
+        dc.update(settings.as_bytes());
 
-        if generate_format {
-            tt_note!(status, "generating format \"{}\"", self.format_name);
-            self.make_format_pass(status)?;
+        if let Some(ref mut bundle) = self.io.bundle {
+            dc.update(bundle.get_digest(status)?);
         }
 
-        // Do the meat of the work.
+        let mut stack = self.io.as_stack();
 
-        let result = match self.pass {
-            PassSetting::Tex => match self.tex_pass(None, status) {
-                Ok(Some(warnings)) => {
-                    tt_warning!(status, "{}", warnings);
-                    Ok(0)
-                }
-                Ok(None) => Ok(0),
+        let format = stack
+            .input_open_format(OsStr::new(&self.format_name), status)
+            .must_exist()?;
+        dc.update(digest_stream(format)?);
+
+        let primary = stack.input_open_primary(status).must_exist()?;
+        dc.update(digest_stream(primary)?);
+
+        Ok(DigestData::from(dc))
+    }
+
+    /// Get the digest of the file that the filesystem currently provides
+    /// under the given name, or None if there is no such file.
+    fn filesystem_digest(
+        &mut self,
+        name: &OsStr,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Option<DigestData>> {
+        let mut stack = self.io.as_filesystem_stack();
+
+        match stack.input_open_name(name, status) {
+            OpenResult::Ok(h) => Ok(Some(digest_stream(h)?)),
+            OpenResult::NotAvailable => Ok(None),
+            OpenResult::Err(e) => Err(e),
+        }
+    }
+
+    /// Check whether the outputs described by a build manifest are still up
+    /// to date. If so, the I/O events are filled in with the inputs recorded
+    /// in the manifest, so that callers can learn about them as if the
+    /// engines had been run.
+    fn check_build_manifest(&mut self, path: &Path, status: &mut dyn StatusBackend) -> bool {
+        let manifest = match BuildManifest::load(path) {
+            Some(m) => m,
+            None => return false,
+        };
+
+        match self.build_manifest_key(status) {
+            Ok(ref key) if *key == manifest.key => {}
+            _ => return false,
+        }
+
+        for (name, digest) in &manifest.inputs {
+            match self.filesystem_digest(OsStr::new(name), status) {
+                Ok(ref current) if current == digest => {}
+                _ => return false,
+            }
+        }
+
+        // The check in `build_manifest_path` ensures that this is never None.
+        let root = self.output_path.as_ref().unwrap();
+
+        for (name, digest) in &manifest.outputs {
+            let current = File::open(root.join(name))
+                .map_err(|e| e.into())
+                .and_then(digest_stream);
+
+            match current {
+                Ok(ref current) if current == digest => {}
+                _ => return false,
+            }
+        }
+
+        for (name, digest) in manifest.inputs {
+            if let Some(digest) = digest {
+                let mut summ = FileSummary::new(AccessPattern::Read, InputOrigin::Filesystem);
+                summ.read_digest = Some(digest);
+                self.events.0.insert(OsString::from(name), summ);
+            }
+        }
+
+        true
+    }
+
+    /// Save a build manifest describing the just-completed session.
+    fn write_build_manifest(&mut self, path: &Path, status: &mut dyn StatusBackend) -> Result<()> {
+        let key = self.build_manifest_key(status)?;
+        let mut read_files = Vec::new();
+        let mut outputs = Vec::new();
+
+        for (name, summ) in &self.events.0 {
+            // Anything that the engines tried to read before writing it
+            // themselves is an input, even if it wasn't found: if it shows up
+            // later, the results might change.
+            if summ.access_pattern == AccessPattern::Read
+                || summ.access_pattern == AccessPattern::ReadThenWritten
+            {
+                read_files.push((name.clone(), summ.clone()));
+            }
+
+            if summ.got_written_to_disk {
+                let digest = match summ.write_digest {
+                    Some(d) => d,
+                    None => {
+                        return Err(errmsg!(
+                            "no digest for output file \"{}\"",
+                            name.to_string_lossy()
+                        ));
+                    }
+                };
+                outputs.push((manifest_name(name)?, digest));
+            }
+        }
+
+        let mut inputs = Vec::new();
+
+        for (name, summ) in read_files {
+            let current = self.filesystem_digest(&name, status)?;
+
+            if summ.input_origin == InputOrigin::Filesystem && !summ.got_written_to_disk {
+                if let (Some(read), Some(current)) = (summ.read_digest, current) {
+                    if read != current {
+                        return Err(errmsg!(
+                            "\"{}\" changed during processing",
+                            name.to_string_lossy()
+                        ));
+                    }
+                }
+            }
+
+            inputs.push((manifest_name(&name)?, current));
+        }
+
+        inputs.sort_by(|a, b| a.0.cmp(&b.0));
+        outputs.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let manifest = BuildManifest {
+            key,
+            inputs,
+            outputs,
+        };
+        manifest.save(path)
+    }
+
+    /// Runs the session, generating the desired outputs.
+    ///
+    /// What this does depends on which [`PassSetting`] you asked for. The most common choice is
+    /// `PassSetting::Default`, in which case this method does the following:
+    ///
+    /// - if a `.fmt` file does not yet exist, generate one and cache it
+    /// - if the preamble cache is enabled, do the same for a `.fmt` file containing the preamble
+    /// - run the TeX engine once
+    /// - run BibTeX, if it seems to be required
+    /// - repeat the last two steps as often as needed
+    /// - write the output files to disk, including a Makefile if it was requested.
+    ///
+    /// If the build cache is enabled and a previous session left behind a
+    /// manifest showing that none of the inputs have changed, nothing is done.
+    pub fn run(&mut self, status: &mut dyn StatusBackend) -> Result<()> {
+        // Is there anything to do at all?
+
+        let manifest_path = self.build_manifest_path();
+
+        if let Some(ref p) = manifest_path {
+            if self.check_build_manifest(p, status) {
+                status.note_highlighted(
+                    "Outputs are ",
+                    "up to date",
+                    "; no inputs have changed since the last build",
+                );
+                return Ok(());
+            }
+
+            // The manifest, if any, is stale. Get rid of it now, so that it
+            // can't be mistaken for valid if this session fails.
+            if p.exists() {
+                ctry!(fs::remove_file(p); "couldn't remove stale build manifest `{}`", p.display());
+            }
+        }
+
+        // Do we need to generate the format file?
+
+        let generate_format = if self.output_format == OutputFormat::Format {
+            false
+        } else {
+            let fmt_result = {
+                let mut stack = self.io.as_stack();
+                stack.input_open_format(OsStr::new(&self.format_name), status)
+            };
+
+            match fmt_result {
+                OpenResult::Ok(_) => false,
+                OpenResult::NotAvailable => true,
+                OpenResult::Err(e) => {
+                    return Err(e)
+                        .chain_err(|| format!("could not open format file {}", self.format_name));
+                }
+            }
+        };
+
+        if generate_format {
+            tt_note!(status, "generating format \"{}\"", self.format_name);
+            self.make_format_pass(status)?;
+        }
+
+        if self.preamble_cache && self.output_format != OutputFormat::Format {
+            self.prepare_preamble_format(status)?;
+        }
+
+        // Do the meat of the work.
+
+        let result = match self.pass {
+            PassSetting::Tex => match self.tex_pass(None, status) {
+                Ok(Some(warnings)) => {
+                    tt_warning!(status, "{}", warnings);
+                    Ok(0)
+                }
+                Ok(None) => Ok(0),
                 Err(e) => Err(e),
             },
             PassSetting::Default => self.default_pass(false, status),
             PassSetting::BibtexFirst => self.default_pass(true, status),
         };
 
+        self.io.override_primary_input(None);
+
         if let Err(e) = result {
             self.write_files(None, status, true)?;
             return Err(e);
@@ -846,6 +1768,14 @@ impl ProcessingSession {
             ctry!(writeln!(mf_dest, ""); "couldn't write to Makefile-rules file");
         }
 
+        // Record what we did, so that the next session can maybe skip it.
+
+        if let Some(ref p) = manifest_path {
+            if let Err(e) = self.write_build_manifest(p, status) {
+                tt_warning!(status, "couldn't save the build manifest `{}`", p.display(); e);
+            }
+        }
+
         // All done.
 
         Ok(())
@@ -875,6 +1805,8 @@ impl ProcessingSession {
 
             let sname = name.to_string_lossy();
             let summ = self.events.0.get_mut(name).unwrap();
+            // SyncTeX data are a product of the build, not an intermediate.
+            let is_synctex = self.synctex_enabled && *name == self.tex_synctex_path;
 
             if !only_logs && (self.output_format == OutputFormat::Aux) {
                 // In this mode we're only writing the .aux file. I initially
@@ -886,6 +1818,7 @@ impl ProcessingSession {
                     continue;
                 }
             } else if !self.keep_intermediates
+                && !is_synctex
                 && (summ.access_pattern != AccessPattern::Written
                     || ALWAYS_INTERMEDIATE_EXTENSIONS
                         .iter()
@@ -895,7 +1828,9 @@ impl ProcessingSession {
                 continue;
             }
 
-            let is_logfile = sname.ends_with(".log") || sname.ends_with(".blg");
+            let is_logfile = sname.ends_with(".log")
+                || sname.ends_with(".blg")
+                || self.index_logs.contains(name);
 
             if is_logfile && !self.keep_logs {
                 continue;
@@ -954,11 +1889,25 @@ impl ProcessingSession {
             self.bibtex_pass(status)?;
             Some(RerunReason::Bibtex)
         } else {
+            self.snapshot_for_rerun();
             warnings = self.tex_pass(None, status)?;
+            self.index_pass(status)?;
 
             if self.is_bibtex_needed() {
                 self.bibtex_pass(status)?;
                 Some(RerunReason::Bibtex)
+            } else if self.is_biber_needed() {
+                if self.biber {
+                    self.biber_pass(status)?;
+                    Some(RerunReason::Biber)
+                } else {
+                    tt_warning!(
+                        status,
+                        "this document needs biber to be run, but running it isn't enabled \
+                         (use --biber to enable it)"
+                    );
+                    self.is_rerun_needed(status)
+                }
             } else {
                 self.is_rerun_needed(status)
             }
@@ -968,7 +1917,7 @@ impl ProcessingSession {
 
         let (pass_count, reruns_fixed) = match self.tex_rerun_specification {
             Some(n) => (n, true),
-            None => (DEFAULT_MAX_TEX_PASSES, false),
+            None => (self.rerun_policy.max_passes, false),
         };
 
         for i in 0..pass_count {
@@ -976,6 +1925,7 @@ impl ProcessingSession {
                 "I was told to".to_owned()
             } else {
                 match rerun_result {
+                    Some(RerunReason::Biber) => "biber was run".to_owned(),
                     Some(RerunReason::Bibtex) => "bibtex was run".to_owned(),
                     Some(RerunReason::FileChange(ref s)) => format!("\"{}\" changed", s),
                     None => break,
@@ -992,16 +1942,18 @@ impl ProcessingSession {
                 summ.read_digest = None;
             }
 
+            self.snapshot_for_rerun();
             warnings = self.tex_pass(Some(&rerun_explanation), status)?;
+            self.index_pass(status)?;
 
             if !reruns_fixed {
                 rerun_result = self.is_rerun_needed(status);
 
-                if rerun_result.is_some() && i == DEFAULT_MAX_TEX_PASSES - 1 {
+                if rerun_result.is_some() && i == pass_count - 1 {
                     tt_warning!(
                         status,
                         "TeX rerun seems needed, but stopping at {} passes",
-                        DEFAULT_MAX_TEX_PASSES
+                        pass_count
                     );
                     break;
                 }
@@ -1025,19 +1977,51 @@ impl ProcessingSession {
     }
 
     fn is_bibtex_needed(&self) -> bool {
+        !self.bibtex_aux_files().is_empty()
+    }
+
+    /// Find the `.aux` files written during this session that call for a
+    /// BibTeX run. Packages like `bibunits`, `chapterbib`, and `multibib`
+    /// write several of these. The main `.aux` file comes first, followed by
+    /// the others in order of name.
+    fn bibtex_aux_files(&self) -> Vec<OsString> {
         const BIBDATA: &[u8] = b"\\bibdata";
 
-        self.io
-            .mem
-            .files
-            .borrow()
-            .get(&self.tex_aux_path)
-            .map(|file| {
-                // We used to use aho-corasick crate here, but it was removed to reduce the code
-                // size.
-                file.data.windows(BIBDATA.len()).any(|s| s == BIBDATA)
+        let files = self.io.mem.files.borrow();
+
+        let mut auxes: Vec<OsString> = self
+            .events
+            .0
+            .iter()
+            .filter(|(name, summ)| {
+                summ.access_pattern != AccessPattern::Read
+                    && Path::new(name).extension() == Some(OsStr::new("aux"))
+            })
+            .filter(|(name, _)| {
+                files
+                    .get(*name)
+                    .map(|file| {
+                        // We used to use aho-corasick crate here, but it was removed to reduce the code
+                        // size.
+                        file.data.windows(BIBDATA.len()).any(|s| s == BIBDATA)
+                    })
+                    .unwrap_or(false)
             })
-            .unwrap_or(false)
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        auxes.sort_by(|a, b| {
+            (*a != self.tex_aux_path)
+                .cmp(&(*b != self.tex_aux_path))
+                .then_with(|| a.cmp(b))
+        });
+        auxes
+    }
+
+    /// Documents using `biblatex` with the biber backend write out a `.bcf`
+    /// control file rather than putting `\bibdata` in the `.aux` file.
+    fn is_biber_needed(&self) -> bool {
+        self.io.mem.files.borrow().contains_key(&self.tex_bcf_path)
     }
 
     /// Use the TeX engine to generate a format file.
@@ -1065,7 +2049,18 @@ impl ProcessingSession {
             ))
             .into()
         });
-        let stem = r?;
+        let stem = r?.to_owned();
+
+        let shell_escape_files = self.io.mem.files.clone();
+
+        // A custom format may depend on local files as well as the bundle, so
+        // we track its I/O separately in order to record its inputs. Standard
+        // formats are generated without access to the filesystem.
+        let mut format_events = IoEvents::new();
+        let is_local = match self.io.format_cache {
+            Some(ref c) => c.is_local_format(OsStr::new(&self.format_name)),
+            None => false,
+        };
 
         let result = {
             let mut stack = self
@@ -1074,9 +2069,10 @@ impl ProcessingSession {
             TexEngine::new()
                 .halt_on_error_mode(true)
                 .initex_mode(true)
+                .shell_escape_files(shell_escape_files)
                 .process(
                     &mut stack,
-                    &mut self.events,
+                    &mut format_events,
                     status,
                     "UNUSED.fmt",
                     "texput",
@@ -1084,6 +2080,57 @@ impl ProcessingSession {
                 )
         };
 
+        self.finish_format_pass(&stem, result, format_events, is_local, status)
+    }
+
+    /// Save the format file produced by an initex pass to the format cache,
+    /// along with the list of inputs that determine whether it's up to date.
+    /// If `is_local` is false, the format was generated without access to
+    /// the filesystem, and so doesn't depend on any local files.
+    fn finish_format_pass(
+        &mut self,
+        stem: &str,
+        result: Result<TexResult>,
+        format_events: IoEvents,
+        is_local: bool,
+        status: &mut dyn StatusBackend,
+    ) -> Result<i32> {
+        let mut inputs = Vec::new();
+
+        for (name, summ) in &format_events.0 {
+            // Anything that was looked for counts, even if it wasn't found or
+            // came from the bundle: a local file with that name would change
+            // the format.
+            if !is_local
+                || (summ.access_pattern != AccessPattern::Read
+                    && summ.access_pattern != AccessPattern::ReadThenWritten)
+            {
+                continue;
+            }
+
+            let recorded_name = match manifest_name(name) {
+                Ok(n) => n,
+                Err(_) => {
+                    tt_warning!(
+                        status,
+                        "changes to \"{}\" won't cause the format to be regenerated, since its name \
+                         can't be recorded",
+                        name.to_string_lossy()
+                    );
+                    continue;
+                }
+            };
+
+            let current = self.filesystem_digest(name, status)?;
+            inputs.push((recorded_name, current));
+        }
+
+        inputs.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (name, summ) in format_events.0 {
+            self.events.0.entry(name).or_insert(summ);
+        }
+
         match result {
             Ok(TexResult::Spotless) => {}
             Ok(TexResult::Warnings) => {
@@ -1116,7 +2163,7 @@ impl ProcessingSession {
             }
 
             // Note that we intentionally pass 'stem', not 'name'.
-            ctry!(format_cache.write_format(stem, &file.data, status); "cannot write format file {}", sname);
+            ctry!(format_cache.write_format_with_inputs(stem, &file.data, &inputs, status); "cannot write format file {}", sname);
         }
 
         // All done. Clear the memory layer since this was a special preparatory step.
@@ -1125,12 +2172,246 @@ impl ProcessingSession {
         Ok(0)
     }
 
+    /// Set up the session so that TeX starts from a format that already
+    /// contains the preamble of the primary input, dumping one if the
+    /// preamble has changed since the last time.
+    ///
+    /// The primary input is then replaced with a copy in which the preamble
+    /// has been blanked out, keeping its line breaks so that line numbers in
+    /// messages and SyncTeX data still match the original file.
+    fn prepare_preamble_format(&mut self, status: &mut dyn StatusBackend) -> Result<()> {
+        let mut data = Vec::new();
+
+        {
+            let mut stack = self.io.as_stack();
+            let mut primary = ctry!(stack.input_open_primary(status).must_exist(); "cannot open the primary input");
+            primary.read_to_end(&mut data)?;
+        }
+
+        // If the preamble is in a file of its own, the primary input is run
+        // as usual when dumping the format, but it sees only the beginning of
+        // that file, followed by the commands that do the dumping.
+        let preamble_file = match self.preamble_file.clone() {
+            Some(name) => {
+                let file_data = self.read_preamble_file(&name, status)?;
+                Some((name, file_data))
+            }
+            None => None,
+        };
+
+        let preamble = match preamble_file {
+            Some((_, ref file_data)) => file_data,
+            None => &data,
+        };
+
+        let split = match find_begin_document(preamble) {
+            Some(i) => i,
+            None => {
+                tt_note!(
+                    status,
+                    "not precompiling the preamble since the input has no \\begin{{document}}"
+                );
+                return Ok(());
+            }
+        };
+
+        let stem = match preamble_file {
+            Some((_, ref file_data)) => {
+                let mut key = data.clone();
+                key.push(0);
+                key.extend_from_slice(&file_data[..split]);
+                self.preamble_format_stem(&key, status)?
+            }
+            None => self.preamble_format_stem(&data[..split], status)?,
+        };
+        let name = format!("{}.fmt", stem);
+
+        let fmt_result = {
+            let mut stack = self.io.as_stack();
+            stack.input_open_format(OsStr::new(&name), status)
+        };
+
+        match fmt_result {
+            OpenResult::Ok(_) => {
+                // The engines won't read the files that went into the
+                // preamble, but they are inputs all the same.
+                let format_cache = self.io.format_cache.as_mut().unwrap();
+                let inputs = format_cache.format_inputs(OsStr::new(&name))?;
+
+                for (input, digest) in inputs {
+                    let summ = match digest {
+                        Some(d) => {
+                            let mut summ =
+                                FileSummary::new(AccessPattern::Read, InputOrigin::Filesystem);
+                            summ.read_digest = Some(d);
+                            summ
+                        }
+                        None => {
+                            let mut summ =
+                                FileSummary::new(AccessPattern::Read, InputOrigin::NotInput);
+                            summ.read_digest = Some(DigestData::of_nothing());
+                            summ
+                        }
+                    };
+                    self.events.0.entry(OsString::from(input)).or_insert(summ);
+                }
+            }
+            OpenResult::NotAvailable => {
+                tt_note!(status, "generating precompiled preamble \"{}\"", name);
+
+                let text = match preamble_file {
+                    Some((ref file_name, ref file_data)) => {
+                        let mut head = file_data[..split].to_owned();
+                        head.extend_from_slice(PREAMBLE_DUMP_TRAILER);
+                        self.io.override_input(file_name, Some(head));
+                        data.clone()
+                    }
+                    None => {
+                        let mut text = data[..split].to_owned();
+                        text.extend_from_slice(PREAMBLE_DUMP_TRAILER);
+                        text
+                    }
+                };
+
+                self.make_preamble_format_pass(text, &stem, status)?;
+            }
+            OpenResult::Err(e) => {
+                return Err(e).chain_err(|| format!("could not open format file {}", name));
+            }
+        }
+
+        // Now the preamble needs to be skipped. Keep the line breaks, so that
+        // line numbers don't change.
+        let blank_out = |text: &mut [u8]| {
+            for b in text {
+                if *b != b'\n' && *b != b'\r' {
+                    *b = b' ';
+                }
+            }
+        };
+
+        match preamble_file {
+            Some((file_name, mut file_data)) => {
+                blank_out(&mut file_data[..split]);
+                self.io.override_input(&file_name, Some(file_data));
+            }
+            None => {
+                blank_out(&mut data[..split]);
+                self.io.override_primary_input(Some(data));
+            }
+        }
+
+        self.preamble_format_name = Some(name);
+        Ok(())
+    }
+
+    /// Read a file holding the preamble through the usual I/O stack. Since
+    /// the engines will only ever see a modified version of it, it's noted as
+    /// an input here, so that it gets tracked like any other.
+    fn read_preamble_file(
+        &mut self,
+        name: &str,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+
+        let origin = {
+            let mut stack = self.io.as_stack();
+            let mut handle = ctry!(
+                stack.input_open_name(OsStr::new(name), status).must_exist();
+                "cannot open the preamble file \"{}\"", name
+            );
+            handle.read_to_end(&mut data)?;
+            handle.origin()
+        };
+
+        let mut summ = FileSummary::new(AccessPattern::Read, origin);
+        summ.read_digest = Some(digest_stream(&data[..])?);
+        self.events.0.entry(OsString::from(name)).or_insert(summ);
+        Ok(data)
+    }
+
+    /// Compute the name of the format holding a preamble. The name is based on
+    /// everything that can affect the dumped format, except for the files
+    /// that it reads, which the format cache keeps track of itself.
+    fn preamble_format_stem(
+        &mut self,
+        preamble: &[u8],
+        status: &mut dyn StatusBackend,
+    ) -> Result<String> {
+        let mut dc = digest::create();
+        dc.update(self.primary_input_tex_path.as_bytes());
+        dc.update(b"\0");
+
+        // The preamble may load local files, so don't share the format
+        // between directory trees.
+        if let Some(ref c) = self.io.format_cache {
+            dc.update(c.inputs_root().to_string_lossy().as_bytes());
+            dc.update(b"\0");
+        }
+
+        {
+            let mut stack = self.io.as_stack();
+            let format = stack
+                .input_open_format(OsStr::new(&self.format_name), status)
+                .must_exist()?;
+            dc.update(digest_stream(format)?);
+        }
+
+        dc.update(preamble);
+
+        let stem = self.format_name.split('.').next().unwrap_or_default();
+        let digest = DigestData::from(dc).to_string();
+        Ok(format!("{}-preamble-{}", stem, &digest[..16]))
+    }
+
+    /// Dump a format from a preamble on top of the main format. The given
+    /// text is used as the primary input, and should end up running
+    /// [`PREAMBLE_DUMP_TRAILER`].
+    fn make_preamble_format_pass(
+        &mut self,
+        text: Vec<u8>,
+        stem: &str,
+        status: &mut dyn StatusBackend,
+    ) -> Result<i32> {
+        if self.io.format_cache.is_none() {
+            return Err(errmsg!(
+                "cannot precompile the preamble without having a place to save it"
+            ));
+        }
+
+        let shell_escape_files = self.io.mem.files.clone();
+        let mut format_events = IoEvents::new();
+
+        let result = {
+            let mut stack = self.io.as_stack_for_preamble(text);
+            TexEngine::new()
+                .halt_on_error_mode(true)
+                .initex_mode(true)
+                .preload_format(true)
+                .build_date(self.build_date)
+                .shell_escape_files(shell_escape_files)
+                .process(
+                    &mut stack,
+                    &mut format_events,
+                    status,
+                    &self.format_name,
+                    &self.primary_input_tex_path,
+                    &self.unstables,
+                )
+        };
+
+        self.finish_format_pass(stem, result, format_events, true, status)
+    }
+
     /// Run one pass of the TeX engine.
     fn tex_pass(
         &mut self,
         rerun_explanation: Option<&str>,
         status: &mut dyn StatusBackend,
     ) -> Result<Option<&'static str>> {
+        let shell_escape_files = self.io.mem.files.clone();
+
         let result = {
             let mut stack = self.io.as_stack();
             if let Some(s) = rerun_explanation {
@@ -1145,11 +2426,14 @@ impl ProcessingSession {
                 .synctex(self.synctex_enabled)
                 .semantic_pagination(self.output_format == OutputFormat::Html)
                 .build_date(self.build_date)
+                .shell_escape_files(shell_escape_files)
                 .process(
                     &mut stack,
                     &mut self.events,
                     status,
-                    &self.format_name,
+                    self.preamble_format_name
+                        .as_ref()
+                        .unwrap_or(&self.format_name),
                     &self.primary_input_tex_path,
                     &self.unstables,
                 )
@@ -1170,15 +2454,41 @@ impl ProcessingSession {
     }
 
     fn bibtex_pass(&mut self, status: &mut dyn StatusBackend) -> Result<i32> {
+        let mut auxes = self.bibtex_aux_files();
+
+        // If we're running BibTeX first, the `.aux` file comes from a
+        // previous session.
+        if auxes.is_empty() {
+            auxes.push(self.tex_aux_path.clone());
+        }
+
+        for aux in auxes {
+            self.bibtex_pass_one(&aux, status)?;
+        }
+
+        Ok(0)
+    }
+
+    fn bibtex_pass_one(&mut self, aux: &OsStr, status: &mut dyn StatusBackend) -> Result<i32> {
         let result = {
             let mut stack = self.io.as_stack();
             let mut engine = BibtexEngine::new();
-            status.note_highlighted("Running ", "BibTeX", " ...");
+
+            if aux == self.tex_aux_path {
+                status.note_highlighted("Running ", "BibTeX", " ...");
+            } else {
+                status.note_highlighted(
+                    "Running ",
+                    "BibTeX",
+                    &format!(" on `{}` ...", aux.to_string_lossy()),
+                );
+            }
+
             engine.process(
                 &mut stack,
                 &mut self.events,
                 status,
-                &self.tex_aux_path.to_str().unwrap(),
+                &aux.to_string_lossy(),
                 &self.unstables,
             )
         };
@@ -1206,10 +2516,143 @@ impl ProcessingSession {
         Ok(0)
     }
 
+    fn biber_pass(&mut self, status: &mut dyn StatusBackend) -> Result<i32> {
+        let bcf = match self.tex_bcf_path.to_str() {
+            Some(s) => s.to_owned(),
+            None => {
+                return Err(errmsg!(
+                    "cannot run biber on \"{}\": the file name is not valid UTF-8",
+                    self.tex_bcf_path.to_string_lossy()
+                ));
+            }
+        };
+
+        let result = {
+            let mut stack = self.io.as_stack();
+            let mut engine = BiberEngine::new();
+            status.note_highlighted("Running ", "biber", " ...");
+            engine.process(&mut stack, &mut self.events, status, &bcf)
+        };
+
+        match result {
+            Ok(TexResult::Spotless) => {}
+            Ok(TexResult::Warnings) => {
+                tt_note!(
+                    status,
+                    "warnings were issued by biber; use --print and/or --keep-logs for details."
+                );
+            }
+            Ok(TexResult::Errors) => {
+                tt_warning!(
+                    status,
+                    "errors were issued by biber, but were ignored; \
+                     use --print and/or --keep-logs for details."
+                );
+            }
+            Err(e) => {
+                return Err(e.chain_err(|| ErrorKind::EngineError("biber")));
+            }
+        }
+
+        Ok(0)
+    }
+
+    /// Run the index processor on the index and glossary files that the TeX
+    /// engine wrote out, if any. The rerun logic then notices if the
+    /// processed files changed.
+    fn index_pass(&mut self, status: &mut dyn StatusBackend) -> Result<i32> {
+        let stem = self
+            .primary_input_tex_path
+            .rsplit_once('.')
+            .map(|(stem, _)| stem)
+            .unwrap_or(&self.primary_input_tex_path)
+            .to_owned();
+
+        // Glossaries announce themselves in the .aux file, along with the
+        // file extensions and style that they use.
+        let aux = self
+            .io
+            .mem
+            .files
+            .borrow()
+            .get(&self.tex_aux_path)
+            .map(|file| String::from_utf8_lossy(&file.data).into_owned())
+            .unwrap_or_default();
+
+        let glossary_style = aux_arguments(&aux, "\\@istfilename", 1)
+            .pop()
+            .map(|args| args[0].to_owned());
+        let letter_ordering = aux_arguments(&aux, "\\@glsorder", 1)
+            .iter()
+            .any(|args| args[0] == "letter");
+
+        let mut jobs = vec![("idx", "ind", "ilg", None)];
+
+        for args in aux_arguments(&aux, "\\@newglossary", 4) {
+            jobs.push((args[3], args[2], args[1], glossary_style.as_deref()));
+        }
+
+        for (in_ext, out_ext, log_ext, style) in jobs {
+            let input = format!("{}.{}", stem, in_ext);
+
+            if !self.io.mem.files.borrow().contains_key(OsStr::new(&input)) {
+                continue;
+            }
+
+            let output = format!("{}.{}", stem, out_ext);
+            let transcript = format!("{}.{}", stem, log_ext);
+            self.index_logs.insert(OsString::from(&transcript));
+
+            let result = {
+                let mut stack = self.io.as_stack();
+                let mut engine = MakeindexEngine::new();
+
+                if let Some(style) = style {
+                    engine.style(style).letter_ordering(letter_ordering);
+                }
+
+                status.note_highlighted("Running ", "makeindex", &format!(" on `{}` ...", input));
+                engine.process(
+                    &mut stack,
+                    &mut self.events,
+                    status,
+                    &input,
+                    &output,
+                    &transcript,
+                )
+            };
+
+            match result {
+                Ok(TexResult::Spotless) => {}
+                Ok(TexResult::Warnings) => {
+                    tt_note!(
+                        status,
+                        "warnings were issued by makeindex; use --print and/or --keep-logs for details."
+                    );
+                }
+                Ok(TexResult::Errors) => {
+                    tt_warning!(
+                        status,
+                        "errors were issued by makeindex, but were ignored; \
+                         use --print and/or --keep-logs for details."
+                    );
+                }
+                Err(e) => {
+                    return Err(e.chain_err(|| ErrorKind::EngineError("makeindex")));
+                }
+            }
+        }
+
+        Ok(0)
+    }
+
     fn xdvipdfmx_pass(&mut self, status: &mut dyn StatusBackend) -> Result<i32> {
         {
             let mut stack = self.io.as_stack();
-            let mut engine = XdvipdfmxEngine::new().with_date(self.build_date);
+            let mut engine = XdvipdfmxEngine::new()
+                .with_date(self.build_date)
+                .with_deterministic_tags(self.reproducible)
+                .with_pdf_options(self.pdf_options.clone());
             status.note_highlighted("Running ", "xdvipdfmx", " ...");
             engine.process(
                 &mut stack,
@@ -1242,6 +2685,36 @@ impl ProcessingSession {
         Ok(0)
     }
 
+    /// Get the SyncTeX data produced by the session, if SyncTeX was enabled.
+    ///
+    /// The data are taken from the memory layer if the engine produced them
+    /// in this session, or otherwise from the output directory, which is
+    /// where they'll be if processing was skipped because nothing had
+    /// changed.
+    pub fn synctex_data(&self) -> Result<Option<SyncTexData>> {
+        if !self.synctex_enabled {
+            return Ok(None);
+        }
+
+        if let Some(file) = self.io.mem.files.borrow().get(&self.tex_synctex_path) {
+            return Ok(Some(SyncTexData::from_gz_reader(&file.data[..])?));
+        }
+
+        match self.output_path {
+            Some(ref root) => {
+                let path = root.join(&self.tex_synctex_path);
+
+                if path.exists() {
+                    Ok(Some(SyncTexData::open(path)?))
+                } else {
+                    Ok(None)
+                }
+            }
+
+            None => Ok(None),
+        }
+    }
+
     /// Consume this session and return the current set of files in memory.
     ///
     /// This convenience function tries to help with the annoyances of getting
@@ -1258,3 +2731,104 @@ impl ProcessingSession {
             .into_inner()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered_lines(n: usize) -> Vec<String> {
+        (1..=n).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn glob_literals_and_wildcards() {
+        assert!(glob_matches("paper.aux", "paper.aux"));
+        assert!(!glob_matches("paper.aux", "paper.log"));
+        assert!(glob_matches("*.aux", "paper.aux"));
+        assert!(!glob_matches("*.aux", "paper.aux.bak"));
+        assert!(glob_matches("?.log", "a.log"));
+        assert!(!glob_matches("?.log", ".log"));
+        assert!(!glob_matches("?.log", "ab.log"));
+    }
+
+    #[test]
+    fn glob_backtracking() {
+        assert!(glob_matches("a*bc", "abcbc"));
+        assert!(!glob_matches("a*bc", "abcb"));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(glob_matches("*?x", "xyx"));
+        assert!(!glob_matches("*?x", "x"));
+        assert!(glob_matches("*a*", "bab"));
+    }
+
+    #[test]
+    fn glob_empty() {
+        assert!(glob_matches("", ""));
+        assert!(!glob_matches("", "a"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("**", ""));
+        assert!(!glob_matches("?", ""));
+    }
+
+    #[test]
+    fn diff_identical_and_empty() {
+        assert_eq!(unified_diff("", "").unwrap(), "");
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n").unwrap(), "");
+        assert_eq!(
+            unified_diff("", "x\ny\n").unwrap(),
+            "@@ -0,0 +1,2 @@\n+x\n+y\n"
+        );
+        assert_eq!(
+            unified_diff("x\ny\n", "").unwrap(),
+            "@@ -1,2 +0,0 @@\n-x\n-y\n"
+        );
+    }
+
+    #[test]
+    fn diff_context() {
+        let old = numbered_lines(10).join("\n");
+        let new = old.replace("\n5\n", "\nfive\n");
+
+        assert_eq!(
+            unified_diff(&old, &new).unwrap(),
+            "@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn diff_hunk_merging() {
+        let old = numbered_lines(20);
+
+        let without = |skip: &[usize]| {
+            old.iter()
+                .enumerate()
+                .filter(|(k, _)| !skip.contains(k))
+                .map(|(_, l)| l.as_str())
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        // Six unchanged lines between the changes: the context overlaps, so
+        // one hunk.
+        let diff = unified_diff(&old.join("\n"), &without(&[2, 9])).unwrap();
+        assert_eq!(diff.matches("@@ -").count(), 1);
+        assert!(diff.starts_with("@@ -1,13 +1,11 @@\n"));
+
+        // Seven unchanged lines: two hunks.
+        let diff = unified_diff(&old.join("\n"), &without(&[2, 10])).unwrap();
+        assert_eq!(diff.matches("@@ -").count(), 2);
+        assert!(diff.starts_with("@@ -1,6 +1,5 @@\n"));
+        assert!(diff.contains("\n@@ -8,7 +7,6 @@\n"));
+    }
+
+    #[test]
+    fn diff_size_cutoff() {
+        // (2047 + 1)^2 cells is exactly the limit.
+        let text = numbered_lines(2047).join("\n");
+        assert_eq!(unified_diff(&text, &text).unwrap(), "");
+
+        let text = numbered_lines(2048).join("\n");
+        assert!(unified_diff(&text, &text).is_none());
+        assert!(unified_diff("", &text).is_some());
+    }
+}
@@ -0,0 +1,190 @@
+// src/engines/biber.rs -- running the biber bibliography processor.
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Support for `biblatex` documents that use the biber backend.
+//!
+//! Tectonic doesn't include an implementation of biber, so we run an external
+//! `biber` program. To keep it from rummaging around the filesystem on its
+//! own, it is run inside a fresh temporary directory that is populated with
+//! the control file (`.bcf`) written by the TeX engine and the bibliography
+//! databases that the control file names. The databases are looked up
+//! through the I/O stack, just like files read by the other engines, so they
+//! may come from the filesystem or from the bundle. The `.bbl` and `.blg`
+//! files that biber creates are then copied back through the I/O stack.
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Component, Path};
+use std::process::Command;
+use tempfile::TempDir;
+
+use super::tex::TexResult;
+use super::{read_whole_input, write_whole_output, IoEventBackend};
+use crate::errors::Result;
+use crate::io::IoStack;
+use crate::status::StatusBackend;
+use crate::{ctry, errmsg, tt_warning};
+
+pub struct BiberEngine {
+    program: OsString,
+}
+
+impl Default for BiberEngine {
+    fn default() -> Self {
+        BiberEngine {
+            program: OsString::from("biber"),
+        }
+    }
+}
+
+impl BiberEngine {
+    pub fn new() -> BiberEngine {
+        Default::default()
+    }
+
+    /// Set the biber program to run. By default, `biber` is looked up in the
+    /// search path.
+    pub fn program<S: AsRef<OsStr>>(&mut self, program: S) -> &mut Self {
+        self.program = program.as_ref().to_owned();
+        self
+    }
+
+    pub fn process(
+        &mut self,
+        io: &mut IoStack,
+        events: &mut dyn IoEventBackend,
+        status: &mut dyn StatusBackend,
+        bcf: &str,
+    ) -> Result<TexResult> {
+        if !is_plain_relative(bcf) {
+            return Err(errmsg!("cannot run biber on \"{}\"", bcf));
+        }
+
+        let work_dir = ctry!(TempDir::new(); "couldn't create a directory for running biber");
+
+        let bcf_data = match read_whole_input(io, events, status, bcf)? {
+            Some(d) => d,
+            None => return Err(errmsg!("biber control file \"{}\" is missing", bcf)),
+        };
+        write_work_file(work_dir.path(), bcf, &bcf_data)?;
+
+        for source in bcf_datasources(&String::from_utf8_lossy(&bcf_data)) {
+            // Biber would download remote resources and read absolute paths
+            // itself, bypassing the I/O stack, so don't let it.
+            if source.contains("://") || !is_plain_relative(&source) {
+                return Err(errmsg!(
+                    "refusing to run biber on a bibliography database outside of the \
+                     document (\"{}\"); only plain relative file names are supported",
+                    source
+                ));
+            }
+
+            match read_whole_input(io, events, status, &source)? {
+                Some(data) => write_work_file(work_dir.path(), &source, &data)?,
+                None => {
+                    tt_warning!(status, "couldn't find bibliography database \"{}\"", source);
+                }
+            }
+        }
+
+        let output = ctry!(
+            Command::new(&self.program).arg(bcf).current_dir(work_dir.path()).output();
+            "couldn't run `{}`; it must be installed to process documents that use biber",
+            self.program.to_string_lossy()
+        );
+
+        let stem = bcf.strip_suffix(".bcf").unwrap_or(bcf);
+
+        for ext in &["bbl", "blg"] {
+            let name = format!("{}.{}", stem, ext);
+            let path = work_dir.path().join(&name);
+
+            if path.exists() {
+                let data = ctry!(fs::read(&path); "couldn't read biber output \"{}\"", name);
+                write_whole_output(io, events, status, &name, &data)?;
+            }
+        }
+
+        // Biber reports its problems on standard output, prefixed with the
+        // severity.
+        let chatter = String::from_utf8_lossy(&output.stdout);
+
+        if !output.status.success() {
+            status.dump_error_logs(&output.stdout);
+            status.dump_error_logs(&output.stderr);
+            return Err(errmsg!("biber failed with {}", output.status));
+        }
+
+        if chatter.contains("ERROR - ") {
+            Ok(TexResult::Errors)
+        } else if chatter.contains("WARN - ") {
+            Ok(TexResult::Warnings)
+        } else {
+            Ok(TexResult::Spotless)
+        }
+    }
+}
+
+/// Check that a name refers to a file inside the working directory.
+fn is_plain_relative(name: &str) -> bool {
+    let path = Path::new(name);
+    !name.is_empty() && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+fn write_work_file(dir: &Path, name: &str, data: &[u8]) -> Result<()> {
+    let path = dir.join(name);
+
+    if let Some(parent) = path.parent() {
+        ctry!(fs::create_dir_all(parent); "couldn't create directory \"{}\"", parent.display());
+    }
+
+    ctry!(fs::write(&path, data); "couldn't write file \"{}\"", path.display());
+    Ok(())
+}
+
+/// Find the bibliography databases named in a biber control file.
+///
+/// The control file is XML, but we only need the contents of its
+/// `<bcf:datasource>` elements, so we don't bother with a real parser.
+fn bcf_datasources(bcf: &str) -> Vec<String> {
+    const OPEN: &str = "<bcf:datasource";
+    const CLOSE: &str = "</bcf:datasource>";
+
+    let mut sources = Vec::new();
+    let mut rest = bcf;
+
+    while let Some(start) = rest.find(OPEN) {
+        rest = &rest[start + OPEN.len()..];
+
+        let tag_end = match rest.find('>') {
+            Some(i) => i,
+            None => break,
+        };
+        let attrs = &rest[..tag_end];
+        rest = &rest[tag_end + 1..];
+
+        let content_end = match rest.find(CLOSE) {
+            Some(i) => i,
+            None => break,
+        };
+        let content = rest[..content_end].trim();
+        rest = &rest[content_end + CLOSE.len()..];
+
+        // Only files of BibTeX data are staged; other kinds of data sources
+        // are left to biber.
+        if attrs.contains("type=\"file\"") && attrs.contains("datatype=\"bibtex\"") {
+            sources.push(xml_unescape(content));
+        }
+    }
+
+    sources
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
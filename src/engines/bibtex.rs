@@ -2,10 +2,8 @@
 // Copyright 2017 the Tectonic Project
 // Licensed under the MIT License.
 
-use std::ffi::{CStr, CString};
-
 use super::tex::TexResult;
-use super::{ExecutionState, IoEventBackend, TectonicBridgeApi};
+use super::{EngineJob, IoEventBackend};
 use crate::errors::{ErrorKind, Result};
 use crate::io::IoStack;
 use crate::status::StatusBackend;
@@ -13,7 +11,7 @@ use crate::unstable_opts::UnstableOptions;
 
 #[repr(C)]
 pub struct BibtexConfig {
-    min_crossrefs: i32,
+    pub(crate) min_crossrefs: i32,
 }
 
 #[derive(Default)]
@@ -32,33 +30,21 @@ impl BibtexEngine {
         aux: &str,
         unstables: &UnstableOptions,
     ) -> Result<TexResult> {
-        let _guard = super::ENGINE_LOCK.lock().unwrap(); // until we're thread-safe ...
-
-        let caux = CString::new(aux)?;
-
-        let mut state = ExecutionState::new(io, events, status);
-        let bridge = TectonicBridgeApi::new(&mut state);
-        let config = BibtexConfig {
+        let job = EngineJob::Bibtex {
+            aux_name: aux.to_owned(),
             min_crossrefs: unstables.min_crossrefs.unwrap_or(2),
         };
-
-        unsafe {
-            match super::bibtex_simple_main(&bridge, &config, caux.as_ptr()) {
-                0 => Ok(TexResult::Spotless),
-                1 => Ok(TexResult::Warnings),
-                2 => Ok(TexResult::Errors),
-                3 => Err(ErrorKind::Msg("unspecified fatal bibtex error".into()).into()),
-                99 => {
-                    let ptr = super::tt_get_error_message();
-                    let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
-                    Err(ErrorKind::Msg(msg).into())
-                }
-                x => Err(ErrorKind::Msg(format!(
-                    "internal error: unexpected 'history' value {}",
-                    x
-                ))
-                .into()),
-            }
+        let outcome = job.run(io, events, status, None)?;
+
+        match outcome.code {
+            0 => Ok(TexResult::Spotless),
+            1 => Ok(TexResult::Warnings),
+            2 => Ok(TexResult::Errors),
+            3 => Err(ErrorKind::Msg("unspecified fatal bibtex error".into()).into()),
+            99 => Err(ErrorKind::Msg(outcome.error_message).into()),
+            x => Err(
+                ErrorKind::Msg(format!("internal error: unexpected 'history' value {}", x)).into(),
+            ),
         }
     }
 }
@@ -0,0 +1,1343 @@
+// src/engines/makeindex.rs -- a makeindex-compatible index processor
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! An implementation of the `makeindex` index processor.
+//!
+//! This reads the raw entries that LaTeX writes to an index file (`.idx`),
+//! sorts and merges them, and writes out the formatted index (`.ind`) along
+//! with a transcript (`.ilg`). The `glossaries` package uses the same
+//! machinery for its glossaries, so those are handled here too, using the
+//! style file (`.ist`) that the package writes out.
+//!
+//! Style files are supported, except for the settings that control line
+//! wrapping and page-number resetting, which are accepted but ignored.
+
+use std::cmp::Ordering;
+
+use super::tex::TexResult;
+use super::{read_whole_input, write_whole_output, IoEventBackend};
+use crate::errmsg;
+use crate::errors::Result;
+use crate::io::IoStack;
+use crate::status::StatusBackend;
+
+/// The maximum number of levels of subentries that makeindex supports.
+const MAX_LEVELS: usize = 3;
+
+#[derive(Default)]
+pub struct MakeindexEngine {
+    style: Option<String>,
+    letter_ordering: bool,
+}
+
+impl MakeindexEngine {
+    pub fn new() -> MakeindexEngine {
+        Default::default()
+    }
+
+    /// Use the named style file rather than the default style.
+    pub fn style(&mut self, name: &str) -> &mut Self {
+        self.style = Some(name.to_owned());
+        self
+    }
+
+    /// Ignore spaces when sorting entries, like `makeindex -l`.
+    pub fn letter_ordering(&mut self, letter_ordering: bool) -> &mut Self {
+        self.letter_ordering = letter_ordering;
+        self
+    }
+
+    pub fn process(
+        &mut self,
+        io: &mut IoStack,
+        events: &mut dyn IoEventBackend,
+        status: &mut dyn StatusBackend,
+        input: &str,
+        output: &str,
+        transcript: &str,
+    ) -> Result<TexResult> {
+        let mut log = Transcript::default();
+        log.line("This is Tectonic's makeindex-compatible index processor.");
+
+        let mut style = Style::default();
+
+        if let Some(ref name) = self.style {
+            let data = match read_whole_input(io, events, status, name)? {
+                Some(d) => d,
+                None => return Err(errmsg!("index style file \"{}\" is missing", name)),
+            };
+
+            log.line(&format!("Scanning style file {}...", name));
+            let (n_set, n_ignored) = style.parse(&String::from_utf8_lossy(&data), name, &mut log);
+            log.line(&format!(
+                "done ({} attributes redefined, {} ignored).",
+                n_set, n_ignored
+            ));
+        }
+
+        let data = match read_whole_input(io, events, status, input)? {
+            Some(d) => d,
+            None => return Err(errmsg!("index file \"{}\" is missing", input)),
+        };
+
+        log.line(&format!("Scanning input file {}...", input));
+        let (mut entries, n_rejected) =
+            scan_entries(&String::from_utf8_lossy(&data), &style, input, &mut log);
+        log.line(&format!(
+            "done ({} entries accepted, {} rejected).",
+            entries.len(),
+            n_rejected
+        ));
+
+        log.line("Sorting entries...");
+        sort_entries(&mut entries, self.letter_ordering);
+        log.line("done.");
+
+        log.line(&format!("Generating output file {}...", output));
+        let text = generate(&entries, &style, input, &mut log);
+        write_whole_output(io, events, status, output, text.as_bytes())?;
+        log.line(&format!(
+            "done ({} lines written, {} warnings).",
+            text.matches('\n').count(),
+            log.n_warnings
+        ));
+        log.line(&format!("Output written in {}.", output));
+        log.line(&format!("Transcript written in {}.", transcript));
+
+        write_whole_output(io, events, status, transcript, log.text.as_bytes())?;
+
+        if log.n_errors > 0 {
+            Ok(TexResult::Errors)
+        } else if log.n_warnings > 0 {
+            Ok(TexResult::Warnings)
+        } else {
+            Ok(TexResult::Spotless)
+        }
+    }
+}
+
+/// The contents of the transcript file, along with counts of the problems
+/// that have been recorded in it.
+#[derive(Default)]
+struct Transcript {
+    text: String,
+    n_errors: usize,
+    n_warnings: usize,
+}
+
+impl Transcript {
+    fn line(&mut self, text: &str) {
+        self.text.push_str(text);
+        self.text.push('\n');
+    }
+
+    fn error(&mut self, kind: &str, file: &str, line: usize, message: &str) {
+        self.line(&format!(
+            "!! {} error (file = {}, line = {}):\n   -- {}.",
+            kind, file, line, message
+        ));
+        self.n_errors += 1;
+    }
+
+    fn warning(&mut self, file: &str, line: usize, message: &str) {
+        self.line(&format!(
+            "## Warning (file = {}, line = {}):\n   -- {}.",
+            file, line, message
+        ));
+        self.n_warnings += 1;
+    }
+}
+
+/// The settings that may be changed by a style file. The defaults are those
+/// of makeindex.
+struct Style {
+    keyword: String,
+    arg_open: char,
+    arg_close: char,
+    range_open: char,
+    range_close: char,
+    level: char,
+    actual: char,
+    encap: char,
+    quote: char,
+    escape: char,
+    page_compositor: String,
+    page_precedence: String,
+    preamble: String,
+    postamble: String,
+    group_skip: String,
+    headings_flag: i64,
+    heading_prefix: String,
+    heading_suffix: String,
+    symhead_positive: String,
+    symhead_negative: String,
+    numhead_positive: String,
+    numhead_negative: String,
+    item_0: String,
+    item_1: String,
+    item_2: String,
+    item_01: String,
+    item_x1: String,
+    item_12: String,
+    item_x2: String,
+    delim_0: String,
+    delim_1: String,
+    delim_2: String,
+    delim_n: String,
+    delim_r: String,
+    delim_t: String,
+    encap_prefix: String,
+    encap_infix: String,
+    encap_suffix: String,
+    suffix_2p: String,
+    suffix_3p: String,
+    suffix_mp: String,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            keyword: "\\indexentry".to_owned(),
+            arg_open: '{',
+            arg_close: '}',
+            range_open: '(',
+            range_close: ')',
+            level: '!',
+            actual: '@',
+            encap: '|',
+            quote: '"',
+            escape: '\\',
+            page_compositor: "-".to_owned(),
+            page_precedence: "rnaRA".to_owned(),
+            preamble: "\\begin{theindex}\n".to_owned(),
+            postamble: "\n\n\\end{theindex}\n".to_owned(),
+            group_skip: "\n\n  \\indexspace\n".to_owned(),
+            headings_flag: 0,
+            heading_prefix: String::new(),
+            heading_suffix: String::new(),
+            symhead_positive: "Symbols".to_owned(),
+            symhead_negative: "symbols".to_owned(),
+            numhead_positive: "Numbers".to_owned(),
+            numhead_negative: "numbers".to_owned(),
+            item_0: "\n  \\item ".to_owned(),
+            item_1: "\n    \\subitem ".to_owned(),
+            item_2: "\n      \\subsubitem ".to_owned(),
+            item_01: "\n    \\subitem ".to_owned(),
+            item_x1: "\n    \\subitem ".to_owned(),
+            item_12: "\n      \\subsubitem ".to_owned(),
+            item_x2: "\n      \\subsubitem ".to_owned(),
+            delim_0: ", ".to_owned(),
+            delim_1: ", ".to_owned(),
+            delim_2: ", ".to_owned(),
+            delim_n: ", ".to_owned(),
+            delim_r: "--".to_owned(),
+            delim_t: String::new(),
+            encap_prefix: "\\".to_owned(),
+            encap_infix: "{".to_owned(),
+            encap_suffix: "}".to_owned(),
+            suffix_2p: String::new(),
+            suffix_3p: String::new(),
+            suffix_mp: String::new(),
+        }
+    }
+}
+
+enum StyleValue {
+    String(String),
+    Char(char),
+    Number(i64),
+}
+
+impl Style {
+    fn string_setting(&mut self, key: &str) -> Option<&mut String> {
+        Some(match key {
+            "keyword" => &mut self.keyword,
+            "page_compositor" => &mut self.page_compositor,
+            "page_precedence" => &mut self.page_precedence,
+            "preamble" => &mut self.preamble,
+            "postamble" => &mut self.postamble,
+            "group_skip" => &mut self.group_skip,
+            "heading_prefix" => &mut self.heading_prefix,
+            "heading_suffix" => &mut self.heading_suffix,
+            "symhead_positive" => &mut self.symhead_positive,
+            "symhead_negative" => &mut self.symhead_negative,
+            "numhead_positive" => &mut self.numhead_positive,
+            "numhead_negative" => &mut self.numhead_negative,
+            "item_0" => &mut self.item_0,
+            "item_1" => &mut self.item_1,
+            "item_2" => &mut self.item_2,
+            "item_01" => &mut self.item_01,
+            "item_x1" => &mut self.item_x1,
+            "item_12" => &mut self.item_12,
+            "item_x2" => &mut self.item_x2,
+            "delim_0" => &mut self.delim_0,
+            "delim_1" => &mut self.delim_1,
+            "delim_2" => &mut self.delim_2,
+            "delim_n" => &mut self.delim_n,
+            "delim_r" => &mut self.delim_r,
+            "delim_t" => &mut self.delim_t,
+            "encap_prefix" => &mut self.encap_prefix,
+            "encap_infix" => &mut self.encap_infix,
+            "encap_suffix" => &mut self.encap_suffix,
+            "suffix_2p" => &mut self.suffix_2p,
+            "suffix_3p" => &mut self.suffix_3p,
+            "suffix_mp" => &mut self.suffix_mp,
+            _ => return None,
+        })
+    }
+
+    fn char_setting(&mut self, key: &str) -> Option<&mut char> {
+        Some(match key {
+            "arg_open" => &mut self.arg_open,
+            "arg_close" => &mut self.arg_close,
+            "range_open" => &mut self.range_open,
+            "range_close" => &mut self.range_close,
+            "level" => &mut self.level,
+            "actual" => &mut self.actual,
+            "encap" => &mut self.encap,
+            "quote" => &mut self.quote,
+            "escape" => &mut self.escape,
+            _ => return None,
+        })
+    }
+
+    fn set(&mut self, key: &str, value: StyleValue) -> std::result::Result<(), String> {
+        if let Some(setting) = self.string_setting(key) {
+            match value {
+                StyleValue::String(s) => *setting = s,
+                _ => return Err(format!("`{}` must be a string", key)),
+            }
+        } else if let Some(setting) = self.char_setting(key) {
+            match value {
+                StyleValue::Char(c) => *setting = c,
+                _ => return Err(format!("`{}` must be a character", key)),
+            }
+        } else {
+            match (key, value) {
+                ("headings_flag", StyleValue::Number(n)) => self.headings_flag = n,
+                ("headings_flag", _) => return Err("`headings_flag` must be a number".to_owned()),
+
+                // Line wrapping and page-number resetting aren't implemented.
+                ("line_max", _)
+                | ("indent_space", _)
+                | ("indent_length", _)
+                | ("setpage_prefix", _)
+                | ("setpage_suffix", _) => {}
+
+                _ => return Err(format!("unknown specifier `{}`", key)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply the settings in a style file, returning the number of settings
+    /// that were applied and the number that were ignored because of errors.
+    fn parse(&mut self, text: &str, file: &str, log: &mut Transcript) -> (usize, usize) {
+        let mut scanner = Scanner::new(text);
+        let mut n_set = 0;
+        let mut n_ignored = 0;
+
+        loop {
+            scanner.skip_blanks();
+
+            let key = scanner.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+
+            if key.is_empty() {
+                match scanner.next() {
+                    None => break,
+                    Some(c) => {
+                        log.error(
+                            "Input style",
+                            file,
+                            scanner.line,
+                            &format!("unexpected character `{}`", c),
+                        );
+                        scanner.skip_line();
+                        n_ignored += 1;
+                        continue;
+                    }
+                }
+            }
+
+            let key = key.to_owned();
+            scanner.skip_blanks();
+            let line = scanner.line;
+
+            let result = scanner.value().and_then(|value| self.set(&key, value));
+
+            match result {
+                Ok(()) => n_set += 1,
+                Err(message) => {
+                    log.error("Input style", file, line, &message);
+                    scanner.skip_line();
+                    n_ignored += 1;
+                }
+            }
+        }
+
+        (n_set, n_ignored)
+    }
+}
+
+/// A minimal tokenizer for style files.
+struct Scanner<'a> {
+    rest: &'a str,
+    line: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(text: &'a str) -> Self {
+        Scanner {
+            rest: text,
+            line: 1,
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.rest.chars().next()?;
+        self.rest = &self.rest[c.len_utf8()..];
+
+        if c == '\n' {
+            self.line += 1;
+        }
+
+        Some(c)
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, pred: F) -> &'a str {
+        let end = self.rest.find(|c| !pred(c)).unwrap_or(self.rest.len());
+        let (taken, rest) = self.rest.split_at(end);
+        self.line += taken.matches('\n').count();
+        self.rest = rest;
+        taken
+    }
+
+    fn skip_line(&mut self) {
+        self.take_while(|c| c != '\n');
+    }
+
+    /// Skip whitespace and comments.
+    fn skip_blanks(&mut self) {
+        loop {
+            self.take_while(char::is_whitespace);
+
+            if self.rest.starts_with('%') {
+                self.skip_line();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Read one character of a quoted string or character, handling
+    /// backslash escapes.
+    fn quoted_char(&mut self) -> Option<char> {
+        match self.next()? {
+            '\\' => match self.next()? {
+                'n' => Some('\n'),
+                't' => Some('\t'),
+                c => Some(c),
+            },
+            c => Some(c),
+        }
+    }
+
+    fn value(&mut self) -> std::result::Result<StyleValue, String> {
+        if self.rest.starts_with('"') {
+            self.next();
+            let mut s = String::new();
+
+            loop {
+                if self.rest.starts_with('"') {
+                    self.next();
+                    return Ok(StyleValue::String(s));
+                }
+
+                match self.quoted_char() {
+                    Some(c) => s.push(c),
+                    None => return Err("unterminated string".to_owned()),
+                }
+            }
+        }
+
+        if self.rest.starts_with('\'') {
+            self.next();
+            let c = self.quoted_char();
+
+            return match (c, self.next()) {
+                (Some(c), Some('\'')) => Ok(StyleValue::Char(c)),
+                _ => Err("malformed character".to_owned()),
+            };
+        }
+
+        let negative = self.rest.starts_with('-');
+        if negative {
+            self.next();
+        }
+
+        match self.take_while(|c| c.is_ascii_digit()).parse::<i64>() {
+            Ok(n) => Ok(StyleValue::Number(if negative { -n } else { n })),
+            Err(_) => Err("expected a string, character, or number".to_owned()),
+        }
+    }
+}
+
+/// One level of the key of an index entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Level {
+    sort: String,
+    display: String,
+}
+
+/// Whether an entry starts or ends an explicit page range. The ordering
+/// matters: on any given page, range starts sort first and range ends last.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum RangeMark {
+    Open,
+    Single,
+    Close,
+}
+
+#[derive(Clone, Debug)]
+struct Page {
+    text: String,
+
+    /// For each component of a composite page number, its rank in the page
+    /// precedence and its value.
+    parts: Vec<(usize, u32)>,
+}
+
+impl Page {
+    fn parse(text: &str, style: &Style) -> Option<Page> {
+        let components: Vec<&str> = if style.page_compositor.is_empty() {
+            vec![text]
+        } else {
+            text.split(style.page_compositor.as_str()).collect()
+        };
+
+        let mut parts = Vec::new();
+
+        for component in components {
+            let (kind, value) = page_component(component)?;
+            let rank = style.page_precedence.find(kind)?;
+            parts.push((rank, value));
+        }
+
+        Some(Page {
+            text: text.to_owned(),
+            parts,
+        })
+    }
+
+    /// Whether this page immediately follows `prev`, so that the two can be
+    /// joined into a range.
+    fn follows(&self, prev: &Page) -> bool {
+        let n = self.parts.len();
+
+        n == prev.parts.len()
+            && self.parts[..n - 1] == prev.parts[..n - 1]
+            && self.parts[n - 1].0 == prev.parts[n - 1].0
+            && self.parts[n - 1].1 == prev.parts[n - 1].1 + 1
+    }
+}
+
+/// Classify one component of a page number, using makeindex's type letters.
+fn page_component(text: &str) -> Option<(char, u32)> {
+    if text.is_empty() {
+        return None;
+    }
+
+    if text.bytes().all(|b| b.is_ascii_digit()) {
+        return text.parse().ok().map(|n| ('n', n));
+    }
+
+    if let Some(n) = roman_value(text, false) {
+        return Some(('r', n));
+    }
+
+    if let Some(n) = roman_value(text, true) {
+        return Some(('R', n));
+    }
+
+    let mut chars = text.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_lowercase() => Some(('a', c as u32 - 'a' as u32 + 1)),
+        (Some(c), None) if c.is_ascii_uppercase() => Some(('A', c as u32 - 'A' as u32 + 1)),
+        _ => None,
+    }
+}
+
+fn roman_value(text: &str, upper: bool) -> Option<u32> {
+    let mut total = 0;
+    let mut prev = 0;
+
+    for c in text.chars().rev() {
+        if c.is_ascii_uppercase() != upper {
+            return None;
+        }
+
+        let v = match c.to_ascii_lowercase() {
+            'i' => 1,
+            'v' => 5,
+            'x' => 10,
+            'l' => 50,
+            'c' => 100,
+            'd' => 500,
+            'm' => 1000,
+            _ => return None,
+        };
+
+        if v < prev {
+            total = u32::checked_sub(total, v)?;
+        } else {
+            total += v;
+            prev = v;
+        }
+    }
+
+    Some(total)
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    levels: Vec<Level>,
+    page: Page,
+    encap: Option<String>,
+    range: RangeMark,
+    line: usize,
+}
+
+/// Pull the entries out of an index file. Returns the accepted entries and
+/// the number of rejected ones.
+fn scan_entries(
+    text: &str,
+    style: &Style,
+    file: &str,
+    log: &mut Transcript,
+) -> (Vec<Entry>, usize) {
+    let mut entries = Vec::new();
+    let mut n_rejected = 0;
+    let mut pos = 0;
+
+    while let Some(offset) = text[pos..].find(&style.keyword) {
+        let start = pos + offset;
+        let line = 1 + text[..start].matches('\n').count();
+        pos = start + style.keyword.len();
+
+        let result = scan_argument(text, &mut pos, style, true)
+            .ok_or_else(|| "malformed or missing first argument".to_owned())
+            .and_then(|key| {
+                let page = scan_argument(text, &mut pos, style, false)
+                    .ok_or_else(|| "malformed or missing second argument".to_owned())?;
+                parse_entry(&key, &page, style, line)
+            });
+
+        match result {
+            Ok(entry) => entries.push(entry),
+            Err(message) => {
+                log.error("Input index", file, line, &message);
+                n_rejected += 1;
+            }
+        }
+    }
+
+    (entries, n_rejected)
+}
+
+/// Read a delimited argument starting at `*pos`. In keys, the quote and
+/// escape characters protect the delimiters; they are left in place to be
+/// interpreted later.
+fn scan_argument(text: &str, pos: &mut usize, style: &Style, is_key: bool) -> Option<String> {
+    let rest = text[*pos..].trim_start_matches([' ', '\t']);
+    let mut chars = rest.char_indices();
+
+    match chars.next() {
+        Some((_, c)) if c == style.arg_open => {}
+        _ => return None,
+    }
+
+    let mut arg = String::new();
+    let mut depth = 0;
+
+    while let Some((i, c)) = chars.next() {
+        if is_key && (c == style.quote || c == style.escape) {
+            arg.push(c);
+            arg.push(chars.next()?.1);
+        } else if c == style.arg_open {
+            depth += 1;
+            arg.push(c);
+        } else if c == style.arg_close {
+            if depth == 0 {
+                *pos = text.len() - rest.len() + i + c.len_utf8();
+                return Some(arg);
+            }
+
+            depth -= 1;
+            arg.push(c);
+        } else {
+            arg.push(c);
+        }
+    }
+
+    None
+}
+
+fn parse_entry(
+    key: &str,
+    page: &str,
+    style: &Style,
+    line: usize,
+) -> std::result::Result<Entry, String> {
+    let mut levels = Vec::new();
+    let mut sort = None;
+    let mut cur = String::new();
+    let mut encap = None;
+    let mut chars = key.chars();
+
+    fn finish(sort: Option<String>, display: String) -> std::result::Result<Level, String> {
+        let sort = sort.unwrap_or_else(|| display.clone());
+
+        if sort.is_empty() {
+            return Err("empty index key".to_owned());
+        }
+
+        Ok(Level { sort, display })
+    }
+
+    while let Some(c) = chars.next() {
+        if c == style.escape {
+            cur.push(c);
+            cur.extend(chars.next());
+        } else if c == style.quote {
+            match chars.next() {
+                Some(n) => cur.push(n),
+                None => return Err(format!("dangling `{}`", c)),
+            }
+        } else if c == style.level {
+            levels.push(finish(sort.take(), std::mem::take(&mut cur))?);
+        } else if c == style.actual {
+            if sort.is_some() {
+                return Err(format!("extra `{}` in index key", c));
+            }
+
+            sort = Some(std::mem::take(&mut cur));
+        } else if c == style.encap {
+            // The quote and escape characters work here too, but nothing
+            // else is special.
+            let mut e = String::new();
+
+            while let Some(c) = chars.next() {
+                if c == style.quote {
+                    e.extend(chars.next());
+                } else {
+                    e.push(c);
+
+                    if c == style.escape {
+                        e.extend(chars.next());
+                    }
+                }
+            }
+
+            encap = Some(e);
+            break;
+        } else {
+            cur.push(c);
+        }
+    }
+
+    levels.push(finish(sort, cur)?);
+
+    if levels.len() > MAX_LEVELS {
+        return Err(format!(
+            "too many levels in index key (the maximum is {})",
+            MAX_LEVELS
+        ));
+    }
+
+    let (range, encap) = match encap.as_deref() {
+        None => (RangeMark::Single, ""),
+        Some(e) => {
+            if let Some(rest) = e.strip_prefix(style.range_open) {
+                (RangeMark::Open, rest)
+            } else if let Some(rest) = e.strip_prefix(style.range_close) {
+                (RangeMark::Close, rest)
+            } else {
+                (RangeMark::Single, e)
+            }
+        }
+    };
+
+    let page =
+        Page::parse(page.trim(), style).ok_or_else(|| format!("illegal page number `{}`", page))?;
+
+    Ok(Entry {
+        levels,
+        page,
+        encap: if encap.is_empty() {
+            None
+        } else {
+            Some(encap.to_owned())
+        },
+        range,
+        line,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum KeyClass {
+    Symbol,
+    Number,
+    Letter,
+}
+
+fn classify(key: &str) -> KeyClass {
+    match key.chars().next() {
+        Some(c) if c.is_alphabetic() => KeyClass::Letter,
+        Some(c) if c.is_ascii_digit() && key.bytes().all(|b| b.is_ascii_digit()) => {
+            KeyClass::Number
+        }
+        _ => KeyClass::Symbol,
+    }
+}
+
+fn compare_keys(a: &str, b: &str, letter_ordering: bool) -> Ordering {
+    let class = classify(a);
+
+    class.cmp(&classify(b)).then_with(|| match class {
+        KeyClass::Number => {
+            let (a, b) = (a.trim_start_matches('0'), b.trim_start_matches('0'));
+            a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+        }
+
+        KeyClass::Letter => {
+            let fold = |s: &str| -> String {
+                s.chars()
+                    .filter(|c| !(letter_ordering && c.is_whitespace()))
+                    .flat_map(char::to_lowercase)
+                    .collect()
+            };
+
+            fold(a).cmp(&fold(b)).then_with(|| a.cmp(b))
+        }
+
+        KeyClass::Symbol => a.cmp(b),
+    })
+}
+
+fn compare_levels(a: &[Level], b: &[Level], letter_ordering: bool) -> Ordering {
+    for (la, lb) in a.iter().zip(b) {
+        let o = compare_keys(&la.sort, &lb.sort, letter_ordering)
+            .then_with(|| la.display.cmp(&lb.display));
+
+        if o != Ordering::Equal {
+            return o;
+        }
+    }
+
+    a.len().cmp(&b.len())
+}
+
+/// Sort entries into the order in which they appear in the index: by key,
+/// then by page, with the input order breaking ties.
+fn sort_entries(entries: &mut [Entry], letter_ordering: bool) {
+    entries.sort_by(|a, b| {
+        compare_levels(&a.levels, &b.levels, letter_ordering)
+            .then_with(|| a.page.parts.cmp(&b.page.parts))
+            .then_with(|| a.range.cmp(&b.range))
+            .then_with(|| a.line.cmp(&b.line))
+    });
+}
+
+/// The group that an entry is filed under, for the purposes of group skips
+/// and headings.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Group {
+    Symbols,
+    Numbers,
+    Letter(char),
+}
+
+impl Group {
+    fn of(entry: &Entry) -> Group {
+        let key = &entry.levels[0].sort;
+
+        match classify(key) {
+            KeyClass::Symbol => Group::Symbols,
+            KeyClass::Number => Group::Numbers,
+            KeyClass::Letter => {
+                Group::Letter(key.chars().next().unwrap().to_uppercase().next().unwrap())
+            }
+        }
+    }
+
+    fn heading(self, style: &Style) -> String {
+        let positive = style.headings_flag > 0;
+
+        let text = match self {
+            Group::Symbols if positive => style.symhead_positive.clone(),
+            Group::Symbols => style.symhead_negative.clone(),
+            Group::Numbers if positive => style.numhead_positive.clone(),
+            Group::Numbers => style.numhead_negative.clone(),
+            Group::Letter(c) if positive => c.to_string(),
+            Group::Letter(c) => c.to_lowercase().to_string(),
+        };
+
+        format!("{}{}{}", style.heading_prefix, text, style.heading_suffix)
+    }
+}
+
+/// Generate the formatted index from the sorted entries.
+fn generate(entries: &[Entry], style: &Style, file: &str, log: &mut Transcript) -> String {
+    let mut out = style.preamble.clone();
+    let mut prev_levels: &[Level] = &[];
+    let mut prev_group = None;
+    // The level of the last item written, and whether it had any pages.
+    let mut prev_item: Option<(usize, bool)> = None;
+    let mut i = 0;
+
+    while i < entries.len() {
+        let levels = &entries[i].levels;
+        let mut j = i + 1;
+
+        while j < entries.len() && entries[j].levels == *levels {
+            j += 1;
+        }
+
+        let group = Group::of(&entries[i]);
+
+        let mut common = 0;
+
+        if prev_group != Some(group) {
+            if prev_group.is_some() {
+                out.push_str(&style.group_skip);
+            }
+
+            if style.headings_flag != 0 {
+                out.push_str(&group.heading(style));
+            }
+
+            prev_group = Some(group);
+        } else {
+            while common < levels.len()
+                && common < prev_levels.len()
+                && levels[common] == prev_levels[common]
+            {
+                common += 1;
+            }
+        }
+
+        let pages = format_pages(&entries[i..j], style, file, log);
+
+        for (depth, level) in levels.iter().enumerate().skip(common) {
+            let item = match (depth, prev_item) {
+                (0, _) => &style.item_0,
+                (1, Some((0, true))) => &style.item_01,
+                (1, Some((0, false))) => &style.item_x1,
+                (1, _) => &style.item_1,
+                (_, Some((1, true))) => &style.item_12,
+                (_, Some((1, false))) => &style.item_x2,
+                (_, _) => &style.item_2,
+            };
+
+            out.push_str(item);
+            out.push_str(&level.display);
+
+            let has_pages = depth == levels.len() - 1 && !pages.is_empty();
+
+            if has_pages {
+                out.push_str(match depth {
+                    0 => &style.delim_0,
+                    1 => &style.delim_1,
+                    _ => &style.delim_2,
+                });
+                out.push_str(&pages.join(&style.delim_n));
+                out.push_str(&style.delim_t);
+            }
+
+            prev_item = Some((depth, has_pages));
+        }
+
+        prev_levels = levels;
+        i = j;
+    }
+
+    out.push_str(&style.postamble);
+    out
+}
+
+enum PageItem<'a> {
+    Single(&'a Page, Option<&'a str>),
+    Range(&'a Page, &'a Page, Option<&'a str>),
+}
+
+/// Format the page list of a single key, merging explicit and implicit page
+/// ranges.
+fn format_pages(entries: &[Entry], style: &Style, file: &str, log: &mut Transcript) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut open: Option<&Entry> = None;
+
+    for entry in entries {
+        let encap = entry.encap.as_deref();
+
+        match entry.range {
+            RangeMark::Open => {
+                if open.is_some() {
+                    log.warning(file, entry.line, "range opened inside another range");
+                } else {
+                    open = Some(entry);
+                }
+            }
+
+            RangeMark::Close => match open.take() {
+                Some(start) => {
+                    if start.encap != entry.encap {
+                        log.warning(
+                            file,
+                            entry.line,
+                            "range closed with a different encapsulator",
+                        );
+                    }
+
+                    items.push(PageItem::Range(
+                        &start.page,
+                        &entry.page,
+                        start.encap.as_deref(),
+                    ));
+                }
+                None => {
+                    log.warning(file, entry.line, "range closed but never opened");
+                    items.push(PageItem::Single(&entry.page, encap));
+                }
+            },
+
+            RangeMark::Single => {
+                // Pages within an explicit range are absorbed into it.
+                match open {
+                    Some(start) if start.encap == entry.encap => {}
+                    _ => items.push(PageItem::Single(&entry.page, encap)),
+                }
+            }
+        }
+    }
+
+    if let Some(start) = open {
+        log.warning(file, start.line, "range opened but never closed");
+        items.push(PageItem::Single(&start.page, start.encap.as_deref()));
+    }
+
+    let wrap = |text: String, encap: Option<&str>| -> String {
+        match encap {
+            Some(e) => format!(
+                "{}{}{}{}{}",
+                style.encap_prefix, e, style.encap_infix, text, style.encap_suffix
+            ),
+            None => text,
+        }
+    };
+
+    let mut pages = Vec::new();
+    let mut k = 0;
+
+    while k < items.len() {
+        match items[k] {
+            PageItem::Range(first, last, encap) => {
+                let text = if first.text == last.text {
+                    first.text.clone()
+                } else {
+                    format!("{}{}{}", first.text, style.delim_r, last.text)
+                };
+                pages.push(wrap(text, encap));
+                k += 1;
+            }
+
+            PageItem::Single(first, encap) => {
+                // Gather a run of consecutive pages, skipping duplicates.
+                let mut last = first;
+                let mut count = 1;
+                k += 1;
+
+                while let Some(&PageItem::Single(next, next_encap)) = items.get(k) {
+                    if next_encap != encap {
+                        break;
+                    }
+
+                    if next.parts == last.parts {
+                        k += 1;
+                    } else if next.follows(last) {
+                        last = next;
+                        count += 1;
+                        k += 1;
+                    } else {
+                        break;
+                    }
+                }
+
+                let text = match count {
+                    1 => first.text.clone(),
+                    2 if !style.suffix_2p.is_empty() => {
+                        format!("{}{}", first.text, style.suffix_2p)
+                    }
+                    2 => {
+                        pages.push(wrap(first.text.clone(), encap));
+                        last.text.clone()
+                    }
+                    3 if !style.suffix_3p.is_empty() => {
+                        format!("{}{}", first.text, style.suffix_3p)
+                    }
+                    _ if !style.suffix_mp.is_empty() => {
+                        format!("{}{}", first.text, style.suffix_mp)
+                    }
+                    _ => format!("{}{}{}", first.text, style.delim_r, last.text),
+                };
+
+                pages.push(wrap(text, encap));
+            }
+        }
+    }
+
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style_from(text: &str) -> Style {
+        let mut style = Style::default();
+        let mut log = Transcript::default();
+        style.parse(text, "test.ist", &mut log);
+        assert_eq!(log.n_errors, 0, "{}", log.text);
+        style
+    }
+
+    fn make_index(idx: &str, style: &Style, letter_ordering: bool) -> String {
+        let mut log = Transcript::default();
+        let (mut entries, n_rejected) = scan_entries(idx, style, "test.idx", &mut log);
+        assert_eq!(n_rejected, 0, "{}", log.text);
+        sort_entries(&mut entries, letter_ordering);
+        generate(&entries, style, "test.idx", &mut log)
+    }
+
+    #[test]
+    fn style_parse() {
+        let mut style = Style::default();
+        let mut log = Transcript::default();
+
+        let (n_set, n_ignored) = style.parse(
+            r#"% glossaries-like settings
+keyword "\\glossaryentry"
+actual '?'
+encap '|'
+headings_flag 1
+delim_0 "\t"
+preamble "\\begin{x}\n"
+line_max 80
+bogus 1
+level 5
+"#,
+            "test.ist",
+            &mut log,
+        );
+
+        assert_eq!((n_set, n_ignored), (7, 2));
+        assert_eq!(log.n_errors, 2);
+        assert!(log.text.contains("line = 9"));
+        assert!(log.text.contains("unknown specifier `bogus`"));
+        assert!(log.text.contains("line = 10"));
+        assert!(log.text.contains("`level` must be a character"));
+
+        assert_eq!(style.keyword, "\\glossaryentry");
+        assert_eq!(style.actual, '?');
+        assert_eq!(style.encap, '|');
+        assert_eq!(style.headings_flag, 1);
+        assert_eq!(style.delim_0, "\t");
+        assert_eq!(style.preamble, "\\begin{x}\n");
+        assert_eq!(style.level, '!');
+    }
+
+    #[test]
+    fn style_parse_malformed() {
+        let mut style = Style::default();
+        let mut log = Transcript::default();
+
+        let (n_set, n_ignored) = style.parse(
+            "\"stray\"\nquote 'ab'\ndelim_r \"-\"\npreamble \"open\n",
+            "test.ist",
+            &mut log,
+        );
+
+        // Bad settings are skipped without affecting the good ones.
+        assert_eq!((n_set, n_ignored), (1, 3));
+        assert!(log.text.contains("unexpected character `\"`"));
+        assert!(log.text.contains("malformed character"));
+        assert!(log.text.contains("unterminated string"));
+        assert_eq!(style.quote, '"');
+        assert_eq!(style.delim_r, "-");
+        assert_eq!(style.preamble, "\\begin{theindex}\n");
+    }
+
+    #[test]
+    fn scan() {
+        let style = Style::default();
+        let mut log = Transcript::default();
+
+        let (entries, n_rejected) = scan_entries(
+            r#"\indexentry{alpha}{1}
+\indexentry{beta@\textbf{beta}|textit}{2}
+\indexentry{a"!b}{3}
+\indexentry{gamma!delta|(}{iv}
+\indexentry{broken}{x y}
+\indexentry{missing}
+\indexentry{a!b!c!d}{1}
+\indexentry{@x}{1}
+"#,
+            &style,
+            "test.idx",
+            &mut log,
+        );
+
+        assert_eq!(n_rejected, 4);
+        assert!(log.text.contains("illegal page number `x y`"));
+        assert!(log.text.contains("line = 6"));
+        assert!(log.text.contains("malformed or missing second argument"));
+        assert!(log.text.contains("too many levels"));
+        assert!(log.text.contains("empty index key"));
+
+        assert_eq!(entries.len(), 4);
+
+        let level = |sort: &str, display: &str| Level {
+            sort: sort.to_owned(),
+            display: display.to_owned(),
+        };
+
+        assert_eq!(entries[0].levels, vec![level("alpha", "alpha")]);
+        assert_eq!(entries[0].page.parts, vec![(1, 1)]);
+        assert_eq!(entries[0].encap, None);
+        assert_eq!(entries[0].range, RangeMark::Single);
+        assert_eq!(entries[0].line, 1);
+
+        assert_eq!(entries[1].levels, vec![level("beta", "\\textbf{beta}")]);
+        assert_eq!(entries[1].encap.as_deref(), Some("textit"));
+        assert_eq!(entries[1].line, 2);
+
+        assert_eq!(entries[2].levels, vec![level("a!b", "a!b")]);
+
+        assert_eq!(
+            entries[3].levels,
+            vec![level("gamma", "gamma"), level("delta", "delta")]
+        );
+        assert_eq!(entries[3].page.parts, vec![(0, 4)]);
+        assert_eq!(entries[3].encap, None);
+        assert_eq!(entries[3].range, RangeMark::Open);
+    }
+
+    #[test]
+    fn page_numbers() {
+        let style = Style::default();
+
+        let parts = |text: &str| Page::parse(text, &style).map(|p| p.parts);
+        assert_eq!(parts("12"), Some(vec![(1, 12)]));
+        assert_eq!(parts("xiv"), Some(vec![(0, 14)]));
+        assert_eq!(parts("XIV"), Some(vec![(3, 14)]));
+        assert_eq!(parts("e"), Some(vec![(2, 5)]));
+        assert_eq!(parts("B-7"), Some(vec![(4, 2), (1, 7)]));
+        assert_eq!(parts("Xv"), None);
+        assert_eq!(parts(""), None);
+
+        let page = |text: &str| Page::parse(text, &style).unwrap();
+        assert!(page("B-8").follows(&page("B-7")));
+        assert!(!page("C-8").follows(&page("B-7")));
+        assert!(!page("viii").follows(&page("7")));
+    }
+
+    #[test]
+    fn page_ranges() {
+        let ind = make_index(
+            r"\indexentry{foo}{1}
+\indexentry{foo}{2}
+\indexentry{foo}{3}
+\indexentry{foo}{5}
+\indexentry{foo}{5}
+\indexentry{foo}{8}
+\indexentry{foo}{7}
+\indexentry{foo|(}{10}
+\indexentry{foo}{11}
+\indexentry{foo|)}{12}
+\indexentry{foo|textbf}{20}
+",
+            &Style::default(),
+            false,
+        );
+
+        assert_eq!(
+            ind,
+            "\\begin{theindex}\n\
+             \n  \\item foo, 1--3, 5, 7, 8, 10--12, \\textbf{20}\
+             \n\n\\end{theindex}\n"
+        );
+
+        // With the suffix settings, runs of pages are abbreviated instead.
+        let style = style_from(
+            r#"suffix_2p "f."
+suffix_mp "ff."
+"#,
+        );
+        let ind = make_index(
+            r"\indexentry{foo}{1}
+\indexentry{foo}{2}
+\indexentry{foo}{4}
+\indexentry{foo}{5}
+\indexentry{foo}{6}
+",
+            &style,
+            false,
+        );
+        assert!(ind.contains("\\item foo, 1f., 4ff."));
+    }
+
+    #[test]
+    fn ordering_and_groups() {
+        let style = style_from(
+            r#"headings_flag 1
+heading_prefix "\n  \\indexgroup{"
+heading_suffix "}"
+"#,
+        );
+        let idx = r"\indexentry{b c}{1}
+\indexentry{ba}{2}
+\indexentry{Apple}{3}
+\indexentry{apple!core}{4}
+\indexentry{10}{5}
+\indexentry{9}{6}
+\indexentry{*star}{7}
+";
+
+        assert_eq!(
+            make_index(idx, &style, false),
+            "\\begin{theindex}\n\
+             \n  \\indexgroup{Symbols}\
+             \n  \\item *star, 7\
+             \n\n  \\indexspace\n\
+             \n  \\indexgroup{Numbers}\
+             \n  \\item 9, 6\
+             \n  \\item 10, 5\
+             \n\n  \\indexspace\n\
+             \n  \\indexgroup{A}\
+             \n  \\item Apple, 3\
+             \n  \\item apple\
+             \n    \\subitem core, 4\
+             \n\n  \\indexspace\n\
+             \n  \\indexgroup{B}\
+             \n  \\item b c, 1\
+             \n  \\item ba, 2\
+             \n\n\\end{theindex}\n"
+        );
+
+        // With letter ordering, spaces don't count.
+        let ind = make_index(idx, &style, true);
+        assert!(ind.find("\\item ba,").unwrap() < ind.find("\\item b c,").unwrap());
+    }
+}
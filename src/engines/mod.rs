@@ -20,7 +20,7 @@ use flate2::{Compression, GzBuilder};
 use lazy_static::lazy_static;
 use md5::{Digest, Md5};
 use std::borrow::Cow;
-use std::ffi::{CStr, OsStr, OsString};
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::io::{Read, SeekFrom, Write};
 use std::path::Path;
 use std::sync::Mutex;
@@ -28,21 +28,35 @@ use std::{io, ptr, slice};
 
 use crate::digest::DigestData;
 use crate::errors::{Error, ErrorKind, Result};
-use crate::io::{InputFeatures, InputHandle, InputOrigin, IoProvider, OpenResult, OutputHandle};
-use crate::status::{MessageKind, StatusBackend};
-use crate::{tt_error, tt_warning};
+use crate::io::{
+    InputFeatures, InputHandle, InputOrigin, IoProvider, IoStack, OpenResult, OutputHandle,
+};
+use crate::status::{MessageKind, SourceDiagnostic, StatusBackend};
+use crate::{ctry, tt_error, tt_warning};
+
+use self::shell_escape::{ShellEscapeHandler, ShellEscapeOutcome};
 
 // Public sub-modules and reexports.
 
+pub mod biber;
 pub mod bibtex;
+pub mod makeindex;
+pub mod shell_escape;
 pub mod spx2html;
 pub mod tex;
+pub mod worker;
 pub mod xdvipdfmx;
 
+pub use self::biber::BiberEngine;
 pub use self::bibtex::BibtexEngine;
+pub use self::makeindex::MakeindexEngine;
+pub use self::shell_escape::ShellEscapePolicy;
 pub use self::spx2html::Spx2HtmlEngine;
 pub use self::tex::TexEngine;
-pub use self::xdvipdfmx::XdvipdfmxEngine;
+pub use self::xdvipdfmx::{
+    PdfConformance, PdfDocInfo, PdfEncryption, PdfOptions, PdfOutputIntent, PdfPermissions,
+    XdvipdfmxEngine,
+};
 
 #[cfg(unix)]
 fn osstr_from_cstr(s: &CStr) -> Cow<OsStr> {
@@ -108,6 +122,60 @@ impl NoopIoEventBackend {
 
 impl IoEventBackend for NoopIoEventBackend {}
 
+/// Read an entire input through the I/O stack, returning None if it doesn't
+/// exist. This is for engines implemented in Rust, which have no need for
+/// the streaming interface used by the C/C++ engines.
+pub(crate) fn read_whole_input(
+    io: &mut IoStack,
+    events: &mut dyn IoEventBackend,
+    status: &mut dyn StatusBackend,
+    name: &str,
+) -> Result<Option<Vec<u8>>> {
+    let mut ih = match io.input_open_name(OsStr::new(name), status) {
+        OpenResult::Ok(ih) => ih,
+        OpenResult::NotAvailable => {
+            events.input_not_available(OsStr::new(name));
+            return Ok(None);
+        }
+        OpenResult::Err(e) => return Err(e),
+    };
+
+    events.input_opened(ih.name(), ih.origin());
+
+    let mut data = Vec::new();
+    ctry!(ih.read_to_end(&mut data); "couldn't read \"{}\"", name);
+
+    let (name, digest) = ih.into_name_digest();
+    events.input_closed(name, digest);
+    Ok(Some(data))
+}
+
+/// Write an output through the I/O stack in one go, so that it lands in the
+/// memory layer and gets tracked like any other engine output.
+pub(crate) fn write_whole_output(
+    io: &mut IoStack,
+    events: &mut dyn IoEventBackend,
+    status: &mut dyn StatusBackend,
+    name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let mut oh = match io.output_open_name(OsStr::new(name)) {
+        OpenResult::Ok(oh) => oh,
+        OpenResult::NotAvailable => {
+            tt_warning!(status, "nowhere to save output file \"{}\"", name);
+            return Ok(());
+        }
+        OpenResult::Err(e) => return Err(e),
+    };
+
+    events.output_opened(oh.name());
+    ctry!(oh.write_all(data).and_then(|_| oh.flush()); "couldn't write \"{}\"", name);
+
+    let (name, digest) = oh.into_name_digest();
+    events.output_closed(name, digest);
+    Ok(())
+}
+
 // Now, the private interfaces for executing various engines implemented in C/C++.
 
 // The C/C++ engines currently maintain global state, which means that we can
@@ -115,7 +183,8 @@ impl IoEventBackend for NoopIoEventBackend {}
 // ensures that this happens. We use the same lock for all C/C++ engines. It's
 // possible that maybe we could run (e.g.) XeTeX and xdvipdfmx at the same
 // time and they won't stomp on each other's toes, but I don't want to risk
-// it.
+// it. If the program has called `worker::activate()`, engines run in their
+// own processes instead, and the lock isn't needed.
 lazy_static! {
     static ref ENGINE_LOCK: Mutex<u8> = Mutex::new(0u8);
 }
@@ -133,6 +202,7 @@ pub struct ExecutionState<'a> {
     input_handles: Vec<Box<InputHandle>>,
     #[allow(clippy::vec_box)]
     output_handles: Vec<Box<OutputHandle>>,
+    shell_escape: Option<Box<dyn ShellEscapeHandler>>,
 }
 
 impl<'a> ExecutionState<'a> {
@@ -147,9 +217,16 @@ impl<'a> ExecutionState<'a> {
             status,
             output_handles: Vec::new(),
             input_handles: Vec::new(),
+            shell_escape: None,
         }
     }
 
+    /// Allow the engine to run `\write18` commands using the given handler.
+    fn with_shell_escape(mut self, handler: Option<Box<dyn ShellEscapeHandler>>) -> Self {
+        self.shell_escape = handler;
+        self
+    }
+
     // Helpers.
 
     fn input_open_name_format(
@@ -377,6 +454,15 @@ impl<'a> ExecutionState<'a> {
         rv
     }
 
+    fn shell_escape(&mut self, command: &str) -> ShellEscapeOutcome {
+        match self.shell_escape {
+            Some(ref mut handler) => {
+                handler.shell_escape(command, self.io, self.events, self.status)
+            }
+            None => ShellEscapeOutcome::Disallowed,
+        }
+    }
+
     fn input_open(&mut self, name: &OsStr, format: FileFormat, is_gz: bool) -> *mut InputHandle {
         let ih = match self.input_open_name_format_gz(name, format, is_gz) {
             OpenResult::Ok(ih) => ih,
@@ -549,23 +635,32 @@ extern "C" {
 pub struct Diagnostic {
     message: String,
     kind: MessageKind,
+    file: Option<String>,
+    line: Option<u32>,
+    context: Vec<String>,
+}
+
+impl Diagnostic {
+    fn new(kind: MessageKind) -> Diagnostic {
+        Diagnostic {
+            message: String::new(),
+            kind,
+            file: None,
+            line: None,
+            context: Vec::new(),
+        }
+    }
 }
 
 #[no_mangle]
 pub extern "C" fn diag_warn_begin() -> *mut Diagnostic {
-    let warning = Box::new(Diagnostic {
-        message: String::new(),
-        kind: MessageKind::Warning,
-    });
+    let warning = Box::new(Diagnostic::new(MessageKind::Warning));
     Box::into_raw(warning)
 }
 
 #[no_mangle]
 pub extern "C" fn diag_error_begin() -> *mut Diagnostic {
-    let warning = Box::new(Diagnostic {
-        message: String::new(),
-        kind: MessageKind::Error,
-    });
+    let warning = Box::new(Diagnostic::new(MessageKind::Error));
     Box::into_raw(warning)
 }
 
@@ -573,8 +668,13 @@ pub extern "C" fn diag_error_begin() -> *mut Diagnostic {
 pub extern "C" fn diag_finish(es: &mut ExecutionState, diag: *mut Diagnostic) {
     let rdiag = unsafe { Box::from_raw(diag as *mut Diagnostic) };
 
-    es.status
-        .report(rdiag.kind, format_args!("{}", rdiag.message), None);
+    es.status.report_diagnostic(&SourceDiagnostic {
+        kind: rdiag.kind,
+        message: rdiag.message,
+        file: rdiag.file,
+        line: rdiag.line,
+        context: rdiag.context,
+    });
 }
 
 #[no_mangle]
@@ -584,6 +684,44 @@ pub extern "C" fn diag_append(diag: &mut Diagnostic, text: *const libc::c_char)
     diag.message.push_str(&rtext.to_string_lossy());
 }
 
+#[no_mangle]
+pub extern "C" fn diag_set_location(
+    diag: &mut Diagnostic,
+    file: *const libc::c_char,
+    line: libc::c_int,
+) {
+    let rfile = unsafe { CStr::from_ptr(file) };
+
+    diag.file = Some(rfile.to_string_lossy().into_owned());
+    diag.line = if line > 0 { Some(line as u32) } else { None };
+}
+
+/// Attach the text of the source line being processed to a diagnostic.
+///
+/// The line is given as a buffer of Unicode scalar values, as stored by the
+/// TeX engine. It is split at `loc`, the position up to which the engine had
+/// read the line, yielding two context lines in the same way that TeX's
+/// `show_context` does.
+#[no_mangle]
+pub extern "C" fn diag_set_source_line(
+    diag: &mut Diagnostic,
+    text: *const i32,
+    len: libc::size_t,
+    loc: libc::size_t,
+) {
+    let rtext = unsafe { slice::from_raw_parts(text, len) };
+    let loc = loc.min(len);
+
+    let decode = |chars: &[i32]| -> String {
+        chars
+            .iter()
+            .map(|c| std::char::from_u32(*c as u32).unwrap_or(std::char::REPLACEMENT_CHARACTER))
+            .collect()
+    };
+
+    diag.context = vec![decode(&rtext[..loc]), decode(&rtext[loc..])];
+}
+
 #[no_mangle]
 pub extern "C" fn issue_warning(es: &mut ExecutionState, text: *const libc::c_char) {
     let rtext = unsafe { CStr::from_ptr(text) };
@@ -627,6 +765,26 @@ pub extern "C" fn get_data_md5(data: *const u8, len: libc::size_t, digest: *mut
     0
 }
 
+/// Run a `\write18` command, returning 0 if it was executed successfully, 1
+/// if it was disallowed by the shell-escape policy, and 2 if it failed. The
+/// command text is given as UTF-16 code units, as stored in TeX's string
+/// pool.
+#[no_mangle]
+pub extern "C" fn shell_escape(
+    es: &mut ExecutionState,
+    cmd: *const u16,
+    len: libc::size_t,
+) -> libc::c_int {
+    let rcmd = unsafe { slice::from_raw_parts(cmd, len) };
+    let rcmd = String::from_utf16_lossy(rcmd);
+
+    match es.shell_escape(&rcmd) {
+        ShellEscapeOutcome::Executed => 0,
+        ShellEscapeOutcome::Disallowed => 1,
+        ShellEscapeOutcome::Failed => 2,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn output_open(
     es: &mut ExecutionState,
@@ -843,6 +1001,210 @@ impl TectonicBridgeApi<'_> {
     }
 }
 
+/// A request to run one of the C/C++ engines, with its settings reduced to
+/// plain data so that the run can be handed off to a worker process.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum EngineJob {
+    Tex {
+        format_name: String,
+        input_name: String,
+        build_date: u64,
+        halt_on_error: bool,
+        initex: bool,
+        preload_format: bool,
+        synctex: bool,
+        semantic_pagination: bool,
+        shell_escape: bool,
+    },
+
+    Bibtex {
+        aux_name: String,
+        min_crossrefs: i32,
+    },
+
+    Xdvipdfmx {
+        dvi_name: String,
+        pdf_name: String,
+        paperspec: String,
+        enable_compression: bool,
+        deterministic_tags: bool,
+        build_date: u64,
+        options: Box<xdvipdfmx::PdfOptions>,
+    },
+}
+
+/// The raw result of an engine run: the engine's return code, and whatever
+/// was left in the C code's error-message buffer. It's up to the caller to
+/// interpret these.
+#[derive(Clone, Debug)]
+pub(crate) struct EngineOutcome {
+    pub code: libc::c_int,
+    pub error_message: String,
+}
+
+impl EngineJob {
+    /// Run the engine, either in a worker process or, if workers haven't
+    /// been activated, in this process while holding the engine lock.
+    pub(crate) fn run(
+        &self,
+        io: &mut dyn IoProvider,
+        events: &mut dyn IoEventBackend,
+        status: &mut dyn StatusBackend,
+        shell_escape: Option<Box<dyn ShellEscapeHandler>>,
+    ) -> Result<EngineOutcome> {
+        if worker::is_active() {
+            return worker::run_job(self, io, events, status, shell_escape);
+        }
+
+        let _guard = ENGINE_LOCK.lock().unwrap();
+        self.invoke(io, events, status, shell_escape)
+    }
+
+    /// Run the engine in the current process. The caller is responsible for
+    /// making sure that no other engine is running in this process at the
+    /// same time.
+    fn invoke(
+        &self,
+        io: &mut dyn IoProvider,
+        events: &mut dyn IoEventBackend,
+        status: &mut dyn StatusBackend,
+        shell_escape: Option<Box<dyn ShellEscapeHandler>>,
+    ) -> Result<EngineOutcome> {
+        let mut state = ExecutionState::new(io, events, status).with_shell_escape(shell_escape);
+        let bridge = TectonicBridgeApi::new(&mut state);
+
+        let code = match *self {
+            EngineJob::Tex {
+                ref format_name,
+                ref input_name,
+                build_date,
+                halt_on_error,
+                initex,
+                preload_format,
+                synctex,
+                semantic_pagination,
+                shell_escape,
+            } => {
+                let cformat = CString::new(format_name.as_str())?;
+                let cinput = CString::new(input_name.as_str())?;
+
+                // initialize globals
+
+                let globals: [(&[u8], bool); 6] = [
+                    (b"shell_escape_enabled\0", shell_escape),
+                    (b"halt_on_error_p\0", halt_on_error),
+                    (b"in_initex_mode\0", initex),
+                    (b"initex_preload_format\0", preload_format),
+                    (b"synctex_enabled\0", synctex),
+                    (b"semantic_pagination_enabled\0", semantic_pagination),
+                ];
+
+                for (name, value) in &globals {
+                    unsafe {
+                        tt_xetex_set_int_variable(name.as_ptr() as _, *value as libc::c_int);
+                    }
+                }
+
+                unsafe {
+                    tex_simple_main(
+                        &bridge,
+                        cformat.as_ptr(),
+                        cinput.as_ptr(),
+                        build_date as libc::time_t,
+                    )
+                }
+            }
+
+            EngineJob::Bibtex {
+                ref aux_name,
+                min_crossrefs,
+            } => {
+                let caux = CString::new(aux_name.as_str())?;
+                let config = bibtex::BibtexConfig { min_crossrefs };
+
+                unsafe { bibtex_simple_main(&bridge, &config, caux.as_ptr()) }
+            }
+
+            EngineJob::Xdvipdfmx {
+                ref dvi_name,
+                ref pdf_name,
+                ref paperspec,
+                enable_compression,
+                deterministic_tags,
+                build_date,
+                ref options,
+            } => {
+                let cdvi = CString::new(dvi_name.as_str())?;
+                let cpdf = CString::new(pdf_name.as_str())?;
+                let cpaperspec = CString::new(paperspec.as_str())?;
+                let default_encryption = xdvipdfmx::PdfEncryption::default();
+                let encryption = options.encryption.as_ref().unwrap_or(&default_encryption);
+                let cowner = CString::new(encryption.owner_password.as_str())?;
+                let cuser = CString::new(encryption.user_password.as_str())?;
+                let intent = options.effective_output_intent();
+                let cprofile = CString::new(intent.as_ref().map_or("", |i| i.profile.as_str()))?;
+                let cidentifier =
+                    CString::new(intent.as_ref().map_or("", |i| i.identifier.as_str()))?;
+                let cdocinfo = CString::new(options.info.to_pdf_dict())?;
+                let clanguage = CString::new(options.info.language.as_deref().unwrap_or(""))?;
+                let config = xdvipdfmx::XdvipdfmxConfig {
+                    paperspec: cpaperspec.as_ptr(),
+                    pdf_version_major: options.version.0 as i32,
+                    pdf_version_minor: options.version.1 as i32,
+                    compression_level: options.compression_level as i32,
+                    annot_grow: options.annot_grow,
+                    bookmark_open: options.bookmark_open,
+                    mag: options.magnification,
+                    font_dpi: options.font_dpi as i32,
+                    enable_thumbnail: options.thumbnails,
+                    pdfdecimaldigits: options.decimal_digits as i32,
+                    x_offset: options.x_offset,
+                    y_offset: options.y_offset,
+                    enable_encryption: options.encryption.is_some(),
+                    // Without encryption, these are the defaults that apply
+                    // if the document asks for it with a `pdf:encrypt`
+                    // special.
+                    key_bits: options
+                        .encryption
+                        .as_ref()
+                        .map_or(40, |e| e.key_bits as i32),
+                    permission: encryption.permissions.to_bits() as i32,
+                    owner_password: cowner.as_ptr(),
+                    user_password: cuser.as_ptr(),
+                    conformance: options.conformance.map_or(0, |c| c.to_c()),
+                    output_intent_profile: cprofile.as_ptr(),
+                    output_intent_identifier: cidentifier.as_ptr(),
+                    docinfo: cdocinfo.as_ptr(),
+                    language: clanguage.as_ptr(),
+                };
+
+                unsafe {
+                    dvipdfmx_simple_main(
+                        &bridge,
+                        &config,
+                        cdvi.as_ptr(),
+                        cpdf.as_ptr(),
+                        enable_compression,
+                        deterministic_tags,
+                        build_date as libc::time_t,
+                    )
+                }
+            }
+        };
+
+        let error_message = unsafe {
+            CStr::from_ptr(tt_get_error_message())
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        Ok(EngineOutcome {
+            code,
+            error_message,
+        })
+    }
+}
+
 // Finally, some support -- several of the C API functions pass arguments that
 // are "file format" enumerations. This code bridges the two. See the
 // `tt_input_format_type` enum in <tectonic/core-bridge.h>.
@@ -927,3 +1289,29 @@ fn c_format_to_rust(format: libc::c_int) -> Option<FileFormat> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnostic_location_and_context() {
+        let mut diag = Diagnostic::new(MessageKind::Error);
+        let file = CString::new("main.tex").unwrap();
+        diag_set_location(&mut diag, file.as_ptr(), 3);
+        assert_eq!(diag.file.as_deref(), Some("main.tex"));
+        assert_eq!(diag.line, Some(3));
+
+        // Line numbers that TeX doesn't know come through as zero.
+        diag_set_location(&mut diag, file.as_ptr(), 0);
+        assert_eq!(diag.line, None);
+
+        let text: Vec<i32> = "Oops \\foo ü".chars().map(|c| c as i32).collect();
+        diag_set_source_line(&mut diag, text.as_ptr(), text.len(), 9);
+        assert_eq!(diag.context, vec!["Oops \\foo", " ü"]);
+
+        // An out-of-range position puts the whole line before the split.
+        diag_set_source_line(&mut diag, text.as_ptr(), text.len(), 100);
+        assert_eq!(diag.context, vec!["Oops \\foo ü", ""]);
+    }
+}
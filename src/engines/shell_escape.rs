@@ -0,0 +1,487 @@
+// src/engines/shell_escape.rs -- support for TeX's \write18 feature
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Support for running external commands through TeX's `\write18` primitive.
+//!
+//! Traditional TeX engines let documents run arbitrary programs by writing to
+//! stream 18 — "shell escape". That's a big hole in the reproducibility (and
+//! security) of a build, so Tectonic only ever does it under an explicit
+//! [`ShellEscapePolicy`]. Commands are run inside a fresh temporary directory
+//! that is populated with the files of the in-memory I/O layer; any files
+//! that the command creates or modifies there are copied back into that layer
+//! afterwards so that the TeX engine can read them.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Component, Path};
+use std::process::Command;
+use std::rc::Rc;
+use tempfile::TempDir;
+
+use super::IoEventBackend;
+use crate::errors::Result;
+use crate::io::memory::MemoryFileCollection;
+use crate::io::{IoProvider, OpenResult};
+use crate::status::StatusBackend;
+use crate::{ctry, errmsg, tt_warning};
+
+/// Which `\write18` commands a TeX engine is allowed to run.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum ShellEscapePolicy {
+    /// Shell escape is disabled entirely. This is the default.
+    #[default]
+    Deny,
+
+    /// Only commands whose program name is in the list may be run. Such
+    /// commands are run directly, without the involvement of a shell, so
+    /// that shell metacharacters can't be used to sneak other programs in.
+    Allowlist(Vec<String>),
+
+    /// Any command may be run, through the system shell.
+    AllowAll,
+}
+
+impl ShellEscapePolicy {
+    /// Returns true if this policy may allow any commands to be run at all.
+    pub fn is_enabled(&self) -> bool {
+        *self != ShellEscapePolicy::Deny
+    }
+}
+
+/// The outcome of an attempt to run a `\write18` command.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ShellEscapeOutcome {
+    /// The command was run and exited successfully.
+    Executed,
+
+    /// The command was not run because the policy forbids it.
+    Disallowed,
+
+    /// The command was run but failed, or couldn't be started.
+    Failed,
+}
+
+/// The files produced by a shell-escape command, and any diagnostic output
+/// it printed.
+pub(crate) struct ShellEscapeOutput {
+    pub outcome: ShellEscapeOutcome,
+    pub files: Vec<(OsString, Vec<u8>)>,
+    pub stderr: String,
+}
+
+/// Something that can service an engine's `\write18` requests.
+///
+/// Handlers are responsible for reporting problems through `status` and for
+/// making the command's output files available through `io`, announcing them
+/// to `events` as they go.
+pub(crate) trait ShellEscapeHandler {
+    fn shell_escape(
+        &mut self,
+        command: &str,
+        io: &mut dyn IoProvider,
+        events: &mut dyn IoEventBackend,
+        status: &mut dyn StatusBackend,
+    ) -> ShellEscapeOutcome;
+}
+
+/// Runs `\write18` commands on behalf of an engine.
+pub(crate) struct ShellEscapeRunner {
+    policy: ShellEscapePolicy,
+    files: Rc<RefCell<MemoryFileCollection>>,
+}
+
+impl ShellEscapeRunner {
+    pub fn new(policy: ShellEscapePolicy, files: Rc<RefCell<MemoryFileCollection>>) -> Self {
+        ShellEscapeRunner { policy, files }
+    }
+
+    /// Run a command according to the policy.
+    ///
+    /// An `Err` return indicates a problem in setting up or tearing down the
+    /// working directory; a command that fails is reported through
+    /// [`ShellEscapeOutcome::Failed`].
+    pub fn run(&self, command: &str) -> Result<ShellEscapeOutput> {
+        let mut cmd = match self.policy {
+            ShellEscapePolicy::Deny => return Ok(ShellEscapeOutput::disallowed()),
+
+            ShellEscapePolicy::Allowlist(ref allowed) => {
+                let words = split_command(command);
+
+                match words.split_first() {
+                    Some((program, args)) if allowed.iter().any(|a| a == program) => {
+                        let mut cmd = Command::new(program);
+                        cmd.args(args);
+                        cmd
+                    }
+                    _ => return Ok(ShellEscapeOutput::disallowed()),
+                }
+            }
+
+            ShellEscapePolicy::AllowAll => shell_command(command),
+        };
+
+        let work_dir =
+            ctry!(TempDir::new(); "couldn't create a directory for shell-escape commands");
+        let seeded = self.seed(work_dir.path())?;
+
+        let (outcome, stderr) = match cmd.current_dir(work_dir.path()).output() {
+            Ok(output) => {
+                let outcome = if output.status.success() {
+                    ShellEscapeOutcome::Executed
+                } else {
+                    ShellEscapeOutcome::Failed
+                };
+                (
+                    outcome,
+                    String::from_utf8_lossy(&output.stderr).into_owned(),
+                )
+            }
+
+            Err(e) => (ShellEscapeOutcome::Failed, e.to_string()),
+        };
+
+        let mut files = Vec::new();
+        collect_changed(work_dir.path(), work_dir.path(), &seeded, &mut files)?;
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(ShellEscapeOutput {
+            outcome,
+            files,
+            stderr,
+        })
+    }
+
+    /// Copy the in-memory files into the working directory, returning the
+    /// contents of what was written so that changes can be detected later.
+    fn seed(&self, dir: &Path) -> Result<HashMap<OsString, Vec<u8>>> {
+        let mut seeded = HashMap::new();
+
+        for (name, info) in self.files.borrow().iter() {
+            // Skip the stdout capture and anything that would escape the
+            // working directory.
+            let rel = Path::new(name);
+
+            if name.is_empty() || !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+                continue;
+            }
+
+            let path = dir.join(rel);
+
+            if let Some(parent) = path.parent() {
+                ctry!(fs::create_dir_all(parent); "couldn't create directory \"{}\"", parent.display());
+            }
+
+            let mut f = ctry!(File::create(&path); "couldn't create file \"{}\"", path.display());
+            ctry!(f.write_all(&info.data); "couldn't write file \"{}\"", path.display());
+            seeded.insert(name.clone(), info.data.clone());
+        }
+
+        Ok(seeded)
+    }
+}
+
+impl ShellEscapeHandler for ShellEscapeRunner {
+    fn shell_escape(
+        &mut self,
+        command: &str,
+        io: &mut dyn IoProvider,
+        events: &mut dyn IoEventBackend,
+        status: &mut dyn StatusBackend,
+    ) -> ShellEscapeOutcome {
+        let output = match self.run(command) {
+            Ok(o) => o,
+            Err(e) => {
+                tt_warning!(status, "couldn't run shell-escape command `{}`", command; e);
+                return ShellEscapeOutcome::Failed;
+            }
+        };
+
+        match output.outcome {
+            ShellEscapeOutcome::Disallowed => {
+                tt_warning!(
+                    status,
+                    "shell-escape command `{}` is not allowed by the current policy",
+                    command
+                );
+            }
+            ShellEscapeOutcome::Failed => {
+                tt_warning!(status, "shell-escape command `{}` failed", command);
+                let stderr = output.stderr.trim_end();
+                if !stderr.is_empty() {
+                    status.dump_error_logs(stderr.as_bytes());
+                }
+            }
+            ShellEscapeOutcome::Executed => {}
+        }
+
+        // Copy back whatever the command produced through the I/O stack, so
+        // that it lands in the memory layer and gets tracked like any other
+        // engine output.
+
+        for (name, data) in output.files {
+            let mut oh = match io.output_open_name(&name) {
+                OpenResult::Ok(oh) => oh,
+                OpenResult::NotAvailable => continue,
+                OpenResult::Err(e) => {
+                    tt_warning!(status, "open of output {} failed", name.to_string_lossy(); e);
+                    continue;
+                }
+            };
+
+            events.output_opened(oh.name());
+
+            if let Err(e) = oh.write_all(&data).and_then(|_| oh.flush()) {
+                tt_warning!(status, "write of output {} failed", name.to_string_lossy(); e.into());
+            }
+
+            let (name, digest) = oh.into_name_digest();
+            events.output_closed(name, digest);
+        }
+
+        output.outcome
+    }
+}
+
+impl ShellEscapeOutput {
+    fn disallowed() -> Self {
+        ShellEscapeOutput {
+            outcome: ShellEscapeOutcome::Disallowed,
+            files: Vec::new(),
+            stderr: String::new(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+/// Split a command line into words, honoring single and double quotes and
+/// backslash escapes in the manner of a POSIX shell. No other shell syntax
+/// is interpreted.
+fn split_command(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                if let Some(next) = chars.next() {
+                    word.push(next);
+                }
+                in_word = true;
+            }
+            (Some(_), c) => word.push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if in_word {
+        words.push(word);
+    }
+
+    words
+}
+
+/// Recursively find files under `dir` that are new or differ from what was
+/// seeded, naming them relative to `root` with forward slashes as TeX does.
+fn collect_changed(
+    root: &Path,
+    dir: &Path,
+    seeded: &HashMap<OsString, Vec<u8>>,
+    changed: &mut Vec<(OsString, Vec<u8>)>,
+) -> Result<()> {
+    let entries = ctry!(fs::read_dir(dir); "couldn't list directory \"{}\"", dir.display());
+
+    for entry in entries {
+        let entry = ctry!(entry; "couldn't list directory \"{}\"", dir.display());
+        let path = entry.path();
+        let file_type = ctry!(entry.file_type(); "couldn't examine \"{}\"", path.display());
+
+        if file_type.is_dir() {
+            collect_changed(root, &path, seeded, changed)?;
+            continue;
+        }
+
+        if !file_type.is_file() {
+            continue;
+        }
+
+        let rel = match path.strip_prefix(root) {
+            Ok(rel) => rel,
+            Err(_) => return Err(errmsg!("unexpected path \"{}\"", path.display())),
+        };
+
+        let mut name = OsString::new();
+
+        for (i, c) in rel.components().enumerate() {
+            if i > 0 {
+                name.push("/");
+            }
+            name.push(c.as_os_str());
+        }
+
+        let data = ctry!(fs::read(&path); "couldn't read file \"{}\"", path.display());
+
+        if seeded.get(&name) != Some(&data) {
+            changed.push((name, data));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::memory::MemoryFileInfo;
+
+    fn runner(policy: ShellEscapePolicy, files: &[(&str, &[u8])]) -> ShellEscapeRunner {
+        let files = files
+            .iter()
+            .map(|(name, data)| {
+                (
+                    OsString::from(name),
+                    MemoryFileInfo {
+                        data: data.to_vec(),
+                        unix_mtime: None,
+                    },
+                )
+            })
+            .collect();
+        ShellEscapeRunner::new(policy, Rc::new(RefCell::new(files)))
+    }
+
+    #[test]
+    fn split_quoting() {
+        assert_eq!(
+            split_command(r#"prog "b c" 'd e' f\ g "h\"i" 'j\k' l"m"n"#),
+            vec!["prog", "b c", "d e", "f g", "h\"i", "j\\k", "lmn"]
+        );
+        assert_eq!(split_command("  a \t b  "), vec!["a", "b"]);
+        assert_eq!(split_command(r#"a "" b"#), vec!["a", "", "b"]);
+
+        // Shell syntax is not interpreted.
+        assert_eq!(split_command("a;b | c"), vec!["a;b", "|", "c"]);
+    }
+
+    #[test]
+    fn split_empty() {
+        assert!(split_command("").is_empty());
+        assert!(split_command("   ").is_empty());
+    }
+
+    #[test]
+    fn allowlist_matches_program_name() {
+        let policy = ShellEscapePolicy::Allowlist(vec!["echo".to_owned()]);
+        let r = runner(policy, &[]);
+
+        for command in &["", "echoevil hi", "ech hi", "/bin/echo hi", "sh -c echo"] {
+            assert_eq!(
+                r.run(command).unwrap().outcome,
+                ShellEscapeOutcome::Disallowed,
+                "command: {:?}",
+                command
+            );
+        }
+
+        let r = runner(ShellEscapePolicy::Deny, &[]);
+        assert_eq!(
+            r.run("echo hi").unwrap().outcome,
+            ShellEscapeOutcome::Disallowed
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn allowlist_runs_without_shell() {
+        let policy = ShellEscapePolicy::Allowlist(vec!["echo".to_owned()]);
+        let r = runner(policy, &[]);
+        let output = r.run("'echo' hi; rm -rf x").unwrap();
+        assert_eq!(output.outcome, ShellEscapeOutcome::Executed);
+        assert!(output.files.is_empty());
+    }
+
+    #[test]
+    fn seed_skips_escaping_names() {
+        let r = runner(
+            ShellEscapePolicy::AllowAll,
+            &[
+                ("", b"stdout"),
+                ("a.tex", b"A"),
+                ("sub/b.tex", b"B"),
+                ("../evil.tex", b"E"),
+                ("/abs.tex", b"E"),
+            ],
+        );
+        let dir = TempDir::new().unwrap();
+        let seeded = r.seed(dir.path()).unwrap();
+
+        let mut names: Vec<_> = seeded.keys().cloned().collect();
+        names.sort();
+        assert_eq!(names, vec!["a.tex", "sub/b.tex"]);
+        assert_eq!(
+            fs::read(dir.path().join("sub").join("b.tex")).unwrap(),
+            b"B"
+        );
+        assert!(!dir.path().parent().unwrap().join("evil.tex").exists());
+    }
+
+    #[test]
+    fn changed_files() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("same.tex"), "A").unwrap();
+        fs::write(dir.path().join("sub").join("edited.tex"), "B2").unwrap();
+        fs::write(dir.path().join("sub").join("new.tex"), "C").unwrap();
+        fs::create_dir(dir.path().join("empty")).unwrap();
+
+        let mut seeded = HashMap::new();
+        seeded.insert(OsString::from("same.tex"), b"A".to_vec());
+        seeded.insert(OsString::from("sub/edited.tex"), b"B".to_vec());
+        seeded.insert(OsString::from("deleted.tex"), b"D".to_vec());
+
+        let mut changed = Vec::new();
+        collect_changed(dir.path(), dir.path(), &seeded, &mut changed).unwrap();
+        changed.sort();
+
+        assert_eq!(
+            changed,
+            vec![
+                (OsString::from("sub/edited.tex"), b"B2".to_vec()),
+                (OsString::from("sub/new.tex"), b"C".to_vec()),
+            ]
+        );
+    }
+}
@@ -4,9 +4,35 @@
 //! Convert Tectonic’s SPX format to HTML
 //!
 //! Yay, an engine actually written in pure Rust!
+//!
+//! In semantic pagination mode, the TeX engine sets each paragraph as a single
+//! line, so the SPX file gives us a stream of lines rather than a layout of
+//! pages. We turn it back into a document as follows:
+//!
+//! - Each new baseline starts a new block. By default, blocks become `<p>`
+//!   elements. This means that material that still spans several lines, such
+//!   as boxed paragraphs or multi-line display math, becomes one block per
+//!   line; the SPX file doesn't record enough structure to do better.
+//! - The special `\special{spx:heading N}`, with N from 1 to 6, makes the
+//!   next block an `<hN>` heading. The first level-1 heading also becomes the
+//!   document title.
+//! - The special `\special{spx:paragraph}` ends the current block, so that
+//!   subsequent text starts a new one even if it's on the same line.
+//! - Native fonts are mapped to CSS classes named `fN`, where N is the TeX
+//!   font number, and text is wrapped in `<span>` elements when the font
+//!   changes.
+//! - Each page that the engine ships out becomes a `<div class="page">`
+//!   element.
+//!
+//! Interword spaces aren't characters in TeX, so we recover them from the gaps
+//! between runs of glyphs. This only works for native fonts; text in
+//! traditional TFM fonts is passed through without any spacing recovery.
 
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
+use std::fmt::Write as FmtWrite;
 use std::io::Write;
+use std::mem;
 use tectonic_xdv::{FileType, XdvEvents, XdvParser};
 
 use super::IoEventBackend;
@@ -15,6 +41,12 @@ use crate::io::{IoProvider, IoStack, OpenResult, OutputHandle};
 use crate::status::StatusBackend;
 use crate::{errmsg, tt_warning};
 
+/// The number of TeX scaled points in a TeX point.
+const SP_PER_PT: f64 = 65536.;
+
+/// The font size assumed if text appears in a font we don't know about.
+const DEFAULT_FONT_SIZE: i32 = 10 * 65536;
+
 #[derive(Default)]
 pub struct Spx2HtmlEngine {}
 
@@ -49,7 +81,7 @@ impl Spx2HtmlEngine {
         {
             let state = State::new(outname, io, events, status);
             let (state, _n_bytes) = XdvParser::process(&mut input, state)?;
-            state.finished();
+            state.finished()?;
         }
 
         let (name, digest_opt) = input.into_name_digest();
@@ -59,6 +91,104 @@ impl Spx2HtmlEngine {
     }
 }
 
+/// What we know about a native font.
+struct FontInfo {
+    family: String,
+    generic_family: &'static str,
+    size: i32,
+    bold: bool,
+    italic: bool,
+}
+
+impl FontInfo {
+    fn new(name: &str, size: i32, slant: Option<u32>, embolden: Option<u32>) -> Self {
+        // Names look like `[path/to/lmroman10-bold.otf]:mapping=tex-text` or
+        // `Latin Modern Roman/B`.
+        let name = name.split(':').next().unwrap_or(name);
+        let mut style_bold = false;
+        let mut style_italic = false;
+
+        let family = if name.starts_with('[') {
+            // A font file, given by its path.
+            let name = name.trim_start_matches('[').trim_end_matches(']');
+            let name = name.rsplit('/').next().unwrap_or(name);
+
+            match name.rfind('.') {
+                Some(i) if i > 0 => &name[..i],
+                _ => name,
+            }
+        } else {
+            // A font name, possibly followed by fontspec-style flags that
+            // select the style or the renderer.
+            let mut name = name;
+
+            while let Some(i) = name.rfind('/') {
+                match &name[i + 1..] {
+                    "B" => style_bold = true,
+                    "I" => style_italic = true,
+                    "BI" | "IB" => {
+                        style_bold = true;
+                        style_italic = true;
+                    }
+                    "ICU" | "AAT" | "OT" | "GR" => {}
+                    _ => break,
+                }
+
+                name = &name[..i];
+            }
+
+            name
+        };
+
+        let lower = family.to_lowercase();
+        let generic_family = if lower.contains("mono") || lower.starts_with("lmtt") {
+            "monospace"
+        } else if lower.contains("sans") || lower.starts_with("lmss") {
+            "sans-serif"
+        } else {
+            "serif"
+        };
+
+        FontInfo {
+            family: family.to_owned(),
+            generic_family,
+            size,
+            bold: style_bold || embolden.is_some() || lower.contains("bold"),
+            italic: style_italic
+                || slant.is_some()
+                || lower.contains("italic")
+                || lower.contains("oblique"),
+        }
+    }
+}
+
+/// The kinds of block-level elements that we create.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BlockKind {
+    Paragraph,
+    Heading(u8),
+}
+
+impl BlockKind {
+    fn tag(self) -> &'static str {
+        match self {
+            BlockKind::Paragraph => "p",
+            BlockKind::Heading(1) => "h1",
+            BlockKind::Heading(2) => "h2",
+            BlockKind::Heading(3) => "h3",
+            BlockKind::Heading(4) => "h4",
+            BlockKind::Heading(5) => "h5",
+            BlockKind::Heading(_) => "h6",
+        }
+    }
+}
+
+/// A block that we're in the middle of writing.
+struct Block {
+    kind: BlockKind,
+    text: String,
+}
+
 struct State<'a, 'b: 'a> {
     outname: String,
     io: &'a mut IoStack<'b>,
@@ -66,7 +196,15 @@ struct State<'a, 'b: 'a> {
     status: &'a mut dyn StatusBackend,
     cur_output: Option<OutputHandle>,
     warned_lost_chars: bool,
-    buf: Vec<u8>,
+    fonts: BTreeMap<i32, FontInfo>,
+    title: Option<String>,
+    body: String,
+    in_page: bool,
+    block: Option<Block>,
+    next_block_kind: BlockKind,
+    cur_font: Option<i32>,
+    baseline: Option<i32>,
+    last_end: Option<i32>,
 }
 
 impl<'a, 'b: 'a> State<'a, 'b> {
@@ -83,25 +221,156 @@ impl<'a, 'b: 'a> State<'a, 'b> {
             status,
             cur_output: None,
             warned_lost_chars: false,
-            buf: Vec::new(),
+            fonts: BTreeMap::new(),
+            title: None,
+            body: String::new(),
+            in_page: false,
+            block: None,
+            next_block_kind: BlockKind::Paragraph,
+            cur_font: None,
+            baseline: None,
+            last_end: None,
         }
     }
-}
 
-#[inline(always)]
-fn as_printable_ascii(c: i32) -> Option<u8> {
-    if c > 0x20 && c < 0x7F {
-        Some(c as u8)
-    } else {
-        None
+    /// Returns false, after warning once, if there's nowhere for text to go.
+    fn check_output(&mut self) -> bool {
+        if self.cur_output.is_some() {
+            return true;
+        }
+
+        if !self.warned_lost_chars {
+            tt_warning!(
+                self.status,
+                "losing characters in SPX file: no current output"
+            );
+            self.warned_lost_chars = true;
+        }
+
+        false
+    }
+
+    fn open_block(&mut self) {
+        if self.block.is_some() {
+            return;
+        }
+
+        let kind = mem::replace(&mut self.next_block_kind, BlockKind::Paragraph);
+        write!(self.body, "<{}>", kind.tag()).unwrap();
+        self.block = Some(Block {
+            kind,
+            text: String::new(),
+        });
+    }
+
+    fn close_block(&mut self) {
+        self.set_font(None);
+
+        if let Some(block) = self.block.take() {
+            writeln!(self.body, "</{}>", block.kind.tag()).unwrap();
+
+            if block.kind == BlockKind::Heading(1) && self.title.is_none() {
+                let title = block.text.trim();
+
+                if !title.is_empty() {
+                    self.title = Some(title.to_owned());
+                }
+            }
+        }
+
+        self.baseline = None;
+        self.last_end = None;
+    }
+
+    fn set_font(&mut self, font: Option<i32>) {
+        if font == self.cur_font {
+            return;
+        }
+
+        if self.cur_font.is_some() {
+            self.body.push_str("</span>");
+        }
+
+        if let Some(f) = font {
+            write!(self.body, "<span class=\"f{}\">", f).unwrap();
+        }
+
+        self.cur_font = font;
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if let Some(ref mut block) = self.block {
+            block.text.push_str(text);
+        }
+
+        escape_html(text, &mut self.body);
+    }
+
+    pub fn finished(mut self) -> Result<()> {
+        self.close_block();
+
+        if self.in_page {
+            self.body.push_str("</div>\n");
+        }
+
+        let mut oh = match self.cur_output.take() {
+            Some(oh) => oh,
+            None => return Ok(()),
+        };
+
+        let mut doc = String::new();
+        doc.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+
+        let title = match self.title {
+            Some(ref t) => t.as_str(),
+            None => self.outname.trim_end_matches(".html"),
+        };
+        doc.push_str("<title>");
+        escape_html(title, &mut doc);
+        doc.push_str("</title>\n<style>\n");
+        doc.push_str(".page { margin: 2em auto; max-width: 40em; }\n");
+
+        for (num, font) in &self.fonts {
+            write!(
+                doc,
+                ".f{} {{ font-family: \"{}\", {}; font-size: {:.2}pt;",
+                num,
+                font.family.replace('"', ""),
+                font.generic_family,
+                font.size as f64 / SP_PER_PT
+            )
+            .unwrap();
+
+            if font.bold {
+                doc.push_str(" font-weight: bold;");
+            }
+
+            if font.italic {
+                doc.push_str(" font-style: italic;");
+            }
+
+            doc.push_str(" }\n");
+        }
+
+        doc.push_str("</style>\n</head>\n<body>\n");
+        doc.push_str(&self.body);
+        doc.push_str("</body>\n</html>\n");
+
+        oh.write_all(doc.as_bytes())?;
+        let (name, digest) = oh.into_name_digest();
+        self.events.output_closed(name, digest);
+        Ok(())
     }
 }
 
-impl<'a, 'b: 'a> State<'a, 'b> {
-    pub fn finished(self) {
-        if let Some(oh) = self.cur_output {
-            let (name, digest) = oh.into_name_digest();
-            self.events.output_closed(name, digest);
+fn escape_html(text: &str, dest: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => dest.push_str("&amp;"),
+            '<' => dest.push_str("&lt;"),
+            '>' => dest.push_str("&gt;"),
+            '"' => dest.push_str("&quot;"),
+            c => dest.push(c),
         }
     }
 }
@@ -129,35 +398,207 @@ impl<'a, 'b: 'a> XdvEvents for State<'a, 'b> {
         Ok(())
     }
 
-    fn handle_char_run(&mut self, chars: &[i32]) -> Result<()> {
-        let dest = match self.cur_output {
-            Some(ref mut h) => h,
-            None => {
-                if !self.warned_lost_chars {
-                    tt_warning!(
-                        self.status,
-                        "losing characters in SPX file: no current output"
-                    );
-                    self.warned_lost_chars = true;
+    fn handle_begin_page(&mut self, counters: &[i32], _previous_bop: i32) -> Result<()> {
+        self.close_block();
+
+        if self.in_page {
+            self.body.push_str("</div>\n");
+        }
+
+        writeln!(
+            self.body,
+            "<div class=\"page\" data-page=\"{}\">",
+            counters[0]
+        )
+        .unwrap();
+        self.in_page = true;
+        Ok(())
+    }
+
+    fn handle_special(&mut self, contents: &[u8]) -> Result<()> {
+        let text = match std::str::from_utf8(contents) {
+            Ok(t) => t,
+            Err(_) => return Ok(()),
+        };
+
+        let command = match text.strip_prefix("spx:") {
+            Some(c) => c.trim(),
+            None => return Ok(()),
+        };
+
+        let mut words = command.split_whitespace();
+
+        match (words.next(), words.next()) {
+            (Some("paragraph"), None) => self.close_block(),
+
+            (Some("heading"), Some(level)) => match level.parse::<u8>() {
+                Ok(n) if (1..=6).contains(&n) => {
+                    self.close_block();
+                    self.next_block_kind = BlockKind::Heading(n);
                 }
+                _ => tt_warning!(
+                    self.status,
+                    "invalid heading level in SPX special `{}`",
+                    text
+                ),
+            },
+
+            _ => tt_warning!(self.status, "ignoring unrecognized SPX special `{}`", text),
+        }
+
+        Ok(())
+    }
+
+    fn handle_char_run(&mut self, chars: &[i32]) -> Result<()> {
+        if !self.check_output() {
+            return Ok(());
+        }
+
+        let text: String = chars
+            .iter()
+            .filter_map(|c| std::char::from_u32(*c as u32))
+            .filter(|c| !c.is_control())
+            .collect();
 
-                return Ok(());
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        self.open_block();
+        self.set_font(None);
+        self.push_text(&text);
+
+        // We don't know the widths of the characters, so we can't tell
+        // where the run ended.
+        self.last_end = None;
+        Ok(())
+    }
+
+    fn handle_define_native_font(
+        &mut self,
+        name: &str,
+        font_num: i32,
+        size: i32,
+        _face_index: u32,
+        _color_rgba: Option<u32>,
+        _extend: Option<u32>,
+        slant: Option<u32>,
+        embolden: Option<u32>,
+    ) -> Result<()> {
+        self.fonts
+            .entry(font_num)
+            .or_insert_with(|| FontInfo::new(name, size, slant, embolden));
+        Ok(())
+    }
+
+    fn handle_text_and_glyphs(
+        &mut self,
+        font_num: i32,
+        text: &str,
+        width: i32,
+        _glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<()> {
+        if !self.check_output() || text.is_empty() {
+            return Ok(());
+        }
+
+        let size = self
+            .fonts
+            .get(&font_num)
+            .map_or(DEFAULT_FONT_SIZE, |f| f.size);
+
+        if let (Some(&x0), Some(&y0)) = (x.first(), y.first()) {
+            // A new baseline means a new line, which in SPX output means a
+            // new paragraph.
+            if let Some(baseline) = self.baseline {
+                if (y0 - baseline).abs() > size {
+                    self.close_block();
+                }
             }
-        };
 
-        self.buf.clear();
+            // A gap of more than a fraction of an em between this run and the
+            // last one must be an interword space.
+            if self.block.is_some() {
+                if let Some(last_end) = self.last_end {
+                    if x0 - last_end > size / 8 && !text.starts_with(' ') {
+                        self.push_text(" ");
+                    }
+                }
+            }
 
-        for c in chars.iter() {
-            if let Some(b) = as_printable_ascii(*c) {
-                self.buf.push(b);
+            if self.baseline.is_none() {
+                self.baseline = Some(y0);
             }
-        }
 
-        if !self.buf.is_empty() {
-            self.buf.push(0x0a); // newline
-            dest.write_all(&self.buf)?;
+            self.last_end = Some(x0 + width);
+        } else {
+            self.last_end = None;
         }
 
+        self.open_block();
+        self.set_font(Some(font_num));
+        self.push_text(text);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(font: &FontInfo) -> (&str, &str, bool, bool) {
+        (&font.family, font.generic_family, font.bold, font.italic)
+    }
+
+    #[test]
+    fn font_files() {
+        let font = FontInfo::new("[lmroman10-regular.otf]:mapping=tex-text", 10, None, None);
+        assert_eq!(style(&font), ("lmroman10-regular", "serif", false, false));
+
+        let font = FontInfo::new("[fonts/lmroman10-bold.otf]", 10, None, None);
+        assert_eq!(style(&font), ("lmroman10-bold", "serif", true, false));
+
+        let font = FontInfo::new("[/usr/share/fonts/lmmono10-italic.otf]", 10, None, None);
+        assert_eq!(style(&font), ("lmmono10-italic", "monospace", false, true));
+
+        // A trailing `/B` in a path is part of a directory name, not a flag.
+        let font = FontInfo::new("[fonts/B/lmsans10-regular.otf]", 10, None, None);
+        assert_eq!(
+            style(&font),
+            ("lmsans10-regular", "sans-serif", false, false)
+        );
+    }
+
+    #[test]
+    fn font_names() {
+        let font = FontInfo::new("Latin Modern Roman", 10, None, None);
+        assert_eq!(style(&font), ("Latin Modern Roman", "serif", false, false));
+
+        let font = FontInfo::new("Latin Modern Roman/B:mapping=tex-text", 10, None, None);
+        assert_eq!(style(&font), ("Latin Modern Roman", "serif", true, false));
+
+        let font = FontInfo::new("Latin Modern Sans/I", 10, None, None);
+        assert_eq!(
+            style(&font),
+            ("Latin Modern Sans", "sans-serif", false, true)
+        );
+
+        let font = FontInfo::new("Latin Modern Mono/BI", 10, None, None);
+        assert_eq!(style(&font), ("Latin Modern Mono", "monospace", true, true));
+
+        let font = FontInfo::new("Latin Modern Roman/ICU/B", 10, None, None);
+        assert_eq!(style(&font), ("Latin Modern Roman", "serif", true, false));
+
+        // Slashes that aren't flags are part of the name.
+        let font = FontInfo::new("AC/DC", 10, None, None);
+        assert_eq!(style(&font), ("AC/DC", "serif", false, false));
+    }
+
+    #[test]
+    fn font_synthetic_styles() {
+        let font = FontInfo::new("Latin Modern Roman", 10, Some(1), Some(2));
+        assert_eq!(style(&font), ("Latin Modern Roman", "serif", true, true));
+    }
+}
@@ -2,11 +2,14 @@
 // Copyright 2017-2018 the Tectonic Project
 // Licensed under the MIT License.
 
-use std::ffi::{CStr, CString};
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::time::SystemTime;
 
-use super::{ExecutionState, IoEventBackend, TectonicBridgeApi};
+use super::shell_escape::{ShellEscapeHandler, ShellEscapeRunner};
+use super::{EngineJob, IoEventBackend};
 use crate::errors::{DefinitelySame, ErrorKind, Result};
+use crate::io::memory::MemoryFileCollection;
 use crate::io::IoStack;
 use crate::status::StatusBackend;
 use crate::unstable_opts::UnstableOptions;
@@ -35,9 +38,11 @@ pub struct TexEngine {
     // though, it's just a proxy for the global constants in the C code.
     halt_on_error: bool,
     initex_mode: bool,
+    preload_format: bool,
     synctex_enabled: bool,
     semantic_pagination_enabled: bool,
     build_date: SystemTime,
+    shell_escape_files: Option<Rc<RefCell<MemoryFileCollection>>>,
 }
 
 impl Default for TexEngine {
@@ -45,9 +50,11 @@ impl Default for TexEngine {
         TexEngine {
             halt_on_error: true,
             initex_mode: false,
+            preload_format: false,
             synctex_enabled: false,
             semantic_pagination_enabled: false,
             build_date: SystemTime::UNIX_EPOCH,
+            shell_escape_files: None,
         }
     }
 }
@@ -70,6 +77,16 @@ impl TexEngine {
         self
     }
 
+    /// Configure the engine to load the format file before processing the
+    /// input, even in "initex" mode. This allows a new format to be dumped on
+    /// top of an existing one, such as one that includes a document preamble.
+    /// It has no effect outside of "initex" mode, where the format is always
+    /// loaded.
+    pub fn preload_format(&mut self, preload: bool) -> &mut Self {
+        self.preload_format = preload;
+        self
+    }
+
     /// Configure the engine to produce SyncTeX data.
     pub fn synctex(&mut self, synctex_enabled: bool) -> &mut Self {
         self.synctex_enabled = synctex_enabled;
@@ -99,6 +116,17 @@ impl TexEngine {
         self
     }
 
+    /// Sets the in-memory files that `\write18` commands get to see. Commands
+    /// are run in a temporary directory populated with these files, and any
+    /// files that they create are added back to the collection. Whether
+    /// commands are run at all is governed by the shell-escape policy in the
+    /// `UnstableOptions` passed to [`TexEngine::process`]; if no files are
+    /// set here, shell escape is unavailable regardless of the policy.
+    pub fn shell_escape_files(&mut self, files: Rc<RefCell<MemoryFileCollection>>) -> &mut Self {
+        self.shell_escape_files = Some(files);
+        self
+    }
+
     // This function can't be generic across the IoProvider trait, for now,
     // since the global pointer that stashes the ExecutionState must have a
     // complete type.
@@ -112,71 +140,39 @@ impl TexEngine {
         input_file_name: &str,
         unstables: &UnstableOptions,
     ) -> Result<TexResult> {
-        let _guard = super::ENGINE_LOCK.lock().unwrap(); // until we're thread-safe ...
-
-        let cformat = CString::new(format_file_name)?;
-        let cinput = CString::new(input_file_name)?;
-
-        let mut state = ExecutionState::new(io, events, status);
-        let bridge = TectonicBridgeApi::new(&mut state);
-
-        // initialize globals
-
-        let v = if unstables.shell_escape { 1 } else { 0 };
-        unsafe {
-            super::tt_xetex_set_int_variable(b"shell_escape_enabled\0".as_ptr() as _, v);
-        }
-
-        let mut halt_on_error = self.halt_on_error;
-        if unstables.continue_on_errors {
-            halt_on_error = false; // command-line override
-        }
-        let v = if halt_on_error { 1 } else { 0 };
-        unsafe {
-            super::tt_xetex_set_int_variable(b"halt_on_error_p\0".as_ptr() as _, v);
-        }
-
-        let v = if self.initex_mode { 1 } else { 0 };
-        unsafe {
-            super::tt_xetex_set_int_variable(b"in_initex_mode\0".as_ptr() as _, v);
-        }
-        let v = if self.synctex_enabled { 1 } else { 0 };
-        unsafe {
-            super::tt_xetex_set_int_variable(b"synctex_enabled\0".as_ptr() as _, v);
-        }
-        let v = if self.semantic_pagination_enabled {
-            1
-        } else {
-            0
+        let shell_escape: Option<Box<dyn ShellEscapeHandler>> = match self.shell_escape_files {
+            Some(ref files) if unstables.shell_escape.is_enabled() => Some(Box::new(
+                ShellEscapeRunner::new(unstables.shell_escape.clone(), files.clone()),
+            )),
+            _ => None,
         };
-        unsafe {
-            super::tt_xetex_set_int_variable(b"semantic_pagination_enabled\0".as_ptr() as _, v);
-        }
 
-        unsafe {
-            match super::tex_simple_main(
-                &bridge,
-                cformat.as_ptr(),
-                cinput.as_ptr(),
-                self.build_date
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .expect("invalid build date")
-                    .as_secs() as libc::time_t,
-            ) {
-                0 => Ok(TexResult::Spotless),
-                1 => Ok(TexResult::Warnings),
-                2 => Ok(TexResult::Errors),
-                3 => {
-                    let ptr = super::tt_get_error_message();
-                    let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
-                    Err(ErrorKind::Msg(msg).into())
-                }
-                x => Err(ErrorKind::Msg(format!(
-                    "internal error: unexpected 'history' value {}",
-                    x
-                ))
-                .into()),
-            }
+        let job = EngineJob::Tex {
+            format_name: format_file_name.to_owned(),
+            input_name: input_file_name.to_owned(),
+            build_date: self
+                .build_date
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .expect("invalid build date")
+                .as_secs(),
+            // command-line override
+            halt_on_error: self.halt_on_error && !unstables.continue_on_errors,
+            initex: self.initex_mode,
+            preload_format: self.preload_format,
+            synctex: self.synctex_enabled,
+            semantic_pagination: self.semantic_pagination_enabled,
+            shell_escape: unstables.shell_escape.is_enabled(),
+        };
+        let outcome = job.run(io, events, status, shell_escape)?;
+
+        match outcome.code {
+            0 => Ok(TexResult::Spotless),
+            1 => Ok(TexResult::Warnings),
+            2 => Ok(TexResult::Errors),
+            3 => Err(ErrorKind::Msg(outcome.error_message).into()),
+            x => Err(
+                ErrorKind::Msg(format!("internal error: unexpected 'history' value {}", x)).into(),
+            ),
         }
     }
 }
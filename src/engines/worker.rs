@@ -0,0 +1,1658 @@
+// src/engines/worker.rs -- running the engines in worker processes
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Running the C/C++ engines in worker processes.
+//!
+//! The engines keep their state in C global variables, so only one of them
+//! can safely run in a given process at a time. By default, engine runs are
+//! serialized with a process-wide lock, so a program that typesets documents
+//! from several threads gets no benefit from the parallelism.
+//!
+//! A program can lift this restriction by calling [`activate`] at the very
+//! start of its `main()` function. After that, every engine run starts a new
+//! copy of the current executable as a worker process, which runs the engine
+//! with a fresh set of globals. The bridge functions that the engine calls
+//! are forwarded over a pair of pipes to the thread that started the run,
+//! which services them with its own I/O, event, and status backends, just as
+//! it would if the engine were running in-process. Since nothing is shared,
+//! there's no need for a lock.
+//!
+//! The protocol between the two processes is private to this module and may
+//! change between versions of Tectonic, so a worker is always a copy of the
+//! same executable as its parent.
+//!
+//! Workers are only supported on Unix, where the protocol can be moved off
+//! of the worker's standard output. Elsewhere, [`activate`] does nothing and
+//! engine runs stay serialized.
+
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, BufReader, BufWriter, Read, SeekFrom, Write};
+use std::process::{self, Command, Stdio};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::shell_escape::{ShellEscapeHandler, ShellEscapeOutcome};
+use super::{
+    EngineJob, EngineOutcome, IoEventBackend, PdfConformance, PdfDocInfo, PdfEncryption,
+    PdfOptions, PdfOutputIntent, PdfPermissions,
+};
+use crate::digest::DigestData;
+use crate::errors::{Error, ErrorKind, Result};
+use crate::io::{InputFeatures, InputHandle, InputOrigin, IoProvider, OpenResult, OutputHandle};
+use crate::status::{MessageKind, SourceDiagnostic, StatusBackend};
+use crate::{ctry, errmsg};
+
+/// The environment variable that marks a process as an engine worker.
+const WORKER_ENV_VAR: &str = "TECTONIC_ENGINE_WORKER";
+
+/// How much data a worker asks for when it reads from an input, and buffers
+/// before sending a write to an output.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The largest single read that a worker will ask for.
+const MAX_READ_SIZE: usize = 16 * 1024 * 1024;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Run engines in worker processes rather than in the current process.
+///
+/// This should be called first thing in `main()`. If the current process was
+/// started as an engine worker, this function runs the requested engine and
+/// exits the process without returning. Otherwise, it arranges for all
+/// subsequent engine runs to happen in worker processes, which allows them
+/// to proceed in parallel when they're started from multiple threads.
+///
+/// Worker processes are started by re-running the current executable, so
+/// it's important that nothing the program does before calling this function
+/// has side effects.
+///
+/// On platforms other than Unix, this function does nothing, and engines
+/// keep running in the current process.
+pub fn activate() {
+    if !cfg!(unix) {
+        return;
+    }
+
+    if env::var_os(WORKER_ENV_VAR).is_some() {
+        process::exit(worker_main());
+    }
+
+    ACTIVE.store(true, Ordering::SeqCst);
+}
+
+/// Returns true if [`activate`] has been called in this process.
+pub(crate) fn is_active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+// Message tags. Everything from the worker to the parent starts with one of
+// these. The "call" messages expect a reply; the rest don't.
+
+mod tag {
+    pub const JOB: u8 = 0;
+    pub const DONE: u8 = 1;
+
+    pub const REPORT: u8 = 10;
+    pub const REPORT_ERROR: u8 = 11;
+    pub const REPORT_DIAGNOSTIC: u8 = 12;
+    pub const NOTE_HIGHLIGHTED: u8 = 13;
+    pub const DUMP_ERROR_LOGS: u8 = 14;
+
+    pub const OUTPUT_OPENED: u8 = 20;
+    pub const STDOUT_OPENED: u8 = 21;
+    pub const OUTPUT_CLOSED: u8 = 22;
+    pub const INPUT_OPENED: u8 = 23;
+    pub const PRIMARY_INPUT_OPENED: u8 = 24;
+    pub const INPUT_NOT_AVAILABLE: u8 = 25;
+    pub const INPUT_CLOSED: u8 = 26;
+
+    pub const CALL_OUTPUT_OPEN_NAME: u8 = 30;
+    pub const CALL_OUTPUT_OPEN_STDOUT: u8 = 31;
+    pub const OUTPUT_WRITE: u8 = 32;
+    pub const CALL_OUTPUT_FLUSH: u8 = 33;
+    pub const OUTPUT_CLOSE: u8 = 34;
+
+    pub const CALL_INPUT_OPEN_NAME: u8 = 40;
+    pub const CALL_INPUT_OPEN_PRIMARY: u8 = 41;
+    pub const CALL_INPUT_OPEN_FORMAT: u8 = 42;
+    pub const CALL_INPUT_READ: u8 = 43;
+    pub const CALL_INPUT_GET_SIZE: u8 = 44;
+    pub const CALL_INPUT_GET_MTIME: u8 = 45;
+    pub const CALL_INPUT_SEEK: u8 = 46;
+    pub const INPUT_CLOSE: u8 = 47;
+
+    pub const CALL_SHELL_ESCAPE: u8 = 50;
+}
+
+// Encoding and decoding of message contents.
+
+#[derive(Default)]
+struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Default::default()
+    }
+
+    fn u8(mut self, v: u8) -> Self {
+        self.buf.push(v);
+        self
+    }
+
+    fn bool(self, v: bool) -> Self {
+        self.u8(v as u8)
+    }
+
+    fn u32(mut self, v: u32) -> Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn u64(mut self, v: u64) -> Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn i64(mut self, v: i64) -> Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn f64(self, v: f64) -> Self {
+        self.u64(v.to_bits())
+    }
+
+    fn bytes(self, v: &[u8]) -> Self {
+        let mut e = self.u32(v.len() as u32);
+        e.buf.extend_from_slice(v);
+        e
+    }
+
+    fn str(self, v: &str) -> Self {
+        self.bytes(v.as_bytes())
+    }
+
+    fn os_str(self, v: &OsStr) -> Self {
+        self.bytes(&os_str_to_bytes(v))
+    }
+
+    fn strings(self, v: &[String]) -> Self {
+        v.iter().fold(self.u32(v.len() as u32), |e, s| e.str(s))
+    }
+
+    fn opt_str(self, v: Option<&str>) -> Self {
+        match v {
+            Some(s) => self.bool(true).str(s),
+            None => self.bool(false),
+        }
+    }
+
+    fn digest(self, v: &DigestData) -> Self {
+        self.str(&v.to_string())
+    }
+
+    fn causes(self, err: &Error) -> Self {
+        let causes: Vec<String> = err.iter().map(|e| e.to_string()).collect();
+        self.strings(&causes)
+    }
+
+    fn message_kind(self, v: MessageKind) -> Self {
+        self.u8(match v {
+            MessageKind::Note => 0,
+            MessageKind::Warning => 1,
+            MessageKind::Error => 2,
+        })
+    }
+
+    fn origin(self, v: InputOrigin) -> Self {
+        self.u8(match v {
+            InputOrigin::Filesystem => 0,
+            InputOrigin::NotInput => 1,
+            InputOrigin::Other => 2,
+        })
+    }
+
+    fn pdf_options(self, v: &PdfOptions) -> Self {
+        self.u8(v.version.0)
+            .u8(v.version.1)
+            .u8(v.compression_level)
+            .f64(v.annot_grow)
+            .i64(v.bookmark_open as i64)
+            .f64(v.magnification)
+            .u32(v.font_dpi)
+            .bool(v.thumbnails)
+            .u8(v.decimal_digits)
+            .f64(v.x_offset)
+            .f64(v.y_offset)
+            .pdf_encryption(v.encryption.as_ref())
+            .u8(v.conformance.map_or(0, |c| c.to_c() as u8))
+            .pdf_output_intent(v.output_intent.as_ref())
+            .pdf_doc_info(&v.info)
+    }
+
+    fn pdf_encryption(self, v: Option<&PdfEncryption>) -> Self {
+        match v {
+            Some(enc) => self
+                .bool(true)
+                .str(&enc.owner_password)
+                .str(&enc.user_password)
+                .u32(enc.key_bits)
+                .u32(enc.permissions.to_bits()),
+            None => self.bool(false),
+        }
+    }
+
+    fn pdf_output_intent(self, v: Option<&PdfOutputIntent>) -> Self {
+        match v {
+            Some(intent) => self.bool(true).str(&intent.profile).str(&intent.identifier),
+            None => self.bool(false),
+        }
+    }
+
+    fn pdf_doc_info(self, v: &PdfDocInfo) -> Self {
+        self.opt_str(v.title.as_deref())
+            .strings(&v.authors)
+            .opt_str(v.subject.as_deref())
+            .strings(&v.keywords)
+            .opt_str(v.language.as_deref())
+    }
+
+    /// Encode a result with no payload.
+    fn unit_result(self, r: Result<()>) -> Self {
+        match r {
+            Ok(()) => self.u8(0),
+            Err(e) => self.u8(1).causes(&e),
+        }
+    }
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "malformed engine worker message",
+    )
+}
+
+impl<'a> Decoder<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Decoder { buf }
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return Err(malformed());
+        }
+
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(i64::from_le_bytes(b))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let n = self.u32()? as usize;
+        Ok(self.take(n)?.to_vec())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| malformed())
+    }
+
+    fn os_string(&mut self) -> io::Result<OsString> {
+        Ok(bytes_to_os_string(self.bytes()?))
+    }
+
+    fn strings(&mut self) -> io::Result<Vec<String>> {
+        let n = self.u32()?;
+        (0..n).map(|_| self.string()).collect()
+    }
+
+    fn opt_string(&mut self) -> io::Result<Option<String>> {
+        if self.bool()? {
+            Ok(Some(self.string()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn digest(&mut self) -> io::Result<DigestData> {
+        self.string()?.parse().map_err(|_| malformed())
+    }
+
+    fn causes(&mut self) -> io::Result<Error> {
+        Ok(error_from_causes(self.strings()?))
+    }
+
+    fn message_kind(&mut self) -> io::Result<MessageKind> {
+        match self.u8()? {
+            0 => Ok(MessageKind::Note),
+            1 => Ok(MessageKind::Warning),
+            2 => Ok(MessageKind::Error),
+            _ => Err(malformed()),
+        }
+    }
+
+    fn origin(&mut self) -> io::Result<InputOrigin> {
+        match self.u8()? {
+            0 => Ok(InputOrigin::Filesystem),
+            1 => Ok(InputOrigin::NotInput),
+            2 => Ok(InputOrigin::Other),
+            _ => Err(malformed()),
+        }
+    }
+
+    fn pdf_options(&mut self) -> io::Result<PdfOptions> {
+        Ok(PdfOptions {
+            version: (self.u8()?, self.u8()?),
+            compression_level: self.u8()?,
+            annot_grow: self.f64()?,
+            bookmark_open: self.i64()? as i32,
+            magnification: self.f64()?,
+            font_dpi: self.u32()?,
+            thumbnails: self.bool()?,
+            decimal_digits: self.u8()?,
+            x_offset: self.f64()?,
+            y_offset: self.f64()?,
+            encryption: self.pdf_encryption()?,
+            conformance: self.pdf_conformance()?,
+            output_intent: self.pdf_output_intent()?,
+            info: self.pdf_doc_info()?,
+        })
+    }
+
+    fn pdf_encryption(&mut self) -> io::Result<Option<PdfEncryption>> {
+        if !self.bool()? {
+            return Ok(None);
+        }
+
+        Ok(Some(PdfEncryption {
+            owner_password: self.string()?,
+            user_password: self.string()?,
+            key_bits: self.u32()?,
+            permissions: PdfPermissions::from_bits(self.u32()?),
+        }))
+    }
+
+    fn pdf_conformance(&mut self) -> io::Result<Option<PdfConformance>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(PdfConformance::PdfA1b)),
+            2 => Ok(Some(PdfConformance::PdfA2b)),
+            3 => Ok(Some(PdfConformance::PdfX4)),
+            _ => Err(malformed()),
+        }
+    }
+
+    fn pdf_output_intent(&mut self) -> io::Result<Option<PdfOutputIntent>> {
+        if !self.bool()? {
+            return Ok(None);
+        }
+
+        Ok(Some(PdfOutputIntent {
+            profile: self.string()?,
+            identifier: self.string()?,
+        }))
+    }
+
+    fn pdf_doc_info(&mut self) -> io::Result<PdfDocInfo> {
+        Ok(PdfDocInfo {
+            title: self.opt_string()?,
+            authors: self.strings()?,
+            subject: self.opt_string()?,
+            keywords: self.strings()?,
+            language: self.opt_string()?,
+        })
+    }
+
+    fn unit_result(&mut self) -> io::Result<Result<()>> {
+        match self.u8()? {
+            0 => Ok(Ok(())),
+            _ => Ok(Err(self.causes()?)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn os_str_to_bytes(s: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    s.as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn bytes_to_os_string(b: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(b)
+}
+
+#[cfg(windows)]
+fn os_str_to_bytes(s: &OsStr) -> Vec<u8> {
+    s.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(windows)]
+fn bytes_to_os_string(b: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&b).into_owned())
+}
+
+/// Rebuild an error from the descriptions of its chain of causes, outermost
+/// first.
+fn error_from_causes(causes: Vec<String>) -> Error {
+    let mut causes = causes.into_iter().rev();
+    let innermost = causes
+        .next()
+        .unwrap_or_else(|| "an unknown error occurred in an engine worker".to_owned());
+    let mut err: Error = ErrorKind::Msg(innermost).into();
+
+    for cause in causes {
+        err = Error::with_chain(err, ErrorKind::Msg(cause));
+    }
+
+    err
+}
+
+fn encode_job(job: &EngineJob, has_shell_escape: bool) -> Encoder {
+    let e = Encoder::new().u8(tag::JOB).bool(has_shell_escape);
+
+    match *job {
+        EngineJob::Tex {
+            ref format_name,
+            ref input_name,
+            build_date,
+            halt_on_error,
+            initex,
+            preload_format,
+            synctex,
+            semantic_pagination,
+            shell_escape,
+        } => e
+            .u8(0)
+            .str(format_name)
+            .str(input_name)
+            .u64(build_date)
+            .bool(halt_on_error)
+            .bool(initex)
+            .bool(preload_format)
+            .bool(synctex)
+            .bool(semantic_pagination)
+            .bool(shell_escape),
+
+        EngineJob::Bibtex {
+            ref aux_name,
+            min_crossrefs,
+        } => e.u8(1).str(aux_name).i64(min_crossrefs as i64),
+
+        EngineJob::Xdvipdfmx {
+            ref dvi_name,
+            ref pdf_name,
+            ref paperspec,
+            enable_compression,
+            deterministic_tags,
+            build_date,
+            ref options,
+        } => e
+            .u8(2)
+            .str(dvi_name)
+            .str(pdf_name)
+            .str(paperspec)
+            .bool(enable_compression)
+            .bool(deterministic_tags)
+            .u64(build_date)
+            .pdf_options(options),
+    }
+}
+
+fn decode_job(d: &mut Decoder) -> io::Result<(EngineJob, bool)> {
+    if d.u8()? != tag::JOB {
+        return Err(malformed());
+    }
+
+    let has_shell_escape = d.bool()?;
+
+    let job = match d.u8()? {
+        0 => EngineJob::Tex {
+            format_name: d.string()?,
+            input_name: d.string()?,
+            build_date: d.u64()?,
+            halt_on_error: d.bool()?,
+            initex: d.bool()?,
+            preload_format: d.bool()?,
+            synctex: d.bool()?,
+            semantic_pagination: d.bool()?,
+            shell_escape: d.bool()?,
+        },
+
+        1 => EngineJob::Bibtex {
+            aux_name: d.string()?,
+            min_crossrefs: d.i64()? as i32,
+        },
+
+        2 => EngineJob::Xdvipdfmx {
+            dvi_name: d.string()?,
+            pdf_name: d.string()?,
+            paperspec: d.string()?,
+            enable_compression: d.bool()?,
+            deterministic_tags: d.bool()?,
+            build_date: d.u64()?,
+            options: Box::new(d.pdf_options()?),
+        },
+
+        _ => return Err(malformed()),
+    };
+
+    Ok((job, has_shell_escape))
+}
+
+/// A bidirectional stream of length-prefixed messages.
+struct Channel {
+    reader: BufReader<Box<dyn Read>>,
+    writer: BufWriter<Box<dyn Write>>,
+}
+
+impl Channel {
+    fn new(reader: Box<dyn Read>, writer: Box<dyn Write>) -> Self {
+        Channel {
+            reader: BufReader::new(reader),
+            writer: BufWriter::new(writer),
+        }
+    }
+
+    /// Queue up a message. It won't necessarily be sent until the next call
+    /// to `flush`.
+    fn send(&mut self, msg: Encoder) -> io::Result<()> {
+        self.writer
+            .write_all(&(msg.buf.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&msg.buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn recv(&mut self) -> io::Result<Vec<u8>> {
+        let mut len = [0; 4];
+        self.reader.read_exact(&mut len)?;
+        let mut msg = vec![0; u32::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut msg)?;
+        Ok(msg)
+    }
+}
+
+// The parent side: start a worker and service its requests.
+
+/// Run an engine job in a new worker process, servicing its requests with
+/// the provided backends.
+pub(crate) fn run_job(
+    job: &EngineJob,
+    io: &mut dyn IoProvider,
+    events: &mut dyn IoEventBackend,
+    status: &mut dyn StatusBackend,
+    shell_escape: Option<Box<dyn ShellEscapeHandler>>,
+) -> Result<EngineOutcome> {
+    let exe = ctry!(env::current_exe(); "couldn't locate the current executable to start an engine worker");
+
+    let mut child = ctry!(
+        Command::new(exe)
+            .env(WORKER_ENV_VAR, "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn();
+        "couldn't start an engine worker process"
+    );
+
+    let reader = child.stdout.take().expect("worker stdout should be piped");
+    let writer = child.stdin.take().expect("worker stdin should be piped");
+    let mut chan = Channel::new(Box::new(reader), Box::new(writer));
+
+    let mut server = Server {
+        io,
+        events,
+        status,
+        shell_escape,
+        inputs: HashMap::new(),
+        outputs: HashMap::new(),
+        next_id: 0,
+    };
+
+    let result = server.serve(&mut chan, job);
+
+    if result.is_err() {
+        let _ = child.kill();
+    }
+
+    drop(chan);
+    let exit_status = child.wait();
+
+    match result {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            let exit = match exit_status {
+                Ok(s) => s.to_string(),
+                Err(e) => e.to_string(),
+            };
+            Err(errmsg!(
+                "lost contact with the engine worker process ({}): {}",
+                exit,
+                e
+            ))
+        }
+    }
+}
+
+/// An output file opened on behalf of a worker. Since writes don't get a
+/// reply, any error is stashed away and reported at the next flush.
+struct ServedOutput {
+    inner: Box<dyn Write>,
+    error: Option<io::Error>,
+}
+
+struct Server<'a> {
+    io: &'a mut dyn IoProvider,
+    events: &'a mut dyn IoEventBackend,
+    status: &'a mut dyn StatusBackend,
+    shell_escape: Option<Box<dyn ShellEscapeHandler>>,
+    inputs: HashMap<u32, Box<dyn InputFeatures>>,
+    outputs: HashMap<u32, ServedOutput>,
+    next_id: u32,
+}
+
+impl<'a> Server<'a> {
+    fn serve(&mut self, chan: &mut Channel, job: &EngineJob) -> io::Result<Result<EngineOutcome>> {
+        chan.send(encode_job(job, self.shell_escape.is_some()))?;
+        chan.flush()?;
+
+        loop {
+            let msg = chan.recv()?;
+            let mut d = Decoder::new(&msg);
+            let msg_tag = d.u8()?;
+
+            if msg_tag == tag::DONE {
+                return match d.u8()? {
+                    0 => Ok(Ok(EngineOutcome {
+                        code: d.i64()? as libc::c_int,
+                        error_message: d.string()?,
+                    })),
+                    _ => Ok(Err(d.causes()?)),
+                };
+            }
+
+            if let Some(reply) = self.handle(msg_tag, &mut d)? {
+                chan.send(reply)?;
+                chan.flush()?;
+            }
+        }
+    }
+
+    fn new_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn reply_output(&mut self, r: OpenResult<OutputHandle>) -> Encoder {
+        match r {
+            OpenResult::Ok(oh) => {
+                let id = self.new_id();
+                let e = Encoder::new().u8(0).u32(id).os_str(oh.name());
+                let inner = oh.into_inner();
+                self.outputs.insert(id, ServedOutput { inner, error: None });
+                e
+            }
+            OpenResult::NotAvailable => Encoder::new().u8(1),
+            OpenResult::Err(e) => Encoder::new().u8(2).causes(&e),
+        }
+    }
+
+    fn reply_input(&mut self, r: OpenResult<InputHandle>) -> Encoder {
+        match r {
+            OpenResult::Ok(ih) => {
+                let id = self.new_id();
+                let e = Encoder::new()
+                    .u8(0)
+                    .u32(id)
+                    .os_str(ih.name())
+                    .origin(ih.origin())
+                    .bool(ih.is_read_only());
+                self.inputs.insert(id, ih.into_inner());
+                e
+            }
+            OpenResult::NotAvailable => Encoder::new().u8(1),
+            OpenResult::Err(e) => Encoder::new().u8(2).causes(&e),
+        }
+    }
+
+    fn input(&mut self, id: u32) -> Result<&mut Box<dyn InputFeatures>> {
+        self.inputs
+            .get_mut(&id)
+            .ok_or_else(|| errmsg!("engine worker used unknown input handle {}", id))
+    }
+
+    /// Handle a message from the worker, returning the reply to send, if
+    /// the message expects one.
+    fn handle(&mut self, msg_tag: u8, d: &mut Decoder) -> io::Result<Option<Encoder>> {
+        let reply = match msg_tag {
+            tag::REPORT => {
+                let kind = d.message_kind()?;
+                let message = d.string()?;
+                let causes = d.strings()?;
+                let err = if causes.is_empty() {
+                    None
+                } else {
+                    Some(error_from_causes(causes))
+                };
+                self.status
+                    .report(kind, format_args!("{}", message), err.as_ref());
+                None
+            }
+
+            tag::REPORT_ERROR => {
+                let err = d.causes()?;
+                self.status.report_error(&err);
+                None
+            }
+
+            tag::REPORT_DIAGNOSTIC => {
+                let diag = SourceDiagnostic {
+                    kind: d.message_kind()?,
+                    message: d.string()?,
+                    file: d.opt_string()?,
+                    line: if d.bool()? { Some(d.u32()?) } else { None },
+                    context: d.strings()?,
+                };
+                self.status.report_diagnostic(&diag);
+                None
+            }
+
+            tag::NOTE_HIGHLIGHTED => {
+                let before = d.string()?;
+                let highlighted = d.string()?;
+                let after = d.string()?;
+                self.status.note_highlighted(&before, &highlighted, &after);
+                None
+            }
+
+            tag::DUMP_ERROR_LOGS => {
+                self.status.dump_error_logs(&d.bytes()?);
+                None
+            }
+
+            tag::OUTPUT_OPENED => {
+                self.events.output_opened(&d.os_string()?);
+                None
+            }
+
+            tag::STDOUT_OPENED => {
+                self.events.stdout_opened();
+                None
+            }
+
+            tag::OUTPUT_CLOSED => {
+                let name = d.os_string()?;
+                let digest = d.digest()?;
+                self.events.output_closed(name, digest);
+                None
+            }
+
+            tag::INPUT_OPENED => {
+                let name = d.os_string()?;
+                let origin = d.origin()?;
+                self.events.input_opened(&name, origin);
+                None
+            }
+
+            tag::PRIMARY_INPUT_OPENED => {
+                self.events.primary_input_opened(d.origin()?);
+                None
+            }
+
+            tag::INPUT_NOT_AVAILABLE => {
+                self.events.input_not_available(&d.os_string()?);
+                None
+            }
+
+            tag::INPUT_CLOSED => {
+                let name = d.os_string()?;
+                let digest = if d.bool()? { Some(d.digest()?) } else { None };
+                self.events.input_closed(name, digest);
+                None
+            }
+
+            tag::CALL_OUTPUT_OPEN_NAME => {
+                let r = self.io.output_open_name(&d.os_string()?);
+                Some(self.reply_output(r))
+            }
+
+            tag::CALL_OUTPUT_OPEN_STDOUT => {
+                let r = self.io.output_open_stdout();
+                Some(self.reply_output(r))
+            }
+
+            tag::OUTPUT_WRITE => {
+                let id = d.u32()?;
+                let data = d.bytes()?;
+
+                if let Some(out) = self.outputs.get_mut(&id) {
+                    if out.error.is_none() {
+                        if let Err(e) = out.inner.write_all(&data) {
+                            out.error = Some(e);
+                        }
+                    }
+                }
+
+                None
+            }
+
+            tag::CALL_OUTPUT_FLUSH => {
+                let id = d.u32()?;
+                let r = match self.outputs.get_mut(&id) {
+                    Some(out) => match out.error.take() {
+                        Some(e) => Err(e.into()),
+                        None => out.inner.flush().map_err(|e| e.into()),
+                    },
+                    None => Err(errmsg!("engine worker used unknown output handle {}", id)),
+                };
+                Some(Encoder::new().unit_result(r))
+            }
+
+            tag::OUTPUT_CLOSE => {
+                self.outputs.remove(&d.u32()?);
+                None
+            }
+
+            tag::CALL_INPUT_OPEN_NAME => {
+                let r = self.io.input_open_name(&d.os_string()?, self.status);
+                Some(self.reply_input(r))
+            }
+
+            tag::CALL_INPUT_OPEN_PRIMARY => {
+                let r = self.io.input_open_primary(self.status);
+                Some(self.reply_input(r))
+            }
+
+            tag::CALL_INPUT_OPEN_FORMAT => {
+                let r = self.io.input_open_format(&d.os_string()?, self.status);
+                Some(self.reply_input(r))
+            }
+
+            tag::CALL_INPUT_READ => {
+                let id = d.u32()?;
+                let len = cmp::min(d.u32()? as usize, MAX_READ_SIZE);
+                let r = self.input(id).and_then(|ih| {
+                    let mut buf = vec![0; len];
+                    let n = ih.read(&mut buf)?;
+                    buf.truncate(n);
+                    Ok(buf)
+                });
+                Some(match r {
+                    Ok(buf) => Encoder::new().u8(0).bytes(&buf),
+                    Err(e) => Encoder::new().u8(1).causes(&e),
+                })
+            }
+
+            tag::CALL_INPUT_GET_SIZE => {
+                let id = d.u32()?;
+                Some(match self.input(id).and_then(|ih| ih.get_size()) {
+                    Ok(size) => Encoder::new().u8(0).u64(size as u64),
+                    Err(e) => Encoder::new().u8(1).causes(&e),
+                })
+            }
+
+            tag::CALL_INPUT_GET_MTIME => {
+                let id = d.u32()?;
+                Some(match self.input(id).and_then(|ih| ih.get_unix_mtime()) {
+                    Ok(Some(t)) => Encoder::new().u8(0).bool(true).i64(t),
+                    Ok(None) => Encoder::new().u8(0).bool(false),
+                    Err(e) => Encoder::new().u8(1).causes(&e),
+                })
+            }
+
+            tag::CALL_INPUT_SEEK => {
+                let id = d.u32()?;
+                let pos = match d.u8()? {
+                    0 => SeekFrom::Start(d.u64()?),
+                    1 => SeekFrom::End(d.i64()?),
+                    2 => SeekFrom::Current(d.i64()?),
+                    _ => return Err(malformed()),
+                };
+                Some(match self.input(id).and_then(|ih| ih.try_seek(pos)) {
+                    Ok(offset) => Encoder::new().u8(0).u64(offset),
+                    Err(e) => Encoder::new().u8(1).causes(&e),
+                })
+            }
+
+            tag::INPUT_CLOSE => {
+                self.inputs.remove(&d.u32()?);
+                None
+            }
+
+            tag::CALL_SHELL_ESCAPE => {
+                let command = d.string()?;
+                let outcome = match self.shell_escape {
+                    Some(ref mut handler) => {
+                        handler.shell_escape(&command, self.io, self.events, self.status)
+                    }
+                    None => ShellEscapeOutcome::Disallowed,
+                };
+                Some(Encoder::new().u8(match outcome {
+                    ShellEscapeOutcome::Executed => 0,
+                    ShellEscapeOutcome::Disallowed => 1,
+                    ShellEscapeOutcome::Failed => 2,
+                }))
+            }
+
+            _ => return Err(malformed()),
+        };
+
+        Ok(reply)
+    }
+}
+
+// The worker side: proxies that forward everything to the parent.
+
+type SharedChannel = Rc<RefCell<Channel>>;
+
+/// If we lose contact with the parent, there's nobody left to report to, so
+/// all we can do is bail out.
+fn channel_failed(e: io::Error) -> ! {
+    eprintln!("tectonic engine worker: lost contact with parent: {}", e);
+    process::exit(1);
+}
+
+/// Send a message that doesn't expect a reply.
+fn notify(chan: &SharedChannel, msg: Encoder) {
+    if let Err(e) = chan.borrow_mut().send(msg) {
+        channel_failed(e);
+    }
+}
+
+/// Send a message and decode the parent's reply.
+fn call<T, F>(chan: &SharedChannel, msg: Encoder, decode: F) -> T
+where
+    F: FnOnce(&mut Decoder) -> io::Result<T>,
+{
+    let mut chan = chan.borrow_mut();
+
+    let reply = chan
+        .send(msg)
+        .and_then(|_| chan.flush())
+        .and_then(|_| chan.recv());
+
+    match reply.and_then(|reply| decode(&mut Decoder::new(&reply))) {
+        Ok(v) => v,
+        Err(e) => channel_failed(e),
+    }
+}
+
+fn worker_main() -> i32 {
+    let (reader, writer) = match protocol_streams() {
+        Ok(s) => s,
+        Err(e) => channel_failed(e),
+    };
+
+    let chan = Rc::new(RefCell::new(Channel::new(reader, writer)));
+
+    let msg = match chan.borrow_mut().recv() {
+        Ok(m) => m,
+        Err(e) => channel_failed(e),
+    };
+
+    let (job, has_shell_escape) = match decode_job(&mut Decoder::new(&msg)) {
+        Ok(j) => j,
+        Err(e) => channel_failed(e),
+    };
+
+    let shell_escape: Option<Box<dyn ShellEscapeHandler>> = if has_shell_escape {
+        Some(Box::new(RemoteShellEscape { chan: chan.clone() }))
+    } else {
+        None
+    };
+
+    let mut io = RemoteIo { chan: chan.clone() };
+    let mut events = RemoteEvents { chan: chan.clone() };
+    let mut status = RemoteStatus { chan: chan.clone() };
+
+    let msg = match job.invoke(&mut io, &mut events, &mut status, shell_escape) {
+        Ok(outcome) => Encoder::new()
+            .u8(tag::DONE)
+            .u8(0)
+            .i64(outcome.code as i64)
+            .str(&outcome.error_message),
+        Err(e) => Encoder::new().u8(tag::DONE).u8(1).causes(&e),
+    };
+
+    notify(&chan, msg);
+
+    if let Err(e) = chan.borrow_mut().flush() {
+        channel_failed(e);
+    }
+
+    0
+}
+
+/// Get the streams used to talk to the parent.
+#[cfg(unix)]
+fn protocol_streams() -> io::Result<(Box<dyn Read>, Box<dyn Write>)> {
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+
+    // The protocol runs over our stdin and stdout. Move those pipes to new
+    // descriptors and point stdout at stderr, so that anything the engines
+    // print directly can't corrupt the message stream.
+    unsafe {
+        let input = libc::dup(0);
+        let output = libc::dup(1);
+
+        if input < 0 || output < 0 || libc::dup2(2, 1) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((
+            Box::new(File::from_raw_fd(input)),
+            Box::new(File::from_raw_fd(output)),
+        ))
+    }
+}
+
+/// Get the streams used to talk to the parent. There's no way to keep the
+/// engines from writing into the message stream here, so workers are never
+/// started on these platforms; see [`activate`].
+#[cfg(not(unix))]
+fn protocol_streams() -> io::Result<(Box<dyn Read>, Box<dyn Write>)> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "engine workers are not supported on this platform",
+    ))
+}
+
+struct RemoteIo {
+    chan: SharedChannel,
+}
+
+impl RemoteIo {
+    fn open_output(&mut self, msg: Encoder) -> OpenResult<OutputHandle> {
+        let r = call(&self.chan, msg, |d| {
+            Ok(match d.u8()? {
+                0 => OpenResult::Ok((d.u32()?, d.os_string()?)),
+                1 => OpenResult::NotAvailable,
+                _ => OpenResult::Err(d.causes()?),
+            })
+        });
+
+        match r {
+            OpenResult::Ok((id, name)) => {
+                let out = RemoteOutput {
+                    chan: self.chan.clone(),
+                    id,
+                };
+                OpenResult::Ok(OutputHandle::new(
+                    &name,
+                    BufWriter::with_capacity(CHUNK_SIZE, out),
+                ))
+            }
+            OpenResult::NotAvailable => OpenResult::NotAvailable,
+            OpenResult::Err(e) => OpenResult::Err(e),
+        }
+    }
+
+    fn open_input(&mut self, msg: Encoder) -> OpenResult<InputHandle> {
+        let r = call(&self.chan, msg, |d| {
+            Ok(match d.u8()? {
+                0 => OpenResult::Ok((d.u32()?, d.os_string()?, d.origin()?, d.bool()?)),
+                1 => OpenResult::NotAvailable,
+                _ => OpenResult::Err(d.causes()?),
+            })
+        });
+
+        match r {
+            OpenResult::Ok((id, name, origin, read_only)) => {
+                let inner = RemoteInput {
+                    chan: self.chan.clone(),
+                    id,
+                    buf: Vec::new(),
+                    pos: 0,
+                };
+
+                OpenResult::Ok(if read_only {
+                    InputHandle::new_read_only(&name, inner, origin)
+                } else {
+                    InputHandle::new(&name, inner, origin)
+                })
+            }
+            OpenResult::NotAvailable => OpenResult::NotAvailable,
+            OpenResult::Err(e) => OpenResult::Err(e),
+        }
+    }
+}
+
+impl IoProvider for RemoteIo {
+    fn output_open_name(&mut self, name: &OsStr) -> OpenResult<OutputHandle> {
+        self.open_output(Encoder::new().u8(tag::CALL_OUTPUT_OPEN_NAME).os_str(name))
+    }
+
+    fn output_open_stdout(&mut self) -> OpenResult<OutputHandle> {
+        self.open_output(Encoder::new().u8(tag::CALL_OUTPUT_OPEN_STDOUT))
+    }
+
+    fn input_open_name(
+        &mut self,
+        name: &OsStr,
+        _status: &mut dyn StatusBackend,
+    ) -> OpenResult<InputHandle> {
+        self.open_input(Encoder::new().u8(tag::CALL_INPUT_OPEN_NAME).os_str(name))
+    }
+
+    fn input_open_primary(&mut self, _status: &mut dyn StatusBackend) -> OpenResult<InputHandle> {
+        self.open_input(Encoder::new().u8(tag::CALL_INPUT_OPEN_PRIMARY))
+    }
+
+    fn input_open_format(
+        &mut self,
+        name: &OsStr,
+        _status: &mut dyn StatusBackend,
+    ) -> OpenResult<InputHandle> {
+        self.open_input(Encoder::new().u8(tag::CALL_INPUT_OPEN_FORMAT).os_str(name))
+    }
+}
+
+struct RemoteOutput {
+    chan: SharedChannel,
+    id: u32,
+}
+
+impl Write for RemoteOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        notify(
+            &self.chan,
+            Encoder::new().u8(tag::OUTPUT_WRITE).u32(self.id).bytes(buf),
+        );
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let msg = Encoder::new().u8(tag::CALL_OUTPUT_FLUSH).u32(self.id);
+
+        call(&self.chan, msg, |d| d.unit_result())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    }
+}
+
+impl Drop for RemoteOutput {
+    fn drop(&mut self) {
+        notify(
+            &self.chan,
+            Encoder::new().u8(tag::OUTPUT_CLOSE).u32(self.id),
+        );
+    }
+}
+
+/// An input file served by the parent. Reads are done in large chunks to
+/// keep the number of round trips down, since the engines tend to read a
+/// byte at a time.
+struct RemoteInput {
+    chan: SharedChannel,
+    id: u32,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for RemoteInput {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            let len = if out.len() > CHUNK_SIZE {
+                cmp::min(out.len(), MAX_READ_SIZE)
+            } else {
+                CHUNK_SIZE
+            };
+            let msg = Encoder::new()
+                .u8(tag::CALL_INPUT_READ)
+                .u32(self.id)
+                .u32(len as u32);
+
+            let r = call(&self.chan, msg, |d| match d.u8()? {
+                0 => Ok(Ok(d.bytes()?)),
+                _ => Ok(Err(d.causes()?)),
+            });
+
+            self.buf = r.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            self.pos = 0;
+        }
+
+        let n = cmp::min(out.len(), self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl InputFeatures for RemoteInput {
+    fn get_size(&mut self) -> Result<usize> {
+        let msg = Encoder::new().u8(tag::CALL_INPUT_GET_SIZE).u32(self.id);
+
+        call(&self.chan, msg, |d| match d.u8()? {
+            0 => Ok(Ok(d.u64()? as usize)),
+            _ => Ok(Err(d.causes()?)),
+        })
+    }
+
+    fn get_unix_mtime(&mut self) -> Result<Option<i64>> {
+        let msg = Encoder::new().u8(tag::CALL_INPUT_GET_MTIME).u32(self.id);
+
+        call(&self.chan, msg, |d| match d.u8()? {
+            0 => Ok(Ok(if d.bool()? { Some(d.i64()?) } else { None })),
+            _ => Ok(Err(d.causes()?)),
+        })
+    }
+
+    fn try_seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        // The parent's idea of the file position is ahead of ours by
+        // whatever we have buffered, which gets discarded.
+        let buffered = (self.buf.len() - self.pos) as i64;
+        self.buf.clear();
+        self.pos = 0;
+
+        let msg = Encoder::new().u8(tag::CALL_INPUT_SEEK).u32(self.id);
+        let msg = match pos {
+            SeekFrom::Start(o) => msg.u8(0).u64(o),
+            SeekFrom::End(o) => msg.u8(1).i64(o),
+            SeekFrom::Current(o) => msg.u8(2).i64(o - buffered),
+        };
+
+        call(&self.chan, msg, |d| match d.u8()? {
+            0 => Ok(Ok(d.u64()?)),
+            _ => Ok(Err(d.causes()?)),
+        })
+    }
+}
+
+impl Drop for RemoteInput {
+    fn drop(&mut self) {
+        notify(&self.chan, Encoder::new().u8(tag::INPUT_CLOSE).u32(self.id));
+    }
+}
+
+struct RemoteEvents {
+    chan: SharedChannel,
+}
+
+impl IoEventBackend for RemoteEvents {
+    fn output_opened(&mut self, name: &OsStr) {
+        notify(
+            &self.chan,
+            Encoder::new().u8(tag::OUTPUT_OPENED).os_str(name),
+        );
+    }
+
+    fn stdout_opened(&mut self) {
+        notify(&self.chan, Encoder::new().u8(tag::STDOUT_OPENED));
+    }
+
+    fn output_closed(&mut self, name: OsString, digest: DigestData) {
+        notify(
+            &self.chan,
+            Encoder::new()
+                .u8(tag::OUTPUT_CLOSED)
+                .os_str(&name)
+                .digest(&digest),
+        );
+    }
+
+    fn input_opened(&mut self, name: &OsStr, origin: InputOrigin) {
+        notify(
+            &self.chan,
+            Encoder::new()
+                .u8(tag::INPUT_OPENED)
+                .os_str(name)
+                .origin(origin),
+        );
+    }
+
+    fn primary_input_opened(&mut self, origin: InputOrigin) {
+        notify(
+            &self.chan,
+            Encoder::new().u8(tag::PRIMARY_INPUT_OPENED).origin(origin),
+        );
+    }
+
+    fn input_not_available(&mut self, name: &OsStr) {
+        notify(
+            &self.chan,
+            Encoder::new().u8(tag::INPUT_NOT_AVAILABLE).os_str(name),
+        );
+    }
+
+    fn input_closed(&mut self, name: OsString, digest: Option<DigestData>) {
+        let msg = Encoder::new().u8(tag::INPUT_CLOSED).os_str(&name);
+        let msg = match digest {
+            Some(ref d) => msg.bool(true).digest(d),
+            None => msg.bool(false),
+        };
+        notify(&self.chan, msg);
+    }
+}
+
+struct RemoteStatus {
+    chan: SharedChannel,
+}
+
+impl StatusBackend for RemoteStatus {
+    fn report(&mut self, kind: MessageKind, args: std::fmt::Arguments, err: Option<&Error>) {
+        let msg = Encoder::new()
+            .u8(tag::REPORT)
+            .message_kind(kind)
+            .str(&args.to_string());
+        let msg = match err {
+            Some(e) => msg.causes(e),
+            None => msg.strings(&[]),
+        };
+        notify(&self.chan, msg);
+    }
+
+    fn report_error(&mut self, err: &Error) {
+        notify(&self.chan, Encoder::new().u8(tag::REPORT_ERROR).causes(err));
+    }
+
+    fn report_diagnostic(&mut self, diag: &SourceDiagnostic) {
+        let msg = Encoder::new()
+            .u8(tag::REPORT_DIAGNOSTIC)
+            .message_kind(diag.kind)
+            .str(&diag.message)
+            .opt_str(diag.file.as_deref());
+        let msg = match diag.line {
+            Some(line) => msg.bool(true).u32(line),
+            None => msg.bool(false),
+        };
+        notify(&self.chan, msg.strings(&diag.context));
+    }
+
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        notify(
+            &self.chan,
+            Encoder::new()
+                .u8(tag::NOTE_HIGHLIGHTED)
+                .str(before)
+                .str(highlighted)
+                .str(after),
+        );
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        notify(
+            &self.chan,
+            Encoder::new().u8(tag::DUMP_ERROR_LOGS).bytes(output),
+        );
+    }
+}
+
+struct RemoteShellEscape {
+    chan: SharedChannel,
+}
+
+impl ShellEscapeHandler for RemoteShellEscape {
+    fn shell_escape(
+        &mut self,
+        command: &str,
+        _io: &mut dyn IoProvider,
+        _events: &mut dyn IoEventBackend,
+        _status: &mut dyn StatusBackend,
+    ) -> ShellEscapeOutcome {
+        let msg = Encoder::new().u8(tag::CALL_SHELL_ESCAPE).str(command);
+
+        call(&self.chan, msg, |d| match d.u8()? {
+            0 => Ok(ShellEscapeOutcome::Executed),
+            1 => Ok(ShellEscapeOutcome::Disallowed),
+            2 => Ok(ShellEscapeOutcome::Failed),
+            _ => Err(malformed()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Encode a job and decode it again.
+    fn round_trip(job: &EngineJob, has_shell_escape: bool) {
+        let msg = encode_job(job, has_shell_escape);
+        let mut d = Decoder::new(&msg.buf);
+        let (decoded, decoded_shell_escape) = decode_job(&mut d).unwrap();
+        assert_eq!(&decoded, job);
+        assert_eq!(decoded_shell_escape, has_shell_escape);
+        assert!(d.buf.is_empty(), "trailing data after job");
+    }
+
+    #[test]
+    fn primitives() {
+        let digest: DigestData = "9b0b1b8e5ef1b1fa1de4cf3cb8ac1eb4de23f1e6aa6b1e2b3af9ae8e0b7d3e77"
+            .parse()
+            .unwrap();
+
+        let msg = Encoder::new()
+            .u8(7)
+            .bool(true)
+            .bool(false)
+            .u32(0xdead_beef)
+            .u64(u64::MAX)
+            .i64(-42)
+            .f64(-1.5)
+            .bytes(b"\x00\xff")
+            .str("h\u{e9}llo")
+            .os_str(OsStr::new("dir/file.tex"))
+            .strings(&["a".to_owned(), String::new()])
+            .opt_str(Some("x"))
+            .opt_str(None)
+            .digest(&digest)
+            .message_kind(MessageKind::Warning)
+            .origin(InputOrigin::Filesystem);
+
+        let mut d = Decoder::new(&msg.buf);
+        assert_eq!(d.u8().unwrap(), 7);
+        assert!(d.bool().unwrap());
+        assert!(!d.bool().unwrap());
+        assert_eq!(d.u32().unwrap(), 0xdead_beef);
+        assert_eq!(d.u64().unwrap(), u64::MAX);
+        assert_eq!(d.i64().unwrap(), -42);
+        assert_eq!(d.f64().unwrap(), -1.5);
+        assert_eq!(d.bytes().unwrap(), b"\x00\xff");
+        assert_eq!(d.string().unwrap(), "h\u{e9}llo");
+        assert_eq!(d.os_string().unwrap(), OsString::from("dir/file.tex"));
+        assert_eq!(d.strings().unwrap(), vec!["a".to_owned(), String::new()]);
+        assert_eq!(d.opt_string().unwrap().as_deref(), Some("x"));
+        assert_eq!(d.opt_string().unwrap(), None);
+        assert_eq!(d.digest().unwrap(), digest);
+        assert_eq!(d.message_kind().unwrap(), MessageKind::Warning);
+        assert_eq!(d.origin().unwrap(), InputOrigin::Filesystem);
+        assert!(d.buf.is_empty());
+
+        // Running off the end is an error, not a panic.
+        assert!(d.u8().is_err());
+        assert!(Decoder::new(&[5, 0, 0, 0, b'a']).bytes().is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let inner: Error = errmsg!("inner");
+        let err = Error::with_chain(inner, ErrorKind::Msg("outer".to_owned()));
+        let msg = Encoder::new().unit_result(Ok(())).unit_result(Err(err));
+
+        let mut d = Decoder::new(&msg.buf);
+        assert!(d.unit_result().unwrap().is_ok());
+
+        let causes: Vec<String> = d
+            .unit_result()
+            .unwrap()
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(causes, vec!["outer".to_owned(), "inner".to_owned()]);
+    }
+
+    #[test]
+    fn tex_job() {
+        round_trip(
+            &EngineJob::Tex {
+                format_name: "latex.fmt".to_owned(),
+                input_name: "texput.tex".to_owned(),
+                build_date: 1_600_000_000,
+                halt_on_error: true,
+                initex: false,
+                preload_format: true,
+                synctex: true,
+                semantic_pagination: false,
+                shell_escape: true,
+            },
+            true,
+        );
+    }
+
+    #[test]
+    fn bibtex_job() {
+        round_trip(
+            &EngineJob::Bibtex {
+                aux_name: "texput.aux".to_owned(),
+                min_crossrefs: -3,
+            },
+            false,
+        );
+    }
+
+    #[test]
+    fn xdvipdfmx_job() {
+        let job = |options: PdfOptions| EngineJob::Xdvipdfmx {
+            dvi_name: "texput.xdv".to_owned(),
+            pdf_name: "texput.pdf".to_owned(),
+            paperspec: "a4".to_owned(),
+            enable_compression: true,
+            deterministic_tags: true,
+            build_date: 0,
+            options: Box::new(options),
+        };
+
+        round_trip(&job(PdfOptions::default()), false);
+
+        for conformance in &[
+            PdfConformance::PdfA1b,
+            PdfConformance::PdfA2b,
+            PdfConformance::PdfX4,
+        ] {
+            round_trip(
+                &job(PdfOptions {
+                    version: (1, 7),
+                    compression_level: 3,
+                    annot_grow: 1.25,
+                    bookmark_open: -1,
+                    magnification: 2.0,
+                    font_dpi: 1200,
+                    thumbnails: true,
+                    decimal_digits: 2,
+                    x_offset: 0.0,
+                    y_offset: -36.5,
+                    encryption: Some(PdfEncryption {
+                        owner_password: "owner".to_owned(),
+                        user_password: String::new(),
+                        key_bits: 256,
+                        permissions: PdfPermissions::from_bits(0xF3C),
+                    }),
+                    conformance: Some(*conformance),
+                    output_intent: Some(PdfOutputIntent {
+                        profile: "sRGB.icc".to_owned(),
+                        identifier: "sRGB IEC61966-2.1".to_owned(),
+                    }),
+                    info: PdfDocInfo {
+                        title: Some("Title".to_owned()),
+                        authors: vec!["A".to_owned(), "B".to_owned()],
+                        subject: None,
+                        keywords: vec!["k".to_owned()],
+                        language: Some("en".to_owned()),
+                    },
+                }),
+                false,
+            );
+        }
+    }
+
+    #[test]
+    fn bad_job() {
+        let msg = Encoder::new().u8(tag::JOB).bool(false).u8(99);
+        assert!(decode_job(&mut Decoder::new(&msg.buf)).is_err());
+
+        let msg = Encoder::new().u8(tag::DONE);
+        assert!(decode_job(&mut Decoder::new(&msg.buf)).is_err());
+    }
+
+    /// A writer whose output can be inspected after it has been boxed up.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn framing() {
+        let wire = SharedBuf::default();
+        let mut sender = Channel::new(Box::new(io::empty()), Box::new(wire.clone()));
+        sender.send(Encoder::new().str("first")).unwrap();
+        sender.send(Encoder::new()).unwrap();
+        sender.send(Encoder::new().u32(3)).unwrap();
+        sender.flush().unwrap();
+
+        let data = wire.0.borrow().clone();
+        let mut receiver = Channel::new(Box::new(Cursor::new(data)), Box::new(io::sink()));
+        assert_eq!(
+            Decoder::new(&receiver.recv().unwrap()).string().unwrap(),
+            "first"
+        );
+        assert!(receiver.recv().unwrap().is_empty());
+        assert_eq!(Decoder::new(&receiver.recv().unwrap()).u32().unwrap(), 3);
+
+        // A peer that goes away shows up as an error.
+        assert!(receiver.recv().is_err());
+    }
+}
@@ -2,10 +2,12 @@
 // Copyright 2017 the Tectonic Project
 // Licensed under the MIT License.
 
-use std::ffi::{CStr, CString};
+use std::fmt;
+use std::str::FromStr;
 use std::time::SystemTime;
 
-use super::{ExecutionState, IoEventBackend, TectonicBridgeApi};
+use super::{EngineJob, IoEventBackend};
+use crate::errmsg;
 use crate::errors::{ErrorKind, Result};
 use crate::io::IoStack;
 use crate::status::StatusBackend;
@@ -13,13 +15,534 @@ use crate::unstable_opts::UnstableOptions;
 
 #[repr(C)]
 pub struct XdvipdfmxConfig {
-    paperspec: *const libc::c_char,
+    pub(crate) paperspec: *const libc::c_char,
+    pub(crate) pdf_version_major: i32,
+    pub(crate) pdf_version_minor: i32,
+    pub(crate) compression_level: i32,
+    pub(crate) annot_grow: f64,
+    pub(crate) bookmark_open: i32,
+    pub(crate) mag: f64,
+    pub(crate) font_dpi: i32,
+    pub(crate) enable_thumbnail: bool,
+    pub(crate) pdfdecimaldigits: i32,
+    pub(crate) x_offset: f64,
+    pub(crate) y_offset: f64,
+    pub(crate) enable_encryption: bool,
+    pub(crate) key_bits: i32,
+    pub(crate) permission: i32,
+    pub(crate) owner_password: *const libc::c_char,
+    pub(crate) user_password: *const libc::c_char,
+    pub(crate) conformance: i32,
+    pub(crate) output_intent_profile: *const libc::c_char,
+    pub(crate) output_intent_identifier: *const libc::c_char,
+    pub(crate) docinfo: *const libc::c_char,
+    pub(crate) language: *const libc::c_char,
+}
+
+/// Settings that control the PDF files created by xdvipdfmx.
+///
+/// The defaults are the ones that Tectonic has always used, which mostly
+/// match those of a standard TeXLive installation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PdfOptions {
+    /// The PDF version to target, as a `(major, minor)` pair. Versions 1.3
+    /// through 2.0 are supported. The default is 1.5.
+    pub version: (u8, u8),
+
+    /// The zlib compression level used for streams, from 0 to 9, if
+    /// compression is enabled. The default is 9.
+    pub compression_level: u8,
+
+    /// A margin, in big points, by which the clickable area of link
+    /// annotations is grown. The default is 0.
+    pub annot_grow: f64,
+
+    /// The depth to which bookmarks are expanded when the PDF is opened. A
+    /// negative value counts up from the deepest level. The default is 0.
+    pub bookmark_open: i32,
+
+    /// A magnification factor applied to the whole document. The default
+    /// is 1.
+    pub magnification: f64,
+
+    /// The resolution, in dots per inch, at which bitmap fonts are used.
+    /// The default is 600.
+    pub font_dpi: u32,
+
+    /// If true, page thumbnails are embedded from image files named after
+    /// the output file, with the page number as the extension: `doc.1`,
+    /// `doc.2`, and so on. The default is false.
+    pub thumbnails: bool,
+
+    /// The number of decimal digits used when writing out coordinates, from
+    /// 0 to 5. The default is 5.
+    pub decimal_digits: u8,
+
+    /// The offset, in big points, of the TeX origin from the left edge of
+    /// the page. The default is 72, or one inch.
+    pub x_offset: f64,
+
+    /// The offset, in big points, of the TeX origin from the top edge of the
+    /// page. The default is 72, or one inch.
+    pub y_offset: f64,
+
+    /// If set, the PDF is encrypted with these settings. The default is
+    /// not to encrypt.
+    pub encryption: Option<PdfEncryption>,
+
+    /// If set, the PDF is made to conform to this standard, and any content
+    /// that breaks it causes the build to fail. The default is not to
+    /// enforce any standard.
+    pub conformance: Option<PdfConformance>,
+
+    /// The output intent embedded in the PDF. If unset, conforming documents
+    /// get [the default intent][PdfOutputIntent::default_for] for their
+    /// standard, and other documents get none.
+    pub output_intent: Option<PdfOutputIntent>,
+
+    /// Metadata recorded in the PDF's document information dictionary. The
+    /// document itself can still override it with `pdf:docinfo` specials.
+    pub info: PdfDocInfo,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        PdfOptions {
+            version: (1, 5),
+            compression_level: 9,
+            annot_grow: 0.0,
+            bookmark_open: 0,
+            magnification: 1.0,
+            font_dpi: 600,
+            thumbnails: false,
+            decimal_digits: 5,
+            x_offset: 72.0,
+            y_offset: 72.0,
+            encryption: None,
+            conformance: None,
+            output_intent: None,
+            info: PdfDocInfo::default(),
+        }
+    }
+}
+
+impl PdfOptions {
+    /// Check that the options are within the ranges that xdvipdfmx supports.
+    pub fn validate(&self) -> Result<()> {
+        let (major, minor) = self.version;
+
+        if minor > 9 || !(13..=20).contains(&(major as u32 * 10 + minor as u32)) {
+            return Err(errmsg!(
+                "unsupported PDF version {}.{}; versions 1.3 through 2.0 are supported",
+                major,
+                minor
+            ));
+        }
+
+        if self.compression_level > 9 {
+            return Err(errmsg!(
+                "invalid PDF compression level {}; it must be between 0 and 9",
+                self.compression_level
+            ));
+        }
+
+        if self.decimal_digits > 5 {
+            return Err(errmsg!(
+                "invalid number of PDF decimal digits {}; it must be between 0 and 5",
+                self.decimal_digits
+            ));
+        }
+
+        if !self.magnification.is_finite() || self.magnification <= 0.0 {
+            return Err(errmsg!(
+                "invalid magnification {}; it must be positive",
+                self.magnification
+            ));
+        }
+
+        if self.font_dpi == 0 || self.font_dpi > i32::MAX as u32 {
+            return Err(errmsg!("invalid font resolution {} DPI", self.font_dpi));
+        }
+
+        if let Some(ref enc) = self.encryption {
+            let version = major as u32 * 10 + minor as u32;
+
+            match enc.key_bits {
+                40 => {}
+                48..=128 if enc.key_bits % 8 == 0 => {
+                    if version < 15 {
+                        return Err(errmsg!(
+                            "{}-bit PDF encryption requires PDF version 1.5 or later",
+                            enc.key_bits
+                        ));
+                    }
+                }
+                256 => {
+                    if version < 17 {
+                        return Err(errmsg!(
+                            "256-bit PDF encryption requires PDF version 1.7 or later"
+                        ));
+                    }
+                }
+                _ => {
+                    return Err(errmsg!(
+                        "unsupported PDF encryption key length of {} bits",
+                        enc.key_bits
+                    ));
+                }
+            }
+
+            for (what, pw) in &[("owner", &enc.owner_password), ("user", &enc.user_password)] {
+                if pw.len() > 127 || pw.contains('\0') {
+                    return Err(errmsg!(
+                        "the PDF {} password must be at most 127 bytes, with no NUL characters",
+                        what
+                    ));
+                }
+            }
+        }
+
+        if let Some(conformance) = self.conformance {
+            let version = major as u32 * 10 + minor as u32;
+            let max = conformance.max_version();
+
+            if version > max.0 as u32 * 10 + max.1 as u32 {
+                return Err(errmsg!(
+                    "{} requires PDF version {}.{} or earlier, but {}.{} was requested",
+                    conformance,
+                    max.0,
+                    max.1,
+                    major,
+                    minor
+                ));
+            }
+
+            if self.encryption.is_some() {
+                return Err(errmsg!("{} does not allow encryption", conformance));
+            }
+        }
+
+        if let Some(ref intent) = self.output_intent {
+            if intent.profile.is_empty() || intent.profile.contains('\0') {
+                return Err(errmsg!("invalid output intent ICC profile name"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The output intent that will be embedded in the PDF, if any.
+    pub fn effective_output_intent(&self) -> Option<PdfOutputIntent> {
+        self.output_intent
+            .clone()
+            .or_else(|| self.conformance.map(PdfOutputIntent::default_for))
+    }
+}
+
+/// A standard that restricts what a PDF file may contain, usually for the
+/// sake of archiving or printing.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PdfConformance {
+    /// PDF/A-1b (ISO 19005-1, level B), for long-term archiving. Based on
+    /// PDF 1.4; forbids transparency.
+    PdfA1b,
+
+    /// PDF/A-2b (ISO 19005-2, level B), for long-term archiving. Based on
+    /// PDF 1.7.
+    PdfA2b,
+
+    /// PDF/X-4 (ISO 15930-7), for exchanging print-ready documents. Based on
+    /// PDF 1.6; forbids annotations such as hyperlinks on the page.
+    PdfX4,
+}
+
+impl PdfConformance {
+    /// The latest PDF version that the standard allows.
+    pub fn max_version(self) -> (u8, u8) {
+        match self {
+            PdfConformance::PdfA1b => (1, 4),
+            PdfConformance::PdfA2b => (1, 7),
+            PdfConformance::PdfX4 => (1, 6),
+        }
+    }
+
+    /// The code used for this level by the C code.
+    pub(crate) fn to_c(self) -> i32 {
+        match self {
+            PdfConformance::PdfA1b => 1,
+            PdfConformance::PdfA2b => 2,
+            PdfConformance::PdfX4 => 3,
+        }
+    }
+}
+
+impl fmt::Display for PdfConformance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PdfConformance::PdfA1b => "PDF/A-1b",
+            PdfConformance::PdfA2b => "PDF/A-2b",
+            PdfConformance::PdfX4 => "PDF/X-4",
+        })
+    }
+}
+
+impl FromStr for PdfConformance {
+    type Err = crate::errors::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "pdf/a-1b" => Ok(PdfConformance::PdfA1b),
+            "pdf/a-2b" => Ok(PdfConformance::PdfA2b),
+            "pdf/x-4" => Ok(PdfConformance::PdfX4),
+            _ => Err(errmsg!(
+                "unknown PDF conformance level `{}`; expected one of `pdf/a-1b`, `pdf/a-2b` or `pdf/x-4`",
+                s
+            )),
+        }
+    }
+}
+
+/// The intended output device of a PDF, described by an ICC color profile.
+///
+/// PDF/A and PDF/X documents must declare one, so that their colors can be
+/// reproduced reliably.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PdfOutputIntent {
+    /// The name of the ICC profile file, which is looked up like any other
+    /// input file.
+    pub profile: String,
+
+    /// A name for the output condition, such as `FOGRA39` or `sRGB
+    /// IEC61966-2.1`.
+    pub identifier: String,
+}
+
+impl PdfOutputIntent {
+    /// The default intent for a conformance level: sRGB for PDF/A, and
+    /// coated offset printing (FOGRA39) for PDF/X.
+    pub fn default_for(conformance: PdfConformance) -> Self {
+        match conformance {
+            PdfConformance::PdfA1b | PdfConformance::PdfA2b => PdfOutputIntent {
+                profile: "sRGB.icc".to_owned(),
+                identifier: "sRGB IEC61966-2.1".to_owned(),
+            },
+            PdfConformance::PdfX4 => PdfOutputIntent {
+                profile: "FOGRA39L.icc".to_owned(),
+                identifier: "FOGRA39".to_owned(),
+            },
+        }
+    }
+}
+
+/// Metadata describing a PDF document. Unset fields are left out of the
+/// file.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PdfDocInfo {
+    /// The document's title.
+    pub title: Option<String>,
+
+    /// The document's authors, in order.
+    pub authors: Vec<String>,
+
+    /// The subject of the document.
+    pub subject: Option<String>,
+
+    /// Keywords associated with the document.
+    pub keywords: Vec<String>,
+
+    /// The natural language of the document, as a language tag such as
+    /// `en-US`. This goes in the document catalog rather than the
+    /// information dictionary.
+    pub language: Option<String>,
+}
+
+impl PdfDocInfo {
+    /// Render the information dictionary entries as a PDF dictionary, like
+    /// the argument of a `pdf:docinfo` special. Returns an empty string if
+    /// there are no entries.
+    pub(crate) fn to_pdf_dict(&self) -> String {
+        let mut entries = Vec::new();
+
+        if let Some(ref title) = self.title {
+            entries.push(("Title", title.clone()));
+        }
+
+        if !self.authors.is_empty() {
+            entries.push(("Author", self.authors.join("; ")));
+        }
+
+        if let Some(ref subject) = self.subject {
+            entries.push(("Subject", subject.clone()));
+        }
+
+        if !self.keywords.is_empty() {
+            entries.push(("Keywords", self.keywords.join(", ")));
+        }
+
+        if entries.is_empty() {
+            return String::new();
+        }
+
+        let mut dict = "<<".to_owned();
+
+        for (key, value) in entries {
+            dict.push_str(&format!(" /{} {}", key, pdf_text_string(&value)));
+        }
+
+        dict.push_str(" >>");
+        dict
+    }
+}
+
+/// Encode text as a PDF string. ASCII text is written as a literal string,
+/// and anything else as UTF-16 with a byte order mark, as PDF requires.
+fn pdf_text_string(text: &str) -> String {
+    if text.is_ascii() {
+        let mut s = "(".to_owned();
+
+        for c in text.chars() {
+            match c {
+                '(' | ')' | '\\' => {
+                    s.push('\\');
+                    s.push(c);
+                }
+                '\n' => s.push_str("\\n"),
+                '\r' => s.push_str("\\r"),
+                c => s.push(c),
+            }
+        }
+
+        s.push(')');
+        s
+    } else {
+        let mut s = "<FEFF".to_owned();
+
+        for unit in text.encode_utf16() {
+            s.push_str(&format!("{:04X}", unit));
+        }
+
+        s.push('>');
+        s
+    }
+}
+
+/// Settings for encrypting a PDF file.
+///
+/// Anyone can open an encrypted PDF if the user password is empty, but
+/// conforming viewers will only grant the listed permissions unless the
+/// owner password is supplied.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PdfEncryption {
+    /// The password that unlocks full access to the document. At most 127
+    /// bytes are allowed.
+    pub owner_password: String,
+
+    /// The password needed to open the document at all. If empty, as it is
+    /// by default, no password is needed. At most 127 bytes are allowed.
+    pub user_password: String,
+
+    /// The length of the encryption key in bits. A 40-bit key uses RC4
+    /// encryption; longer keys, which must be a multiple of 8 bits up to
+    /// 128, use AES and require PDF 1.5 or later. A 256-bit key uses
+    /// AES-256 and requires PDF 1.7 or later. The default is 128.
+    pub key_bits: u32,
+
+    /// What users may do with the document without the owner password.
+    pub permissions: PdfPermissions,
+}
+
+impl Default for PdfEncryption {
+    fn default() -> Self {
+        PdfEncryption {
+            owner_password: String::new(),
+            user_password: String::new(),
+            key_bits: 128,
+            permissions: PdfPermissions::default(),
+        }
+    }
+}
+
+/// The operations that a PDF viewer allows on an encrypted document when
+/// the owner password hasn't been given.
+///
+/// The default matches that of xdvipdfmx: printing, modification, copying
+/// and annotation are permitted, and nothing else.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PdfPermissions {
+    /// Print the document, possibly at a low resolution.
+    pub print: bool,
+
+    /// Modify the document in ways not covered by the other permissions.
+    pub modify: bool,
+
+    /// Copy or otherwise extract text and graphics.
+    pub copy: bool,
+
+    /// Add or modify annotations, and fill in forms.
+    pub annotate: bool,
+
+    /// Fill in existing form fields, even if `annotate` isn't permitted.
+    pub fill_forms: bool,
+
+    /// Extract text and graphics for accessibility purposes.
+    pub extract_for_accessibility: bool,
+
+    /// Insert, rotate or delete pages and create bookmarks or thumbnails.
+    pub assemble: bool,
+
+    /// Print the document at full quality.
+    pub print_high_quality: bool,
+}
+
+impl Default for PdfPermissions {
+    fn default() -> Self {
+        PdfPermissions::from_bits(0x3C)
+    }
+}
+
+impl PdfPermissions {
+    /// Decode permissions from the `P` entry of a PDF encryption dictionary.
+    /// Unknown bits are ignored.
+    pub fn from_bits(bits: u32) -> Self {
+        // Bits are numbered from 1 in the PDF specification.
+        let has = |n: u32| bits & (1 << (n - 1)) != 0;
+
+        PdfPermissions {
+            print: has(3),
+            modify: has(4),
+            copy: has(5),
+            annotate: has(6),
+            fill_forms: has(9),
+            extract_for_accessibility: has(10),
+            assemble: has(11),
+            print_high_quality: has(12),
+        }
+    }
+
+    /// Encode these permissions as the `P` entry of a PDF encryption
+    /// dictionary.
+    pub fn to_bits(self) -> u32 {
+        [
+            (self.print, 3),
+            (self.modify, 4),
+            (self.copy, 5),
+            (self.annotate, 6),
+            (self.fill_forms, 9),
+            (self.extract_for_accessibility, 10),
+            (self.assemble, 11),
+            (self.print_high_quality, 12),
+        ]
+        .iter()
+        .filter(|(allowed, _)| *allowed)
+        .fold(0, |bits, (_, n)| bits | (1 << (n - 1)))
+    }
 }
 
 pub struct XdvipdfmxEngine {
     enable_compression: bool,
     deterministic_tags: bool,
     build_date: SystemTime,
+    options: PdfOptions,
 }
 
 impl XdvipdfmxEngine {
@@ -28,6 +551,7 @@ impl XdvipdfmxEngine {
             enable_compression: true,
             deterministic_tags: false,
             build_date: SystemTime::UNIX_EPOCH,
+            options: PdfOptions::default(),
         }
     }
 
@@ -50,6 +574,100 @@ impl XdvipdfmxEngine {
         self
     }
 
+    /// Sets all of the PDF output options at once.
+    pub fn with_pdf_options(mut self, options: PdfOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Sets the PDF version to target. See [`PdfOptions::version`].
+    pub fn with_pdf_version(mut self, major: u8, minor: u8) -> Self {
+        self.options.version = (major, minor);
+        self
+    }
+
+    /// Sets the compression level used when compression is enabled.
+    pub fn with_compression_level(mut self, level: u8) -> Self {
+        self.options.compression_level = level;
+        self
+    }
+
+    /// Sets the margin added around link annotations, in big points.
+    pub fn with_annot_grow(mut self, amount: f64) -> Self {
+        self.options.annot_grow = amount;
+        self
+    }
+
+    /// Sets the depth to which bookmarks are initially expanded.
+    pub fn with_bookmark_open(mut self, depth: i32) -> Self {
+        self.options.bookmark_open = depth;
+        self
+    }
+
+    /// Sets the magnification factor applied to the document.
+    pub fn with_magnification(mut self, mag: f64) -> Self {
+        self.options.magnification = mag;
+        self
+    }
+
+    /// Sets the resolution at which bitmap fonts are used.
+    pub fn with_font_dpi(mut self, dpi: u32) -> Self {
+        self.options.font_dpi = dpi;
+        self
+    }
+
+    /// Sets whether page thumbnails are embedded. See
+    /// [`PdfOptions::thumbnails`].
+    pub fn with_thumbnails(mut self, flag: bool) -> Self {
+        self.options.thumbnails = flag;
+        self
+    }
+
+    /// Sets the number of decimal digits used for coordinates.
+    pub fn with_decimal_digits(mut self, digits: u8) -> Self {
+        self.options.decimal_digits = digits;
+        self
+    }
+
+    /// Sets the offset of the TeX origin from the top left corner of the
+    /// page, in big points.
+    pub fn with_offsets(mut self, x: f64, y: f64) -> Self {
+        self.options.x_offset = x;
+        self.options.y_offset = y;
+        self
+    }
+
+    /// Sets how the PDF is encrypted, if at all. See [`PdfEncryption`].
+    pub fn with_encryption(mut self, encryption: Option<PdfEncryption>) -> Self {
+        self.options.encryption = encryption;
+        self
+    }
+
+    /// Sets the standard that the PDF must conform to, if any. See
+    /// [`PdfConformance`]. The PDF version is lowered if the standard
+    /// requires it.
+    pub fn with_conformance(mut self, conformance: Option<PdfConformance>) -> Self {
+        if let Some(c) = conformance {
+            self.options.version = self.options.version.min(c.max_version());
+        }
+
+        self.options.conformance = conformance;
+        self
+    }
+
+    /// Sets the output intent embedded in the PDF. See
+    /// [`PdfOptions::output_intent`].
+    pub fn with_output_intent(mut self, intent: Option<PdfOutputIntent>) -> Self {
+        self.options.output_intent = intent;
+        self
+    }
+
+    /// Set the metadata of the PDF. See [`PdfOptions::info`].
+    pub fn with_doc_info(mut self, info: PdfDocInfo) -> Self {
+        self.options.info = info;
+        self
+    }
+
     pub fn process(
         &mut self,
         io: &mut IoStack,
@@ -59,53 +677,30 @@ impl XdvipdfmxEngine {
         pdf: &str,
         unstables: &UnstableOptions,
     ) -> Result<i32> {
-        let _guard = super::ENGINE_LOCK.lock().unwrap(); // until we're thread-safe ...
-
-        // This conversion is probably way too complex, because we need to convert String to
-        // something which holds a CStr (which needs to be a local so it doesn't disappear). And
-        // all of this happens in an Option.
+        self.options.validate()?;
 
-        // Keep a local reference so the string doesn't get dropped too early
-        let paperspec_str = unstables
-            .paper_size
-            .as_ref()
-            .and_then(|s| CString::new(s.clone()).ok());
-
-        // We default to "letter" paper size by default
-        let paperspec_default = CStr::from_bytes_with_nul(b"letter\0").unwrap();
-
-        let config = XdvipdfmxConfig {
-            paperspec: paperspec_str
-                .as_ref()
-                .map_or(paperspec_default.as_ptr(), |s| s.as_ptr()),
+        let job = EngineJob::Xdvipdfmx {
+            dvi_name: dvi.to_owned(),
+            pdf_name: pdf.to_owned(),
+            // We default to "letter" paper size by default
+            paperspec: unstables
+                .paper_size
+                .clone()
+                .unwrap_or_else(|| "letter".to_owned()),
+            enable_compression: self.enable_compression,
+            deterministic_tags: self.deterministic_tags,
+            build_date: self
+                .build_date
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .expect("invalid build date")
+                .as_secs(),
+            options: Box::new(self.options.clone()),
         };
+        let outcome = job.run(io, events, status, None)?;
 
-        let cdvi = CString::new(dvi)?;
-        let cpdf = CString::new(pdf)?;
-
-        let mut state = ExecutionState::new(io, events, status);
-        let bridge = TectonicBridgeApi::new(&mut state);
-
-        unsafe {
-            match super::dvipdfmx_simple_main(
-                &bridge,
-                &config,
-                cdvi.as_ptr(),
-                cpdf.as_ptr(),
-                self.enable_compression,
-                self.deterministic_tags,
-                self.build_date
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .expect("invalid build date")
-                    .as_secs() as libc::time_t,
-            ) {
-                99 => {
-                    let ptr = super::tt_get_error_message();
-                    let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
-                    Err(ErrorKind::Msg(msg).into())
-                }
-                x => Ok(x as i32),
-            }
+        match outcome.code {
+            99 => Err(ErrorKind::Msg(outcome.error_message).into()),
+            x => Ok(x as i32),
         }
     }
 }
@@ -115,3 +710,57 @@ impl Default for XdvipdfmxEngine {
         XdvipdfmxEngine::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn docinfo_dict() {
+        assert_eq!(PdfDocInfo::default().to_pdf_dict(), "");
+
+        // The language goes in the catalog, not the info dictionary.
+        let info = PdfDocInfo {
+            language: Some("en-US".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(info.to_pdf_dict(), "");
+
+        let info = PdfDocInfo {
+            title: Some("Notes (draft)".to_owned()),
+            authors: vec!["Ann".to_owned(), "Bob".to_owned()],
+            subject: Some("Testing".to_owned()),
+            keywords: vec!["pdf".to_owned(), "tex".to_owned()],
+            language: Some("en-US".to_owned()),
+        };
+        assert_eq!(
+            info.to_pdf_dict(),
+            "<< /Title (Notes \\(draft\\)) /Author (Ann; Bob) /Subject (Testing) \
+             /Keywords (pdf, tex) >>"
+        );
+    }
+
+    #[test]
+    fn text_string_escaping() {
+        assert_eq!(pdf_text_string(""), "()");
+        assert_eq!(pdf_text_string("plain"), "(plain)");
+        assert_eq!(pdf_text_string("a(b)c"), "(a\\(b\\)c)");
+        assert_eq!(pdf_text_string("back\\slash"), "(back\\\\slash)");
+        assert_eq!(pdf_text_string("one\ntwo\r"), "(one\\ntwo\\r)");
+    }
+
+    #[test]
+    fn text_string_utf16() {
+        assert_eq!(pdf_text_string("Café"), "<FEFF00430061006600E9>");
+
+        // Characters outside the BMP become surrogate pairs, and nothing is
+        // escaped in hex strings.
+        assert_eq!(pdf_text_string("(\u{1D11E})"), "<FEFF0028D834DD1E0029>");
+
+        let info = PdfDocInfo {
+            title: Some("Über".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(info.to_pdf_dict(), "<< /Title <FEFF00DC006200650072> >>");
+    }
+}
@@ -10,9 +10,10 @@ use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs::{self, File};
 use std::io::ErrorKind as IoErrorKind;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
 use super::{try_open_file, Bundle, InputHandle, InputOrigin, IoProvider, OpenResult};
 use crate::app_dirs;
@@ -659,6 +660,113 @@ impl CachedITarBundle {
 
         OpenResult::Ok(final_path)
     }
+
+    /// Get the names of all of the files in the bundle, in sorted order.
+    pub fn all_files(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.index.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Figure out which files an export should contain: the requested names
+    /// that actually exist in the bundle, plus the digest file, so that the
+    /// exported bundle reports the same digest as this one.
+    fn export_names<'a, I: IntoIterator<Item = &'a str>>(&self, names: I) -> Vec<String> {
+        let mut names: Vec<String> = names
+            .into_iter()
+            .filter(|n| self.index.contains_key(*n))
+            .map(|n| n.to_owned())
+            .collect();
+        names.push(digest::DIGEST_NAME.to_owned());
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Get the contents of a file for export, downloading it if needed.
+    fn export_data(&mut self, name: &str, status: &mut dyn StatusBackend) -> Result<Vec<u8>> {
+        let path = match self.path_for_name(name, status) {
+            OpenResult::Ok(p) => p,
+            OpenResult::NotAvailable => {
+                bail!("bundle file \"{}\" is not available locally", name);
+            }
+            OpenResult::Err(e) => return Err(e),
+        };
+
+        Ok(ctry!(fs::read(&path); "couldn't read cached bundle file \"{}\"", path.display()))
+    }
+
+    /// Export files from this bundle into a Zip archive that can be loaded
+    /// with [`super::zipbundle::ZipBundle`].
+    ///
+    /// Only the named files that exist in the bundle are exported; the
+    /// bundle’s digest file is always included, so that the new bundle
+    /// reports the same digest as this one. Files that aren’t in the local
+    /// cache are downloaded first. Returns the number of files exported.
+    pub fn export_zip<'a, I, W>(
+        &mut self,
+        names: I,
+        dest: W,
+        status: &mut dyn StatusBackend,
+    ) -> Result<usize>
+    where
+        I: IntoIterator<Item = &'a str>,
+        W: Write + Seek,
+    {
+        let names = self.export_names(names);
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        let mut zip = ZipWriter::new(dest);
+
+        for name in &names {
+            let data = self.export_data(name, status)?;
+            zip.start_file(name.as_str(), options)?;
+            ctry!(zip.write_all(&data); "couldn't write \"{}\" to the Zip archive", name);
+        }
+
+        zip.finish()?;
+        Ok(names.len())
+    }
+
+    /// Export files from this bundle into a directory that can be loaded
+    /// with [`super::dirbundle::DirBundle`].
+    ///
+    /// The selection of files follows the same rules as
+    /// [`CachedITarBundle::export_zip`]. The directory is created if
+    /// needed. Returns the number of files exported.
+    pub fn export_dir<'a, I>(
+        &mut self,
+        names: I,
+        dest: &Path,
+        status: &mut dyn StatusBackend,
+    ) -> Result<usize>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let names = self.export_names(names);
+
+        for name in &names {
+            if !Path::new(name)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+            {
+                bail!(
+                    "refusing to export bundle file with unsafe name \"{}\"",
+                    name
+                );
+            }
+
+            let data = self.export_data(name, status)?;
+            let path = dest.join(name);
+
+            if let Some(parent) = path.parent() {
+                ctry!(fs::create_dir_all(parent); "failed to create directory {}", parent.display());
+            }
+
+            file_create_write(&path, |f| f.write_all(&data))?;
+        }
+
+        Ok(names.len())
+    }
 }
 
 impl IoProvider for CachedITarBundle {
@@ -6,11 +6,12 @@
 //! Code for locally caching compiled format files.
 
 use std::ffi::OsStr;
-use std::io::{BufReader, Write};
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
 
 use super::{InputHandle, InputOrigin, IoProvider, OpenResult};
-use crate::digest::DigestData;
+use crate::digest::{self, Digest, DigestData};
 use crate::errors::{ErrorKind, Result};
 use crate::status::StatusBackend;
 
@@ -20,11 +21,35 @@ use crate::status::StatusBackend;
 /// same root cache directory as the `LocalCache` item, but is implemented
 /// separately so that there is a way to save the format files associated with
 /// backends that may not have their own LocalCache.
+///
+/// Formats can be built from files found on the local filesystem as well as
+/// from the bundle. Since the bundle digest can't tell us when those files
+/// change, each format is saved alongside a small manifest listing every
+/// name that the engine looked for while generating it. A format is only
+/// used if every file in its manifest still has the same contents, and every
+/// file that was missing or came from the bundle is still absent from the
+/// filesystem. Otherwise, it is reported as unavailable so that it gets
+/// regenerated.
+///
+/// Only custom formats, whose `tectonic-format-<stem>.tex` source file is
+/// found under the inputs root, should depend on local files. Since they can
+/// differ from one directory tree to the next, their file names include a
+/// digest of the inputs root, so that they don't replace each other in the
+/// cache. The standard formats are built from the bundle alone, and are
+/// shared. The manifest is a line-oriented text file:
+///
+/// ```text
+/// tectonic-format-inputs 1
+/// input <digest, or "-" if the file must not exist> <name>
+/// ```
 pub struct FormatCache {
     bundle_digest: DigestData,
     formats_base: PathBuf,
+    inputs_root: PathBuf,
 }
 
+const INPUTS_MANIFEST_HEADER: &str = "tectonic-format-inputs 1";
+
 impl FormatCache {
     /// Create a new `FormatCache`.
     ///
@@ -35,6 +60,38 @@ impl FormatCache {
         FormatCache {
             bundle_digest,
             formats_base,
+            inputs_root: PathBuf::new(),
+        }
+    }
+
+    /// Set the directory relative to which the names recorded in format
+    /// input manifests are resolved.
+    ///
+    /// This should match the root of the filesystem I/O layer used when
+    /// generating formats. By default, names are resolved relative to the
+    /// current directory.
+    pub fn set_inputs_root<P: AsRef<Path>>(&mut self, root: P) {
+        self.inputs_root = root.as_ref().to_owned();
+    }
+
+    /// Get the directory relative to which format inputs are resolved.
+    pub fn inputs_root(&self) -> &Path {
+        &self.inputs_root
+    }
+
+    /// Check whether the named format is a custom one, built from a
+    /// `tectonic-format-<stem>.tex` file found under the inputs root rather
+    /// than from the bundle.
+    ///
+    /// Only custom formats should be generated with access to the local
+    /// filesystem.
+    pub fn is_local_format(&self, name: &OsStr) -> bool {
+        match name.to_str().and_then(|s| s.split('.').next()) {
+            Some(stem) => self
+                .inputs_root
+                .join(format!("tectonic-format-{}.tex", stem))
+                .is_file(),
+            None => false,
         }
     }
 
@@ -56,14 +113,186 @@ impl FormatCache {
         };
 
         let mut p = self.formats_base.clone();
-        p.push(format!(
-            "{}-{}-{}.fmt",
-            self.bundle_digest.to_string(),
-            stem,
-            crate::FORMAT_SERIAL
-        ));
+
+        if self.is_local_format(name) {
+            let mut dc = digest::create();
+            dc.update(self.inputs_root.to_string_lossy().as_bytes());
+            let root_digest = DigestData::from(dc).to_string();
+
+            p.push(format!(
+                "{}-{}-{}-{}.fmt",
+                self.bundle_digest.to_string(),
+                stem,
+                &root_digest[..16],
+                crate::FORMAT_SERIAL
+            ));
+        } else {
+            p.push(format!(
+                "{}-{}-{}.fmt",
+                self.bundle_digest.to_string(),
+                stem,
+                crate::FORMAT_SERIAL
+            ));
+        }
+
         Ok(p)
     }
+
+    /// Get the path of the input manifest that goes with a format file.
+    fn inputs_path_for_format(&mut self, name: &OsStr) -> Result<PathBuf> {
+        Ok(self.path_for_format(name)?.with_extension("inputs"))
+    }
+
+    /// Load the inputs recorded in a format's manifest, returning None if it
+    /// does not exist or can't be understood.
+    fn load_inputs(manifest_path: &Path) -> Option<Vec<(String, Option<DigestData>)>> {
+        let f = File::open(manifest_path).ok()?;
+        let mut lines = BufReader::new(f).lines();
+
+        if lines.next()?.ok()? != INPUTS_MANIFEST_HEADER {
+            return None;
+        }
+
+        let mut inputs = Vec::new();
+
+        for line in lines {
+            let line = line.ok()?;
+            let mut pieces = line.splitn(3, ' ');
+
+            if pieces.next()? != "input" {
+                return None;
+            }
+
+            let digest = match pieces.next()? {
+                "-" => None,
+                d => Some(d.parse().ok()?),
+            };
+            inputs.push((pieces.next()?.to_owned(), digest));
+        }
+
+        Some(inputs)
+    }
+
+    /// Check whether the inputs recorded for a format are unchanged. Missing
+    /// or unreadable manifests count as changes. (Formats cached before
+    /// manifests were introduced have an older `FORMAT_SERIAL` in their file
+    /// names, so they are never looked at.)
+    fn inputs_are_current(&self, manifest_path: &Path) -> bool {
+        let inputs = match FormatCache::load_inputs(manifest_path) {
+            Some(i) => i,
+            None => return false,
+        };
+
+        inputs
+            .iter()
+            .all(|(name, expected)| match self.digest_input(name) {
+                Ok(ref current) => current == expected,
+                Err(_) => false,
+            })
+    }
+
+    /// Get the inputs that were recorded when a format was saved, as passed
+    /// to [`FormatCache::write_format_with_inputs`].
+    ///
+    /// This lets callers that use a cached format learn which files it
+    /// depends on, without regenerating it.
+    pub fn format_inputs(&mut self, name: &OsStr) -> Result<Vec<(String, Option<DigestData>)>> {
+        let path = self.inputs_path_for_format(name)?;
+
+        match FormatCache::load_inputs(&path) {
+            Some(inputs) => Ok(inputs),
+            None => Err(ErrorKind::Msg(format!(
+                "cannot read the list of inputs for format \"{}\"",
+                name.to_string_lossy()
+            ))
+            .into()),
+        }
+    }
+
+    /// Compute the digest of a recorded input as it currently exists on the
+    /// filesystem, or None if it does not exist.
+    fn digest_input(&self, name: &str) -> Result<Option<DigestData>> {
+        let mut f = match super::try_open_file(self.inputs_root.join(name)) {
+            OpenResult::Ok(f) => f,
+            OpenResult::NotAvailable => return Ok(None),
+            OpenResult::Err(e) => return Err(e),
+        };
+
+        let mut dc = digest::create();
+        let mut buf = [0u8; 8192];
+
+        loop {
+            let n = f.read(&mut buf)?;
+
+            if n == 0 {
+                break;
+            }
+
+            dc.update(&buf[..n]);
+        }
+
+        Ok(Some(DigestData::from(dc)))
+    }
+
+    /// Save a format file along with a record of the inputs that were used to
+    /// generate it.
+    ///
+    /// Each input is a name, relative to the inputs root, paired with the
+    /// digest of that file on the filesystem or None if the format must be
+    /// regenerated should the file appear. Names may not contain newlines.
+    pub fn write_format_with_inputs(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        inputs: &[(String, Option<DigestData>)],
+        _status: &mut dyn StatusBackend,
+    ) -> Result<()> {
+        let final_path = self.path_for_format(OsStr::new(name))?;
+        let inputs_path = self.inputs_path_for_format(OsStr::new(name))?;
+
+        let mut text = format!("{}\n", INPUTS_MANIFEST_HEADER);
+
+        for (input_name, digest) in inputs {
+            if input_name.contains('\n') {
+                return Err(ErrorKind::Msg(format!(
+                    "cannot record the format input name \"{}\"",
+                    input_name
+                ))
+                .into());
+            }
+
+            let digest = match digest {
+                Some(d) => d.to_string(),
+                None => "-".to_owned(),
+            };
+            text.push_str(&format!("input {} {}\n", digest, input_name));
+        }
+
+        let mut temp_inputs = tempfile::Builder::new()
+            .prefix("format_")
+            .rand_bytes(6)
+            .tempfile_in(&self.formats_base)?;
+        temp_inputs.write_all(text.as_bytes())?;
+
+        let mut temp_dest = tempfile::Builder::new()
+            .prefix("format_")
+            .rand_bytes(6)
+            .tempfile_in(&self.formats_base)?;
+        temp_dest.write_all(data)?;
+
+        // Make sure that a reader never pairs the new format with the old
+        // manifest: drop the manifest first, and only write out the new one
+        // once the format is in place.
+        match fs::remove_file(&inputs_path) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        temp_dest.persist(&final_path)?;
+        temp_inputs.persist(&inputs_path)?;
+        Ok(())
+    }
 }
 
 impl IoProvider for FormatCache {
@@ -77,6 +306,12 @@ impl IoProvider for FormatCache {
             Err(e) => return OpenResult::Err(e),
         };
 
+        let inputs_path = path.with_extension("inputs");
+
+        if !self.inputs_are_current(&inputs_path) {
+            return OpenResult::NotAvailable;
+        }
+
         let f = match super::try_open_file(&path) {
             OpenResult::Ok(f) => f,
             OpenResult::NotAvailable => return OpenResult::NotAvailable,
@@ -94,15 +329,106 @@ impl IoProvider for FormatCache {
         &mut self,
         name: &str,
         data: &[u8],
-        _status: &mut dyn StatusBackend,
+        status: &mut dyn StatusBackend,
     ) -> Result<()> {
-        let final_path = self.path_for_format(OsStr::new(name))?;
-        let mut temp_dest = tempfile::Builder::new()
-            .prefix("format_")
-            .rand_bytes(6)
-            .tempfile_in(&self.formats_base)?;
-        temp_dest.write_all(data)?;
-        temp_dest.persist(&final_path)?;
-        Ok(())
+        self.write_format_with_inputs(name, data, &[], status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::NoopStatusBackend;
+
+    fn is_available(cache: &mut FormatCache) -> bool {
+        let mut sb = NoopStatusBackend::new();
+
+        match cache.input_open_format(OsStr::new("custom.fmt"), &mut sb) {
+            OpenResult::Ok(_) => true,
+            OpenResult::NotAvailable => false,
+            OpenResult::Err(e) => panic!("error opening format: {}", e),
+        }
+    }
+
+    fn digest_of(text: &str) -> DigestData {
+        let mut dc = digest::create();
+        dc.update(text.as_bytes());
+        DigestData::from(dc)
+    }
+
+    /// Formats built from local files should be dropped as soon as those
+    /// files change, or as soon as a file that they didn't see appears.
+    #[test]
+    fn format_inputs_invalidate() {
+        let formats = tempfile::tempdir().unwrap();
+        let inputs = tempfile::tempdir().unwrap();
+        let mut sb = NoopStatusBackend::new();
+
+        let mut cache = FormatCache::new(DigestData::zeros(), formats.path().to_owned());
+        cache.set_inputs_root(inputs.path());
+
+        fs::write(inputs.path().join("preamble.tex"), "one").unwrap();
+        let recorded = vec![
+            ("preamble.tex".to_owned(), Some(digest_of("one"))),
+            ("missing.cfg".to_owned(), None),
+        ];
+
+        cache
+            .write_format_with_inputs("custom", b"format", &recorded, &mut sb)
+            .unwrap();
+        assert!(is_available(&mut cache));
+
+        fs::write(inputs.path().join("preamble.tex"), "two").unwrap();
+        assert!(!is_available(&mut cache));
+
+        fs::write(inputs.path().join("preamble.tex"), "one").unwrap();
+        assert!(is_available(&mut cache));
+
+        fs::write(inputs.path().join("missing.cfg"), "").unwrap();
+        assert!(!is_available(&mut cache));
+    }
+
+    /// Custom formats from different directory trees shouldn't share a
+    /// cache entry, while standard formats should.
+    #[test]
+    fn local_formats_keyed_by_root() {
+        let formats = tempfile::tempdir().unwrap();
+        let inputs_a = tempfile::tempdir().unwrap();
+        let inputs_b = tempfile::tempdir().unwrap();
+
+        let mut cache_a = FormatCache::new(DigestData::zeros(), formats.path().to_owned());
+        cache_a.set_inputs_root(inputs_a.path());
+        let mut cache_b = FormatCache::new(DigestData::zeros(), formats.path().to_owned());
+        cache_b.set_inputs_root(inputs_b.path());
+
+        let name = OsStr::new("custom.fmt");
+        assert!(!cache_a.is_local_format(name));
+        assert_eq!(
+            cache_a.path_for_format(name).unwrap(),
+            cache_b.path_for_format(name).unwrap()
+        );
+
+        fs::write(inputs_a.path().join("tectonic-format-custom.tex"), "").unwrap();
+        fs::write(inputs_b.path().join("tectonic-format-custom.tex"), "").unwrap();
+        assert!(cache_a.is_local_format(name));
+        assert_ne!(
+            cache_a.path_for_format(name).unwrap(),
+            cache_b.path_for_format(name).unwrap()
+        );
+    }
+
+    /// Formats saved without an input manifest can't be trusted.
+    #[test]
+    fn format_without_manifest() {
+        let formats = tempfile::tempdir().unwrap();
+        let mut sb = NoopStatusBackend::new();
+
+        let mut cache = FormatCache::new(DigestData::zeros(), formats.path().to_owned());
+        cache.write_format("custom", b"format", &mut sb).unwrap();
+        assert!(is_available(&mut cache));
+
+        let path = cache.inputs_path_for_format(OsStr::new("custom")).unwrap();
+        fs::remove_file(path).unwrap();
+        assert!(!is_available(&mut cache));
     }
 }
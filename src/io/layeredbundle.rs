@@ -0,0 +1,164 @@
+// src/io/layeredbundle.rs -- a bundle composed of other bundles
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+use std::ffi::OsStr;
+
+use super::{Bundle, InputHandle, IoProvider, OpenResult};
+use crate::digest::{self, Digest, DigestData};
+use crate::errmsg;
+use crate::errors::Result;
+use crate::status::StatusBackend;
+
+/// A LayeredBundle combines an ordered list of bundles into one. Files are
+/// looked up in each member in turn, so that earlier bundles shadow later
+/// ones. This makes it possible to, say, layer a small collection of in-house
+/// fonts and class files on top of a standard TeX distribution bundle.
+pub struct LayeredBundle {
+    bundles: Vec<Box<dyn Bundle>>,
+}
+
+impl LayeredBundle {
+    pub fn new(bundles: Vec<Box<dyn Bundle>>) -> LayeredBundle {
+        LayeredBundle { bundles }
+    }
+
+    /// Combine a list of bundles into a single bundle. A single bundle is
+    /// returned as-is, rather than being wrapped up, and an empty list is an
+    /// error.
+    pub fn compose(mut bundles: Vec<Box<dyn Bundle>>) -> Result<Box<dyn Bundle>> {
+        match bundles.len() {
+            0 => Err(errmsg!("at least one bundle must be specified")),
+            1 => Ok(bundles.pop().unwrap()),
+            _ => Ok(Box::new(LayeredBundle::new(bundles))),
+        }
+    }
+}
+
+impl IoProvider for LayeredBundle {
+    fn input_open_name(
+        &mut self,
+        name: &OsStr,
+        status: &mut dyn StatusBackend,
+    ) -> OpenResult<InputHandle> {
+        for bundle in &mut self.bundles {
+            match bundle.input_open_name(name, status) {
+                OpenResult::NotAvailable => continue,
+                r => return r,
+            }
+        }
+
+        OpenResult::NotAvailable
+    }
+}
+
+impl Bundle for LayeredBundle {
+    /// The digest of a layered bundle is the SHA256 of the concatenated
+    /// digests of its members, in order. It therefore changes if any member
+    /// changes or if the members are reordered, either of which can change
+    /// which files the bundle provides.
+    ///
+    /// A layered bundle with only one member has that member's digest.
+    fn get_digest(&mut self, status: &mut dyn StatusBackend) -> Result<DigestData> {
+        if self.bundles.len() == 1 {
+            return self.bundles[0].get_digest(status);
+        }
+
+        let mut dc = digest::create();
+
+        for bundle in &mut self.bundles {
+            dc.update(bundle.get_digest(status)?);
+        }
+
+        Ok(DigestData::from(dc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::zipbundle::ZipBundle;
+    use crate::status::NoopStatusBackend;
+    use std::io::{Cursor, Read, Write};
+    use zip::{write::FileOptions, ZipWriter};
+
+    fn zip_bundle(files: &[(&str, &str)]) -> Box<dyn Bundle> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (name, contents) in files {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+
+        let data = zip.finish().unwrap().into_inner();
+        Box::new(ZipBundle::new(Cursor::new(data)).unwrap())
+    }
+
+    fn read(bundle: &mut dyn Bundle, name: &str) -> Option<String> {
+        let mut sb = NoopStatusBackend::new();
+
+        match bundle.input_open_name(OsStr::new(name), &mut sb) {
+            OpenResult::Ok(mut h) => {
+                let mut s = String::new();
+                h.read_to_string(&mut s).unwrap();
+                Some(s)
+            }
+            OpenResult::NotAvailable => None,
+            OpenResult::Err(e) => panic!("error opening {}: {}", name, e),
+        }
+    }
+
+    const DIGEST_A: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+    const DIGEST_B: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+
+    #[test]
+    fn shadowing() {
+        let mut layered = LayeredBundle::new(vec![
+            zip_bundle(&[("a.tex", "upper"), ("SHA256SUM", DIGEST_A)]),
+            zip_bundle(&[("a.tex", "lower"), ("b.tex", "b"), ("SHA256SUM", DIGEST_B)]),
+        ]);
+
+        assert_eq!(read(&mut layered, "a.tex").as_deref(), Some("upper"));
+        assert_eq!(read(&mut layered, "b.tex").as_deref(), Some("b"));
+        assert_eq!(read(&mut layered, "c.tex"), None);
+    }
+
+    #[test]
+    fn combined_digest() {
+        let mut sb = NoopStatusBackend::new();
+        let a = || zip_bundle(&[("SHA256SUM", DIGEST_A)]);
+        let b = || zip_bundle(&[("SHA256SUM", DIGEST_B)]);
+
+        let single = LayeredBundle::new(vec![a()]).get_digest(&mut sb).unwrap();
+        assert_eq!(single, a().get_digest(&mut sb).unwrap());
+
+        let ab = LayeredBundle::new(vec![a(), b()])
+            .get_digest(&mut sb)
+            .unwrap();
+        let ba = LayeredBundle::new(vec![b(), a()])
+            .get_digest(&mut sb)
+            .unwrap();
+        assert_ne!(ab, single);
+        assert_ne!(ab, ba);
+    }
+
+    #[test]
+    fn digest_without_sha256sum() {
+        let mut sb = NoopStatusBackend::new();
+        let remote = || zip_bundle(&[("SHA256SUM", DIGEST_A)]);
+
+        // A plain local zip, with no SHA256SUM, still has a digest, which
+        // changes along with its contents.
+        let v1 = LayeredBundle::new(vec![zip_bundle(&[("a.sty", "v1")]), remote()])
+            .get_digest(&mut sb)
+            .unwrap();
+        let v1_again = LayeredBundle::new(vec![zip_bundle(&[("a.sty", "v1")]), remote()])
+            .get_digest(&mut sb)
+            .unwrap();
+        let v2 = LayeredBundle::new(vec![zip_bundle(&[("a.sty", "v2")]), remote()])
+            .get_digest(&mut sb)
+            .unwrap();
+        assert_eq!(v1, v1_again);
+        assert_ne!(v1, v2);
+    }
+}
@@ -21,6 +21,7 @@ pub mod cached_itarbundle;
 pub mod dirbundle;
 pub mod filesystem;
 pub mod format_cache;
+pub mod layeredbundle;
 pub mod memory;
 pub mod setup;
 pub mod stack;
@@ -90,6 +91,8 @@ pub struct InputHandle {
     ever_read: bool,
     did_unhandled_seek: bool,
     ungetc_char: Option<u8>,
+    /// If set, reported as the modification time instead of the real one.
+    frozen_mtime: Option<i64>,
 }
 
 impl InputHandle {
@@ -107,6 +110,7 @@ impl InputHandle {
             ever_read: false,
             did_unhandled_seek: false,
             ungetc_char: None,
+            frozen_mtime: None,
         }
     }
 
@@ -124,6 +128,7 @@ impl InputHandle {
             ever_read: false,
             did_unhandled_seek: false,
             ungetc_char: None,
+            frozen_mtime: None,
         }
     }
 
@@ -131,10 +136,22 @@ impl InputHandle {
         self.name.as_os_str()
     }
 
+    /// Returns true if this handle was created with
+    /// [`InputHandle::new_read_only`].
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     pub fn origin(&self) -> InputOrigin {
         self.origin
     }
 
+    /// Make this handle report the given Unix time as the modification time
+    /// of the file, whatever the truth may be.
+    pub fn freeze_mtime(&mut self, mtime: i64) {
+        self.frozen_mtime = Some(mtime);
+    }
+
     /// Consumes the object and returns the underlying readable handle that
     /// it references.
     pub fn into_inner(self) -> Box<dyn InputFeatures> {
@@ -246,7 +263,10 @@ impl InputFeatures for InputHandle {
     }
 
     fn get_unix_mtime(&mut self) -> Result<Option<i64>> {
-        self.inner.get_unix_mtime()
+        match self.frozen_mtime {
+            Some(t) => Ok(Some(t)),
+            None => self.inner.get_unix_mtime(),
+        }
     }
 
     fn try_seek(&mut self, pos: SeekFrom) -> Result<u64> {
@@ -1,12 +1,15 @@
 use std::collections::HashSet;
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 
 use crate::ctry;
 use crate::errors::Result;
 use crate::io::format_cache::FormatCache;
+use crate::io::memory::MemoryFileInfo;
 use crate::io::stdstreams::BufferedPrimaryIo;
 use crate::io::{
-    Bundle, FilesystemIo, FilesystemPrimaryInputIo, GenuineStdoutIo, IoProvider, IoStack, MemoryIo,
+    normalize_tex_path, Bundle, FilesystemIo, FilesystemPrimaryInputIo, GenuineStdoutIo,
+    IoProvider, IoStack, MemoryIo,
 };
 use crate::status::StatusBackend;
 
@@ -30,6 +33,9 @@ pub struct IoSetup {
     pub format_cache: Option<FormatCache>,
     genuine_stdout: Option<GenuineStdoutIo>,
     format_primary: Option<BufferedPrimaryIo>,
+    primary_override: Option<BufferedPrimaryIo>,
+    input_overrides: MemoryIo,
+    frozen_mtime: Option<i64>,
 }
 
 impl IoSetup {
@@ -40,8 +46,13 @@ impl IoSetup {
             providers.push(p);
         }
 
-        providers.push(&mut *self.primary_input);
+        match self.primary_override {
+            Some(ref mut p) => providers.push(p),
+            None => providers.push(&mut *self.primary_input),
+        }
+
         providers.push(&mut self.mem);
+        providers.push(&mut self.input_overrides);
         providers.push(&mut self.filesystem);
 
         if let Some(ref mut b) = self.bundle {
@@ -52,34 +63,72 @@ impl IoSetup {
             providers.push(&mut *c);
         }
 
-        IoStack::new(providers)
+        IoStack::new(providers).frozen_mtime(self.frozen_mtime)
+    }
+
+    /// Creates an `IoStack` containing only the filesystem layer.
+    ///
+    /// This lets callers find out what the filesystem currently holds for a
+    /// given name, without being confused by files that the engines have
+    /// created in memory or by resources provided by the bundle.
+    pub fn as_filesystem_stack(&mut self) -> IoStack {
+        IoStack::new(vec![&mut self.filesystem])
     }
 
     /// Creates an `IoStack` for the specific purpose of writing out a format file.
     ///
-    /// This differs from [`IoSetup::as_stack`] in two ways:
+    /// This differs from [`IoSetup::as_stack`] in that the primary input is not used here; instead,
+    /// this method provides a "dummy" primary input file containing only
+    /// "\input format-file-name.tex".
     ///
-    /// - the primary input is not used here; instead, this method provides a "dummy" primary input
-    ///   file containing only "\input format-file-name.tex"
-    /// - the filesystem is not included, and so only files that are present in the bundle can have
-    ///   an effect on the format file.
+    /// If the format file itself is found on the filesystem, making this a custom format, the
+    /// filesystem is included so that it can pull in other local files. Callers should then note
+    /// which files were looked up and save them along with the format using
+    /// [`FormatCache::write_format_with_inputs`], so that the format gets regenerated when they
+    /// change. Standard formats are built from the bundle alone, since they're shared between
+    /// all documents.
     ///
     /// You can use the resulting `IoStack` to run the TeX engine with `initex_mode` set to `true`;
     /// then the resulting format file(s) can be read from the memory I/O layer (i.e. `self.mem`).
 
     pub fn as_stack_for_format<'a>(&'a mut self, format_file_name: &str) -> IoStack<'a> {
+        self.format_primary = Some(BufferedPrimaryIo::from_text(&format!(
+            "\\input {}",
+            format_file_name
+        )));
+
+        let local = match self.format_cache {
+            Some(ref c) => c.inputs_root().join(format_file_name).is_file(),
+            None => false,
+        };
+        self.format_stack(local)
+    }
+
+    /// Creates an `IoStack` for the specific purpose of dumping a format from a document preamble.
+    ///
+    /// This is like [`IoSetup::as_stack_for_format`], except that the primary input is the given
+    /// text, which should contain the preamble followed by the commands needed to dump the format.
+    /// Run the TeX engine with `initex_mode` and `preload_format` set to `true`. The filesystem
+    /// is always included, since a preamble is free to load local files.
+    pub fn as_stack_for_preamble(&mut self, text: Vec<u8>) -> IoStack<'_> {
+        self.format_primary = Some(BufferedPrimaryIo::from_buffer(text));
+        self.format_stack(true)
+    }
+
+    fn format_stack(&mut self, with_filesystem: bool) -> IoStack<'_> {
         let mut providers: Vec<&mut dyn IoProvider> = Vec::new();
 
         if let Some(ref mut p) = self.genuine_stdout {
             providers.push(p);
         }
 
-        self.format_primary = Some(BufferedPrimaryIo::from_text(&format!(
-            "\\input {}",
-            format_file_name
-        )));
         providers.push(self.format_primary.as_mut().unwrap());
         providers.push(&mut self.mem);
+        providers.push(&mut self.input_overrides);
+
+        if with_filesystem {
+            providers.push(&mut self.filesystem);
+        }
 
         if let Some(ref mut b) = self.bundle {
             providers.push(b.as_ioprovider_mut());
@@ -89,7 +138,43 @@ impl IoSetup {
             providers.push(&mut *c);
         }
 
-        IoStack::new(providers)
+        IoStack::new(providers).frozen_mtime(self.frozen_mtime)
+    }
+
+    /// Replace the primary input with the given data in the stacks created by
+    /// [`IoSetup::as_stack`], or restore the original primary input if `None`
+    /// is given.
+    ///
+    /// This is used when a document is processed using a format that already
+    /// contains its preamble.
+    pub fn override_primary_input(&mut self, data: Option<Vec<u8>>) {
+        self.primary_override = data.map(BufferedPrimaryIo::from_buffer);
+    }
+
+    /// Make the engines read the given data instead of the named file, or
+    /// stop doing so if `None` is given. This is like
+    /// [`IoSetup::override_primary_input`], but for a file that the engines
+    /// open by name.
+    ///
+    /// Files created by the engines in the memory layer still take
+    /// precedence.
+    pub fn override_input(&mut self, name: &str, data: Option<Vec<u8>>) {
+        let mut files = self.input_overrides.files.borrow_mut();
+
+        match data {
+            Some(data) => {
+                files.insert(
+                    normalize_tex_path(OsStr::new(name)).into_owned(),
+                    MemoryFileInfo {
+                        data,
+                        unix_mtime: None,
+                    },
+                );
+            }
+            None => {
+                files.remove(&*normalize_tex_path(OsStr::new(name)));
+            }
+        }
     }
 }
 
@@ -117,6 +202,7 @@ pub struct IoSetupBuilder {
     bundle: Option<Box<dyn Bundle>>,
     use_genuine_stdout: bool,
     hidden_input_paths: HashSet<PathBuf>,
+    frozen_mtime: Option<i64>,
 }
 
 impl Default for IoSetupBuilder {
@@ -128,6 +214,7 @@ impl Default for IoSetupBuilder {
             bundle: None,
             use_genuine_stdout: false,
             hidden_input_paths: HashSet::new(),
+            frozen_mtime: None,
         }
     }
 }
@@ -206,6 +293,14 @@ impl IoSetupBuilder {
         self
     }
 
+    /// Makes every input opened through the resulting `IoSetup`'s stacks
+    /// report the given Unix time as its modification time. See
+    /// [`IoStack::frozen_mtime`].
+    pub fn frozen_mtime(&mut self, mtime: i64) -> &mut Self {
+        self.frozen_mtime = Some(mtime);
+        self
+    }
+
     /// Creates an `IoSetup`.
     ///
     /// # Panics
@@ -215,7 +310,9 @@ impl IoSetupBuilder {
         let format_cache = if let Some(ref mut b) = self.bundle {
             let default_path = self.filesystem_root.clone(); // unwrap_or_else() causes borrowck issues
             let format_cache_path = self.format_cache_path.unwrap_or(default_path);
-            Some(FormatCache::new(b.get_digest(status)?, format_cache_path))
+            let mut cache = FormatCache::new(b.get_digest(status)?, format_cache_path);
+            cache.set_inputs_root(&self.filesystem_root);
+            Some(cache)
         } else {
             None
         };
@@ -251,6 +348,9 @@ impl IoSetupBuilder {
                 None
             },
             format_primary: None,
+            primary_override: None,
+            input_overrides: MemoryIo::new(false),
+            frozen_mtime: self.frozen_mtime,
         })
     }
 }
@@ -14,11 +14,33 @@ use crate::status::StatusBackend;
 
 pub struct IoStack<'a> {
     items: Vec<&'a mut dyn IoProvider>,
+    frozen_mtime: Option<i64>,
 }
 
 impl<'a> IoStack<'a> {
     pub fn new(items: Vec<&'a mut dyn IoProvider>) -> IoStack<'a> {
-        IoStack { items }
+        IoStack {
+            items,
+            frozen_mtime: None,
+        }
+    }
+
+    /// If `mtime` is set, every input opened through this stack reports it
+    /// as its modification time. Reproducible builds use this so that their
+    /// results don't depend on when the input files were last touched.
+    pub fn frozen_mtime(mut self, mtime: Option<i64>) -> IoStack<'a> {
+        self.frozen_mtime = mtime;
+        self
+    }
+}
+
+fn freeze(r: OpenResult<InputHandle>, mtime: Option<i64>) -> OpenResult<InputHandle> {
+    match (r, mtime) {
+        (OpenResult::Ok(mut handle), Some(t)) => {
+            handle.freeze_mtime(t);
+            OpenResult::Ok(handle)
+        }
+        (r, _) => r,
     }
 }
 
@@ -54,12 +76,14 @@ impl<'a> IoProvider for IoStack<'a> {
         name: &OsStr,
         status: &mut dyn StatusBackend,
     ) -> OpenResult<InputHandle> {
+        let mtime = self.frozen_mtime;
+
         for item in &mut self.items {
             let r = item.input_open_name(name, status);
 
             match r {
                 OpenResult::NotAvailable => continue,
-                _ => return r,
+                _ => return freeze(r, mtime),
             };
         }
 
@@ -67,12 +91,14 @@ impl<'a> IoProvider for IoStack<'a> {
     }
 
     fn input_open_primary(&mut self, status: &mut dyn StatusBackend) -> OpenResult<InputHandle> {
+        let mtime = self.frozen_mtime;
+
         for item in &mut self.items {
             let r = item.input_open_primary(status);
 
             match r {
                 OpenResult::NotAvailable => continue,
-                _ => return r,
+                _ => return freeze(r, mtime),
             };
         }
 
@@ -84,15 +110,68 @@ impl<'a> IoProvider for IoStack<'a> {
         name: &OsStr,
         status: &mut dyn StatusBackend,
     ) -> OpenResult<InputHandle> {
+        let mtime = self.frozen_mtime;
+
         for item in &mut self.items {
             let r = item.input_open_format(name, status);
 
             match r {
                 OpenResult::NotAvailable => continue,
-                _ => return r,
+                _ => return freeze(r, mtime),
             };
         }
 
         OpenResult::NotAvailable
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{InputFeatures, IoSetupBuilder, MemoryIo};
+    use crate::status::NoopStatusBackend;
+    use std::io::Write;
+
+    #[test]
+    fn frozen_mtime() {
+        let mut mem = MemoryIo::new(false);
+        let name = OsStr::new("test.tex");
+        let mut sb = NoopStatusBackend::new();
+
+        {
+            let mut h = mem.output_open_name(name).unwrap();
+            writeln!(h, "0123456789").unwrap();
+        }
+
+        let mut stack = IoStack::new(vec![&mut mem]).frozen_mtime(Some(1234));
+        let mut h = stack.input_open_name(name, &mut sb).unwrap();
+        assert_eq!(h.get_unix_mtime().unwrap(), Some(1234));
+    }
+
+    /// The stacks used to generate formats must freeze modification times
+    /// too, since whatever they read ends up baked into the format.
+    #[test]
+    fn frozen_mtime_for_formats() {
+        let mut sb = NoopStatusBackend::new();
+        let mut builder = IoSetupBuilder::default();
+        builder
+            .primary_input_buffer(b"Hello.".to_vec())
+            .frozen_mtime(1234);
+        let mut setup = builder.create(&mut sb).unwrap();
+        setup.override_input("preamble.tex", Some(b"\\relax".to_vec()));
+
+        {
+            let mut stack = setup.as_stack_for_format("plain.tex");
+            let mut h = stack.input_open_primary(&mut sb).unwrap();
+            assert_eq!(h.get_unix_mtime().unwrap(), Some(1234));
+        }
+
+        let mut stack = setup.as_stack_for_preamble(b"\\input preamble.tex".to_vec());
+        let mut h = stack.input_open_primary(&mut sb).unwrap();
+        assert_eq!(h.get_unix_mtime().unwrap(), Some(1234));
+        let mut h = stack
+            .input_open_name(OsStr::new("preamble.tex"), &mut sb)
+            .unwrap();
+        assert_eq!(h.get_unix_mtime().unwrap(), Some(1234));
+    }
+}
@@ -6,10 +6,13 @@ use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{Cursor, Read, Seek};
 use std::path::Path;
+use std::str::FromStr;
 use zip::result::ZipError;
 use zip::ZipArchive;
 
 use super::{Bundle, InputHandle, InputOrigin, IoProvider, OpenResult};
+use crate::ctry;
+use crate::digest::{self, Digest, DigestData};
 use crate::errors::Result;
 use crate::status::StatusBackend;
 
@@ -74,4 +77,31 @@ impl<R: Read + Seek> IoProvider for ZipBundle<R> {
     }
 }
 
-impl<R: Read + Seek> Bundle for ZipBundle<R> {}
+impl<R: Read + Seek> Bundle for ZipBundle<R> {
+    /// If the archive contains a `SHA256SUM` file, that is its digest.
+    /// Otherwise, which is typical of small hand-made bundles, the digest is
+    /// computed from the names, CRC-32 checksums and sizes of its members,
+    /// so that it changes whenever the contents of the archive do.
+    fn get_digest(&mut self, _status: &mut dyn StatusBackend) -> Result<DigestData> {
+        if !self.zip.file_names().any(|n| n == digest::DIGEST_NAME) {
+            let mut dc = digest::create();
+
+            for i in 0..self.zip.len() {
+                let f = self.zip.by_index(i)?;
+                dc.update(f.name().as_bytes());
+                dc.update([0]);
+                dc.update(f.crc32().to_le_bytes());
+                dc.update(f.size().to_le_bytes());
+            }
+
+            return Ok(DigestData::from(dc));
+        }
+
+        let mut digest_text = String::new();
+        self.zip
+            .by_name(digest::DIGEST_NAME)?
+            .take(64)
+            .read_to_string(&mut digest_text)?;
+        Ok(ctry!(DigestData::from_str(&digest_text); "corrupted SHA256 digest data"))
+    }
+}
@@ -58,6 +58,7 @@ pub mod engines;
 pub mod errors;
 pub mod io;
 pub mod status;
+pub mod synctex;
 pub mod unstable_opts;
 #[cfg(feature = "serialization")]
 pub mod workspace;
@@ -67,16 +68,21 @@ pub mod workspace;
 #[doc(hidden)]
 pub mod test_util;
 
+pub use crate::engines::biber::BiberEngine;
 pub use crate::engines::bibtex::BibtexEngine;
+pub use crate::engines::makeindex::MakeindexEngine;
 pub use crate::engines::spx2html::Spx2HtmlEngine;
 pub use crate::engines::tex::{TexEngine, TexResult};
-pub use crate::engines::xdvipdfmx::XdvipdfmxEngine;
+pub use crate::engines::xdvipdfmx::{
+    PdfConformance, PdfDocInfo, PdfEncryption, PdfOptions, PdfOutputIntent, PdfPermissions,
+    XdvipdfmxEngine,
+};
 pub use crate::errors::{Error, ErrorKind, Result};
 
 // Increase this whenever the engine internals change such that the contents
 // of the "format" files must be regenerated.
 
-pub const FORMAT_SERIAL: u32 = 29;
+pub const FORMAT_SERIAL: u32 = 30;
 
 /// Compile LaTeX text to a PDF.
 ///
@@ -111,11 +117,13 @@ pub const FORMAT_SERIAL: u32 = 29;
 /// control over their behavior.
 ///
 /// Note that the current engine implementations use lots of global state, so
-/// they are not thread-safe. This crate uses a global mutex to serialize
-/// invocations of the engines. This means that if you call this function from
-/// multiple threads simultaneously, the bulk of the work will be done in
-/// serial. The aim is to lift this limitation one day, but it will require
-/// extensive work on the underlying C/C++ code.
+/// they are not thread-safe. By default, this crate uses a global mutex to
+/// serialize invocations of the engines. This means that if you call this
+/// function from multiple threads simultaneously, the bulk of the work will be
+/// done in serial. To avoid this, call [`engines::worker::activate`] at the
+/// start of your program's `main()` function: each engine invocation will
+/// then run in its own worker process, so that invocations from different
+/// threads can proceed in parallel.
 pub fn latex_to_pdf<T: AsRef<str>>(latex: T) -> Result<Vec<u8>> {
     use std::ffi::OsStr;
 
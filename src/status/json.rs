@@ -0,0 +1,113 @@
+// src/status/json.rs -- machine-readable status reporting
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A status backend that emits JSON Lines for consumption by other programs.
+//!
+//! Every message is written to standard output as a single JSON object on
+//! its own line. Each object has a `kind` field, which is one of `"note"`,
+//! `"warning"`, `"error"`, or `"log"`, along with fields that depend on the
+//! kind of message.
+
+use serde_json::{json, Value};
+use std::fmt::Arguments;
+use std::io::{self, Write};
+
+use super::{ChatterLevel, MessageKind, SourceDiagnostic, StatusBackend};
+use crate::errors::Error;
+
+pub struct JsonStatusBackend {
+    chatter: ChatterLevel,
+}
+
+impl JsonStatusBackend {
+    pub fn new(chatter: ChatterLevel) -> Self {
+        JsonStatusBackend { chatter }
+    }
+
+    fn emit(&mut self, record: Value) {
+        let stdout = io::stdout();
+        let mut lock = stdout.lock();
+        writeln!(lock, "{}", record).expect("write to stdout failed");
+        lock.flush().expect("flush of stdout failed");
+    }
+}
+
+fn kind_name(kind: MessageKind) -> &'static str {
+    match kind {
+        MessageKind::Note => "note",
+        MessageKind::Warning => "warning",
+        MessageKind::Error => "error",
+    }
+}
+
+fn error_causes(err: Option<&Error>) -> Vec<String> {
+    match err {
+        Some(e) => e.iter().map(|item| item.to_string()).collect(),
+        None => Vec::new(),
+    }
+}
+
+impl StatusBackend for JsonStatusBackend {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>) {
+        if kind == MessageKind::Note && self.chatter <= ChatterLevel::Minimal {
+            return;
+        }
+
+        self.emit(json!({
+            "kind": kind_name(kind),
+            "message": args.to_string(),
+            "causes": error_causes(err),
+        }));
+    }
+
+    fn report_error(&mut self, err: &Error) {
+        let mut causes = error_causes(Some(err));
+        let message = if causes.is_empty() {
+            "an error occurred".to_owned()
+        } else {
+            causes.remove(0)
+        };
+
+        self.emit(json!({
+            "kind": "error",
+            "message": message,
+            "causes": causes,
+        }));
+    }
+
+    fn report_diagnostic(&mut self, diag: &SourceDiagnostic) {
+        self.emit(json!({
+            "kind": kind_name(diag.kind),
+            "message": diag.message,
+            "causes": [],
+            "file": diag.file,
+            "line": diag.line,
+            "context": diag.context,
+        }));
+    }
+
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        if self.chatter <= ChatterLevel::Minimal {
+            return;
+        }
+
+        self.emit(json!({
+            "kind": "note",
+            "message": format!("{}{}{}", before, highlighted, after),
+            "causes": [],
+            "highlighted": {
+                "before": before,
+                "highlighted": highlighted,
+                "after": after,
+            },
+        }));
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        self.emit(json!({
+            "kind": "log",
+            "log": String::from_utf8_lossy(output),
+        }));
+    }
+}
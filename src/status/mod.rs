@@ -4,11 +4,12 @@
 
 //! A framework for showing status messages to the user.
 
+pub mod json;
 pub mod plain;
 pub mod termcolor;
 
 use std::cmp;
-use std::fmt::Arguments;
+use std::fmt::{Arguments, Display, Error as FmtError, Formatter};
 use std::result::Result as StdResult;
 use std::str::FromStr;
 
@@ -61,6 +62,44 @@ pub enum MessageKind {
     Error,
 }
 
+/// A structured diagnostic produced by an engine while processing a source
+/// file.
+///
+/// Unlike the free-form messages passed to [`StatusBackend::report`], these
+/// records keep the location of the problem separate from its description, so
+/// that tools can point directly at the offending line.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceDiagnostic {
+    /// Whether this diagnostic is a warning or an error.
+    pub kind: MessageKind,
+
+    /// The text of the diagnostic, not including any location information.
+    pub message: String,
+
+    /// The name of the input file that was being read when the diagnostic
+    /// was issued, if any.
+    pub file: Option<String>,
+
+    /// The line number within `file` that the diagnostic refers to.
+    pub line: Option<u32>,
+
+    /// Context lines describing the state of the input when the diagnostic
+    /// was issued. For TeX, this is the current source line split at the
+    /// point where the engine stopped reading, as in the “l.123” lines
+    /// shown in the log.
+    pub context: Vec<String>,
+}
+
+impl Display for SourceDiagnostic {
+    fn fmt(&self, f: &mut Formatter) -> StdResult<(), FmtError> {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{}: {}", file, line, self.message),
+            (Some(file), None) => write!(f, "{}: {}", file, self.message),
+            _ => write!(f, "! {}", self.message),
+        }
+    }
+}
+
 pub trait StatusBackend {
     /// Report a message to the status backend.
     ///
@@ -96,6 +135,15 @@ pub trait StatusBackend {
         )
     }
 
+    /// Report a structured diagnostic issued by an engine.
+    ///
+    /// The default implementation flattens the diagnostic into a plain
+    /// message, with the location prefixed in the usual `file:line:` style,
+    /// and delegates to `report()`.
+    fn report_diagnostic(&mut self, diag: &SourceDiagnostic) {
+        self.report(diag.kind, format_args!("{}", diag), None)
+    }
+
     /// This is used to print TeX engine logs after it encountered errors. This prints the log,
     /// surrounded by lines of equal signs.
     fn dump_error_logs(&mut self, output: &[u8]);
@@ -157,3 +205,40 @@ impl StatusBackend for NoopStatusBackend {
     fn report(&mut self, _kind: MessageKind, _args: Arguments, _err: Option<&Error>) {}
     fn dump_error_logs(&mut self, _output: &[u8]) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(file: Option<&str>, line: Option<u32>) -> SourceDiagnostic {
+        SourceDiagnostic {
+            kind: MessageKind::Error,
+            message: "Undefined control sequence.".to_owned(),
+            file: file.map(|f| f.to_owned()),
+            line,
+            context: vec!["l.3 Oops \\foo".to_owned(), " there.".to_owned()],
+        }
+    }
+
+    #[test]
+    fn diagnostic_display() {
+        assert_eq!(
+            diagnostic(Some("main.tex"), Some(3)).to_string(),
+            "main.tex:3: Undefined control sequence."
+        );
+        assert_eq!(
+            diagnostic(Some("main.tex"), None).to_string(),
+            "main.tex: Undefined control sequence."
+        );
+        assert_eq!(
+            diagnostic(None, None).to_string(),
+            "! Undefined control sequence."
+        );
+
+        // A line without a file can't be shown meaningfully.
+        assert_eq!(
+            diagnostic(None, Some(3)).to_string(),
+            "! Undefined control sequence."
+        );
+    }
+}
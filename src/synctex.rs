@@ -0,0 +1,773 @@
+// src/synctex.rs -- reading SyncTeX data
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Reading the SyncTeX data produced by the TeX engine.
+//!
+//! When SyncTeX is enabled, the engine writes a `.synctex.gz` file recording
+//! where the boxes, glue, kerns and so on of each output page came from in
+//! the source. This module parses that file and answers the two questions
+//! that editors ask of it: *forward* search, from a source line to the places
+//! where it appears in the output; and *inverse* search, from a point on a
+//! page back to a source line.
+//!
+//! Positions on the page are given in PDF big points, measured rightwards
+//! and downwards from the top left corner. The TeX origin is placed one inch
+//! from each edge by default, matching the default offsets of
+//! [`PdfOptions`](crate::PdfOptions); see [`SyncTexData::set_origin`].
+
+use flate2::read::GzDecoder;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::errors::Result;
+use crate::{ctry, errmsg};
+
+/// The number of TeX scaled points in a big point.
+const SP_PER_BP: f64 = 65536.0 * 72.27 / 72.0;
+
+/// The kind of a SyncTeX record.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NodeKind {
+    /// A vertical box.
+    VBox,
+    /// A horizontal box, such as a line of a paragraph.
+    HBox,
+    /// An empty vertical box.
+    VoidVBox,
+    /// An empty horizontal box.
+    VoidHBox,
+    /// A kern.
+    Kern,
+    /// Glue, such as the space between words.
+    Glue,
+    /// The start or end of a formula.
+    Math,
+    /// A rule.
+    Rule,
+    /// A position recorded by the engine for other material, such as a
+    /// glyph.
+    Current,
+}
+
+impl NodeKind {
+    fn is_hbox(self) -> bool {
+        self == NodeKind::HBox || self == NodeKind::VoidHBox
+    }
+}
+
+/// One record of SyncTeX data: a node of a typeset page, and the source
+/// location it came from.
+///
+/// Positions and dimensions are given in TeX scaled points, as recorded in
+/// the file, before the unit and magnification are applied.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Node {
+    /// What sort of node this is.
+    pub kind: NodeKind,
+
+    /// The input file that the node came from; see [`SyncTexData::input_name`].
+    pub tag: u32,
+
+    /// The source line that the node came from.
+    pub line: u32,
+
+    /// The source column that the node came from, if it was recorded.
+    pub column: Option<u32>,
+
+    /// The horizontal position of the node's reference point.
+    pub h: i64,
+
+    /// The vertical position of the node's reference point, which is on its
+    /// baseline.
+    pub v: i64,
+
+    /// The width of the node, if it has one.
+    pub width: i64,
+
+    /// The height of the node above its baseline, if it has one.
+    pub height: i64,
+
+    /// The depth of the node below its baseline, if it has one.
+    pub depth: i64,
+
+    /// The index, within the page's nodes, of the box containing this node.
+    pub parent: Option<usize>,
+}
+
+/// The SyncTeX data of one output page.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Page {
+    /// The page number, counting from 1.
+    pub number: u32,
+
+    /// The records of the page, in the order in which they appear in the
+    /// file.
+    pub nodes: Vec<Node>,
+}
+
+/// A location in the document source.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceLocation {
+    /// The name of the input file, as the TeX engine opened it.
+    pub file: String,
+
+    /// The line number, counting from 1.
+    pub line: u32,
+
+    /// The column, if known.
+    pub column: Option<u32>,
+}
+
+/// A region of an output page. Positions are in big points from the top left
+/// corner of the page.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageRegion {
+    /// The page number, counting from 1.
+    pub page: u32,
+
+    /// The position of the left edge of the region.
+    pub x: f64,
+
+    /// The position of the baseline of the region.
+    pub y: f64,
+
+    /// The width of the region.
+    pub width: f64,
+
+    /// The extent of the region above its baseline.
+    pub height: f64,
+
+    /// The extent of the region below its baseline.
+    pub depth: f64,
+}
+
+impl PageRegion {
+    /// The distance from a point to the region, which is zero if the point
+    /// lies within it.
+    fn distance_to(&self, x: f64, y: f64) -> f64 {
+        let dx = if x < self.x {
+            self.x - x
+        } else if x > self.x + self.width {
+            x - self.x - self.width
+        } else {
+            0.0
+        };
+
+        let dy = if y < self.y - self.height {
+            self.y - self.height - y
+        } else if y > self.y + self.depth {
+            y - self.y - self.depth
+        } else {
+            0.0
+        };
+
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    fn area(&self) -> f64 {
+        self.width * (self.height + self.depth)
+    }
+}
+
+/// The parsed contents of a SyncTeX file.
+#[derive(Clone, Debug)]
+pub struct SyncTexData {
+    inputs: HashMap<u32, String>,
+    pages: Vec<Page>,
+    magnification: i64,
+    unit: i64,
+    x_offset: i64,
+    y_offset: i64,
+    origin: (f64, f64),
+}
+
+impl SyncTexData {
+    /// Read SyncTeX data from a file. Files whose names end in `.gz` are
+    /// decompressed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = ctry!(File::open(path); "couldn't open SyncTeX file `{}`", path.display());
+
+        let data = if path.extension() == Some(OsStr::new("gz")) {
+            SyncTexData::from_gz_reader(file)
+        } else {
+            SyncTexData::from_reader(file)
+        };
+
+        Ok(ctry!(data; "couldn't read SyncTeX file `{}`", path.display()))
+    }
+
+    /// Read gzip-compressed SyncTeX data, as found in a `.synctex.gz` file.
+    pub fn from_gz_reader<R: Read>(reader: R) -> Result<Self> {
+        SyncTexData::from_reader(GzDecoder::new(reader))
+    }
+
+    /// Read uncompressed SyncTeX data.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut data = SyncTexData {
+            inputs: HashMap::new(),
+            pages: Vec::new(),
+            magnification: 1000,
+            unit: 1,
+            x_offset: 0,
+            y_offset: 0,
+            origin: (72.0, 72.0),
+        };
+
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::new();
+        let mut lineno = 0;
+        let mut page: Option<Page> = None;
+        let mut boxes: Vec<usize> = Vec::new();
+        let mut form_depth = 0;
+
+        loop {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            lineno += 1;
+
+            let text = String::from_utf8_lossy(&buf);
+            let text = text.trim_end_matches(&['\n', '\r'][..]);
+
+            if let Some((key, value)) = split_preamble_line(text) {
+                match key {
+                    "Input" => {
+                        let (tag, name) = match value.find(':') {
+                            Some(i) => (&value[..i], &value[i + 1..]),
+                            None => return Err(bad_line(lineno, text)),
+                        };
+                        let tag = tag.parse().map_err(|_| bad_line(lineno, text))?;
+                        data.inputs.insert(tag, name.to_owned());
+                    }
+                    "Magnification" => {
+                        data.magnification = value.parse().map_err(|_| bad_line(lineno, text))?
+                    }
+                    "Unit" => data.unit = value.parse().map_err(|_| bad_line(lineno, text))?,
+                    "X Offset" => {
+                        data.x_offset = value.parse().map_err(|_| bad_line(lineno, text))?
+                    }
+                    "Y Offset" => {
+                        data.y_offset = value.parse().map_err(|_| bad_line(lineno, text))?
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            let mut chars = text.chars();
+            let first = match chars.next() {
+                Some(c) => c,
+                None => continue,
+            };
+            let rest = chars.as_str();
+
+            match first {
+                '{' => {
+                    let number = rest.parse().map_err(|_| bad_line(lineno, text))?;
+                    page = Some(Page {
+                        number,
+                        nodes: Vec::new(),
+                    });
+                    boxes.clear();
+                }
+
+                '}' => {
+                    if let Some(p) = page.take() {
+                        data.pages.push(p);
+                    }
+                }
+
+                // Forms are typeset once and referenced from pages; we don't
+                // try to place their contents.
+                '<' => form_depth += 1,
+                '>' => form_depth -= 1,
+
+                ']' | ')' if form_depth == 0 => {
+                    boxes.pop();
+                }
+
+                '[' | '(' | 'v' | 'h' | 'k' | 'g' | '$' | 'r' | 'x' => {
+                    if form_depth > 0 {
+                        continue;
+                    }
+
+                    let page = match page.as_mut() {
+                        Some(p) => p,
+                        None => continue,
+                    };
+
+                    let kind = match first {
+                        '[' => NodeKind::VBox,
+                        '(' => NodeKind::HBox,
+                        'v' => NodeKind::VoidVBox,
+                        'h' => NodeKind::VoidHBox,
+                        'k' => NodeKind::Kern,
+                        'g' => NodeKind::Glue,
+                        '$' => NodeKind::Math,
+                        'r' => NodeKind::Rule,
+                        _ => NodeKind::Current,
+                    };
+
+                    let mut node = parse_node(kind, rest).ok_or_else(|| bad_line(lineno, text))?;
+                    node.parent = boxes.last().cloned();
+                    page.nodes.push(node);
+
+                    if first == '[' || first == '(' {
+                        boxes.push(page.nodes.len() - 1);
+                    }
+                }
+
+                // Byte counts, form references and the like.
+                _ => {}
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Set the position of the TeX origin on the page, in big points from
+    /// the top left corner. The default is one inch from each edge.
+    pub fn set_origin(&mut self, x: f64, y: f64) -> &mut Self {
+        self.origin = (x, y);
+        self
+    }
+
+    /// The pages described by the data.
+    pub fn pages(&self) -> &[Page] {
+        &self.pages
+    }
+
+    /// The name of the input file with the given tag, as the TeX engine
+    /// opened it.
+    pub fn input_name(&self, tag: u32) -> Option<&str> {
+        self.inputs.get(&tag).map(|s| s.as_ref())
+    }
+
+    /// Find the tag of an input file. Names are compared leniently: `./`
+    /// prefixes are ignored, a missing `.tex` extension is allowed, and a
+    /// name matches any input whose path ends with it, or vice versa, so
+    /// that paths relative to different directories can be used.
+    pub fn find_input(&self, name: &str) -> Option<u32> {
+        fn normalize(s: &str) -> &str {
+            s.trim_start_matches("./")
+        }
+
+        let wanted = normalize(name);
+        let wanted_tex = format!("{}.tex", wanted);
+        let is_suffix =
+            |long: &str, short: &str| long == short || long.ends_with(&format!("/{}", short));
+
+        let mut tags: Vec<_> = self.inputs.keys().cloned().collect();
+        tags.sort_unstable();
+
+        // Prefer exact matches, then looser ones.
+        tags.iter()
+            .cloned()
+            .find(|t| normalize(&self.inputs[t]) == wanted)
+            .or_else(|| {
+                tags.iter().cloned().find(|t| {
+                    let input = normalize(&self.inputs[t]);
+                    is_suffix(input, wanted)
+                        || is_suffix(wanted, input)
+                        || is_suffix(input, &wanted_tex)
+                })
+            })
+    }
+
+    fn to_bp(&self, sp: i64) -> f64 {
+        (sp * self.unit) as f64 * self.magnification as f64 / 1000.0 / SP_PER_BP
+    }
+
+    /// The region of the page covered by a node.
+    pub fn node_region(&self, page: &Page, node: &Node) -> PageRegion {
+        PageRegion {
+            page: page.number,
+            x: self.origin.0 + self.to_bp(node.h + self.x_offset),
+            y: self.origin.1 + self.to_bp(node.v + self.y_offset),
+            width: self.to_bp(node.width),
+            height: self.to_bp(node.height),
+            depth: self.to_bp(node.depth),
+        }
+    }
+
+    /// Forward search: find where a line of an input file appears in the
+    /// output.
+    ///
+    /// The result holds the lines of output, or other boxes, containing
+    /// material from that source line, in page order. If the line itself
+    /// produced no output, the nearest following line that did is used, or
+    /// failing that the nearest preceding one. The result is empty if the
+    /// input file isn't known.
+    pub fn forward(&self, file: &str, line: u32) -> Vec<PageRegion> {
+        let tag = match self.find_input(file) {
+            Some(t) => t,
+            None => return Vec::new(),
+        };
+
+        let lines: Vec<u32> = self
+            .pages
+            .iter()
+            .flat_map(|p| p.nodes.iter())
+            .filter(|n| n.tag == tag)
+            .map(|n| n.line)
+            .collect();
+
+        let line = if lines.contains(&line) {
+            line
+        } else if let Some(l) = lines.iter().cloned().filter(|l| *l > line).min() {
+            l
+        } else if let Some(l) = lines.iter().cloned().max() {
+            l
+        } else {
+            return Vec::new();
+        };
+
+        let mut regions = Vec::new();
+
+        for page in &self.pages {
+            let mut seen = Vec::new();
+
+            for (i, node) in page.nodes.iter().enumerate() {
+                if node.tag != tag || node.line != line {
+                    continue;
+                }
+
+                // Report the line of output containing the node, if there
+                // is one, rather than a single glyph or space.
+                let index = if node.kind.is_hbox() {
+                    i
+                } else {
+                    match node.parent {
+                        Some(p) if page.nodes[p].kind.is_hbox() => p,
+                        _ => i,
+                    }
+                };
+
+                if !seen.contains(&index) {
+                    seen.push(index);
+                    regions.push(self.node_region(page, &page.nodes[index]));
+                }
+            }
+        }
+
+        regions
+    }
+
+    /// Inverse search: find the source location that produced the material
+    /// at a point of an output page.
+    ///
+    /// The innermost horizontal box containing the point is found, and
+    /// within it the node nearest to the point. If no box contains the
+    /// point, the nearest node of the page is used. `None` is returned only
+    /// if the page has no SyncTeX data.
+    pub fn inverse(&self, page_number: u32, x: f64, y: f64) -> Option<SourceLocation> {
+        let page = self.pages.iter().find(|p| p.number == page_number)?;
+
+        let regions: Vec<_> = page
+            .nodes
+            .iter()
+            .map(|n| self.node_region(page, n))
+            .collect();
+
+        let container = page
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(i, n)| n.kind.is_hbox() && regions[*i].distance_to(x, y) == 0.0)
+            .min_by(|(i, _), (j, _)| {
+                regions[*i]
+                    .area()
+                    .partial_cmp(&regions[*j].area())
+                    .unwrap_or(Ordering::Equal)
+            })
+            .map(|(i, _)| i);
+
+        let nearest = |candidates: &mut dyn Iterator<Item = usize>| {
+            candidates.min_by(|i, j| {
+                regions[*i]
+                    .distance_to(x, y)
+                    .partial_cmp(&regions[*j].distance_to(x, y))
+                    .unwrap_or(Ordering::Equal)
+            })
+        };
+
+        let index = match container {
+            Some(c) => {
+                nearest(&mut (0..page.nodes.len()).filter(|i| page.nodes[*i].parent == Some(c)))
+                    .unwrap_or(c)
+            }
+            None => nearest(&mut (0..page.nodes.len()))?,
+        };
+
+        let node = &page.nodes[index];
+
+        Some(SourceLocation {
+            file: self.input_name(node.tag).unwrap_or_default().to_owned(),
+            line: node.line,
+            column: node.column,
+        })
+    }
+}
+
+/// Split a line of the form `Key:value`, as used outside of the page content.
+fn split_preamble_line(text: &str) -> Option<(&str, &str)> {
+    const KEYS: &[&str] = &[
+        "SyncTeX Version",
+        "Input",
+        "Output",
+        "Magnification",
+        "Unit",
+        "X Offset",
+        "Y Offset",
+        "Content",
+        "Postamble",
+        "Count",
+        "Post scriptum",
+    ];
+
+    let i = text.find(':')?;
+    let key = &text[..i];
+
+    if KEYS.contains(&key) {
+        Some((key, &text[i + 1..]))
+    } else {
+        None
+    }
+}
+
+/// Parse the body of a node record, `tag,line[,column]:h,v[:W[,H,D]]`.
+fn parse_node(kind: NodeKind, text: &str) -> Option<Node> {
+    fn numbers(text: &str) -> Option<Vec<i64>> {
+        text.split(',').map(|s| s.trim().parse().ok()).collect()
+    }
+
+    let mut parts = text.split(':');
+    let link = numbers(parts.next()?)?;
+    let point = numbers(parts.next()?)?;
+    let size = match parts.next() {
+        Some(s) => numbers(s)?,
+        None => Vec::new(),
+    };
+
+    if link.len() < 2 || point.len() != 2 {
+        return None;
+    }
+
+    let dimen = |i: usize| size.get(i).cloned().unwrap_or(0);
+
+    Some(Node {
+        kind,
+        tag: link[0] as u32,
+        line: link[1] as u32,
+        column: link
+            .get(2)
+            .and_then(|&c| if c >= 0 { Some(c as u32) } else { None }),
+        h: point[0],
+        v: point[1],
+        width: dimen(0),
+        height: dimen(1),
+        depth: dimen(2),
+        parent: None,
+    })
+}
+
+fn bad_line(lineno: usize, text: &str) -> crate::errors::Error {
+    errmsg!("malformed SyncTeX data at line {}: `{}`", lineno, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = "SyncTeX Version:1
+Input:1:./main.tex
+Input:2:/bundle/chap.tex
+Output:pdf
+Magnification:1000
+Unit:1
+X Offset:0
+Y Offset:0
+Content:
+!172
+{1
+[1,1:0,0:6553600,6553600,0
+(1,3:0,655360:3276800,655360,131072
+x1,3:0,655360
+g1,3:1310720,655360
+k1,4:1966080,655360:65536
+)
+h1,6:0,1310720:3276800,655360,0
+(2,2,5:0,1966080:6553600,655360,0
+)
+]
+}1
+!40
+{2
+(1,8:0,655360:3276800,655360,0
+)
+}2
+Postamble:
+Count:10
+Post scriptum:
+";
+
+    fn load(text: &str) -> SyncTexData {
+        SyncTexData::from_reader(text.as_bytes()).unwrap()
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn records() {
+        let data = load(DATA);
+        assert_eq!(data.input_name(1), Some("./main.tex"));
+        assert_eq!(data.input_name(2), Some("/bundle/chap.tex"));
+        assert_eq!(data.input_name(3), None);
+
+        let pages = data.pages();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].number, 1);
+        assert_eq!(pages[1].number, 2);
+
+        let kinds: Vec<_> = pages[0].nodes.iter().map(|n| n.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                NodeKind::VBox,
+                NodeKind::HBox,
+                NodeKind::Current,
+                NodeKind::Glue,
+                NodeKind::Kern,
+                NodeKind::VoidHBox,
+                NodeKind::HBox,
+            ]
+        );
+
+        let parents: Vec<_> = pages[0].nodes.iter().map(|n| n.parent).collect();
+        assert_eq!(
+            parents,
+            vec![None, Some(0), Some(1), Some(1), Some(1), Some(0), Some(0)]
+        );
+
+        assert_eq!(
+            pages[0].nodes[1],
+            Node {
+                kind: NodeKind::HBox,
+                tag: 1,
+                line: 3,
+                column: None,
+                h: 0,
+                v: 655_360,
+                width: 3_276_800,
+                height: 655_360,
+                depth: 131_072,
+                parent: Some(0),
+            }
+        );
+        assert_eq!(pages[0].nodes[4].width, 65536);
+        assert_eq!(pages[0].nodes[6].tag, 2);
+        assert_eq!(pages[0].nodes[6].column, Some(5));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(SyncTexData::from_reader(&b"Input:x:main.tex\n"[..]).is_err());
+        assert!(SyncTexData::from_reader(&b"Unit:one\n"[..]).is_err());
+        assert!(SyncTexData::from_reader(&b"{1\n(1,x:0,0\n"[..]).is_err());
+        assert!(SyncTexData::from_reader(&b"{1\nk1,2:0\n"[..]).is_err());
+    }
+
+    #[test]
+    fn scaling() {
+        // 473628672sp is 7227pt, or 7200bp.
+        let mut data = load(
+            "Unit:8
+Magnification:500
+X Offset:118407168
+Y Offset:-59203584
+{1
+(1,1:0,118407168:118407168,59203584,0
+)
+}1
+",
+        );
+        data.set_origin(0.0, 0.0);
+
+        let page = data.pages()[0].clone();
+        let region = data.node_region(&page, &page.nodes[0]);
+        assert_eq!(region.page, 1);
+        assert_close(region.x, 7200.0);
+        assert_close(region.y, 3600.0);
+        assert_close(region.width, 7200.0);
+        assert_close(region.height, 3600.0);
+        assert_close(region.depth, 0.0);
+
+        data.set_origin(10.0, 20.0);
+        let region = data.node_region(&page, &page.nodes[0]);
+        assert_close(region.x, 7210.0);
+        assert_close(region.y, 3620.0);
+    }
+
+    #[test]
+    fn forward() {
+        let data = load(DATA);
+        let page = &data.pages()[0];
+        let line_box = data.node_region(page, &page.nodes[1]);
+
+        // Material inside a line reports the whole line.
+        assert_eq!(data.forward("main.tex", 3), vec![line_box]);
+        assert_eq!(data.forward("./main", 4), vec![line_box]);
+
+        // Line 5 produced no output, so the next line that did is used.
+        assert_eq!(
+            data.forward("main.tex", 5),
+            vec![data.node_region(page, &page.nodes[5])]
+        );
+
+        // Past the end, the last line with output is used.
+        let page2 = &data.pages()[1];
+        assert_eq!(
+            data.forward("main.tex", 100),
+            vec![data.node_region(page2, &page2.nodes[0])]
+        );
+
+        assert_eq!(data.forward("chap", 2).len(), 1);
+        assert!(data.forward("other.tex", 1).is_empty());
+    }
+
+    #[test]
+    fn inverse() {
+        let data = load(DATA);
+        let at = |page, x, y| {
+            data.inverse(page, x, y)
+                .map(|loc| (loc.file, loc.line, loc.column))
+        };
+        let main = || "./main.tex".to_owned();
+
+        // Within the first line, near its start and on the kern.
+        assert_eq!(at(1, 80.0, 80.0), Some((main(), 3, None)));
+        assert_eq!(at(1, 102.5, 81.0), Some((main(), 4, None)));
+
+        // An empty box has no contents, so it's reported itself.
+        assert_eq!(at(1, 80.0, 88.0), Some((main(), 6, None)));
+
+        assert_eq!(
+            at(1, 80.0, 100.0),
+            Some(("/bundle/chap.tex".to_owned(), 2, Some(5)))
+        );
+
+        // Outside of every box, the nearest node is used.
+        assert_eq!(at(2, 300.0, 300.0), Some((main(), 8, None)));
+
+        assert_eq!(at(3, 80.0, 80.0), None);
+    }
+}
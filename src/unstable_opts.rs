@@ -8,6 +8,7 @@
 //! to be reliable or very polished. In particular, many of these prevent the build from being
 //! reproducible.
 
+use crate::engines::ShellEscapePolicy;
 use crate::errors::{Error, Result};
 use std::default::Default;
 use std::str::FromStr;
@@ -19,11 +20,12 @@ const HELPMSG: &str = r#"Available unstable options:
     -Z min-crossrefs=<num>      Equivalent to bibtex's -min-crossrefs flag - "include after <num>
                                     crossrefs" [default: 2]
     -Z paper-size=<spec>        Change the default paper size [default: letter]
+    -Z shell-escape             Enable \write18 for any command
+    -Z shell-escape-allow=<cmds>
+                                Enable \write18 only for the listed programs (comma-separated;
+                                    may be given multiple times)
 "#;
 
-// Re-add this when \write18 gets implemented
-//    -Z shell-escape             Enable \write18
-
 // Each entry of this should correspond to a field of UnstableOptions.
 #[derive(Debug)]
 pub enum UnstableArg {
@@ -32,6 +34,7 @@ pub enum UnstableArg {
     MinCrossrefs(i32),
     PaperSize(String),
     ShellEscapeEnabled,
+    ShellEscapeAllow(Vec<String>),
 }
 
 impl FromStr for UnstableArg {
@@ -66,8 +69,21 @@ impl FromStr for UnstableArg {
                 })
                 .map(|s| UnstableArg::PaperSize(s.to_string())),
 
-            // Re-add this when \write18 gets implemetned
-            // "shell-escape" => Ok(UnstableArg::ShellEscapeEnabled),
+            "shell-escape" => Ok(UnstableArg::ShellEscapeEnabled),
+
+            "shell-escape-allow" => value
+                .ok_or_else(|| {
+                    "'-Z shell-escape-allow <cmds>' requires a value but none was supplied".into()
+                })
+                .map(|s| {
+                    UnstableArg::ShellEscapeAllow(
+                        s.split(',')
+                            .filter(|c| !c.is_empty())
+                            .map(|c| c.to_owned())
+                            .collect(),
+                    )
+                }),
+
             _ => Err(format!("Unknown unstable option '{}'", arg).into()),
         }
     }
@@ -77,7 +93,7 @@ impl FromStr for UnstableArg {
 pub struct UnstableOptions {
     pub continue_on_errors: bool,
     pub paper_size: Option<String>,
-    pub shell_escape: bool,
+    pub shell_escape: ShellEscapePolicy,
     pub min_crossrefs: Option<i32>,
 }
 
@@ -98,7 +114,14 @@ impl UnstableOptions {
                 ContinueOnErrors => opts.continue_on_errors = true,
                 MinCrossrefs(num) => opts.min_crossrefs = Some(num),
                 PaperSize(size) => opts.paper_size = Some(size),
-                ShellEscapeEnabled => opts.shell_escape = true,
+                ShellEscapeEnabled => opts.shell_escape = ShellEscapePolicy::AllowAll,
+                ShellEscapeAllow(cmds) => match opts.shell_escape {
+                    ShellEscapePolicy::AllowAll => {}
+                    ShellEscapePolicy::Allowlist(ref mut allowed) => allowed.extend(cmds),
+                    ShellEscapePolicy::Deny => {
+                        opts.shell_escape = ShellEscapePolicy::Allowlist(cmds)
+                    }
+                },
             }
         }
 
@@ -3,9 +3,8 @@
 
 //! A Tectonic document-build workspace.
 //!
-//! For the time being, this is just a thin wrapper to provide access to a
-//! `Document` instance. This API exists to future-proof a bit for a potential
-//! world where one workspace can contain multiple documents.
+//! A workspace is a directory containing a `Tectonic.toml` file, which
+//! defines one or more `Document`s sharing the workspace's source tree.
 
 use std::{
     env, fs,
@@ -24,25 +23,50 @@ pub struct Workspace {
     /// The root directory of the workspace.
     root_dir: PathBuf,
 
-    /// This workspace's document. In the future, there might be more than one.
-    doc: Document,
+    /// This workspace's documents. There is always at least one.
+    docs: Vec<Document>,
 }
 
 impl Workspace {
     /// Get the first document in the workspace.
     ///
-    /// Right now, workspaces in fact only include one document. That may change
-    /// in the future.
+    /// This is the first document defined in `Tectonic.toml`, which is the
+    /// only one in most workspaces.
     pub fn first_document(&self) -> &Document {
-        &self.doc
+        &self.docs[0]
     }
 
     /// Get the first document in the workspace, mutably.
-    ///
-    /// Right now, workspaces in fact only include one document. That may change
-    /// in the future.
     pub fn first_document_mut(&mut self) -> &mut Document {
-        &mut self.doc
+        &mut self.docs[0]
+    }
+
+    /// Get all of the documents in the workspace, in the order that they are
+    /// defined in `Tectonic.toml`.
+    pub fn documents(&self) -> &[Document] {
+        &self.docs
+    }
+
+    /// Get the root directory of the workspace, which contains its
+    /// `Tectonic.toml` file.
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
+    }
+
+    /// Find the document in the workspace with the given name.
+    pub fn find_document(&self, name: &str) -> Result<&Document> {
+        match self.docs.iter().find(|d| d.name() == name) {
+            Some(doc) => Ok(doc),
+            None => Err(errmsg!(
+                "no document named `{}` in this workspace; it defines: {}",
+                name,
+                self.docs
+                    .iter()
+                    .map(|d| d.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
     }
 
     /// Open up a workspace baced on the current process environment.
@@ -65,9 +89,9 @@ impl Workspace {
             root_dir.pop();
             let mut doc_build_dir = root_dir.clone();
             doc_build_dir.push("build");
-            let doc = Document::new_from_toml(root_dir.clone(), doc_build_dir, &mut doc_file)?;
+            let docs = Document::load_from_toml(root_dir.clone(), doc_build_dir, &mut doc_file)?;
 
-            return Ok(Workspace { root_dir, doc });
+            return Ok(Workspace { root_dir, docs });
         }
 
         Err(errmsg!(
@@ -147,7 +171,7 @@ impl WorkspaceCreator {
 
         Ok(Workspace {
             root_dir: self.root_dir,
-            doc,
+            docs: vec![doc],
         })
     }
 
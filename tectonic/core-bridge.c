@@ -136,6 +136,18 @@ ttstub_diag_append(diagnostic_t diag, char const *text)
     diag_append(diag, text);
 }
 
+void
+ttstub_diag_set_location(diagnostic_t diag, char const *file, int line)
+{
+    diag_set_location(diag, file, line);
+}
+
+void
+ttstub_diag_set_source_line(diagnostic_t diag, int32_t const *text, size_t len, size_t loc)
+{
+    diag_set_source_line(diag, text, len, loc);
+}
+
 PRINTF_FUNC(2,0) void
 ttstub_diag_vprintf(diagnostic_t diag, const char *format, va_list ap)
 {
@@ -210,6 +222,12 @@ ttstub_get_data_md5(char const *data, size_t len, char *digest)
     return get_data_md5((uint8_t const*) data, len, (uint8_t*) digest);
 }
 
+int
+ttstub_shell_escape(const unsigned short *cmd, size_t len)
+{
+    return shell_escape(TGB->context, cmd, len);
+}
+
 rust_output_handle_t
 ttstub_output_open(char const *path, int is_gz)
 {
@@ -75,6 +75,10 @@ diagnostic_t ttstub_diag_error_begin(void);
 void ttstub_diag_finish(diagnostic_t diag);
 // Append a string the diagnostic's message
 void ttstub_diag_append(diagnostic_t diag, char const *text);
+// Record the input file and line number that the diagnostic refers to
+void ttstub_diag_set_location(diagnostic_t diag, char const *file, int line);
+// Record the source line being processed, split at the current read position
+void ttstub_diag_set_source_line(diagnostic_t diag, int32_t const *text, size_t len, size_t loc);
 // Convenience functions to append to the message using printf specifiers
 PRINTF_FUNC(2,3) void ttstub_diag_printf(diagnostic_t diag, const char *format, ...);
 // Append to diagnostic message - for higher-level abstractions.
@@ -89,6 +93,11 @@ PRINTF_FUNC(2,3) int ttstub_fprintf(rust_output_handle_t handle, const char *for
 int ttstub_get_file_md5 (char const *path, char *digest);
 int ttstub_get_data_md5 (char const *data, size_t len, char *digest);
 
+// Run a \write18 command given as UTF-16 code units. Returns 0 if the command
+// was executed successfully, 1 if the shell-escape policy disallowed it, and
+// 2 if it failed.
+int ttstub_shell_escape (const unsigned short *cmd, size_t len);
+
 rust_output_handle_t ttstub_output_open (char const *path, int is_gz);
 rust_output_handle_t ttstub_output_open_stdout (void);
 int ttstub_output_putc (rust_output_handle_t handle, int c);
@@ -42,6 +42,7 @@
 #include "dpx-dpxutil.h"
 #include "dpx-error.h"
 #include "dpx-mem.h"
+#include "dpx-pdfconform.h"
 #include "dpx-pdfobj.h"
 
 #define CIDFONT_DEBUG     3
@@ -350,6 +351,9 @@ CIDFont_dofont (CIDFont *font)
     _tt_abort("%s: Unknown CIDFontType %d.", CIDFONT_DEBUG_STR, font->subtype);
     break;
   }
+
+  if (pdf_conformance_level() != PDF_CONFORMANCE_NONE && !font->options->embed)
+    pdf_conformance_violation("font \"%s\" is not embedded", font->ident);
 }
 
 
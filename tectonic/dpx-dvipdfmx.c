@@ -44,6 +44,7 @@
 #include "dpx-fontmap.h"
 #include "dpx-mem.h"
 #include "dpx-mpost.h"
+#include "dpx-pdfconform.h"
 #include "dpx-pdfdev.h"
 #include "dpx-pdfdoc.h"
 #include "dpx-pdfencrypt.h"
@@ -440,14 +441,25 @@ dvipdfmx_main (
    * arguments, so we emulate the default TeXLive config file by copying those
    * code bits. */
 
-  pdf_set_version (5);
   select_paper(dpx_config->paperspec);
-  annot_grow = 0;
-  bookmark_open = 0;
-  key_bits = 40;
-  permission = 0x003C;
-  font_dpi = 600;
-  pdfdecimaldigits = 5;
+  pdf_version_major = dpx_config->pdf_version_major;
+  pdf_version_minor = dpx_config->pdf_version_minor;
+  pdf_set_version(pdf_version_major * 10 + pdf_version_minor);
+  compression_level = dpx_config->compression_level;
+  annot_grow = dpx_config->annot_grow;
+  bookmark_open = dpx_config->bookmark_open;
+  mag = dpx_config->mag;
+  do_encryption = dpx_config->enable_encryption;
+  key_bits = dpx_config->key_bits;
+  permission = dpx_config->permission;
+  strncpy(oplain, dpx_config->owner_password, sizeof(oplain) - 1);
+  strncpy(uplain, dpx_config->user_password, sizeof(uplain) - 1);
+  font_dpi = dpx_config->font_dpi;
+  enable_thumbnail = dpx_config->enable_thumbnail;
+  pdfdecimaldigits = dpx_config->pdfdecimaldigits;
+  x_offset = dpx_config->x_offset;
+  y_offset = dpx_config->y_offset;
+  pdf_conformance_init(dpx_config->conformance);
   image_cache_life = -2;
   source_date_epoch = build_date;
   pdf_load_fontmap_file("pdftex.map", FONTMAP_RMODE_APPEND);
@@ -478,6 +490,28 @@ dvipdfmx_main (
                       &has_id, id1, id2);
   }
 
+  /* The document can ask for these through specials, so check them again. */
+  if (pdf_conformance_level() != PDF_CONFORMANCE_NONE) {
+    int version = pdf_version_major * 10 + pdf_version_minor;
+    int max_version = 17;
+
+    if (pdf_conformance_level() == PDF_CONFORMANCE_A_1B)
+      max_version = 14;
+    else if (pdf_conformance_level() == PDF_CONFORMANCE_X_4)
+      max_version = 16;
+
+    if (version > max_version) {
+      pdf_conformance_violation("PDF version %d.%d is too new; at most %d.%d is allowed",
+                                pdf_version_major, pdf_version_minor,
+                                max_version / 10, max_version % 10);
+    }
+
+    if (do_encryption) {
+      pdf_conformance_violation("encryption is not allowed");
+      do_encryption = 0;
+    }
+  }
+
   /* Encryption and Other Settings */
   {
     memset(&settings.encrypt, 0, sizeof(struct pdf_enc_setting));
@@ -518,6 +552,14 @@ dvipdfmx_main (
   settings.device.precision   = pdfdecimaldigits;
   settings.device.ignore_colors = ignore_colors;
 
+  if (dpx_config->output_intent_profile && dpx_config->output_intent_profile[0]) {
+    settings.output_intent.profile    = dpx_config->output_intent_profile;
+    settings.output_intent.identifier = dpx_config->output_intent_identifier;
+  } else {
+    settings.output_intent.profile    = NULL;
+    settings.output_intent.identifier = NULL;
+  }
+
   set_distiller_template(filter_template);
   /* This must come before pdf_open_document where initialization
    * of pdf_enc takes place.
@@ -528,8 +570,7 @@ dvipdfmx_main (
     pdf_set_version(version);
   }
   pdf_set_compression(compress ? compression_level : 0);
-  if (enable_thumbnail)
-    pdf_doc_enable_manual_thumbnails();
+  pdf_doc_set_manual_thumbnails(enable_thumbnail);
 
   if (!has_id) {
     const char *producer = "xdvipdfmx-0.1, Copyright 2002-2015 by Jin-Hwan Cho, Matthias Franz, and Shunsaku Hirata";
@@ -540,6 +581,26 @@ dvipdfmx_main (
   /* Initialize PDF document creation routine. */
   pdf_open_document(pdf_filename, creator, id1, id2, settings);
 
+  /* Metadata given by the document settings. This is merged in before the
+   * DVI file is read, so that any pdf:docinfo specials take precedence. */
+  if (dpx_config->docinfo && dpx_config->docinfo[0]) {
+    const char *p = dpx_config->docinfo;
+    pdf_obj *dict = parse_pdf_dict(&p, p + strlen(p), NULL);
+
+    if (dict) {
+      pdf_merge_dict(pdf_doc_docinfo(), dict);
+      pdf_release_obj(dict);
+    } else {
+      dpx_warning("Could not parse the document information given in the settings.");
+    }
+  }
+
+  if (dpx_config->language && dpx_config->language[0]) {
+    pdf_add_dict(pdf_doc_catalog(),
+                 pdf_new_name("Lang"),
+                 pdf_new_string(dpx_config->language, strlen(dpx_config->language)));
+  }
+
   if (opt_flags & OPT_CIDFONT_FIXEDPITCH)
     CIDFont_set_flags(CIDFONT_FORCE_FIXEDPITCH);
   /* Please move this to spc_init_specials(). */
@@ -559,5 +620,10 @@ dvipdfmx_main (
   dpx_message("\n");
   free(page_ranges);
 
+  if (pdf_conformance_num_violations() > 0) {
+    _tt_abort("the output does not conform to %s: %d problem(s) were found",
+              pdf_conformance_name(), pdf_conformance_num_violations());
+  }
+
   return 0;
 }
@@ -0,0 +1,181 @@
+/* tectonic/dpx-pdfconform.c: checking PDF output against PDF/A and PDF/X
+   Copyright 2020 the Tectonic Project
+   Licensed under the MIT License.
+*/
+
+/* The PDF/A and PDF/X standards restrict what a PDF file may contain. When a
+ * conformance level is selected, the document-level requirements (metadata,
+ * output intents, page boxes) are taken care of in pdfdoc.c. This file keeps
+ * track of the requirements that the document content might break. Every
+ * problem is reported as an error as soon as it's found, and the caller
+ * fails the run at the end if there were any, so that users get to see all
+ * of the problems at once.
+ */
+
+#include "dpx-pdfconform.h"
+
+#include <stdarg.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "core-bridge.h"
+
+static int conformance_level = PDF_CONFORMANCE_NONE;
+static int num_violations = 0;
+
+void
+pdf_conformance_init (int level)
+{
+  conformance_level = level;
+  num_violations = 0;
+}
+
+int
+pdf_conformance_level (void)
+{
+  return conformance_level;
+}
+
+const char *
+pdf_conformance_name (void)
+{
+  switch (conformance_level) {
+  case PDF_CONFORMANCE_A_1B:
+    return "PDF/A-1b";
+  case PDF_CONFORMANCE_A_2B:
+    return "PDF/A-2b";
+  case PDF_CONFORMANCE_X_4:
+    return "PDF/X-4";
+  default:
+    return NULL;
+  }
+}
+
+int
+pdf_conformance_is_pdfa (void)
+{
+  return conformance_level == PDF_CONFORMANCE_A_1B ||
+    conformance_level == PDF_CONFORMANCE_A_2B;
+}
+
+int
+pdf_conformance_is_pdfx (void)
+{
+  return conformance_level == PDF_CONFORMANCE_X_4;
+}
+
+PRINTF_FUNC(1,2) void
+pdf_conformance_violation (const char *format, ...)
+{
+  char    buf[1024];
+  va_list ap;
+
+  va_start(ap, format);
+  vsnprintf(buf, sizeof(buf), format, ap);
+  va_end(ap);
+
+  ttstub_issue_error("%s: %s", pdf_conformance_name(), buf);
+  num_violations++;
+}
+
+int
+pdf_conformance_num_violations (void)
+{
+  return num_violations;
+}
+
+static int
+name_is (pdf_obj *obj, const char *name)
+{
+  return PDF_OBJ_NAMETYPE(obj) && !strcmp(pdf_name_value(obj), name);
+}
+
+static int
+uses_lzw (pdf_obj *filter)
+{
+  unsigned int i;
+
+  if (name_is(filter, "LZWDecode"))
+    return 1;
+
+  if (PDF_OBJ_ARRAYTYPE(filter)) {
+    for (i = 0; i < pdf_array_length(filter); i++) {
+      if (name_is(pdf_get_array(filter, i), "LZWDecode"))
+        return 1;
+    }
+  }
+
+  return 0;
+}
+
+static void
+check_transparency (pdf_obj *dict)
+{
+  pdf_obj *value;
+
+  value = pdf_lookup_dict(dict, "SMask");
+  if (value && !name_is(value, "None")) {
+    pdf_conformance_violation("soft masks are not allowed, but the document uses transparency");
+    return;
+  }
+
+  value = pdf_lookup_dict(dict, "CA");
+  if (PDF_OBJ_NUMBERTYPE(value) && pdf_number_value(value) != 1.0) {
+    pdf_conformance_violation("stroking opacity must be 1, but the document uses %g",
+                              pdf_number_value(value));
+    return;
+  }
+
+  value = pdf_lookup_dict(dict, "ca");
+  if (PDF_OBJ_NUMBERTYPE(value) && pdf_number_value(value) != 1.0) {
+    pdf_conformance_violation("fill opacity must be 1, but the document uses %g",
+                              pdf_number_value(value));
+    return;
+  }
+
+  value = pdf_lookup_dict(dict, "BM");
+  if (PDF_OBJ_NAMETYPE(value) &&
+      !name_is(value, "Normal") && !name_is(value, "Compatible")) {
+    pdf_conformance_violation("blend modes are not allowed, but the document uses /%s",
+                              pdf_name_value(value));
+    return;
+  }
+
+  if (name_is(pdf_lookup_dict(dict, "S"), "Transparency")) {
+    pdf_conformance_violation("transparency groups are not allowed");
+  }
+}
+
+void
+pdf_conformance_check_dict (pdf_obj *dict)
+{
+  pdf_obj *value;
+
+  if (conformance_level == PDF_CONFORMANCE_NONE)
+    return;
+
+  if (pdf_conformance_is_pdfa()) {
+    static const char *forbidden_actions[] = {
+      "JavaScript", "Launch", "Sound", "Movie", "ResetForm", "ImportData", NULL
+    };
+    int i;
+
+    value = pdf_lookup_dict(dict, "S");
+    for (i = 0; forbidden_actions[i]; i++) {
+      if (name_is(value, forbidden_actions[i])) {
+        pdf_conformance_violation("%s actions are not allowed", forbidden_actions[i]);
+        break;
+      }
+    }
+
+    if (uses_lzw(pdf_lookup_dict(dict, "Filter"))) {
+      pdf_conformance_violation("LZW-compressed streams are not allowed");
+    }
+  }
+
+  /* Only PDF/A-1 forbids transparency outright; the later standards allow it
+   * as long as there is an output intent, which we always provide. */
+  if (conformance_level == PDF_CONFORMANCE_A_1B) {
+    check_transparency(dict);
+  }
+}
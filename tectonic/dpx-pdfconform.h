@@ -0,0 +1,30 @@
+/* tectonic/dpx-pdfconform.h: checking PDF output against PDF/A and PDF/X
+   Copyright 2020 the Tectonic Project
+   Licensed under the MIT License.
+*/
+
+#ifndef _PDFCONFORM_H_
+#define _PDFCONFORM_H_
+
+#include "dpx-core.h"
+#include "dpx-pdfobj.h"
+
+/* These values must match those used by `XdvipdfmxConfig` on the Rust side. */
+#define PDF_CONFORMANCE_NONE  0
+#define PDF_CONFORMANCE_A_1B  1
+#define PDF_CONFORMANCE_A_2B  2
+#define PDF_CONFORMANCE_X_4   3
+
+void        pdf_conformance_init        (int level);
+int         pdf_conformance_level       (void);
+const char *pdf_conformance_name        (void);
+int         pdf_conformance_is_pdfa     (void);
+int         pdf_conformance_is_pdfx     (void);
+
+PRINTF_FUNC(1,2) void pdf_conformance_violation (const char *format, ...);
+int         pdf_conformance_num_violations (void);
+
+/* Called on every dictionary as it is written out. */
+void        pdf_conformance_check_dict  (pdf_obj *dict);
+
+#endif /* _PDFCONFORM_H_ */
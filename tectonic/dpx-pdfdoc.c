@@ -44,6 +44,7 @@
 #include "dpx-mem.h"
 #include "dpx-numbers.h"
 #include "dpx-pdfcolor.h"
+#include "dpx-pdfconform.h"
 #include "dpx-pdfdev.h"
 #include "dpx-pdfdraw.h"
 #include "dpx-pdfencrypt.h"
@@ -54,6 +55,7 @@
 #include "dpx-pdfximage.h"
 #include "dpx-pngimage.h"
 #include "dpx-system.h"
+#include "dpx-unicode.h"
 
 #define PDFDOC_PAGES_ALLOC_SIZE   128u
 #define PDFDOC_ARTICLE_ALLOC_SIZE 16
@@ -63,9 +65,9 @@ static char  manual_thumb_enabled  = 0;
 static char *thumb_basename = NULL;
 
 void
-pdf_doc_enable_manual_thumbnails (void)
+pdf_doc_set_manual_thumbnails (int enabled)
 {
-  manual_thumb_enabled = 1;
+  manual_thumb_enabled = enabled ? 1 : 0;
   // without HAVE_LIBPNG:
   // dpx_warning("Manual thumbnail is not supported without the libpng library.");
 }
@@ -227,6 +229,13 @@ typedef struct pdf_doc
 
   struct form_list_node *pending_forms;
 
+  struct {
+    const char   *profile;
+    const char   *identifier;
+    unsigned char document_id[16];
+    int           annots_reported;
+  } conform;
+
 } pdf_doc;
 static pdf_doc pdoc;
 
@@ -417,6 +426,303 @@ pdf_doc_init_docinfo (pdf_doc *p)
   return;
 }
 
+/*
+ * PDF/A and PDF/X need the document information to be repeated as an XMP
+ * metadata stream attached to the catalog.
+ */
+
+static void
+xmp_puts (pdf_obj *xmp, const char *s)
+{
+  pdf_add_stream(xmp, s, strlen(s));
+}
+
+static void
+xmp_put_char (pdf_obj *xmp, int32_t c)
+{
+  unsigned char  buf[8];
+  unsigned char *q = buf;
+
+  switch (c) {
+  case '&': xmp_puts(xmp, "&amp;");  return;
+  case '<': xmp_puts(xmp, "&lt;");   return;
+  case '>': xmp_puts(xmp, "&gt;");   return;
+  case '"': xmp_puts(xmp, "&quot;"); return;
+  }
+
+  /* XML can't represent most control characters, nor lone surrogates. */
+  if (c < 0x20 && c != '\t' && c != '\n' && c != '\r')
+    return;
+  if (c >= 0xd800 && c < 0xe000)
+    return;
+
+  UC_UTF8_encode_char(c, &q, buf + sizeof(buf));
+  pdf_add_stream(xmp, buf, q - buf);
+}
+
+/* Append a PDF text string, which is either UTF-16BE with a byte order mark
+ * or PDFDocEncoding, as escaped UTF-8. The characters where PDFDocEncoding
+ * differs from Latin-1 are treated as Latin-1. */
+static void
+xmp_put_text (pdf_obj *xmp, pdf_obj *string)
+{
+  const unsigned char *s   = pdf_string_value(string);
+  unsigned int         len = pdf_string_length(string);
+  unsigned int         i   = 0;
+  int32_t              c;
+
+  if (len >= 2 && s[0] == 0xfe && s[1] == 0xff) {
+    for (i = 2; i + 1 < len; i += 2) {
+      c = (s[i] << 8) | s[i+1];
+      if (c >= 0xd800 && c < 0xdc00 && i + 3 < len) {
+        int32_t lo = (s[i+2] << 8) | s[i+3];
+
+        if (lo >= 0xdc00 && lo < 0xe000) {
+          c = 0x10000 + ((c - 0xd800) << 10) + (lo - 0xdc00);
+          i += 2;
+        }
+      }
+      xmp_put_char(xmp, c);
+    }
+  } else {
+    for (i = 0; i < len; i++)
+      xmp_put_char(xmp, s[i]);
+  }
+}
+
+/* Convert a PDF date, "D:YYYYMMDDHHmmSSOHH'mm'" where everything after the
+ * year is optional, to the ISO 8601 form used by XMP. */
+static void
+xmp_put_date (pdf_obj *xmp, pdf_obj *date)
+{
+  const char *s   = pdf_string_value(date);
+  const char *end = s + pdf_string_length(date);
+  int         field[6] = { 0, 1, 1, 0, 0, 0 };
+  int         widths[6] = { 4, 2, 2, 2, 2, 2 };
+  char        buf[64];
+  int         i, j;
+
+  if (end - s >= 2 && s[0] == 'D' && s[1] == ':')
+    s += 2;
+
+  for (i = 0; i < 6; i++) {
+    int value = 0;
+
+    if (end - s < widths[i])
+      break;
+    for (j = 0; j < widths[i]; j++) {
+      if (s[j] < '0' || s[j] > '9')
+        break;
+      value = value * 10 + (s[j] - '0');
+    }
+    if (j < widths[i])
+      break;
+    field[i] = value;
+    s += widths[i];
+  }
+
+  sprintf(buf, "%04d-%02d-%02dT%02d:%02d:%02d",
+          field[0], field[1], field[2], field[3], field[4], field[5]);
+  xmp_puts(xmp, buf);
+
+  if (s < end && *s == 'Z') {
+    xmp_puts(xmp, "Z");
+  } else if (end - s >= 3 && (*s == '+' || *s == '-')) {
+    int hours = 0, minutes = 0;
+
+    sscanf(s + 1, "%2d", &hours);
+    if (end - s >= 7 && s[3] == '\'')
+      sscanf(s + 4, "%2d", &minutes);
+    sprintf(buf, "%c%02d:%02d", *s, hours, minutes);
+    xmp_puts(xmp, buf);
+  }
+}
+
+static void
+xmp_put_property (pdf_obj *xmp, pdf_obj *docinfo, const char *key,
+                  const char *open, const char *close)
+{
+  pdf_obj *value = pdf_lookup_dict(docinfo, key);
+
+  if (!value || !PDF_OBJ_STRINGTYPE(value))
+    return;
+
+  xmp_puts(xmp, open);
+  if (!strcmp(key, "CreationDate") || !strcmp(key, "ModDate"))
+    xmp_put_date(xmp, value);
+  else
+    xmp_put_text(xmp, value);
+  xmp_puts(xmp, close);
+}
+
+static void
+pdf_doc_add_metadata (pdf_doc *p, pdf_obj *docinfo)
+{
+  pdf_obj      *xmp, *dict;
+  char          buf[128];
+  unsigned char *id = p->conform.document_id;
+
+  xmp  = pdf_new_stream(0);
+  dict = pdf_stream_dict(xmp);
+  pdf_add_dict(dict, pdf_new_name("Type"),    pdf_new_name("Metadata"));
+  pdf_add_dict(dict, pdf_new_name("Subtype"), pdf_new_name("XML"));
+
+  xmp_puts(xmp,
+           "<?xpacket begin=\"\xef\xbb\xbf\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n"
+           "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n"
+           "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n"
+           "<rdf:Description rdf:about=\"\"\n"
+           "  xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n"
+           "  xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"\n"
+           "  xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n"
+           "  xmlns:xmpMM=\"http://ns.adobe.com/xap/1.0/mm/\"\n");
+
+  switch (pdf_conformance_level()) {
+  case PDF_CONFORMANCE_A_1B:
+  case PDF_CONFORMANCE_A_2B:
+    sprintf(buf,
+            "  xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\">\n"
+            "<pdfaid:part>%d</pdfaid:part>\n"
+            "<pdfaid:conformance>B</pdfaid:conformance>\n",
+            pdf_conformance_level() == PDF_CONFORMANCE_A_1B ? 1 : 2);
+    xmp_puts(xmp, buf);
+    break;
+  case PDF_CONFORMANCE_X_4:
+    xmp_puts(xmp,
+             "  xmlns:pdfxid=\"http://www.npes.org/pdfx/ns/id/\">\n"
+             "<pdfxid:GTS_PDFXVersion>PDF/X-4</pdfxid:GTS_PDFXVersion>\n");
+    break;
+  }
+
+  xmp_puts(xmp, "<dc:format>application/pdf</dc:format>\n");
+  xmp_put_property(xmp, docinfo, "Title",
+                   "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">",
+                   "</rdf:li></rdf:Alt></dc:title>\n");
+  xmp_put_property(xmp, docinfo, "Author",
+                   "<dc:creator><rdf:Seq><rdf:li>",
+                   "</rdf:li></rdf:Seq></dc:creator>\n");
+  xmp_put_property(xmp, docinfo, "Subject",
+                   "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">",
+                   "</rdf:li></rdf:Alt></dc:description>\n");
+  xmp_put_property(xmp, docinfo, "Keywords",
+                   "<pdf:Keywords>", "</pdf:Keywords>\n");
+  xmp_put_property(xmp, docinfo, "Producer",
+                   "<pdf:Producer>", "</pdf:Producer>\n");
+  if (pdf_conformance_is_pdfx()) {
+    xmp_puts(xmp, "<pdf:Trapped>False</pdf:Trapped>\n");
+  }
+  xmp_put_property(xmp, docinfo, "Creator",
+                   "<xmp:CreatorTool>", "</xmp:CreatorTool>\n");
+  xmp_put_property(xmp, docinfo, "CreationDate",
+                   "<xmp:CreateDate>", "</xmp:CreateDate>\n");
+  xmp_put_property(xmp, docinfo, "ModDate",
+                   "<xmp:ModifyDate>", "</xmp:ModifyDate>\n");
+  xmp_put_property(xmp, docinfo, "ModDate",
+                   "<xmp:MetadataDate>", "</xmp:MetadataDate>\n");
+
+  sprintf(buf,
+          "<xmpMM:DocumentID>uuid:%02x%02x%02x%02x-%02x%02x-%02x%02x-"
+          "%02x%02x-%02x%02x%02x%02x%02x%02x</xmpMM:DocumentID>\n",
+          id[0], id[1], id[2], id[3], id[4], id[5], id[6], id[7],
+          id[8], id[9], id[10], id[11], id[12], id[13], id[14], id[15]);
+  xmp_puts(xmp, buf);
+  if (pdf_conformance_is_pdfx()) {
+    xmp_puts(xmp,
+             "<xmpMM:VersionID>1</xmpMM:VersionID>\n"
+             "<xmpMM:RenditionClass>default</xmpMM:RenditionClass>\n");
+  }
+
+  xmp_puts(xmp,
+           "</rdf:Description>\n"
+           "</rdf:RDF>\n"
+           "</x:xmpmeta>\n"
+           "<?xpacket end=\"w\"?>\n");
+
+  pdf_add_dict(p->root.dict, pdf_new_name("Metadata"), pdf_ref_obj(xmp));
+  pdf_release_obj(xmp);
+}
+
+static void
+pdf_doc_add_output_intent (pdf_doc *p)
+{
+  rust_input_handle_t handle;
+  size_t              size;
+  unsigned char      *data;
+  int                 num_comps;
+  pdf_obj            *profile, *intent, *intents;
+
+  handle = ttstub_input_open(p->conform.profile, TTIF_BINARY, 0);
+  if (handle == NULL) {
+    if (pdf_conformance_level() != PDF_CONFORMANCE_NONE)
+      pdf_conformance_violation("couldn't open the output intent ICC profile \"%s\"",
+                                p->conform.profile);
+    else
+      dpx_warning("Could not open ICC profile \"%s\" for the output intent.",
+                  p->conform.profile);
+    return;
+  }
+
+  size = ttstub_input_get_size(handle);
+  data = NEW(size > 0 ? size : 1, unsigned char);
+  if (ttstub_input_read(handle, (char *) data, size) != (ssize_t) size)
+    _tt_abort("failed to read ICC profile \"%s\"", p->conform.profile);
+  ttstub_input_close(handle);
+
+  if (size < 128) {
+    num_comps = 0;
+  } else if (!memcmp(data + 16, "RGB ", 4)) {
+    num_comps = 3;
+  } else if (!memcmp(data + 16, "CMYK", 4)) {
+    num_comps = 4;
+  } else if (!memcmp(data + 16, "GRAY", 4)) {
+    num_comps = 1;
+  } else {
+    num_comps = 0;
+  }
+
+  if (num_comps == 0) {
+    free(data);
+    if (pdf_conformance_level() != PDF_CONFORMANCE_NONE)
+      pdf_conformance_violation("\"%s\" is not a usable ICC profile", p->conform.profile);
+    else
+      dpx_warning("\"%s\" is not a usable ICC profile; no output intent added.",
+                  p->conform.profile);
+    return;
+  }
+
+  /* PDF/A-1 is based on PDF 1.4, which only knows about version 2 profiles. */
+  if (pdf_conformance_level() == PDF_CONFORMANCE_A_1B && data[8] > 2) {
+    pdf_conformance_violation("the ICC profile \"%s\" is version %d, but only version 2 profiles may be used",
+                              p->conform.profile, data[8]);
+  }
+
+  profile = pdf_new_stream(STREAM_COMPRESS);
+  pdf_add_dict(pdf_stream_dict(profile),
+               pdf_new_name("N"), pdf_new_number(num_comps));
+  pdf_add_stream(profile, data, size);
+  free(data);
+
+  intent = pdf_new_dict();
+  pdf_add_dict(intent, pdf_new_name("Type"), pdf_new_name("OutputIntent"));
+  pdf_add_dict(intent, pdf_new_name("S"),
+               pdf_new_name(pdf_conformance_is_pdfx() ? "GTS_PDFX" : "GTS_PDFA1"));
+  pdf_add_dict(intent, pdf_new_name("OutputConditionIdentifier"),
+               pdf_new_string(p->conform.identifier, strlen(p->conform.identifier)));
+  pdf_add_dict(intent, pdf_new_name("Info"),
+               pdf_new_string(p->conform.identifier, strlen(p->conform.identifier)));
+  if (pdf_conformance_is_pdfx()) {
+    pdf_add_dict(intent, pdf_new_name("RegistryName"),
+                 pdf_new_string("http://www.color.org", 20));
+  }
+  pdf_add_dict(intent, pdf_new_name("DestOutputProfile"), pdf_ref_obj(profile));
+  pdf_release_obj(profile);
+
+  intents = pdf_new_array();
+  pdf_add_array(intents, pdf_ref_obj(intent));
+  pdf_release_obj(intent);
+  pdf_add_dict(p->root.dict, pdf_new_name("OutputIntents"), intents);
+}
+
 static void
 pdf_doc_close_docinfo (pdf_doc *p)
 {
@@ -474,6 +780,37 @@ pdf_doc_close_docinfo (pdf_doc *p)
                  pdf_new_string(now, strlen(now)));
   }
 
+  if (pdf_conformance_level() != PDF_CONFORMANCE_NONE) {
+    /* Both standards want the dates, and the XMP metadata must agree with
+     * the Info dictionary. */
+    if (!pdf_lookup_dict(docinfo, "CreationDate")) {
+      char now[80];
+
+      dpx_util_format_asn_date(now, 1);
+      pdf_add_dict(docinfo,
+                   pdf_new_name ("CreationDate"),
+                   pdf_new_string(now, strlen(now)));
+    }
+    if (!pdf_lookup_dict(docinfo, "ModDate")) {
+      pdf_add_dict(docinfo,
+                   pdf_new_name("ModDate"),
+                   pdf_link_obj(pdf_lookup_dict(docinfo, "CreationDate")));
+    }
+
+    if (pdf_conformance_is_pdfx()) {
+      if (!pdf_lookup_dict(docinfo, "Trapped")) {
+        pdf_add_dict(docinfo,
+                     pdf_new_name("Trapped"), pdf_new_name("False"));
+      }
+      if (!pdf_lookup_dict(docinfo, "Title")) {
+        pdf_conformance_violation("the document must have a title "
+                                  "(set one with hyperref's pdftitle option or a pdf:docinfo special)");
+      }
+    }
+
+    pdf_doc_add_metadata(p, docinfo);
+  }
+
   pdf_release_obj(docinfo);
   p->info = NULL;
 
@@ -571,6 +908,21 @@ doc_flush_page (pdf_doc *p, pdf_page *page, pdf_obj *parent_ref)
     pdf_add_dict(page->page_obj, pdf_new_name("MediaBox"),  mediabox);
   }
 
+  /* PDF/X wants every page to say where it will be trimmed. */
+  if (pdf_conformance_is_pdfx() &&
+      !pdf_lookup_dict(page->page_obj, "TrimBox") &&
+      !pdf_lookup_dict(page->page_obj, "ArtBox")) {
+    pdf_rect *box = (page->flags & USE_MY_MEDIABOX) ? &page->cropbox : &p->pages.mediabox;
+    pdf_obj  *trimbox;
+
+    trimbox = pdf_new_array();
+    pdf_add_array(trimbox, pdf_new_number(ROUND(box->llx, 0.01)));
+    pdf_add_array(trimbox, pdf_new_number(ROUND(box->lly, 0.01)));
+    pdf_add_array(trimbox, pdf_new_number(ROUND(box->urx, 0.01)));
+    pdf_add_array(trimbox, pdf_new_number(ROUND(box->ury, 0.01)));
+    pdf_add_dict(page->page_obj, pdf_new_name("TrimBox"), trimbox);
+  }
+
   count = 0;
   contents_array = pdf_new_array();
   if (page->content_refs[0]) { /* global bop */
@@ -1729,6 +2081,44 @@ pdf_doc_close_names (pdf_doc *p)
 
 static void pdf_doc_get_mediabox (unsigned page_no, pdf_rect *mediabox);
 
+static void
+pdf_doc_check_annot (pdf_doc *p, pdf_obj *annot_dict)
+{
+  pdf_obj    *subtype = pdf_lookup_dict(annot_dict, "Subtype");
+  pdf_obj    *flags;
+  const char *name = PDF_OBJ_NAMETYPE(subtype) ? pdf_name_value(subtype) : "";
+
+  if (pdf_conformance_is_pdfx()) {
+    /* Only report the first one; documents often have hundreds of links. */
+    if (strcmp(name, "TrapNet") && strcmp(name, "PrinterMark") &&
+        !p->conform.annots_reported) {
+      pdf_conformance_violation("%s annotations are not allowed on the page "
+                                "(for hyperref, use the draft or final=false options)", name);
+      p->conform.annots_reported = 1;
+    }
+    return;
+  }
+
+  /* PDF/A: annotations must be printed, and shown the way they're printed. */
+  flags = pdf_lookup_dict(annot_dict, "F");
+  if (!flags) {
+    pdf_add_dict(annot_dict, pdf_new_name("F"), pdf_new_number(4));
+  } else if (PDF_OBJ_NUMBERTYPE(flags)) {
+    int value = (int) pdf_number_value(flags);
+
+    if (!(value & 4) || (value & (1 | 2 | 32))) {
+      pdf_conformance_violation("%s annotations must be printable and not hidden (/F %d)",
+                                name, value);
+    }
+  }
+
+  if (pdf_conformance_level() == PDF_CONFORMANCE_A_2B &&
+      strcmp(name, "Link") && strcmp(name, "Popup") &&
+      !pdf_lookup_dict(annot_dict, "AP")) {
+    pdf_conformance_violation("%s annotations must have an appearance stream", name);
+  }
+}
+
 void
 pdf_doc_add_annot (unsigned page_no, const pdf_rect *rect,
                    pdf_obj *annot_dict, int new_annot)
@@ -1773,6 +2163,9 @@ pdf_doc_add_annot (unsigned page_no, const pdf_rect *rect,
   pdf_add_array(rect_array, pdf_new_number(ROUND(annbox.ury, 0.001)));
   pdf_add_dict (annot_dict, pdf_new_name("Rect"), rect_array);
 
+  if (pdf_conformance_level() != PDF_CONFORMANCE_NONE)
+    pdf_doc_check_annot(p, annot_dict);
+
   pdf_add_array(page->annots, pdf_ref_obj(annot_dict));
 
   if (new_annot)
@@ -2458,6 +2851,14 @@ pdf_open_document (const char *filename,
   p->opt.annot_grow = settings.annot_grow_amount;
   p->opt.outline_open_depth = settings.outline_open_depth;
 
+  p->conform.profile = settings.output_intent.profile;
+  p->conform.identifier = settings.output_intent.identifier ? settings.output_intent.identifier : "";
+  p->conform.annots_reported = 0;
+  if (id1)
+    memcpy(p->conform.document_id, id1, 16);
+  else
+    memset(p->conform.document_id, 0, 16);
+
   pdf_init_resources();
   pdf_init_colors();
   pdf_init_fonts();
@@ -2529,6 +2930,9 @@ pdf_close_document (void)
   pdf_doc_close_page_tree(p);
   pdf_doc_close_docinfo  (p);
 
+  if (p->conform.profile)
+    pdf_doc_add_output_intent(p);
+
   pdf_doc_close_catalog  (p);
 
   pdf_close_images();
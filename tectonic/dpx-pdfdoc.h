@@ -72,6 +72,10 @@ struct pdf_setting
     struct pdf_enc_setting encrypt;
     struct pdf_dev_setting device;
     struct pdf_obj_setting object;
+    struct {
+      const char *profile;    /* ICC profile file name, or NULL for none */
+      const char *identifier; /* OutputConditionIdentifier */
+    } output_intent;
 };
 
 void pdf_open_document (const char *filename,
@@ -162,7 +166,7 @@ void     pdf_doc_break_annot (void);
 void     pdf_doc_expand_box  (const pdf_rect *rect);
 
 /* Manual thumbnail */
-void     pdf_doc_enable_manual_thumbnails (void);
+void     pdf_doc_set_manual_thumbnails (int enabled);
 
 /* Similar to bop_content */
 #include "dpx-pdfcolor.h"
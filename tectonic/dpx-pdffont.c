@@ -42,6 +42,7 @@
 #include "dpx-dpxconf.h"
 #include "dpx-error.h"
 #include "dpx-mem.h"
+#include "dpx-pdfconform.h"
 #include "dpx-pdfencoding.h"
 #include "dpx-pdflimits.h"
 #include "dpx-pdfobj.h"
@@ -187,6 +188,9 @@ pdf_flush_font (pdf_font *font)
   if (font->resource && font->reference) {
     if (font->subtype != PDF_FONT_FONTTYPE_TYPE3) {
       if (pdf_font_get_flag(font, PDF_FONT_FLAG_NOEMBED)) {
+        if (font->subtype != PDF_FONT_FONTTYPE_TYPE0 &&
+            pdf_conformance_level() != PDF_CONFORMANCE_NONE)
+          pdf_conformance_violation("font \"%s\" is not embedded", font->ident);
         pdf_add_dict(font->resource,
                      pdf_new_name("BaseFont"), pdf_new_name(font->fontname));
         if (font->descriptor) {
@@ -33,6 +33,7 @@
 #include "dpx-error.h"
 #include "dpx-mem.h"
 #include "dpx-mfileio.h"
+#include "dpx-pdfconform.h"
 #include "dpx-pdfencrypt.h"
 #include "dpx-pdflimits.h"
 #include "dpx-pdfparse.h"
@@ -2657,6 +2658,7 @@ pdf_write_obj (pdf_obj *object, rust_output_handle_t handle)
         write_array(object->data, handle);
         break;
     case PDF_DICT:
+        pdf_conformance_check_dict(object);
         write_dict (object->data, handle);
         break;
     case PDF_STREAM:
@@ -21,6 +21,7 @@ int32_t first;
 int32_t last;
 int32_t max_buf_stack;
 bool in_initex_mode;
+bool initex_preload_format;
 int32_t error_line;
 int32_t half_error_line;
 int32_t max_print_line;
@@ -4320,7 +4321,11 @@ tt_run_engine(const char *dump_name, const char *input_file_name, time_t build_d
 
     no_new_control_sequence = true;
 
-    if (!in_initex_mode) {
+    /* In INITEX mode we normally start from scratch, but we can also start
+     * from an existing format, so that a new one can be dumped on top of it
+     * (like the "&fmt" first-line trick of web2c's "-ini" mode). */
+
+    if (!in_initex_mode || initex_preload_format) {
         if (!load_fmt_file())
             return history;
     }
@@ -20,6 +20,36 @@ capture_to_diagnostic(diagnostic_t diagnostic)
     current_diagnostic = diagnostic;
 }
 
+static void
+diagnostic_set_source_line(diagnostic_t diagnostic, int32_t level)
+{
+    // Find the input level that is reading from the given file, and attach
+    // the text of its current line. This mirrors the "l.NNN" part of
+    // show_context.
+
+    int32_t p;
+
+    for (p = input_ptr; p >= 0; p--) {
+        input_state_t st = (p == input_ptr) ? cur_input : input_stack[p];
+        int32_t end;
+
+        if (st.state == TOKEN_LIST || st.name <= 19 || st.index != level)
+            continue;
+
+        if (buffer[st.limit] == INTPAR(end_line_char))
+            end = st.limit;
+        else
+            end = st.limit + 1;
+
+        if (end < st.start)
+            end = st.start;
+
+        ttstub_diag_set_source_line(diagnostic, &buffer[st.start], end - st.start,
+                                    st.loc > st.start ? st.loc - st.start : 0);
+        return;
+    }
+}
+
 static void
 diagnostic_print_file_line(diagnostic_t diagnostic)
 {
@@ -30,17 +60,17 @@ diagnostic_print_file_line(diagnostic_t diagnostic)
     while (level > 0 && full_source_filename_stack[level] == 0)
         level--;
 
-    if (level == 0) {
-        ttstub_diag_append(diagnostic, "!");
-    } else {
+    if (level != 0) {
         int32_t source_line = line;
         if (level != in_open) {
             source_line = line_stack[level + 1];
         }
 
         char* filename = gettexstring(full_source_filename_stack[level]);
-        ttstub_diag_printf(diagnostic, "%s:%d: ", filename, source_line);
+        ttstub_diag_set_location(diagnostic, filename, source_line);
         free(filename);
+
+        diagnostic_set_source_line(diagnostic, level);
     }
 }
 
@@ -2117,6 +2117,7 @@ write_out(int32_t p)
     small_number j;
     int32_t q, r;
     int32_t d;
+    int shell_escape_status = -1;
 
     q = get_avail();
     mem[q].b32.s0 = (RIGHT_BRACE_TOKEN + '}' );
@@ -2176,26 +2177,31 @@ write_out(int32_t p)
         if (!log_opened)
             selector = SELECTOR_TERM_ONLY;
 
-        // Existing warning for shell-escape
-        diagnostic_begin_capture_warning_here();
+        if (shell_escape_enabled)
+            shell_escape_status = ttstub_shell_escape(&str_pool[str_start[str_ptr - TOO_BIG_CHAR]],
+                                                      cur_length());
+
+        // Anything other than a successfully executed command is worth a warning
+        if (shell_escape_status != 0)
+            diagnostic_begin_capture_warning_here();
 
         print_nl_cstr("runsystem(");
         for (d = 0; d <= (cur_length()) - 1; d++)
             print(str_pool[str_start[str_ptr - TOO_BIG_CHAR] + d]);
 
         print_cstr(")...");
-        if (!shell_escape_enabled) {
+        if (!shell_escape_enabled)
             print_cstr("disabled");
-            print_char('.');
-        } else {
-            // Currently, -Z shell-escape is implemented but hidden (see
-            // src/unstable_opts.rs). When this gets actually implemented,
-            // uncomment the relevant parts in that file.
-
-            print_cstr("enabled but not implemented yet!");
-        }
+        else if (shell_escape_status == 0)
+            print_cstr("executed");
+        else if (shell_escape_status == 1)
+            print_cstr("disallowed by policy");
+        else
+            print_cstr("failed");
+        print_char('.');
 
-        capture_to_diagnostic(NULL);
+        if (shell_escape_status != 0)
+            capture_to_diagnostic(NULL);
 
         print_nl_cstr("");
         print_ln();
@@ -381,6 +381,7 @@ extern int32_t first;
 extern int32_t last;
 extern int32_t max_buf_stack;
 extern bool in_initex_mode;
+extern bool initex_preload_format;
 extern int32_t error_line;
 extern int32_t half_error_line;
 extern int32_t max_print_line;
@@ -16,7 +16,10 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use tectonic::config::PersistentConfig;
 use tectonic::driver::ProcessingSessionBuilder;
-use tectonic::io::OpenResult;
+use tectonic::io::cached_itarbundle::CachedITarBundle;
+use tectonic::io::dirbundle::DirBundle;
+use tectonic::io::zipbundle::ZipBundle;
+use tectonic::io::{Bundle, IoProvider, OpenResult};
 use tectonic::status::termcolor::TermcolorStatusBackend;
 use tectonic::status::ChatterLevel;
 use tokio::runtime::current_thread;
@@ -460,3 +463,71 @@ fn test_bundle_update() {
         }
     });
 }
+
+#[test]
+fn test_bundle_export() {
+    let tar_index = {
+        let mut builder = TarIndexBuilder::new();
+        builder
+            .push("plain.tex", b"test")
+            .push("other.tex", b"other content")
+            .push(
+                tectonic::digest::DIGEST_NAME,
+                b"1111111111111111111111111111111111111111111111111111111111111111",
+            );
+        builder.finish()
+    };
+
+    let requests = run_test(Some(tar_index), |_, url| {
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+
+        let mut bundle =
+            CachedITarBundle::new(&url, false, Some(tempdir.path()), &mut status).unwrap();
+        let original_digest = bundle.get_digest(&mut status).unwrap();
+
+        // Export everything into a Zip file.
+        let zip_path = tempdir.path().join("all.zip");
+        let names = bundle.all_files();
+        let n = bundle
+            .export_zip(
+                names.iter().map(|n| n.as_ref()),
+                fs::File::create(&zip_path).unwrap(),
+                &mut status,
+            )
+            .unwrap();
+        assert_eq!(n, 3);
+
+        let mut zip = ZipBundle::open(&zip_path).unwrap();
+        assert_eq!(zip.get_digest(&mut status).unwrap(), original_digest);
+
+        match zip.input_open_name(OsStr::new("other.tex"), &mut status) {
+            OpenResult::Ok(_) => {}
+            _ => panic!("Failed to open other.tex from the exported bundle"),
+        }
+
+        // Export a subset into a directory. Names that aren't in the bundle
+        // are ignored, and the digest file always comes along.
+        let dir_path = tempdir.path().join("subset");
+        let n = bundle
+            .export_dir(
+                vec!["plain.tex", "my-favourite-file.tex"],
+                &dir_path,
+                &mut status,
+            )
+            .unwrap();
+        assert_eq!(n, 2);
+
+        let mut dir = DirBundle::new(dir_path);
+        assert_eq!(dir.get_digest(&mut status).unwrap(), original_digest);
+
+        match dir.input_open_name(OsStr::new("other.tex"), &mut status) {
+            OpenResult::NotAvailable => {}
+            _ => panic!("other.tex should not have been exported"),
+        }
+    });
+
+    // Everything was fetched exactly once, even though it was exported twice.
+    check_req_count(&requests, TectonicRequest::File("plain.tex".into()), 1);
+    check_req_count(&requests, TectonicRequest::File("other.tex".into()), 1);
+}
@@ -202,6 +202,56 @@ fn check_file(tempdir: &TempDir, rest: &str) {
     }
 }
 
+/// Create a new V2 document and modify it so that it can be built in our
+/// test setup. Returns the temporary directory and the document path.
+fn setup_v2() -> (TempDir, PathBuf) {
+    util::set_test_root();
+
+    let tempdir = setup_and_copy_files(&[]);
+    let mut temppath = tempdir.path().to_owned();
+    let output = run_tectonic(&temppath, &["-X", "new", "doc"]);
+    success_or_panic(output);
+
+    temppath.push("doc");
+
+    // To run a build in our test setup, we can only use plain TeX. So, jankily
+    // change the format ...
+
+    {
+        let mut toml_path = temppath.clone();
+        toml_path.push("Tectonic.toml");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .append(true)
+            .open(toml_path)
+            .unwrap();
+        writeln!(file, "tex_format = 'plain'").unwrap();
+    }
+
+    // ... and write some files that are plain TeX.
+
+    {
+        let mut path = temppath.clone();
+        path.push("src");
+
+        {
+            path.push("_preamble.tex");
+            let mut file = File::create(&path).unwrap();
+            writeln!(file).unwrap();
+            path.pop();
+        }
+
+        {
+            path.push("_postamble.tex");
+            let mut file = File::create(&path).unwrap();
+            writeln!(file, "\\end").unwrap();
+            path.pop();
+        }
+    }
+
+    (tempdir, temppath)
+}
+
 /* Keep tests alphabetized */
 
 #[test]
@@ -228,12 +278,155 @@ fn bad_outfmt_1() {
     error_or_panic(output);
 }
 
+#[test]
+fn biber_rejects_outside_datasource() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+
+    // Biber would read this file itself, so the run must fail before biber
+    // is even looked for.
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--biber"],
+        r#"\immediate\openout1=texput.bcf
+\immediate\write1{<bcf:datasource type="file" datatype="bibtex">/etc/refs.bib</bcf:datasource>}
+\immediate\closeout1
+\bye"#,
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    error_or_panic(output);
+    assert!(stderr.contains("/etc/refs.bib"));
+}
+
+#[test]
+#[cfg(unix)]
+fn biber_runs_for_bcf() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+
+    // A stand-in for biber that insists on seeing the database named in the
+    // control file, and writes out a trivial .bbl file.
+    let bindir = tempdir.path().join("bin");
+    fs::create_dir(&bindir).unwrap();
+    let biber = bindir.join("biber");
+    fs::write(
+        &biber,
+        "#!/bin/sh\ntest -f refs.bib || exit 1\necho '\\def\\bibok{}' > \"${1%.bcf}.bbl\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&biber, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(tempdir.path().join("refs.bib"), "@misc{x, title={X}}\n").unwrap();
+
+    let path = env::join_paths(
+        std::iter::once(bindir).chain(env::split_paths(&env::var_os("PATH").unwrap_or_default())),
+    )
+    .unwrap();
+
+    let mut command = prep_tectonic(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--keep-intermediates", "--biber"],
+    );
+    command
+        .env("PATH", path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn().expect("tectonic failed to start");
+    child
+        .stdin
+        .as_mut()
+        .unwrap()
+        .write_all(
+            br#"\immediate\openout1=texput.bcf
+\immediate\write1{<bcf:datasource type="file" datatype="bibtex">refs.bib</bcf:datasource>}
+\immediate\closeout1
+\openin2=texput.bbl \ifeof2 \else \closein2 \input texput.bbl \fi
+\ifx\bibok\undefined \else Bibliography. \fi \bye"#,
+        )
+        .unwrap();
+    success_or_panic(child.wait_with_output().unwrap());
+
+    check_file(&tempdir, "texput.bbl");
+}
+
+#[test]
+fn bibtex_runs_for_each_aux() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+
+    // A minimal style whose .bbl files just count how many of them were read.
+    fs::write(
+        tempdir.path().join("count.bst"),
+        "ENTRY {} {} {}\nREAD\n\
+         FUNCTION {bump} { \"\\global\\advance\\nbbl by 1\" write$ newline$ }\n\
+         EXECUTE {bump}\n",
+    )
+    .unwrap();
+    fs::write(tempdir.path().join("refs.bib"), "@misc{x, title={X}}\n").unwrap();
+
+    // Like `chapterbib` and friends, write a second .aux file with its own
+    // bibliography, and read both .bbl files.
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--keep-intermediates"],
+        r"\newcount\nbbl
+\immediate\openout1=texput.aux
+\immediate\write1{\string\citation{x}\string\bibstyle{count}\string\bibdata{refs}}
+\immediate\closeout1
+\immediate\openout2=chap1.aux
+\immediate\write2{\string\citation{x}\string\bibstyle{count}\string\bibdata{refs}}
+\immediate\closeout2
+\openin3=texput.bbl \ifeof3 \else \closein3 \input texput.bbl \fi
+\openin3=chap1.bbl \ifeof3 \else \closein3 \input chap1.bbl \fi
+\immediate\openout4=texput.count
+\immediate\write4{\the\nbbl}
+\immediate\closeout4
+Hello. \bye",
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+
+    assert!(stdout.contains("on `chap1.aux`"));
+    check_file(&tempdir, "texput.bbl");
+    check_file(&tempdir, "chap1.bbl");
+
+    // The rerun after BibTeX must have seen both .bbl files.
+    let count = fs::read_to_string(tempdir.path().join("texput.count")).unwrap();
+    assert_eq!(count.trim(), "2");
+}
+
 #[test]
 fn help_flag() {
     let output = run_tectonic(&PathBuf::from("."), &["-h"]);
     success_or_panic(output);
 }
 
+#[test]
+fn html_output() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--outfmt", "html"],
+        r"\special{spx:heading 1}Title\par \special{spx:paragraph}Body\par\bye",
+    );
+    success_or_panic(output);
+
+    let mut html = String::new();
+    File::open(tempdir.path().join("texput.html"))
+        .expect("`texput.html` not found")
+        .read_to_string(&mut html)
+        .expect("cannot read `texput.html`");
+
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<title>Title</title>"));
+    assert!(html.contains("<h1>Title</h1>"));
+    assert!(html.contains("<p>Body</p>"));
+    assert!(html.contains("<div class=\"page\""));
+}
+
 #[test]
 fn keep_logs_on_error() {
     // No input files here, but output files are created.
@@ -255,6 +448,165 @@ fn keep_logs_on_error() {
     assert!(log.contains(r"job aborted, no legal \end found"));
 }
 
+#[test]
+fn makeindex_runs_for_glossaries() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+
+    // This mimics what the `glossaries` package writes out: the glossary
+    // definitions and style file name in the .aux file, a style file, and
+    // the raw entries.
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--keep-intermediates"],
+        r#"\immediate\openout1=texput.aux
+\immediate\write1{\string\@newglossary{main}{glg}{gls}{glo}}
+\immediate\write1{\string\@istfilename{texput.ist}}
+\immediate\write1{\string\@glsorder{word}}
+\immediate\closeout1
+\immediate\openout2=texput.ist
+\immediate\write2{keyword "\string\\glossaryentry"}
+\immediate\write2{actual '?'}
+\immediate\write2{preamble "BEGIN"}
+\immediate\write2{postamble " END"}
+\immediate\write2{group_skip ""}
+\immediate\write2{item_0 " ITEM "}
+\immediate\closeout2
+\immediate\openout3=texput.glo
+\immediate\write3{\string\glossaryentry{beta?Beta}{2}}
+\immediate\write3{\string\glossaryentry{alpha?Alpha|emph}{1}}
+\immediate\closeout3
+Hello. \bye"#,
+    );
+    success_or_panic(output);
+
+    let gls =
+        fs::read_to_string(tempdir.path().join("texput.gls")).expect("cannot read `texput.gls`");
+    assert_eq!(gls, "BEGIN ITEM Alpha, \\emph{1} ITEM Beta, 2 END");
+    check_file(&tempdir, "texput.glg");
+}
+
+#[test]
+fn makeindex_runs_for_idx() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--keep-intermediates"],
+        r"\immediate\openout1=texput.idx
+\immediate\write1{\string\indexentry{beta}{2}}
+\immediate\write1{\string\indexentry{alpha!sub}{3}}
+\immediate\write1{\string\indexentry{alpha}{1}}
+\immediate\write1{\string\indexentry{alpha}{2}}
+\immediate\write1{\string\indexentry{alpha}{3}}
+\immediate\closeout1
+Hello. \bye",
+    );
+    success_or_panic(output);
+
+    let mut ind = String::new();
+    File::open(tempdir.path().join("texput.ind"))
+        .expect("`texput.ind` not found")
+        .read_to_string(&mut ind)
+        .expect("Cannot read `texput.ind`");
+
+    assert_eq!(
+        ind,
+        "\\begin{theindex}\n\
+         \n  \\item alpha, 1--3\
+         \n    \\subitem sub, 3\
+         \n\n  \\indexspace\n\
+         \n  \\item beta, 2\
+         \n\n\\end{theindex}\n"
+    );
+}
+
+#[test]
+fn message_format_json() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--message-format=json"],
+        "no end to this file",
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    error_or_panic(output);
+
+    let mut saw_error = false;
+
+    for line in stdout.lines() {
+        let record: serde_json::Value = serde_json::from_str(line)
+            .unwrap_or_else(|e| panic!("stdout line {:?} is not JSON: {}", line, e));
+        let kind = record["kind"].as_str().expect("record has no kind");
+        assert!(["note", "warning", "error", "log"].contains(&kind));
+        saw_error |= kind == "error";
+    }
+
+    assert!(saw_error);
+}
+
+#[test]
+fn message_format_json_diagnostic() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("main.tex"),
+        "Hello.\n\nOops \\undefinedcs there.\n\\bye\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[&fmt_arg, "main.tex", "--message-format=json"],
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    error_or_panic(output);
+
+    let diag = stdout
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|record| record["kind"] == "error" && record["line"].is_number())
+        .expect("no error diagnostic with a line number");
+
+    assert!(diag["message"]
+        .as_str()
+        .unwrap()
+        .contains("Undefined control sequence"));
+    assert!(diag["file"].as_str().unwrap().ends_with("main.tex"));
+    assert_eq!(diag["line"], 3);
+
+    // The source line is split where TeX stopped reading it.
+    let context = diag["context"].as_array().unwrap();
+    assert_eq!(context.len(), 2);
+    assert!(context[0].as_str().unwrap().ends_with("Oops \\undefinedcs"));
+    assert!(context[1].as_str().unwrap().starts_with(" there."));
+}
+
+#[test]
+fn message_format_json_with_print() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--message-format=json", "--print"],
+        "\\bye",
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    error_or_panic(output);
+
+    // The refusal itself should still be well-formed JSON.
+    for line in stdout.lines() {
+        serde_json::from_str::<serde_json::Value>(line)
+            .unwrap_or_else(|e| panic!("stdout line {:?} is not JSON: {}", line, e));
+    }
+
+    assert!(stdout.contains("--print"));
+}
+
 #[test]
 fn no_color_option() {
     // No input files here, but output files are created.
@@ -336,6 +688,29 @@ fn outdir_option_is_file() {
     success_or_panic(output);
 }
 
+#[test]
+fn rerun_verbose() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--rerun-verbose"],
+        r"\openin1=texput.aux \ifeof1 \else \closein1 \input texput.aux \fi
+\immediate\openout2=texput.aux \immediate\write2{\relax}\immediate\closeout2
+Hello. \bye",
+    );
+
+    let chatter = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    success_or_panic(output);
+
+    assert!(chatter.contains("\"texput.aux\" changed"));
+    assert!(chatter.contains("+\\relax"));
+}
+
 #[test] // GitHub #31
 fn relative_include() {
     let fmt_arg = get_plain_format_arg();
@@ -352,6 +727,54 @@ fn relative_include() {
     check_file(&tempdir, "subdirectory/relative_include.pdf");
 }
 
+#[test]
+#[cfg(unix)]
+fn shell_escape_allow_all() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "-Z", "shell-escape"],
+        r"\immediate\write18{echo 'Shell output.' > shellout.tex}\input shellout \bye",
+    );
+    success_or_panic(output);
+}
+
+#[test]
+#[cfg(unix)]
+fn shell_escape_allowlist() {
+    let fmt_arg = get_plain_format_arg();
+    let input = r"\immediate\write18{touch made.tex}\input made \bye";
+
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "-Z", "shell-escape-allow=touch"],
+        input,
+    );
+    success_or_panic(output);
+
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "-Z", "shell-escape-allow=echo"],
+        input,
+    );
+    error_or_panic(output);
+}
+
+#[test]
+fn shell_escape_disabled() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-"],
+        r"\immediate\write18{touch made.tex}\input made \bye",
+    );
+    error_or_panic(output);
+}
+
 // Regression #36
 #[test]
 fn space_in_filename() {
@@ -376,53 +799,555 @@ fn stdin_content() {
 }
 
 #[test]
-fn v2_new_build() {
-    util::set_test_root();
+fn v2_build_up_to_date() {
+    let (_tempdir, temppath) = setup_v2();
 
-    let tempdir = setup_and_copy_files(&[]);
-    let mut temppath = tempdir.path().to_owned();
-    let output = run_tectonic(&temppath, &["-X", "new", "doc"]);
+    let output = run_tectonic(&temppath, &["-X", "build"]);
     success_or_panic(output);
 
-    temppath.push("doc");
+    // Nothing changed, so the second build should be skipped.
 
-    // To run a build in our test setup, we can only use plain TeX. So, jankily
-    // change the format ...
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert!(stdout.contains("up to date"));
+    assert!(!stdout.contains("Running TeX"));
+
+    // Touching the content should trigger a real rebuild.
+
+    {
+        let mut path = temppath.clone();
+        path.push("src");
+        path.push("index.tex");
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        writeln!(file, "More content.").unwrap();
+    }
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert!(!stdout.contains("up to date"));
+}
+
+#[test]
+fn v2_build_up_to_date_settings() {
+    let (_tempdir, temppath) = setup_v2();
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(output);
+
+    // The sources are unchanged, but a build with different settings might
+    // produce different output, so it must not be skipped.
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--biber"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert!(!stdout.contains("up to date"));
+    assert!(stdout.contains("Running TeX"));
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--biber"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert!(stdout.contains("up to date"));
+}
+
+#[test]
+fn v2_multiple_formats() {
+    let (_tempdir, temppath) = setup_v2();
 
     {
-        let mut toml_path = temppath.clone();
-        toml_path.push("Tectonic.toml");
         let mut file = OpenOptions::new()
-            .write(true)
             .append(true)
-            .open(toml_path)
+            .open(temppath.join("Tectonic.toml"))
             .unwrap();
-        writeln!(file, "tex_format = 'plain'").unwrap();
+        writeln!(
+            file,
+            "\n[[output]]\nname = \"web\"\ntype = \"html\"\ntex_format = \"plain\""
+        )
+        .unwrap();
+        writeln!(
+            file,
+            "\n[[output]]\nname = \"raw\"\ntype = \"xdv\"\ntex_format = \"plain\""
+        )
+        .unwrap();
     }
 
-    // ... and write some files that are plain TeX.
+    fs::write(
+        temppath.join("src").join("index.tex"),
+        "\\special{spx:paragraph}Body\\par\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(output);
+
+    let build_path = temppath.join("build");
+    assert!(build_path.join("default").join("default.pdf").exists());
+    assert!(build_path.join("raw").join("raw.xdv").exists());
+
+    let html = fs::read_to_string(build_path.join("web").join("web.html")).unwrap();
+    assert!(html.contains("<p>Body</p>"));
+}
+
+#[test]
+fn v2_metadata() {
+    let (_tempdir, temppath) = setup_v2();
 
     {
-        let mut path = temppath.clone();
-        path.push("src");
+        let toml_path = temppath.join("Tectonic.toml");
+        let toml = fs::read_to_string(&toml_path).unwrap().replacen(
+            "[doc]\n",
+            "[doc]\ntitle = \"My (Long) Thesis\"\nauthors = [\"A. Writer\", \"B. Writer\"]\nlanguage = \"en\"\ndate = 2020-05-01T12:00:00Z\n",
+            1,
+        );
+        fs::write(&toml_path, toml).unwrap();
+
+        // Avoid object streams, so that the metadata is easy to find.
+        let mut file = OpenOptions::new().append(true).open(&toml_path).unwrap();
+        writeln!(file, "\n[output.pdf]\nversion = \"1.4\"").unwrap();
+    }
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(output);
+
+    let pdf = fs::read(temppath.join("build").join("default").join("default.pdf")).unwrap();
+    let contains = |needle: &[u8]| pdf.windows(needle.len()).any(|w| w == needle);
+    assert!(contains(b"/Title (My \\(Long\\) Thesis)"));
+    assert!(contains(b"/Author (A. Writer; B. Writer)"));
+    assert!(contains(b"/Lang (en)"));
+    assert!(contains(b"(D:20200501120000"));
+}
+
+#[test]
+fn v2_multiple_docs() {
+    let (_tempdir, temppath) = setup_v2();
+
+    // Turn the single document into two that share the source tree and the
+    // output definitions.
+
+    let toml_path = temppath.join("Tectonic.toml");
+    let toml = fs::read_to_string(&toml_path).unwrap();
+    let split = toml.find("[[output]]").unwrap();
+    let (doc_part, output_part) = toml.split_at(split);
+
+    let slides_part: String = doc_part
+        .lines()
+        .map(|line| {
+            if line.starts_with("name") {
+                "name = \"slides\"\nindex = \"slides.tex\"\n".to_owned()
+            } else {
+                format!("{}\n", line)
+            }
+        })
+        .collect();
+
+    fs::write(
+        &toml_path,
+        format!(
+            "{}\n{}\n{}",
+            doc_part.replace("[doc]", "[[doc]]"),
+            slides_part.replace("[doc]", "[[doc]]"),
+            output_part
+        ),
+    )
+    .unwrap();
+
+    fs::write(temppath.join("src").join("slides.tex"), "Slides.\n").unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(output);
+
+    let build_path = temppath.join("build");
+    assert!(build_path
+        .join("doc")
+        .join("default")
+        .join("default.pdf")
+        .exists());
+    assert!(build_path
+        .join("slides")
+        .join("default")
+        .join("default.pdf")
+        .exists());
+
+    // `--doc` builds just the one.
+
+    fs::remove_dir_all(&build_path).unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--doc", "slides"]);
+    success_or_panic(output);
+    assert!(!build_path.join("doc").exists());
+    assert!(build_path
+        .join("slides")
+        .join("default")
+        .join("default.pdf")
+        .exists());
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--doc", "poster"]);
+    error_or_panic(output);
+
+    // Document names become build subdirectories, so they can't be paths.
+
+    let toml = fs::read_to_string(&toml_path).unwrap();
+    fs::write(
+        &toml_path,
+        toml.replace("name = \"slides\"", "name = \"../slides\""),
+    )
+    .unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    error_or_panic(output);
+    assert!(!temppath.join("slides").exists());
+}
+
+#[test]
+fn v2_new_build() {
+    let (_tempdir, temppath) = setup_v2();
+
+    // Now we can build.
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(output);
+}
+
+#[test]
+fn v2_output_settings() {
+    let (_tempdir, temppath) = setup_v2();
+
+    let toml_path = temppath.join("Tectonic.toml");
+
+    {
+        let mut file = OpenOptions::new().append(true).open(&toml_path).unwrap();
+        writeln!(
+            file,
+            "keep_logs = true\npaper_size = \"a4\"\n\n[output.defs]\ngreeting = \"Hello\""
+        )
+        .unwrap();
+    }
+
+    // If the definition weren't made, `\greeting` would be undefined.
+
+    fs::write(temppath.join("src").join("index.tex"), "\\greeting\n").unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(output);
+
+    let mut build_path = temppath.clone();
+    build_path.push("build");
+    build_path.push("default");
+    assert!(build_path.join("default.log").exists());
+
+    // Bad settings should be rejected when the document is loaded.
+
+    {
+        let mut file = OpenOptions::new().append(true).open(&toml_path).unwrap();
+        writeln!(file, "\"not a name\" = \"oops\"").unwrap();
+    }
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    error_or_panic(output);
+}
+
+#[test]
+fn v2_pdf_conformance() {
+    let (_tempdir, temppath) = setup_v2();
+
+    let mut toml_path = temppath.clone();
+    toml_path.push("Tectonic.toml");
+
+    {
+        let mut file = OpenOptions::new().append(true).open(&toml_path).unwrap();
+        writeln!(file, "\n[output.pdf]\nconformance = \"pdf/a-2b\"").unwrap();
+    }
+
+    // The test bundle doesn't provide the default sRGB profile, so the output
+    // intent can't be embedded and the build must fail, saying why.
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    error_or_panic(output);
+    assert!(stderr.contains("PDF/A-2b"));
+    assert!(stderr.contains("sRGB.icc"));
+
+    // Encryption isn't allowed at all.
+
+    {
+        let mut file = OpenOptions::new().append(true).open(&toml_path).unwrap();
+        writeln!(
+            file,
+            "\n[output.pdf.encryption]\nowner_password = \"owner\""
+        )
+        .unwrap();
+    }
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    error_or_panic(output);
+}
+
+#[test]
+fn v2_pdf_conforming() {
+    // A bare-bones ICC profile header: enough for the output intent to be
+    // embedded, if not for a real color workflow.
+    fn icc_profile(color_space: &[u8; 4]) -> Vec<u8> {
+        let mut data = vec![0; 128];
+        data[3] = 128;
+        data[8] = 2;
+        data[12..16].copy_from_slice(b"prtr");
+        data[16..20].copy_from_slice(color_space);
+        data[20..24].copy_from_slice(b"Lab ");
+        data[36..40].copy_from_slice(b"acsp");
+        data
+    }
+
+    for (settings, checks) in &[
+        (
+            "conformance = \"pdf/a-2b\"",
+            &["/OutputIntents", "/GTS_PDFA1", "<pdfaid:part>2</pdfaid:part>"][..],
+        ),
+        (
+            "conformance = \"pdf/x-4\"\nicc_profile = \"coated.icc\"\noutput_condition = \"FOGRA39\"",
+            &[
+                "/OutputIntents",
+                "/GTS_PDFX",
+                "(FOGRA39)",
+                "<pdfxid:GTS_PDFXVersion>PDF/X-4</pdfxid:GTS_PDFXVersion>",
+                "/TrimBox",
+            ][..],
+        ),
+    ] {
+        let (_tempdir, temppath) = setup_v2();
+        let src_path = temppath.join("src");
+        fs::write(src_path.join("sRGB.icc"), icc_profile(b"RGB ")).unwrap();
+        fs::write(src_path.join("coated.icc"), icc_profile(b"CMYK")).unwrap();
 
         {
-            path.push("_preamble.tex");
-            let mut file = File::create(&path).unwrap();
-            writeln!(file).unwrap();
-            path.pop();
+            // PDF/X requires a title. Stick to PDF 1.4 so that nothing ends
+            // up in a compressed object stream.
+            let toml_path = temppath.join("Tectonic.toml");
+            let toml = fs::read_to_string(&toml_path).unwrap().replacen(
+                "[doc]\n",
+                "[doc]\ntitle = \"Conforming\"\n",
+                1,
+            );
+            fs::write(&toml_path, toml).unwrap();
+
+            let mut file = OpenOptions::new().append(true).open(&toml_path).unwrap();
+            writeln!(file, "\n[output.pdf]\nversion = \"1.4\"\n{}", settings).unwrap();
         }
 
-        {
-            path.push("_postamble.tex");
-            let mut file = File::create(&path).unwrap();
-            writeln!(file, "\\end").unwrap();
-            path.pop();
+        let output = run_tectonic(&temppath, &["-X", "build"]);
+        success_or_panic(output);
+
+        let pdf = fs::read(temppath.join("build").join("default").join("default.pdf")).unwrap();
+        for check in *checks {
+            assert!(
+                pdf.windows(check.len()).any(|w| w == check.as_bytes()),
+                "PDF built with `{}` lacks `{}`",
+                settings,
+                check
+            );
         }
     }
+}
 
-    // Now we can build.
+#[test]
+fn v2_pdf_encryption() {
+    let (_tempdir, temppath) = setup_v2();
+
+    {
+        let mut toml_path = temppath.clone();
+        toml_path.push("Tectonic.toml");
+        let mut file = OpenOptions::new().append(true).open(&toml_path).unwrap();
+        writeln!(
+            file,
+            "\n[output.pdf.encryption]\nowner_password = \"owner\"\npermissions = [\"print\"]"
+        )
+        .unwrap();
+    }
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(output);
+
+    let mut pdf_path = temppath.clone();
+    pdf_path.push("build");
+    pdf_path.push("default");
+    pdf_path.push("default.pdf");
+    let pdf = fs::read(&pdf_path).unwrap();
+    assert!(pdf.windows(8).any(|w| w == b"/Encrypt"));
+}
+
+#[test]
+fn v2_pdf_options() {
+    let (_tempdir, temppath) = setup_v2();
+
+    let mut toml_path = temppath.clone();
+    toml_path.push("Tectonic.toml");
+
+    {
+        let mut file = OpenOptions::new().append(true).open(&toml_path).unwrap();
+        writeln!(file, "\n[output.pdf]\nversion = \"1.4\"").unwrap();
+    }
 
     let output = run_tectonic(&temppath, &["-X", "build"]);
     success_or_panic(output);
+
+    let mut pdf_path = temppath.clone();
+    pdf_path.push("build");
+    pdf_path.push("default");
+    pdf_path.push("default.pdf");
+    let pdf = fs::read(&pdf_path).unwrap();
+    assert!(pdf.starts_with(b"%PDF-1.4"));
+
+    // Out-of-range settings should be rejected when the document is loaded.
+
+    {
+        let mut file = OpenOptions::new().append(true).open(&toml_path).unwrap();
+        writeln!(file, "compression_level = 12").unwrap();
+    }
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    error_or_panic(output);
+}
+
+#[test]
+fn v2_preamble_cache() {
+    let (_tempdir, temppath) = setup_v2();
+
+    let mut src_path = temppath.clone();
+    src_path.push("src");
+
+    // Plain TeX has no `\begin`, so make one up. If the precompiled preamble
+    // weren't used, `\greeting` would be undefined.
+
+    let write_preamble = |greeting: &str| {
+        let mut file = File::create(src_path.join("_preamble.tex")).unwrap();
+        writeln!(file, "\\def\\begin#1{{}}").unwrap();
+        writeln!(file, "\\def\\greeting{{{}}}", greeting).unwrap();
+        writeln!(file, "\\begin{{document}}").unwrap();
+    };
+
+    write_preamble("Hello");
+    {
+        let mut file = File::create(src_path.join("index.tex")).unwrap();
+        writeln!(file, "\\greeting").unwrap();
+    }
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--preamble-cache"]);
+    success_or_panic(output);
+
+    // The preamble didn't change, so it shouldn't be regenerated.
+
+    {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(src_path.join("index.tex"))
+            .unwrap();
+        writeln!(file, "More content.").unwrap();
+    }
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--preamble-cache"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert!(!stdout.contains("generating precompiled preamble"));
+
+    // The preamble file is read through the I/O stack, so changing it is
+    // noticed like changing any other input.
+
+    write_preamble("Goodbye");
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--preamble-cache"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert!(stdout.contains("generating precompiled preamble"));
+    assert!(!stdout.contains("up to date"));
+}
+
+#[test]
+fn v2_reproducible() {
+    let (_tempdir, temppath) = setup_v2();
+
+    let index_path = temppath.join("src").join("index.tex");
+    let build_path = temppath.join("build");
+    let pdf_path = build_path.join("default").join("default.pdf");
+
+    let build = || {
+        let mut command = prep_tectonic(&temppath, &["-X", "build", "--reproducible"]);
+        command.env("SOURCE_DATE_EPOCH", "1600000000");
+        println!("running {:?}", command);
+        success_or_panic(command.output().expect("tectonic failed to start"));
+        let pdf = fs::read(&pdf_path).unwrap();
+        fs::remove_dir_all(&build_path).unwrap();
+        pdf
+    };
+
+    fs::write(&index_path, "Hello, world!\n").unwrap();
+    let first = build();
+
+    // Rewriting the input gives it a new modification time, but the same
+    // contents, so the output shouldn't change.
+
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    fs::write(&index_path, "Hello, world!\n").unwrap();
+    let second = build();
+
+    assert!(first == second, "reproducible builds differ");
+
+    let contains = |needle: &[u8]| first.windows(needle.len()).any(|w| w == needle);
+    assert!(contains(b"(D:20200913122640"));
+}
+
+#[test]
+fn v2_synctex() {
+    let (_tempdir, temppath) = setup_v2();
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--synctex"]);
+    success_or_panic(output);
+
+    let mut synctex_path = temppath.clone();
+    synctex_path.push("build");
+    synctex_path.push("default");
+    synctex_path.push("default.synctex.gz");
+    assert!(synctex_path.is_file());
+
+    let output = run_tectonic(&temppath, &["-X", "synctex", "view", "src/index.tex", "1"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert!(stdout.contains("Page:1"));
+
+    let output = run_tectonic(&temppath, &["-X", "synctex", "edit", "1", "100", "80"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert!(stdout.contains("index.tex"));
+    assert!(stdout.contains("Line:1"));
+
+    let output = run_tectonic(
+        &temppath,
+        &["-X", "synctex", "view", "nonexistent.tex", "1"],
+    );
+    error_or_panic(output);
+}
+
+/// If a worker process dies in the middle of an engine run, that should be
+/// reported as an engine error rather than, say, hanging or panicking.
+#[test]
+#[cfg(unix)]
+fn worker_crash() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+
+    // Shell-escape commands are run by the parent process on the worker's
+    // behalf, so this kills the worker while it waits for the reply.
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "-Z", "shell-escape"],
+        r"\immediate\write18{pkill -KILL -P $PPID -x tectonic}\bye",
+    );
+
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    error_or_panic(output);
+    assert!(text.contains("the TeX engine had an unrecoverable error"));
+    assert!(text.contains("lost contact with the engine worker process"));
 }
@@ -109,6 +109,67 @@ impl tectonic_xdv::XdvEvents for Stats {
         );
         Ok(())
     }
+
+    fn handle_define_native_font(
+        &mut self,
+        name: &str,
+        font_num: i32,
+        size: i32,
+        face_index: u32,
+        _color_rgba: Option<u32>,
+        _extend: Option<u32>,
+        _slant: Option<u32>,
+        _embolden: Option<u32>,
+    ) -> Result<(), Self::Error> {
+        println!(
+            "native font {}: {} (face {}) at {}sp",
+            font_num, name, face_index, size
+        );
+        Ok(())
+    }
+
+    fn handle_glyph_run(
+        &mut self,
+        font_num: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<(), Self::Error> {
+        println!(
+            "glyphs: font={} n_glyphs={} at=({}, {})",
+            font_num,
+            glyphs.len(),
+            x.first().copied().unwrap_or(0),
+            y.first().copied().unwrap_or(0)
+        );
+        Ok(())
+    }
+
+    fn handle_rule(&mut self, x: i32, y: i32, height: i32, width: i32) -> Result<(), Self::Error> {
+        println!("rule: {}x{} at=({}, {})", width, height, x, y);
+        Ok(())
+    }
+
+    fn handle_text_and_glyphs(
+        &mut self,
+        font_num: i32,
+        text: &str,
+        width: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<(), Self::Error> {
+        println!(
+            "text: {:?} font={} width={} n_glyphs={} at=({}, {})",
+            text,
+            font_num,
+            width,
+            glyphs.len(),
+            x.first().copied().unwrap_or(0),
+            y.first().copied().unwrap_or(0)
+        );
+        Ok(())
+    }
 }
 
 fn main() {
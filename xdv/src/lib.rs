@@ -131,6 +131,89 @@ pub trait XdvEvents {
     fn handle_char_run(&mut self, chars: &[i32]) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    /// Handle a change in the current position.
+    ///
+    /// This is called after every command that explicitly moves the current
+    /// position, including `pop`. Commands that typeset material also advance
+    /// *h*, but this is not called for them.
+    #[allow(unused)]
+    fn handle_movement(&mut self, registers: &Registers) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle a rule: a solid rectangle whose bottom left corner is at (*x*,
+    /// *y*) on the page. Rules with a non-positive width or height, which
+    /// produce no output, are not reported.
+    #[allow(unused)]
+    fn handle_rule(&mut self, x: i32, y: i32, height: i32, width: i32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle the definition of a native (OpenType/TrueType/AAT) font.
+    ///
+    /// The *size* is in TeX scaled points. The *extend*, *slant*, and
+    /// *embolden* values, if present, are 16.16 fixed-point numbers. Fonts
+    /// are defined both within the pages and again in the postamble, so this
+    /// may be called more than once for the same font.
+    #[allow(unused, clippy::too_many_arguments)]
+    fn handle_define_native_font(
+        &mut self,
+        name: &str,
+        font_num: i32,
+        size: i32,
+        face_index: u32,
+        color_rgba: Option<u32>,
+        extend: Option<u32>,
+        slant: Option<u32>,
+        embolden: Option<u32>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle a run of glyphs in a native font.
+    ///
+    /// The *x* and *y* arrays give the absolute position of each glyph on
+    /// the page, in TeX scaled points.
+    #[allow(unused)]
+    fn handle_glyph_run(
+        &mut self,
+        font_num: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle a run of glyphs in a native font along with the Unicode text
+    /// that they represent.
+    ///
+    /// The *x* and *y* arrays give the absolute position of each glyph on
+    /// the page, in TeX scaled points. The *width* is the total advance of
+    /// the run.
+    #[allow(unused)]
+    fn handle_text_and_glyphs(
+        &mut self,
+        font_num: i32,
+        text: &str,
+        width: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Returns true if the given opcode interrupts a run of characters.
+fn ends_char_run(opcode: u8) -> bool {
+    !(opcode == Opcode::Noop as u8
+        || (opcode >= Opcode::DefineFont1 as u8 && opcode <= Opcode::DefineFont4 as u8)
+        || opcode == Opcode::DefineNativeFont as u8
+        || opcode == Opcode::PushStack as u8
+        || (opcode >= Opcode::SetCharNumber0 as u8 && opcode <= Opcode::SetCharNumber127 as u8)
+        || (opcode >= Opcode::SetChar1 as u8 && opcode <= Opcode::SetChar4 as u8))
 }
 
 /// State for parsing an XDV file.
@@ -139,7 +222,7 @@ pub struct XdvParser<T: XdvEvents> {
     events: T,
     filetype: FileType,
     state: ParserState,
-    stack: Vec<State>,
+    stack: Vec<Registers>,
     cur_font_num: Option<i32>,
     offset: u64,
     cur_char_run: Vec<i32>,
@@ -284,23 +367,24 @@ impl<T: XdvEvents> XdvParser<T> {
             }
 
             let opcode = cursor.get_u8().unwrap();
-            let mut char_run_ended = true; // most commands end runs of characters
+
+            // Most commands end runs of characters. Deliver the run before
+            // handling the command, so that events arrive in document order.
+            if ends_char_run(opcode) && !self.cur_char_run.is_empty() {
+                self.events.handle_char_run(&self.cur_char_run)?;
+                self.cur_char_run.clear();
+            }
 
             let rv = match opcode {
                 // This is the least ugly way I've found to map the u8 to the
                 // symbolic enum values.
-                oc if oc == Opcode::Noop as u8 => {
-                    char_run_ended = false;
-                    Ok(())
-                }
+                oc if oc == Opcode::Noop as u8 => Ok(()),
 
                 oc if oc >= Opcode::DefineFont1 as u8 && oc <= Opcode::DefineFont4 as u8 => {
-                    char_run_ended = false;
                     self.do_define_font(oc, &mut cursor)
                 }
 
                 oc if oc == Opcode::DefineNativeFont as u8 => {
-                    char_run_ended = false;
                     self.do_define_native_font(oc, &mut cursor)
                 }
 
@@ -310,10 +394,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
                 oc if oc == Opcode::EndOfPage as u8 => self.do_end_of_page(oc, &mut cursor),
 
-                oc if oc == Opcode::PushStack as u8 => {
-                    char_run_ended = false;
-                    self.do_push_stack(oc, &mut cursor)
-                }
+                oc if oc == Opcode::PushStack as u8 => self.do_push_stack(oc, &mut cursor),
 
                 oc if oc == Opcode::PopStack as u8 => self.do_pop_stack(oc, &mut cursor),
 
@@ -360,15 +441,17 @@ impl<T: XdvEvents> XdvParser<T> {
                 oc if oc >= Opcode::SetCharNumber0 as u8
                     && oc <= Opcode::SetCharNumber127 as u8 =>
                 {
-                    char_run_ended = false;
                     self.do_set_char_number(oc, &mut cursor)
                 }
 
                 oc if oc >= Opcode::SetChar1 as u8 && oc <= Opcode::SetChar4 as u8 => {
-                    char_run_ended = false;
                     self.do_set_char(oc, &mut cursor)
                 }
 
+                oc if oc == Opcode::SetRule as u8 || oc == Opcode::PutRule as u8 => {
+                    self.do_rule(oc, &mut cursor)
+                }
+
                 oc if oc == Opcode::SetGlyphs as u8 => self.do_set_glyphs(oc, &mut cursor),
 
                 oc if oc == Opcode::SetTextAndGlyphs as u8 => {
@@ -403,11 +486,6 @@ impl<T: XdvEvents> XdvParser<T> {
                     return Err(e);
                 }
             }
-
-            if char_run_ended && !self.cur_char_run.is_empty() {
-                self.events.handle_char_run(&self.cur_char_run)?;
-                self.cur_char_run.clear();
-            }
         }
 
         self.offset += cursor.checkpoint as u64;
@@ -473,41 +551,40 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let _font_num = cursor.get_i32()?;
-        let _size = cursor.get_i32()?; // fixed-point
+        let font_num = cursor.get_i32()?;
+        let size = cursor.get_i32()?; // fixed-point
         let flags = cursor.get_u16()?;
         let name_len = cursor.get_u8()?;
-        // XXX TEMP
-        use std::str::from_utf8;
-        let _name_str = from_utf8(cursor.get_slice(name_len as usize)?)
-            .unwrap()
-            .to_owned();
-        let _face_index = cursor.get_u32()?;
+        let name = String::from_utf8_lossy(cursor.get_slice(name_len as usize)?).into_owned();
+        let face_index = cursor.get_u32()?;
 
-        let _color_rgba = if flags & NativeFontFlags::Colored as u16 != 0 {
+        let color_rgba = if flags & NativeFontFlags::Colored as u16 != 0 {
             Some(cursor.get_u32()?)
         } else {
             None
         };
 
-        let _extend = if flags & NativeFontFlags::Extend as u16 != 0 {
+        let extend = if flags & NativeFontFlags::Extend as u16 != 0 {
             Some(cursor.get_u32()?) // fixed-point
         } else {
             None
         };
 
-        let _slant = if flags & NativeFontFlags::Slant as u16 != 0 {
+        let slant = if flags & NativeFontFlags::Slant as u16 != 0 {
             Some(cursor.get_u32()?) // fixed-point
         } else {
             None
         };
 
-        let _embolden = if flags & NativeFontFlags::Embolden as u16 != 0 {
+        let embolden = if flags & NativeFontFlags::Embolden as u16 != 0 {
             Some(cursor.get_u32()?) // fixed-point
         } else {
             None
         };
 
+        self.events.handle_define_native_font(
+            &name, font_num, size, face_index, color_rgba, extend, slant, embolden,
+        )?;
         Ok(())
     }
 
@@ -531,7 +608,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
         self.state = ParserState::InPage;
         self.stack.clear();
-        self.stack.push(State::new());
+        self.stack.push(Registers::default());
         self.cur_font_num = None;
         Ok(())
     }
@@ -562,7 +639,7 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let dup = self.stack.last().unwrap().clone();
+        let dup = *self.stack.last().unwrap();
         self.stack.push(dup);
         Ok(())
     }
@@ -577,7 +654,7 @@ impl<T: XdvEvents> XdvParser<T> {
         }
 
         self.stack.pop();
-        Ok(())
+        self.moved()
     }
 
     fn do_right(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
@@ -587,7 +664,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
         let n = cursor.get_compact_i32_smneg(opcode - Opcode::Right1 as u8)?;
         self.stack.last_mut().unwrap().h += n;
-        Ok(())
+        self.moved()
     }
 
     fn do_right_by_w(
@@ -601,7 +678,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
         let state = self.stack.last_mut().unwrap();
         state.h += state.w;
-        Ok(())
+        self.moved()
     }
 
     fn do_set_w(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
@@ -613,7 +690,7 @@ impl<T: XdvEvents> XdvParser<T> {
         let state = self.stack.last_mut().unwrap();
         state.w = n;
         state.h += n;
-        Ok(())
+        self.moved()
     }
 
     fn do_right_by_x(
@@ -627,7 +704,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
         let state = self.stack.last_mut().unwrap();
         state.h += state.x;
-        Ok(())
+        self.moved()
     }
 
     fn do_set_x(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
@@ -639,7 +716,7 @@ impl<T: XdvEvents> XdvParser<T> {
         let state = self.stack.last_mut().unwrap();
         state.x = n;
         state.h += n;
-        Ok(())
+        self.moved()
     }
 
     fn do_down(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
@@ -649,7 +726,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
         let n = cursor.get_compact_i32_smneg(opcode - Opcode::Down1 as u8)?;
         self.stack.last_mut().unwrap().v += n;
-        Ok(())
+        self.moved()
     }
 
     fn do_down_by_y(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
@@ -659,7 +736,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
         let state = self.stack.last_mut().unwrap();
         state.v += state.y;
-        Ok(())
+        self.moved()
     }
 
     fn do_set_y(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
@@ -671,7 +748,7 @@ impl<T: XdvEvents> XdvParser<T> {
         let state = self.stack.last_mut().unwrap();
         state.y = n;
         state.v += n;
-        Ok(())
+        self.moved()
     }
 
     fn do_down_by_z(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
@@ -681,7 +758,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
         let state = self.stack.last_mut().unwrap();
         state.v += state.z;
-        Ok(())
+        self.moved()
     }
 
     fn do_set_z(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
@@ -693,7 +770,7 @@ impl<T: XdvEvents> XdvParser<T> {
         let state = self.stack.last_mut().unwrap();
         state.z = n;
         state.v += n;
-        Ok(())
+        self.moved()
     }
 
     /// This variant uses the opcode to encode the font number.
@@ -755,18 +832,12 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let _width = cursor.get_i32()?;
-        let n_glyphs = cursor.get_u16()?;
-
-        for _ in 0..n_glyphs {
-            let _x = cursor.get_u32()?;
-            let _y = cursor.get_u32()?;
-        }
-
-        for _ in 0..n_glyphs {
-            let _glyph_id = cursor.get_i16()?;
-        }
+        let font_num = self.native_font_num(cursor)?;
+        let width = cursor.get_i32()?;
+        let (glyphs, x, y) = self.get_glyphs(cursor)?;
 
+        self.events.handle_glyph_run(font_num, &glyphs, &x, &y)?;
+        self.stack.last_mut().unwrap().h += width;
         Ok(())
     }
 
@@ -779,23 +850,76 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
+        let font_num = self.native_font_num(cursor)?;
         let n_chars = cursor.get_u16()?;
-        let mut chars = Vec::new();
+        let mut chars = Vec::with_capacity(n_chars as usize);
 
         for _ in 0..n_chars {
             chars.push(cursor.get_u16()?);
         }
 
-        let _width = cursor.get_i32()?;
-        let n_glyphs = cursor.get_u16()?;
+        let width = cursor.get_i32()?;
+        let (glyphs, x, y) = self.get_glyphs(cursor)?;
+
+        let text = String::from_utf16_lossy(&chars);
+        self.events
+            .handle_text_and_glyphs(font_num, &text, width, &glyphs, &x, &y)?;
+        self.stack.last_mut().unwrap().h += width;
+        Ok(())
+    }
+
+    /// Glyphs can only be set if a font has been selected.
+    fn native_font_num(&self, cursor: &Cursor<T>) -> InternalResult<i32, T::Error> {
+        match self.cur_font_num {
+            Some(f) => Ok(f),
+            None => Err(XdvError::Malformed(cursor.global_offset()).into_internal()),
+        }
+    }
+
+    /// Read the glyph IDs and positions of a glyph run, converting the
+    /// positions from offsets to absolute coordinates.
+    #[allow(clippy::type_complexity)]
+    fn get_glyphs(
+        &self,
+        cursor: &mut Cursor<T>,
+    ) -> InternalResult<(Vec<u16>, Vec<i32>, Vec<i32>), T::Error> {
+        let n_glyphs = cursor.get_u16()? as usize;
+        let state = self.stack.last().unwrap();
+        let mut x = Vec::with_capacity(n_glyphs);
+        let mut y = Vec::with_capacity(n_glyphs);
 
         for _ in 0..n_glyphs {
-            let _x = cursor.get_u32()?;
-            let _y = cursor.get_u32()?;
+            x.push(state.h + cursor.get_i32()?);
+            y.push(state.v + cursor.get_i32()?);
         }
 
+        let mut glyphs = Vec::with_capacity(n_glyphs);
+
         for _ in 0..n_glyphs {
-            let _glyph_id = cursor.get_i16()?;
+            glyphs.push(cursor.get_u16()?);
+        }
+
+        Ok((glyphs, x, y))
+    }
+
+    fn do_rule(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
+        if self.state != ParserState::InPage {
+            return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
+        }
+
+        let height = cursor.get_i32()?;
+        let width = cursor.get_i32()?;
+        let (h, v) = {
+            let state = self.stack.last().unwrap();
+            (state.h, state.v)
+        };
+
+        if height > 0 && width > 0 {
+            self.events.handle_rule(h, v, height, width)?;
+        }
+
+        if opcode == Opcode::SetRule as u8 {
+            self.stack.last_mut().unwrap().h += width;
         }
 
         Ok(())
@@ -855,6 +979,18 @@ impl<T: XdvEvents> XdvParser<T> {
         self.offset
     }
 
+    /// Get the current position registers, if we're inside a page.
+    pub fn registers(&self) -> Option<&Registers> {
+        self.stack.last()
+    }
+
+    /// Tell the event handler about the new register state after a command
+    /// that moved the current position.
+    fn moved(&mut self) -> InternalResult<(), T::Error> {
+        self.events.handle_movement(self.stack.last().unwrap())?;
+        Ok(())
+    }
+
     /// Finish parsing, consume this object, and return the underlying event
     /// handler, assuming all went well.
     pub fn finish(self) -> Result<T, T::Error> {
@@ -866,28 +1002,31 @@ impl<T: XdvEvents> XdvParser<T> {
     }
 }
 
-/// The states that may be stacked while processing the DVI.
-#[derive(Clone, Debug, Eq, PartialEq)]
-struct State {
+/// The DVI position registers, which are saved and restored by the `push`
+/// and `pop` commands. All values are in TeX scaled points, with *v*
+/// increasing down the page.
+///
+/// Note that the parser doesn’t know the widths of the characters in
+/// traditional TFM fonts, so *h* is not advanced when they’re typeset.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Registers {
+    /// The current horizontal position.
     pub h: i32,
+
+    /// The current vertical position.
     pub v: i32,
+
+    /// A saved horizontal spacing amount.
     pub w: i32,
+
+    /// A second saved horizontal spacing amount.
     pub x: i32,
+
+    /// A saved vertical spacing amount.
     pub y: i32,
-    pub z: i32,
-}
 
-impl State {
-    pub fn new() -> Self {
-        State {
-            h: 0,
-            v: 0,
-            w: 0,
-            x: 0,
-            y: 0,
-            z: 0,
-        }
-    }
+    /// A second saved vertical spacing amount.
+    pub z: i32,
 }
 
 /// A simple cursor on a buffer.
@@ -1095,6 +1234,8 @@ enum Opcode {
     SetCharNumber127 = 127,
     SetChar1 = 128,
     SetChar4 = 131,
+    SetRule = 132,
+    PutRule = 137,
     Noop = 138,
     BeginningOfPage = 139,
     EndOfPage = 140,
@@ -1151,3 +1292,257 @@ enum NativeFontFlags {
     Slant = 0x2000,
     Embolden = 0x4000,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    enum Event {
+        Movement(Registers),
+        Rule(i32, i32, i32, i32),
+        Glyphs(i32, Vec<u16>, Vec<i32>, Vec<i32>),
+        Text(i32, String, i32, Vec<u16>, Vec<i32>, Vec<i32>),
+    }
+
+    #[derive(Default)]
+    struct Recorder(Vec<Event>);
+
+    impl XdvEvents for Recorder {
+        type Error = XdvError;
+
+        fn handle_movement(&mut self, registers: &Registers) -> Result<(), Self::Error> {
+            self.0.push(Event::Movement(*registers));
+            Ok(())
+        }
+
+        fn handle_rule(&mut self, x: i32, y: i32, height: i32, width: i32) -> Result<(), XdvError> {
+            self.0.push(Event::Rule(x, y, height, width));
+            Ok(())
+        }
+
+        fn handle_glyph_run(
+            &mut self,
+            font_num: i32,
+            glyphs: &[u16],
+            x: &[i32],
+            y: &[i32],
+        ) -> Result<(), Self::Error> {
+            self.0.push(Event::Glyphs(
+                font_num,
+                glyphs.to_vec(),
+                x.to_vec(),
+                y.to_vec(),
+            ));
+            Ok(())
+        }
+
+        fn handle_text_and_glyphs(
+            &mut self,
+            font_num: i32,
+            text: &str,
+            width: i32,
+            glyphs: &[u16],
+            x: &[i32],
+            y: &[i32],
+        ) -> Result<(), Self::Error> {
+            self.0.push(Event::Text(
+                font_num,
+                text.to_owned(),
+                width,
+                glyphs.to_vec(),
+                x.to_vec(),
+                y.to_vec(),
+            ));
+            Ok(())
+        }
+    }
+
+    /// Builds an XDV byte stream.
+    struct Xdv(Vec<u8>);
+
+    impl Xdv {
+        /// Start a stream with a preamble and the beginning of a page.
+        fn page() -> Self {
+            let mut x = Xdv(Vec::new());
+            x.op(Opcode::Preamble).u8(IdByte::Xdv as u8);
+            x.i32(25_400_000).i32(473_628_672).i32(1000).u8(0);
+            x.op(Opcode::BeginningOfPage);
+
+            for _ in 0..10 {
+                x.i32(0);
+            }
+
+            x.i32(-1);
+            x
+        }
+
+        /// End the page and the stream.
+        fn finish(mut self) -> Vec<u8> {
+            self.op(Opcode::EndOfPage);
+            self.op(Opcode::Postamble).i32(0);
+            self.i32(25_400_000).i32(473_628_672).i32(1000);
+            self.i32(0).i32(0).u16(1).u16(1);
+            self.op(Opcode::DoublePostamble)
+                .i32(0)
+                .u8(IdByte::Xdv as u8);
+            self.i32(-0x2020_2021); // 0xDFDFDFDF
+            self.0
+        }
+
+        fn op(&mut self, opcode: Opcode) -> &mut Self {
+            self.u8(opcode as u8)
+        }
+
+        fn u8(&mut self, v: u8) -> &mut Self {
+            self.0.push(v);
+            self
+        }
+
+        fn u16(&mut self, v: u16) -> &mut Self {
+            self.0.extend_from_slice(&v.to_be_bytes());
+            self
+        }
+
+        fn i32(&mut self, v: i32) -> &mut Self {
+            self.0.extend_from_slice(&v.to_be_bytes());
+            self
+        }
+
+        /// A glyph run with the given advance, glyph offsets and glyph IDs.
+        fn glyphs(&mut self, width: i32, offsets: &[(i32, i32)], ids: &[u16]) -> &mut Self {
+            self.i32(width).u16(offsets.len() as u16);
+
+            for &(dx, dy) in offsets {
+                self.i32(dx).i32(dy);
+            }
+
+            for &id in ids {
+                self.u16(id);
+            }
+
+            self
+        }
+    }
+
+    fn parse(data: &[u8]) -> (XdvParser<Recorder>, Result<usize, XdvError>) {
+        let mut parser = XdvParser::new(Recorder::default());
+        let r = parser.parse(data);
+        (parser, r)
+    }
+
+    fn regs(h: i32, v: i32) -> Registers {
+        Registers {
+            h,
+            v,
+            ..Registers::default()
+        }
+    }
+
+    #[test]
+    fn glyph_positions() {
+        let mut x = Xdv::page();
+        x.op(Opcode::Right1).u8(100);
+        x.op(Opcode::Down1).u8(10);
+        x.op(Opcode::SetFontNumber0);
+        x.op(Opcode::SetGlyphs)
+            .glyphs(500, &[(0, 0), (250, -5)], &[3, 4]);
+        x.op(Opcode::SetTextAndGlyphs).u16(2);
+        x.u16(u16::from(b'H')).u16(u16::from(b'i'));
+        x.glyphs(300, &[(0, 0)], &[7]);
+        let data = x.finish();
+
+        let (parser, r) = parse(&data);
+        assert_eq!(r, Ok(data.len()));
+        assert_eq!(
+            parser.finish().unwrap().0,
+            vec![
+                Event::Movement(regs(100, 0)),
+                Event::Movement(regs(100, 10)),
+                Event::Glyphs(0, vec![3, 4], vec![100, 350], vec![10, 5]),
+                Event::Text(0, "Hi".to_owned(), 300, vec![7], vec![600], vec![10]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rules() {
+        let mut x = Xdv::page();
+        x.op(Opcode::SetRule).i32(20).i32(30);
+        x.op(Opcode::PutRule).i32(20).i32(40);
+
+        // Empty rules aren't reported, but a set rule still advances.
+        x.op(Opcode::SetRule).i32(0).i32(50);
+        x.op(Opcode::PutRule).i32(20).i32(-10);
+
+        let (parser, r) = parse(&x.0);
+        assert_eq!(r, Ok(x.0.len()));
+        assert_eq!(parser.registers(), Some(&regs(80, 0)));
+        assert_eq!(
+            parser.events.0,
+            vec![Event::Rule(0, 0, 20, 30), Event::Rule(30, 0, 20, 40)]
+        );
+    }
+
+    #[test]
+    fn push_pop() {
+        let mut x = Xdv::page();
+        x.op(Opcode::Right1).u8(10);
+        x.op(Opcode::PushStack);
+        x.op(Opcode::SetW1).u8(5);
+        x.op(Opcode::SetY1).u8(7);
+        x.op(Opcode::RightByW);
+        x.op(Opcode::PopStack);
+
+        let (parser, r) = parse(&x.0);
+        assert_eq!(r, Ok(x.0.len()));
+        assert_eq!(parser.registers(), Some(&regs(10, 0)));
+        assert_eq!(
+            parser.events.0,
+            vec![
+                Event::Movement(regs(10, 0)),
+                Event::Movement(Registers {
+                    h: 15,
+                    w: 5,
+                    ..Registers::default()
+                }),
+                Event::Movement(Registers {
+                    h: 15,
+                    v: 7,
+                    w: 5,
+                    y: 7,
+                    ..Registers::default()
+                }),
+                Event::Movement(Registers {
+                    h: 20,
+                    v: 7,
+                    w: 5,
+                    y: 7,
+                    ..Registers::default()
+                }),
+                Event::Movement(regs(10, 0)),
+            ]
+        );
+
+        // Popping more than was pushed is an error.
+        let mut x = Xdv::page();
+        x.op(Opcode::PopStack);
+        let (_, r) = parse(&x.0);
+        assert!(matches!(r, Err(XdvError::Malformed(_))));
+    }
+
+    #[test]
+    fn glyphs_without_font() {
+        let mut x = Xdv::page();
+        x.op(Opcode::SetGlyphs).glyphs(10, &[(0, 0)], &[1]);
+        let (parser, r) = parse(&x.0);
+        assert!(matches!(r, Err(XdvError::Malformed(_))));
+        assert!(parser.events.0.is_empty());
+
+        let mut x = Xdv::page();
+        x.op(Opcode::SetTextAndGlyphs).u16(1).u16(u16::from(b'a'));
+        x.glyphs(10, &[(0, 0)], &[1]);
+        let (_, r) = parse(&x.0);
+        assert!(matches!(r, Err(XdvError::Malformed(_))));
+    }
+}